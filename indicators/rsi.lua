@@ -0,0 +1,41 @@
+-- relative strength index (wilder's smoothing)
+-- - the value is between 0 and 100, there is no value until periods + 1 bars have been received
+local periods = input("periods", "integer", 14, {min = 1})
+
+local previous_close = nil
+local average_gain = 0
+local average_loss = 0
+local count = 0
+
+-- when a new bar of data is received
+-- passes through the current bar and the latest bars of the symbol and interval of the indicator
+function on_bar(bar, history)
+    if previous_close == nil then
+        previous_close = bar.close
+        return
+    end
+
+    local change = bar.close - previous_close
+    local gain = math.max(change, 0)
+    local loss = math.max(-change, 0)
+    previous_close = bar.close
+    count = count + 1
+
+    if count <= periods then
+        -- the first averages are simple averages of the first changes
+        average_gain = average_gain + gain / periods
+        average_loss = average_loss + loss / periods
+        if count < periods then
+            return
+        end
+    else
+        average_gain = (average_gain * (periods - 1) + gain) / periods
+        average_loss = (average_loss * (periods - 1) + loss) / periods
+    end
+
+    if average_loss == 0 then
+        return update({value = 100})
+    end
+
+    return update({value = 100 - 100 / (1 + average_gain / average_loss)})
+end
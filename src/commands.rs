@@ -1,4 +1,4 @@
-use crate::{system::Core};
+use crate::{system::Core, database::tasks::merge::ConflictPolicy};
 
 
 
@@ -12,6 +12,14 @@ pub enum CommandType {
     ListDatasets,
     ListStrategies,
     ListIndicators,
+    UpdateDataset,
+    MergeDatasets,
+    PartitionDataset,
+    RepairFile,
+    ListSnapshots,
+    BuildRollups,
+    AsOf,
+    ImportOrderBooks,
 
     // strategy commands (controlling running strategies)
     StatusStrategyThread,
@@ -66,6 +74,86 @@ impl Command {
             }
         }
 
+        // update [exchange] [symbol] [source file] [policy]
+        else if raw_input.starts_with("update") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 4 || params.len() == 5 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::UpdateDataset;
+            }
+        }
+
+        // merge [exchange] [symbol] [policy] [source files...]
+        else if raw_input.starts_with("merge") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() >= 5 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::MergeDatasets;
+            }
+        }
+
+        // partition [exchange] [symbol]
+        else if raw_input.starts_with("partition") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 3 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::PartitionDataset;
+            }
+        }
+
+        // rollup [exchange] [symbol]
+        else if raw_input.starts_with("rollup") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 3 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::BuildRollups;
+            }
+        }
+
+        // snapshots [exchange] [symbol]
+        else if raw_input.starts_with("snapshots") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 3 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::ListSnapshots;
+            }
+        }
+
+        // asof [exchange] [symbol] [interval] [time]
+        else if raw_input.starts_with("asof") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 5 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::AsOf;
+            }
+        }
+
+        // book [exchange] [symbol] [csv file]
+        else if raw_input.starts_with("book") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 4 {
+                command_params = params[1..].iter().map(|param| param.to_string()).collect();
+                command_type = CommandType::ImportOrderBooks;
+            }
+        }
+
+        // repair [file]
+        else if raw_input.starts_with("repair") {
+            let params: Vec<&str> = raw_input.split_whitespace().collect();
+
+            if params.len() == 2 {
+                command_params.push(params[1].to_string());
+                command_type = CommandType::RepairFile;
+            }
+        }
+
         else if raw_input.starts_with("status") || raw_input.starts_with("start") || raw_input.starts_with("stop") || raw_input.starts_with("pause") || raw_input.starts_with("resume") {
             let params: Vec<&str> = raw_input.split_whitespace().collect();
 
@@ -95,7 +183,7 @@ impl Command {
 
         Command {
             raw: raw_input,
-            command_type: command_type,
+            command_type,
             params: command_params
         }
     }
@@ -109,11 +197,21 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
             // output help messaging
             println!("Available commands:");
             println!("list   [type]          - lists out the loaded items of a given type (datasets, strategies, indicators, etc.)");
-            println!("start  [strategy name] - starts a new strategy thread");
+            println!("start  [strategy name] - starts a new strategy thread (backtests the datasets of its settings)");
+            println!("status [strategy name] - shows if the strategy thread is running, paused, stopped or finished");
             println!("stop   [strategy name] - stops the strategy thread");
             println!("pause  [strategy name] - pauses the strategy thread");
             println!("resume [strategy name] - resumes a paused strategy thread");
             println!("reload                 - reloads all the plugins (datasets, strategies, indicators, etc.)");
+            println!("update [exchange] [symbol] [file] [policy]    - appends the newer records of a stmdb/csv file to a dataset");
+            println!("merge  [exchange] [symbol] [policy] [files..] - merges overlapping stmdb/csv files into a dataset");
+            println!("                                                (policies: newer, volume, fail)");
+            println!("partition [exchange] [symbol]                 - splits a single file dataset into monthly files");
+            println!("rollup [exchange] [symbol]                    - builds the rollups of a dataset (intervals from the rollups config key)");
+            println!("snapshots [exchange] [symbol]                 - lists the versions of a dataset (a query can pin a snapshot id)");
+            println!("asof [exchange] [symbol] [interval] [time]    - shows the latest completed bar at or before a time (unix seconds or 2020-06-01T13:37)");
+            println!("book [exchange] [symbol] [file]               - imports the order book snapshots of a csv file (timestamp, then bid price, bid size, ask price, ask size per level)");
+            println!("repair [file]                                 - writes the readable records of a damaged stmdb file into a repaired copy");
             println!("help                   - displays this help message");
             println!("exit                   - exits the program");
             // continue the loop
@@ -142,8 +240,49 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
                 println!("{}", key);
             }
         },
+        CommandType::UpdateDataset => {
+            let policy = command.params.get(3).map_or("fail", |policy| policy.as_str());
+            let policy = match ConflictPolicy::from_string(policy) {
+                Some(policy) => policy,
+                None => {
+                    println!("Unsupported conflict policy {}", policy);
+                    return false;
+                }
+            };
+
+            core.update_dataset(command.params[0].clone(), command.params[1].clone(), command.params[2].clone(), policy);
+        },
+        CommandType::MergeDatasets => {
+            let policy = match ConflictPolicy::from_string(&command.params[2]) {
+                Some(policy) => policy,
+                None => {
+                    println!("Unsupported conflict policy {}", command.params[2]);
+                    return false;
+                }
+            };
+
+            core.merge_datasets(command.params[0].clone(), command.params[1].clone(), command.params[3..].to_vec(), policy);
+        },
+        CommandType::PartitionDataset => {
+            core.partition_dataset(command.params[0].clone(), command.params[1].clone());
+        },
+        CommandType::BuildRollups => {
+            core.build_rollups(command.params[0].clone(), command.params[1].clone());
+        },
+        CommandType::ListSnapshots => {
+            core.list_snapshots(command.params[0].clone(), command.params[1].clone());
+        },
+        CommandType::AsOf => {
+            core.as_of(command.params[0].clone(), command.params[1].clone(), command.params[2].clone(), command.params[3].clone());
+        },
+        CommandType::ImportOrderBooks => {
+            core.import_order_books(command.params[0].clone(), command.params[1].clone(), command.params[2].clone());
+        },
+        CommandType::RepairFile => {
+            core.repair_file(command.params[0].clone());
+        },
         CommandType::StatusStrategyThread => {
-            let strategy_name = command.params.get(0).unwrap();
+            let strategy_name = command.params.first().unwrap();
 
             let status = core.status(strategy_name.to_string());
             
@@ -152,7 +291,7 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
         CommandType::StartStrategyThread => {
             println!("Start strategy thread");
 
-            let strategy_name = command.params.get(0).unwrap();
+            let strategy_name = command.params.first().unwrap();
 
             let is_started: bool = core.start(strategy_name.to_string());
             if is_started {
@@ -165,7 +304,7 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
         CommandType::StopStrategyThread => {
             println!("Stop strategy thread");
 
-            let strategy_name = command.params.get(0).unwrap();
+            let strategy_name = command.params.first().unwrap();
 
             let is_stopped: bool = core.stop(strategy_name.to_string());
             if is_stopped {
@@ -178,7 +317,7 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
         CommandType::PauseStrategyThread => {
             println!("Pause strategy thread");
 
-            let strategy_name = command.params.get(0).unwrap();
+            let strategy_name = command.params.first().unwrap();
 
             let is_paused: bool = core.pause(strategy_name.to_string());
             if is_paused {
@@ -191,7 +330,7 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
         CommandType::ResumeStrategyThread => {
             println!("Resume strategy thread");
 
-            let strategy_name = command.params.get(0).unwrap();
+            let strategy_name = command.params.first().unwrap();
 
             let is_resumed: bool = core.resume(strategy_name.to_string());
             if is_resumed {
@@ -202,9 +341,9 @@ pub fn handle_command(core: &mut Core, command: Command) -> bool {
             }
         },
         CommandType::Unsupported => {
-            println!("Unsupported command");
+            println!("Unsupported command: {}", command.raw);
         }
     }
 
-    return false;
+    false
 }
\ No newline at end of file
@@ -20,53 +20,51 @@ impl InMemoryCache {
         }
     }
 
-    // get a set number of bars from the cache
+    // get a set number of bars from the cache (the oldest bars first)
+    // - the bars are only marked as the last ones once the last page has been added and no bar is left in the cache
     pub fn get(&mut self, key: String, limit: i32) -> Option<BarSet> {
-        // obtain a read handle to the cache
-        let read_handle = self.read_handle.read();
-        if let Some(view) = &read_handle {
-            // search for the key in the cache
-            if let Some(barset) = view.get_one(&key) {
-                // check if the limit is greater than the number of bars
-                if limit > barset.bars.len() as i32 {
-                    // remove the bars from the cache
-                    let empty_barset = BarSet::new();
-                    self.write_handle.remove(key, empty_barset);
-                    self.write_handle.refresh();
-
-                    // return all the bars
-                    return Some(barset.clone());
-                }
-
-                // get bars up to the limit
-                let bars = barset.bars[0..limit as usize].to_vec();
-                let is_last = barset.is_last.clone();
-
-                // remove the bars that are returned from the cache
-                let remaining_bars = barset.bars[limit as usize..].to_vec();
-                if !remaining_bars.is_empty() {
-                    self.write_handle.update(key.clone(), BarSet::new_with(remaining_bars, is_last));
-                }
-                else {
-                    self.write_handle.remove(key.clone(), BarSet::new());
-                }
-                self.write_handle.refresh();
-
-                // return the barsets up to the limit
-                return Some(BarSet::new_with(bars, is_last));
-            }
+        // search for the key in the cache (the read handle is released before the cache is updated)
+        let barset = self.read_handle.get_one(&key).map(|barset| barset.clone())?;
+
+        // check if the limit is greater than or equal to the number of bars
+        if limit >= barset.bars.len() as i32 {
+            // remove the bars from the cache
+            self.write_handle.empty(key);
+            self.write_handle.refresh();
+
+            // return all the bars
+            return Some(barset);
         }
 
-        None
+        // get bars up to the limit, the remaining bars stay in the cache
+        let bars = barset.bars[0..limit as usize].to_vec();
+        let remaining_bars = barset.bars[limit as usize..].to_vec();
+        self.write_handle.update(key, BarSet::new_with(remaining_bars, barset.is_last));
+        self.write_handle.refresh();
+
+        // return the barsets up to the limit
+        Some(BarSet::new_with(bars, false))
+    }
+
+    // remove the bars of a key from the cache
+    pub fn remove(&mut self, key: String) {
+        self.write_handle.empty(key);
+        self.write_handle.refresh();
     }
 
-    // add a set of barsets to the cache
+    // replace the bars of a key with the last barset of a query that failed
+    // - the error is returned by the next get of the key
+    pub fn fail(&mut self, key: String, barset: BarSet) {
+        self.write_handle.update(key, barset);
+        self.write_handle.refresh();
+    }
+
+    // add a page of bars to the cache after the bars that are already cached
     pub fn add(&mut self, key: String, bars: Vec<Bar>, is_last: bool) -> bool {
         // get a read handle to the cache
         let barset = if let Some(existing_barset) = self.write_handle.get_one(&key) {
             let existing_bars = existing_barset.bars.clone();
-            // let is_last = existing_barset.is_last.clone();
-            BarSet::new_with(bars.clone().into_iter().chain(existing_bars).collect(), is_last)
+            BarSet::new_with(existing_bars.into_iter().chain(bars).collect(), is_last)
         }
         else {
             BarSet::new_with(bars, is_last)
@@ -78,4 +76,57 @@ impl InMemoryCache {
 
         true
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(start: i64, count: i64) -> Vec<Bar> {
+        (start..start + count).map(Bar::new).collect()
+    }
+
+    #[test]
+    fn pages_are_returned_in_order() {
+        let mut cache = InMemoryCache::new();
+        cache.add("query".to_string(), page(0, 1000), false);
+        cache.add("query".to_string(), page(1000, 500), true);
+
+        let first = cache.get("query".to_string(), 1000).unwrap();
+        assert_eq!(first.bars.len(), 1000);
+        assert_eq!(first.bars[0].timestamp, 0);
+        assert!(!first.is_last);
+
+        let second = cache.get("query".to_string(), 1000).unwrap();
+        assert_eq!(second.bars.len(), 500);
+        assert_eq!(second.bars[0].timestamp, 1000);
+        assert!(second.is_last);
+
+        assert!(cache.get("query".to_string(), 1000).is_none());
+    }
+
+    #[test]
+    fn last_page_is_only_reported_once_the_cache_is_empty() {
+        let mut cache = InMemoryCache::new();
+        cache.add("query".to_string(), page(0, 300), true);
+
+        let first = cache.get("query".to_string(), 200).unwrap();
+        assert_eq!(first.bars.len(), 200);
+        assert!(!first.is_last);
+
+        let second = cache.get("query".to_string(), 200).unwrap();
+        assert_eq!(second.bars.iter().map(|bar| bar.timestamp).collect::<Vec<i64>>(), (200..300).collect::<Vec<i64>>());
+        assert!(second.is_last);
+    }
+
+    #[test]
+    fn pages_added_while_reading_follow_the_remaining_bars() {
+        let mut cache = InMemoryCache::new();
+        cache.add("query".to_string(), page(0, 10), false);
+        assert_eq!(cache.get("query".to_string(), 4).unwrap().bars[0].timestamp, 0);
+        cache.add("query".to_string(), page(10, 10), true);
+
+        let rest = cache.get("query".to_string(), 100).unwrap();
+        assert_eq!(rest.bars.iter().map(|bar| bar.timestamp).collect::<Vec<i64>>(), (4..20).collect::<Vec<i64>>());
+        assert!(rest.is_last);
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::io::Error;
-use super::{engine::DatabaseEngine, models::{query::Query, query_result::QueryResult, bar::Bar}};
+use super::{engine::DatabaseEngine, tasks::{merge::{ConflictPolicy, MergeSummary}, repair::RepairReport}, models::{history::History, candlestick::Candlestick, interval::Interval, index::{Snapshot, Rollup}, query::Query, query_result::QueryResult, bar::Bar, exchange::Exchange, symbol::Symbol, calendar::TradingCalendar}};
 
 // Database struct. It is used to represent the database system and all of its clients.
 // we support the stmdb file format
@@ -10,7 +11,8 @@ use super::{engine::DatabaseEngine, models::{query::Query, query_result::QueryRe
 #[derive(Clone)]
 pub struct Database {
     engine: DatabaseEngine,
-    clients: Vec<u64>,
+    // the clients are shared by every clone of the database
+    clients: Arc<Mutex<Vec<u64>>>,
 }
 
 impl Database {
@@ -19,34 +21,136 @@ impl Database {
     pub fn new() -> Database {
         Database {
             engine: DatabaseEngine::new(),
-            clients: Vec::new(),
+            clients: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    // reads and writes the datasets of the given folder instead of ./data
+    #[allow(dead_code)]
+    pub fn with_path(mut self, path: String) -> Self {
+        self.engine.path = path;
+        self
+    }
+
+    // keeps rollups of the given intervals in sync with every dataset that is written
+    // - queries read the coarsest rollup that can be consolidated into their intervals instead of the 1m bars
+    pub fn with_rollups(mut self, intervals: Vec<Interval>) -> Self {
+        self.engine.rollup_intervals = intervals;
+        self
+    }
+
     // adds a new client connection to the database
     pub fn connect_client(&mut self, client_id: u64) {
-        self.clients.push(client_id);
+        self.clients.lock().unwrap().push(client_id);
     }
 
     // removes a client connection from the database
     pub fn disconnect_client(&mut self, client_id: u64) {
-        self.clients.retain(|id| *id != client_id);
+        self.clients.lock().unwrap().retain(|id| *id != client_id);
     }
 
     // gets historical data from the database using a query and fill cache
     // gets first chunk and returns a query id to further chunks of data
     pub fn query(&mut self, client_id: u64, query: Query) -> Result<QueryResult, Error> {
-        Ok(self.engine.start_query(client_id, query).unwrap())
+        self.engine.start_query(client_id, query)
     }
 
     // gets historical data from the database using a query id from cache
     // parameters: index - index in number of records (a multiplied value of bytes)
     pub fn query_chunk(&mut self, query_id: String, parameters: HashMap<String, String>) -> Result<QueryResult, Error> {
-        Ok(self.engine.query_chunk(query_id, parameters).unwrap())
+        self.engine.query_chunk(query_id, parameters)
+    }
+
+    // stops a query that was started and removes its data from the cache
+    pub fn stop_query(&mut self, query_id: String) -> Result<(), Error> {
+        self.engine.stop_query(query_id)
+    }
+
+    // import the order book snapshots of a csv file for a symbol (returns the number of snapshots and levels)
+    pub fn import_order_books(&self, exchange: Exchange, symbol: Symbol, source: String) -> Result<(u64, u8), Error> {
+        self.engine.import_order_books(exchange.name, symbol.name, source)
+    }
+
+    // gets a symbol with the contract specification from the symbol registry of the exchange
+    // - the symbol has no specification when the exchange has no registry or does not list it
+    pub fn get_symbol(&self, exchange: Exchange, symbol: Symbol) -> Result<Symbol, Error> {
+        self.engine.get_symbol(exchange, symbol)
+    }
+
+    // gets the base resolution of a dataset (the interval of the base bars of its queries)
+    pub fn get_resolution(&self, exchange: Exchange, symbol: Symbol) -> Result<Interval, Error> {
+        self.engine.get_resolution(exchange.name, symbol.name)
+    }
+
+    // gets the trading calendar of an exchange
+    // - exchanges without a calendar file trade 24/7 in UTC
+    pub fn get_calendar(&self, exchange: Exchange) -> Result<TradingCalendar, Error> {
+        self.engine.get_calendar(exchange)
+    }
+
+    // appends the records of a source file (stmdb or csv) that are newer than the end of a dataset
+    pub fn update_dataset(&self, exchange: Exchange, symbol: Symbol, source: String, policy: ConflictPolicy) -> Result<MergeSummary, Error> {
+        self.engine.update_dataset(exchange.name, symbol.name, source, policy)
+    }
+
+    // merges overlapping source files (stmdb or csv) into a dataset
+    pub fn merge_datasets(&self, exchange: Exchange, symbol: Symbol, sources: Vec<String>, policy: ConflictPolicy) -> Result<MergeSummary, Error> {
+        self.engine.merge_datasets(exchange.name, symbol.name, sources, policy)
+    }
+
+    // splits a dataset that is stored in a single file into time partitions
+    pub fn partition_dataset(&self, exchange: Exchange, symbol: Symbol) -> Result<MergeSummary, Error> {
+        self.engine.partition_dataset(exchange.name, symbol.name)
+    }
+
+    // builds the rollups of a dataset that was written before rollups were enabled
+    pub fn build_rollups(&self, exchange: Exchange, symbol: Symbol) -> Result<Vec<Rollup>, Error> {
+        self.engine.build_rollups(exchange.name, symbol.name)
+    }
+
+    // gets the snapshots of a dataset (a snapshot id can be pinned by a query to read the dataset as it was)
+    pub fn snapshots(&self, exchange: Exchange, symbol: Symbol) -> Result<Vec<Snapshot>, Error> {
+        self.engine.snapshots(exchange.name, symbol.name)
+    }
+
+    // gets the last bars of every symbol and interval of a query that end at or before a timestamp
+    pub fn history(&self, query: Query, count: usize, end_timestamp: i64) -> Result<Vec<History>, Error> {
+        self.engine.history(query, count, end_timestamp)
+    }
+
+    // gets the latest completed bar of an interval for a symbol at or before a timestamp
+    pub fn as_of(&self, exchange: Exchange, symbol: Symbol, interval: String, timestamp: i64) -> Result<Option<Candlestick>, Error> {
+        Ok(self.as_of_many(vec![(exchange, symbol)], interval, timestamp)?.pop().flatten())
+    }
+
+    // gets the latest completed bar of an interval for many symbols at or before a timestamp
+    // - the bars are returned in the order of the symbols (none when a symbol has no bar before the timestamp)
+    pub fn as_of_many(&self, symbols: Vec<(Exchange, Symbol)>, interval: String, timestamp: i64) -> Result<Vec<Option<Candlestick>>, Error> {
+        let interval = Interval::from_string(&interval)?;
+        let length = interval.length();
+
+        // only one bar of every symbol is read so the symbol limit of queries does not apply
+        let mut query = Query::new(self.clone()).with_intervals(vec![interval.name]);
+        query.symbols = symbols.clone();
+        let history = self.engine.history(query, 1, timestamp)?;
+
+        Ok(symbols.iter().map(|(exchange, symbol)| {
+            history.iter()
+                // the base bars are named after the base resolution of the symbol (1m and 60s are the same interval)
+                .filter(|history| history.exchange == exchange.name && history.symbol == symbol.name)
+                .find(|history| Interval::from_string(&history.interval).is_ok_and(|interval| interval.length() == length))
+                .and_then(|history| history.candlesticks.last().cloned())
+        }).collect())
+    }
+
+    // scans a damaged file and writes a repaired copy with a report of what was lost
+    pub fn repair_file(&self, filename: String) -> Result<RepairReport, Error> {
+        self.engine.repair_file(filename)
     }
 
     // inserts data into the database
-    pub fn insert(&self, client_id: u64, data: Vec<Bar>) -> Result<bool, Error> {
+    #[allow(dead_code)]
+    pub fn insert(&self, _client_id: u64, _data: Vec<Bar>) -> Result<bool, Error> {
         // Ok(self.engine.insert(client_id, data))
         Ok(true)
     }
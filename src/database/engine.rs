@@ -1,15 +1,31 @@
 use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::{Instant, Duration};
 use std::{sync::Arc, collections::HashMap};
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use crossbeam::channel::{unbounded, Sender, Receiver};
 use uuid::Uuid;
-use super::models::bar::Bar;
-use super::models::index::DatabaseIndex;
+use super::models::index::{DatabaseIndex, Corpus, Snapshot, Rollup, hash_file};
+use super::models::order_book::OrderBook;
+use super::storage::{Reader, Writer};
+use super::models::header::Header;
 use super::models::query::Query;
 use super::tasks::Task;
-use super::tasks::query::QueryTask;
+use super::tasks::query::{QueryTask, QuerySource};
+use super::tasks::consolidate::ConsolidateTask;
+use super::tasks::merge::{MergeTask, MergeSummary, ConflictPolicy};
+use super::tasks::repair::{RepairTask, RepairReport};
+use super::tasks::history::HistoryTask;
+use super::tasks::rollup::RollupTask;
+use super::models::history::History;
+use super::models::interval::Interval;
+use super::models::calendar::TradingCalendar;
+use super::models::symbol_table::{SymbolTable, SymbolId};
+use super::models::header::DEFAULT_RESOLUTION;
+use super::models::coverage::{AlignmentPolicy, Coverage};
+use super::models::partition::default_partition_interval;
+use super::models::{exchange::Exchange, symbol::Symbol};
 use super::{models::{barset::BarSet, query_result::QueryResult}, cache::InMemoryCache, threads::ThreadPool};
 
 
@@ -30,6 +46,18 @@ pub struct DatabaseEngine {
 
     // the hashmap that stores the query channels
     query_channels: HashMap<String, Arc<Receiver<BarSet>>>,
+
+    // the flags that stop the queries that are still running (a query stops reading pages once its flag is set)
+    query_stops: HashMap<String, Arc<AtomicBool>>,
+
+    // the interval new datasets are partitioned into (a file per month by default)
+    pub partition_interval: Interval,
+
+    // the ids of the exchange and symbol pairs used by the bars of every query
+    symbols: Arc<RwLock<SymbolTable>>,
+
+    // the intervals of the rollups that are kept in sync with every dataset that is written (none by default)
+    pub rollup_intervals: Vec<Interval>,
 }
 
 impl DatabaseEngine {
@@ -48,10 +76,15 @@ impl DatabaseEngine {
             thread_pool,
             cache,
             query_channels: HashMap::new(),
+            query_stops: HashMap::new(),
+            partition_interval: default_partition_interval(),
+            symbols: Arc::new(RwLock::new(SymbolTable::new())),
+            rollup_intervals: Vec::new(),
         }
     }
 
     // create a new database engine with a given path
+    #[allow(dead_code)]
     pub fn new_with(path: String, min_workers: i8) -> DatabaseEngine {
         // create a thread pool for the database engine
         let thread_pool = Arc::new(ThreadPool::new(min_workers as usize));
@@ -64,29 +97,40 @@ impl DatabaseEngine {
             thread_pool,
             cache,
             query_channels: HashMap::new(),
+            query_stops: HashMap::new(),
+            partition_interval: default_partition_interval(),
+            symbols: Arc::new(RwLock::new(SymbolTable::new())),
+            rollup_intervals: Vec::new(),
         }
     }
 
     // query the database for bars
     pub fn start_query(&mut self, client_id: u64, query: Query) -> Result<QueryResult, Error> {
         // generate a uuid for the query id
-        let query_id = format!("{}_{}", client_id, Uuid::new_v4().to_string());
+        let query_id = format!("{}_{}", client_id, Uuid::new_v4());
         let query_id_thread = query_id.clone();
         let query_id_task = query_id.clone();
 
-        // use index to look up the files that contain the data for the query
-        let index = DatabaseIndex::from_query("index.json".to_string(), "data".to_string(), query.clone());
-        let index_clone = index.clone();
-        let path = self.path.clone();
-        let filenames = index_clone.corpus_map.clone().values().map(|s| {
-            format!("{}/{}.stmdb", path, s.filename.to_string())
-        }).collect::<Vec<String>>();
+        // look up the files, coverage, intervals and calendars of the query
+        let (mut sources, order_book_filenames, symbol_table) = self.query_sources(&query, query.start_timestamp.unwrap_or(0), query.end_timestamp.unwrap_or(i64::MAX))?;
+        let (coverage, start_timestamp, end_timestamp) = query_coverage(&query, &mut sources)?;
+        let intervals = query_intervals(&query)?;
+        let calendars = self.query_calendars(&query, &sources, Some(start_timestamp), Some(end_timestamp))?;
+        if query.rollups {
+            self.plan_rollups(&query, &intervals, &mut sources)?;
+        }
+        // every symbol is consolidated from its own base resolution (the finest one is the default)
+        let resolutions = sources.iter().map(|source| (source.symbol_id, source.base_seconds)).collect::<HashMap<SymbolId, i64>>();
+        let base_seconds = resolutions.values().copied().min().unwrap_or(DEFAULT_RESOLUTION as i64);
+        let consolidate_task = ConsolidateTask::new(intervals, calendars, query.extended_hours, base_seconds).with_resolutions(resolutions.clone());
 
         // create a new query channel and store it in a hashmap
         let (query_channel, receiver_channel): (Sender<BarSet>, Receiver<BarSet>) = unbounded();
         let query_channel = Arc::new(query_channel);
         let receiver_channel = Arc::new(receiver_channel);
         self.query_channels.insert(query_id.clone(), receiver_channel.clone());
+        let stop = Arc::new(AtomicBool::new(false));
+        self.query_stops.insert(query_id.clone(), stop.clone());
 
         // start the tasks that need to be started on the thread pool to perform the query
         // - multiple tasks to read a chunk from a file
@@ -94,52 +138,207 @@ impl DatabaseEngine {
         // - a single task to consolidate different intervals of the data
         let thread_pool = self.thread_pool.clone();
         let cache = self.cache.clone();
-        let mut query_channels = self.query_channels.clone();
         self.thread_pool.execute(move || {
             println!("thread.query: starting query");
             let start = Instant::now();
 
             // create a new query task
+            // - the task owns the only sender of the channel so the results are drained once it is dropped
             let limit = query.limit;
             let mut task = QueryTask::new(
                 thread_pool,
-                query_channel.clone(),
-                filenames.clone(),
-                query.clone().start_timestamp.unwrap(),
-                query.clone().end_timestamp.unwrap(),
-                limit
-            );
+                query_channel,
+                sources,
+                start_timestamp,
+                end_timestamp,
+                limit,
+                query.fields.clone()
+            ).with_order_books(order_book_filenames, query.order_book_levels.unwrap_or(0))
+            .with_adjustment(query.adjustment)
+            .with_verify(query.verify)
+            .with_consolidation(consolidate_task)
+            .with_stop(stop.clone());
 
             // start the query task
             task.execute(Some(Box::new(move |result: bool| {
                 let elapsed = start.elapsed().as_millis();
                 // print the result of the query task
-                println!("query {} completed in {}ms", query_id_task.clone(), elapsed);
+                let status = if result { "completed" } else { "failed" };
+                println!("query {} {} in {}ms", query_id_task.clone(), status, elapsed);
             })));
+            drop(task);
 
             // wait for results on the channel in a loop until there are none left
+            // - the results of a stopped query are dropped
             println!("thread.query: waiting for results");
             while let Ok(results) = receiver_channel.recv() {
-
-                // println!("thread.query: got results: {:?}", results.bars.get(100).unwrap());
-
                 // try to get the lock on the cache
                 let mut cache = cache.lock().unwrap();
+                if stop.load(Ordering::SeqCst) {
+                    continue;
+                }
 
-                // add the results to the cache
-                cache.add(query_id_thread.clone(), results.bars.clone(), results.is_last.clone());
+                // add the results to the cache (the error of a failed query replaces the bars that are left)
+                if results.error.is_some() {
+                    cache.fail(query_id_thread.clone(), results);
+                    break;
+                }
+                cache.add(query_id_thread.clone(), results.bars, results.is_last);
+                if results.is_last {
+                    break;
+                }
 
                 // sleep on all by the last result
-                if !results.is_last {
-                    thread::sleep(Duration::from_micros(10));
-                }
+                drop(cache);
+                thread::sleep(Duration::from_micros(10));
             }
-
-            // remove the query channel from the hashmap when it is done
-            query_channels.remove(&query_id_thread.clone());
+            println!("thread.query: query {} finished", query_id_thread);
         });
 
-        Ok(QueryResult::new(query_id, "running".to_string()))
+        Ok(QueryResult::new(query_id, "running".to_string()).with_symbols(symbol_table).with_coverage(coverage).with_resolutions(resolutions))
+    }
+
+    // stop a query that was started and remove its bars from the cache
+    // - the query thread stops reading pages and exits once the pages that were read are drained
+    pub fn stop_query(&mut self, query_id: String) -> Result<(), Error> {
+        let stop = match self.query_stops.remove(&query_id) {
+            Some(stop) => stop,
+            None => return Err(Error::new(ErrorKind::NotFound, "engine: query id does not exist")),
+        };
+        stop.store(true, Ordering::SeqCst);
+        self.query_channels.remove(&query_id);
+        self.cache.lock().unwrap().remove(query_id);
+
+        Ok(())
+    }
+
+    // read the last bars of every symbol and interval of a query that end at or before a timestamp
+    // - used to seed the history of strategies and indicators before the first bar of a backtest
+    pub fn history(&self, query: Query, count: usize, end_timestamp: i64) -> Result<Vec<History>, Error> {
+        let (sources, _, _) = self.query_sources(&query, 0, end_timestamp)?;
+        let intervals = query_intervals(&query)?;
+        let calendars = self.query_calendars(&query, &sources, None, Some(end_timestamp))?;
+
+        HistoryTask::new(sources, intervals, count, end_timestamp)
+            .with_calendars(calendars, query.extended_hours)
+            .with_adjustment(query.adjustment)
+            .with_verify(query.verify)
+            .run()
+    }
+
+    // look up the files of the symbols of a query that overlap a time range and intern the symbols
+    // - symbols that are partitioned only read the partitions that overlap the range
+    // - a pinned snapshot reads the files of every symbol as they were at that snapshot
+    // - the base resolution of a symbol comes from the catalog (or the header of files that are not in the catalog)
+    fn query_sources(&self, query: &Query, start_timestamp: i64, end_timestamp: i64) -> Result<QuerySources, Error> {
+        let path = self.path.clone();
+        let catalog = DatabaseIndex::from_file(format!("{}/index.csv", path), path.clone())?;
+        if let Some(snapshot_id) = query.snapshot_id {
+            if snapshot_id >= catalog.next_snapshot_id() {
+                return Err(Error::new(std::io::ErrorKind::NotFound, format!("engine: snapshot {} does not exist", snapshot_id)));
+            }
+        }
+
+        // symbols that are not in the catalog are read from [path]/[exchange]_[symbol].stmdb
+        let mut symbols = self.symbols.write().unwrap();
+        let sources = query.symbols.iter().map(|(exchange, symbol)| {
+            let (exchange, symbol) = (&exchange.name, &symbol.name);
+            let files = catalog.files(exchange, symbol, query.snapshot_id);
+            let base_seconds = files.first().map(|corpus| corpus.resolution.length());
+            let files = match (files.iter().any(|file| file.partition.is_some()), files.first()) {
+                (true, _) => catalog.partitions(exchange, symbol, start_timestamp, end_timestamp, query.snapshot_id).iter().map(|partition| {
+                    format!("{}/{}.stmdb", path, partition.filename)
+                }).collect::<Vec<String>>(),
+                (false, Some(file)) => vec![format!("{}/{}.stmdb", path, file.filename)],
+                (false, None) => vec![format!("{}/{}_{}.stmdb", path, exchange, symbol)],
+            };
+            let base_seconds = match base_seconds {
+                Some(base_seconds) => base_seconds,
+                None => files.first().and_then(|file| Reader::new(file.clone()).and_then(|mut reader| Ok(reader.header()?.resolution as i64)).ok()).unwrap_or(DEFAULT_RESOLUTION as i64),
+            };
+
+            Ok(QuerySource::new(symbols.intern(exchange, symbol)?, exchange.clone(), symbol.clone(), files, format!("{}/{}_{}.actions.csv", path, exchange, symbol))
+                .with_base_seconds(base_seconds))
+        }).collect::<Result<Vec<QuerySource>, Error>>()?;
+
+        // a query fails when a file does not declare every extra field that was requested
+        for filename in sources.iter().flat_map(|source| source.files.iter()) {
+            let mut reader = Reader::new(filename.clone())?;
+            let header = reader.header()?;
+            if let Some(field) = query.fields.iter().find(|field| !header.has_field(field)) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("engine: file {} does not have field {}", filename, field)));
+            }
+        }
+
+        // a query with order books fails when a symbol has no order book snapshots
+        // - order book snapshots are stored next to the candlesticks of the same symbol ([exchange]_[symbol]_L2.stmdb)
+        let mut order_book_filenames = Vec::new();
+        for (exchange, symbol) in query.symbols.iter().filter(|_| query.order_book_levels.is_some()) {
+            let filename = format!("{}/{}_{}_L2.stmdb", path, exchange.name, symbol.name);
+            if !std::path::Path::new(&filename).exists() {
+                return Err(Error::new(ErrorKind::NotFound, format!("engine: no order book snapshots for {}:{}", exchange.name, symbol.name)));
+            }
+            order_book_filenames.push((symbols.intern(&exchange.name, &symbol.name)?, filename));
+        }
+
+        Ok((sources, order_book_filenames, symbols.clone()))
+    }
+
+    // use the trading calendar of every exchange to align the consolidated bars to sessions
+    // and make sure every symbol was listed on its exchange during the query
+    fn query_calendars(&self, query: &Query, sources: &[QuerySource], start_timestamp: Option<i64>, end_timestamp: Option<i64>) -> Result<HashMap<SymbolId, TradingCalendar>, Error> {
+        let mut calendars = HashMap::new();
+        for (exchange, symbol) in query.symbols.iter() {
+            let mut exchange = exchange.clone();
+            if exchange.calendar.is_none() {
+                exchange.load_calendar(&self.path)?;
+            }
+            if exchange.symbols.is_empty() {
+                exchange.load_symbols(&self.path)?;
+            }
+            exchange.symbols.validate(&symbol.name, start_timestamp, end_timestamp)?;
+            calendars.insert(exchange.name.clone(), exchange.get_calendar());
+        }
+
+        Ok(sources.iter().filter_map(|source| {
+            calendars.get(&source.exchange).map(|calendar| (source.symbol_id, calendar.clone()))
+        }).collect())
+    }
+
+    // read the coarsest rollup that can be consolidated into every interval of a query instead of the base bars (queries opt in with rollups)
+    // - every symbol needs a rollup of the snapshot the query reads that is coarser than its base resolution
+    // - the rollup bars become the base bars of the query, only the rollup bars that start in the range of the query are read
+    // - queries of the base bars, without intervals or with pre/post-market bars read the base bars
+    fn plan_rollups(&self, query: &Query, intervals: &[Interval], sources: &mut [QuerySource]) -> Result<(), Error> {
+        if query.extended_hours || intervals.is_empty() || sources.is_empty() {
+            return Ok(());
+        }
+
+        let catalog = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        let rollup = |source: &QuerySource, interval: &Interval| {
+            let snapshot_id = catalog.snapshot(&source.exchange, &source.symbol, query.snapshot_id).map_or(0, |snapshot| snapshot.snapshot_id);
+            catalog.rollup(&source.exchange, &source.symbol, &interval.name).filter(|rollup| rollup.snapshot_id == snapshot_id)
+        };
+
+        let tier = catalog.rollups.iter()
+            .filter_map(|rollup| Interval::from_string(&rollup.interval).ok())
+            .filter(|tier| intervals.iter().all(|interval| tier.divides(interval)))
+            .filter(|tier| sources.iter().filter(|source| !source.files.is_empty()).all(|source| tier.length() > source.base_seconds && rollup(source, tier).is_some()))
+            .max_by_key(|tier| tier.length());
+        let tier = match tier {
+            Some(tier) => tier,
+            None => return Ok(()),
+        };
+
+        // sources without bars in the range of the query are not read
+        for source in sources.iter_mut().filter(|source| !source.files.is_empty()) {
+            let filename = rollup(source, &tier).map(|rollup| format!("{}/{}.stmdb", self.path, rollup.filename)).unwrap();
+            source.files = vec![filename];
+            source.base_seconds = tier.length();
+        }
+        println!("engine: query planned on the {} rollups", tier.name);
+
+        Ok(())
     }
 
     // query the database for bars with a given query id from a first query
@@ -148,48 +347,446 @@ impl DatabaseEngine {
         // get limit parameter from the parameters hashmap
         let limit = parameters.get("limit").unwrap_or(&"1000".to_string()).parse::<i32>().unwrap();
 
+        // the symbol table resolves the symbol ids of the bars
+        let symbols = self.symbols.read().unwrap().clone();
+
         // get the lock for the cache
         let mut cache = self.cache.lock().unwrap();
 
         // check if the query id exists in the cache
-        let cache_result = cache.get(query_id.clone(), limit);
-        if let Some(cache_result) = cache_result {
-            if cache_result.bars.len() > 0 {
+        // - the pages of the query are only read from the cache so they are returned in order
+        // - the query is complete once the last page has been added and every bar has been returned
+        if let Some(cache_result) = cache.get(query_id.clone(), limit) {
+            if let Some((kind, message)) = cache_result.error {
+                return Err(Error::new(kind, format!("engine: query {} failed: {}", query_id, message)));
+            }
+            if !cache_result.bars.is_empty() || cache_result.is_last {
                 let status = if cache_result.is_last { "complete" } else { "running" };
-            
+
                 // return the cached results
-                return Ok(QueryResult::new_with(query_id, status.to_string(), cache_result.bars));
+                return Ok(QueryResult::new_with(query_id, status.to_string(), cache_result.bars).with_symbols(symbols));
             }
         }
 
         // check if the query id exists in the query channels hashmap
         if !self.query_channels.contains_key(&query_id) {
-            return Err(Error::new(std::io::ErrorKind::Other, "engine: query id does not exist"));
+            return Err(Error::new(std::io::ErrorKind::NotFound, "engine: query id does not exist"));
         }
 
-        // wait for results on the channel in a loop until there are none left
-        let query_channel = self.query_channels.get(&query_id).unwrap().clone();
-        
-        // wait for results on the channel in a loop until there are none left
-        // println!("engine: waiting for query results");
-        match query_channel.recv_timeout(Duration::from_micros(10)) {
-            Ok(results) => {
-                // last page of results
-                if results.is_last {
-                    return Ok(QueryResult::new_with(query_id, "complete".to_string(), results.bars));
-                }
+        Ok(QueryResult::new_with(query_id, "running".to_string(), vec![]).with_symbols(symbols))
+    }
+
+    // import the order book snapshots of a csv file into the order book file of a symbol ([path]/[exchange]_[symbol]_L2.stmdb)
+    // - the snapshots are written into a temporary file that replaces the order book file once it is complete
+    pub fn import_order_books(&self, exchange: String, symbol: String, source: String) -> Result<(u64, u8), Error> {
+        let (order_books, levels) = OrderBook::from_csv(&source)?;
+        let (start_timestamp, end_timestamp) = match (order_books.first(), order_books.last()) {
+            (Some(first), Some(last)) => (first.timestamp, last.timestamp),
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("engine: no order book snapshots in {}", source))),
+        };
+
+        let filename = format!("{}/{}_{}_L2.stmdb", self.path, exchange, symbol);
+        let temporary = format!("{}.tmp", filename);
+        let mut writer = Writer::new(temporary.clone())?;
+        writer.write_header(Header::new_order_book(0, start_timestamp as u32, end_timestamp as u32, levels))?;
+        writer.write_order_books(&order_books)?;
+        writer.flush()?;
+        std::fs::rename(&temporary, &filename)?;
+
+        Ok((order_books.len() as u64, levels))
+    }
+
+    // look up the contract specification of a symbol in the symbol registry of its exchange
+    pub fn get_symbol(&self, exchange: Exchange, symbol: Symbol) -> Result<Symbol, Error> {
+        let mut exchange = exchange;
+        if exchange.symbols.is_empty() {
+            exchange.load_symbols(&self.path)?;
+        }
+
+        Ok(exchange.get_symbol(&symbol))
+    }
+
+    // get the base resolution of a dataset from the catalog (or the header of its file when it is not in the catalog)
+    pub fn get_resolution(&self, exchange: String, symbol: String) -> Result<Interval, Error> {
+        let catalog = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        if let Some(corpus) = catalog.files(&exchange, &symbol, None).first() {
+            return Ok(corpus.resolution.clone());
+        }
+
+        let filename = format!("{}/{}_{}.stmdb", self.path, exchange, symbol);
+        if !std::path::Path::new(&filename).exists() {
+            return Err(Error::new(std::io::ErrorKind::NotFound, format!("engine: no dataset for {} {}", exchange, symbol)));
+        }
+        let resolution = Reader::new(filename)?.header()?.resolution as i64;
+
+        Ok(Interval::from_seconds(resolution))
+    }
+
+    // get the trading calendar of an exchange (loaded from the calendars folder when it is not set)
+    pub fn get_calendar(&self, exchange: Exchange) -> Result<TradingCalendar, Error> {
+        let mut exchange = exchange;
+        if exchange.calendar.is_none() {
+            exchange.load_calendar(&self.path)?;
+        }
+
+        Ok(exchange.get_calendar())
+    }
+
+    // append the records of a source file that are newer than the last record of a dataset
+    // - the change is written as a new snapshot of the dataset
+    pub fn update_dataset(&self, exchange: String, symbol: String, source: String, policy: ConflictPolicy) -> Result<MergeSummary, Error> {
+        let mut index = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        let snapshot_id = index.next_snapshot_id();
+
+        let task = MergeTask::new_update(String::new(), source.clone(), policy);
+        let mut summary = self.dataset_task(&index, &exchange, &symbol, task, snapshot_id)?.run()?;
+
+        summary.snapshot_id = self.update_catalog(&mut index, exchange.clone(), symbol.clone(), &summary, &[source], snapshot_id)?;
+        self.update_rollups(&mut index, &exchange, &symbol, Some(summary.changed_from.unwrap_or(i64::MAX)))?;
+
+        Ok(summary)
+    }
 
-                // println!("engine: got query results");
-                return Ok(QueryResult::new_with(query_id, "running".to_string(), results.bars));
-            },
-            _ => (),
-            // Err(e) => {
-            //     println!("{:?}", e);
-            //     println!("engine: no results");
-            //     return Err(Error::new(std::io::ErrorKind::Other, "engine: no results"));
-            // }
+    // merge a set of overlapping source files into a dataset (the files of the dataset that receive records are written as a new snapshot)
+    pub fn merge_datasets(&self, exchange: String, symbol: String, sources: Vec<String>, policy: ConflictPolicy) -> Result<MergeSummary, Error> {
+        let mut index = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        let snapshot_id = index.next_snapshot_id();
+
+        let task = MergeTask::new(String::new(), sources.clone(), policy);
+        let mut summary = self.dataset_task(&index, &exchange, &symbol, task, snapshot_id)?.run()?;
+
+        summary.snapshot_id = self.update_catalog(&mut index, exchange.clone(), symbol.clone(), &summary, &sources, snapshot_id)?;
+        self.update_rollups(&mut index, &exchange, &symbol, Some(summary.changed_from.unwrap_or(i64::MAX)))?;
+
+        Ok(summary)
+    }
+
+    // split a dataset that is stored in a single file into partitions
+    // - the single file is left in place so earlier snapshots can still be read
+    pub fn partition_dataset(&self, exchange: String, symbol: String) -> Result<MergeSummary, Error> {
+        let mut index = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        if index.is_partitioned(&exchange, &symbol) {
+            return Err(Error::new(std::io::ErrorKind::AlreadyExists, format!("engine: {}:{} is already partitioned", exchange, symbol)));
+        }
+        let snapshot_id = index.next_snapshot_id();
+
+        let data_name = format!("{}_{}", exchange, symbol);
+        let source = match index.files(&exchange, &symbol, None).first() {
+            Some(corpus) => format!("{}/{}.stmdb", self.path, corpus.filename),
+            None => format!("{}/{}.stmdb", self.path, data_name),
+        };
+        let mut summary = MergeTask::new(format!("{}/{}", self.path, data_name), vec![source.clone()], ConflictPolicy::Fail)
+            .with_dataset_id(index.dataset_id(&exchange, &symbol)? as u32)
+            .with_partitions(self.partition_interval.clone())
+            .with_version(snapshot_id, Vec::new())
+            .run()?;
+
+        summary.snapshot_id = self.update_catalog(&mut index, exchange.clone(), symbol.clone(), &summary, &[source], snapshot_id)?;
+
+        // the records do not change when a dataset is partitioned so only the last period of the rollups is consolidated again
+        self.update_rollups(&mut index, &exchange, &symbol, Some(i64::MAX))?;
+
+        Ok(summary)
+    }
+
+    // consolidate the current snapshot of a dataset into the rollup intervals of the engine
+    pub fn build_rollups(&self, exchange: String, symbol: String) -> Result<Vec<Rollup>, Error> {
+        if self.rollup_intervals.is_empty() {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, "engine: no rollup intervals are configured"));
+        }
+
+        let mut index = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+        self.update_rollups(&mut index, &exchange, &symbol, None)?;
+
+        Ok(self.rollup_intervals.iter().filter_map(|interval| index.rollup(&exchange, &symbol, &interval.name).cloned()).collect())
+    }
+
+    // get the snapshots of a dataset in the order they were written
+    pub fn snapshots(&self, exchange: String, symbol: String) -> Result<Vec<Snapshot>, Error> {
+        let index = DatabaseIndex::from_file(format!("{}/index.csv", self.path), self.path.clone())?;
+
+        Ok(index.snapshots.into_iter().filter(|snapshot| snapshot.exchange == exchange && snapshot.symbol == symbol).collect())
+    }
+
+    // scan a damaged stmdb file and write the records that can still be read into a repaired copy
+    pub fn repair_file(&self, filename: String) -> Result<RepairReport, Error> {
+        if !std::path::Path::new(&filename).exists() {
+            return Err(Error::new(std::io::ErrorKind::NotFound, format!("engine: file {} does not exist", filename)));
+        }
+
+        RepairTask::new(filename).run()
+    }
+
+    // point a merge task at the current files of a dataset
+    // - new datasets are partitioned and existing single file datasets are kept in a single file
+    fn dataset_task(&self, index: &DatabaseIndex, exchange: &str, symbol: &str, task: MergeTask, snapshot_id: u32) -> Result<MergeTask, Error> {
+        let data_name = format!("{}_{}", exchange, symbol);
+        let filename = format!("{}/{}.stmdb", self.path, data_name);
+
+        // datasets written before the catalog are a single file that is not in the catalog
+        let mut current_files = index.files(exchange, symbol, None).iter().map(|corpus| {
+            (corpus.partition.clone(), format!("{}/{}.stmdb", self.path, corpus.filename))
+        }).collect::<Vec<(Option<String>, String)>>();
+        if current_files.is_empty() && std::path::Path::new(&filename).exists() {
+            current_files.push((None, filename.clone()));
+        }
+        let partitioned = current_files.is_empty() || current_files.iter().any(|(partition, _)| partition.is_some());
+
+        let task = MergeTask {
+            target: filename,
+            ..task
+        }.with_dataset_id(index.dataset_id(exchange, symbol)? as u32)
+        .with_version(snapshot_id, current_files);
+
+        Ok(match partitioned {
+            true => MergeTask {
+                target: format!("{}/{}", self.path, data_name),
+                ..task
+            }.with_partitions(self.partition_interval.clone()),
+            false => task,
+        })
+    }
+
+    // bring the rollups of a dataset in sync with its current snapshot
+    // - a rollup of the previous snapshot only consolidates the records from the first changed timestamp again
+    //   (every record when the rollup is older, no changed timestamp rebuilds every rollup)
+    fn update_rollups(&self, index: &mut DatabaseIndex, exchange: &str, symbol: &str, changed_from: Option<i64>) -> Result<(), Error> {
+        if self.rollup_intervals.is_empty() {
+            return Ok(());
+        }
+
+        let snapshot_id = index.snapshot(exchange, symbol, None).map_or(0, |snapshot| snapshot.snapshot_id);
+        let previous_id = index.snapshots.iter()
+            .filter(|snapshot| snapshot.exchange == exchange && snapshot.symbol == symbol && snapshot.snapshot_id < snapshot_id)
+            .map(|snapshot| snapshot.snapshot_id)
+            .max()
+            .unwrap_or(0);
+
+        // the base files of the current snapshot in timestamp order
+        let data_name = format!("{}_{}", exchange, symbol);
+        let mut corpora = index.files(exchange, symbol, None);
+        corpora.sort_by_key(|corpus| corpus.start_timestamp);
+        let mut files = corpora.iter().map(|corpus| format!("{}/{}.stmdb", self.path, corpus.filename)).collect::<Vec<String>>();
+        if files.is_empty() && std::path::Path::new(&format!("{}/{}.stmdb", self.path, data_name)).exists() {
+            files.push(format!("{}/{}.stmdb", self.path, data_name));
+        }
+
+        let mut exchange_calendar = Exchange::new_with(exchange.to_string());
+        exchange_calendar.load_calendar(&self.path)?;
+        let calendar = exchange_calendar.get_calendar();
+
+        // only tiers that are coarser than the base resolution and made of whole base bars are rolled up
+        let resolution = corpora.first().map_or(Interval::from_seconds(DEFAULT_RESOLUTION as i64), |corpus| corpus.resolution.clone());
+        for interval in self.rollup_intervals.iter().filter(|interval| interval.length() > resolution.length() && resolution.divides(interval)) {
+            let existing = index.rollup(exchange, symbol, &interval.name);
+            if changed_from.is_some() && existing.is_some_and(|rollup| rollup.snapshot_id == snapshot_id) {
+                continue;
+            }
+
+            let filename = format!("{}.{}", data_name, interval.name);
+            let mut task = RollupTask::new(files.clone(), format!("{}/{}.stmdb", self.path, filename), interval.clone(), calendar.clone())
+                .with_base_seconds(resolution.length());
+            if let Some(changed_from) = changed_from.filter(|_| existing.is_some_and(|rollup| rollup.snapshot_id == previous_id)) {
+                task = task.with_resume(changed_from);
+            }
+            let summary = task.run()?;
+
+            index.update_rollup(Rollup {
+                exchange: exchange.to_string(),
+                symbol: symbol.to_string(),
+                interval: interval.name.clone(),
+                filename,
+                snapshot_id,
+                start_timestamp: summary.start_timestamp,
+                end_timestamp: summary.end_timestamp,
+                record_count: summary.record_count,
+                content_hash: hash_file(&summary.filename)?,
+            });
         }
 
-        Ok(QueryResult::new_with(query_id, "running".to_string(), vec![]))
+        index.save()
     }
-}
\ No newline at end of file
+
+    // store the files that were written in the catalog and add a snapshot of the dataset that uses them
+    // - files of the previous snapshot that were not rewritten are shared with the new snapshot
+    // - returns the snapshot id (the current snapshot when nothing was written)
+    fn update_catalog(&self, index: &mut DatabaseIndex, exchange: String, symbol: String, summary: &MergeSummary, sources: &[String], snapshot_id: u32) -> Result<u32, Error> {
+        if summary.files.is_empty() {
+            return Ok(index.snapshot(&exchange, &symbol, None).map_or(0, |snapshot| snapshot.snapshot_id));
+        }
+
+        let dataset_id = index.dataset_id(&exchange, &symbol)?;
+        let last_updated = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i32);
+
+        let partitioned = summary.files.iter().any(|file| file.partition.is_some());
+        let written = summary.files.iter().map(|file| file.partition.clone()).collect::<Vec<Option<String>>>();
+        let mut files = index.files(&exchange, &symbol, None).iter()
+            .filter(|corpus| corpus.partition.is_some() == partitioned && !written.contains(&corpus.partition))
+            .map(|corpus| corpus.filename.clone())
+            .collect::<Vec<String>>();
+
+        for file in summary.files.iter() {
+            // catalog filenames are relative to the root of the database without the extension
+            let filename = file.filename.trim_start_matches(&format!("{}/", self.path)).trim_end_matches(".stmdb").to_string();
+
+            let mut corpus = Corpus::new(dataset_id, exchange.clone(), symbol.clone(), file.start_timestamp as i32, file.end_timestamp as i32, filename.clone(), last_updated);
+            corpus.record_count = file.record_count;
+            corpus.partition = file.partition.clone();
+            corpus.version = snapshot_id;
+            corpus.content_hash = hash_file(&file.filename)?;
+            corpus.resolution = Interval::from_seconds(summary.resolution);
+            index.update_corpus(corpus);
+            files.push(filename);
+        }
+        files.sort();
+
+        // the source hash of a change with several sources combines the hash of every source
+        let mut source_hashes = Vec::new();
+        for source in sources.iter() {
+            source_hashes.push(hash_file(source)?);
+        }
+        let source_hash = match source_hashes.len() {
+            1 => source_hashes.remove(0),
+            _ => format!("{:08x}", crc32fast::hash(source_hashes.join(";").as_bytes())),
+        };
+
+        index.add_snapshot(snapshot_id, exchange, symbol, source_hash, files);
+        index.save()?;
+
+        Ok(snapshot_id)
+    }
+}
+
+// the candlestick sources, order book files and symbol table of a query
+type QuerySources = (Vec<QuerySource>, Vec<(SymbolId, String)>, SymbolTable);
+
+// find the range of the bars of every symbol of a query and the range that is read for it using the alignment policy
+// - the range of the query defaults to the range of the bars of its symbols
+// - sources are limited to their effective range (sources without one are not read)
+// - returns the coverage of every symbol and the range of the query
+fn query_coverage(query: &Query, sources: &mut [QuerySource]) -> Result<(Vec<Coverage>, i64, i64), Error> {
+    let query_start = query.start_timestamp.unwrap_or(i64::MIN);
+    let query_end = query.end_timestamp.unwrap_or(i64::MAX);
+
+    let mut coverage = Vec::new();
+    for source in sources.iter() {
+        coverage.push(Coverage::new(source.exchange.clone(), source.symbol.clone(), source_range(source)?));
+    }
+
+    // the bars of every symbol in the range of the query
+    let ranges = coverage.iter()
+        .map(|coverage| coverage.range().map(|(start, end)| (start.max(query_start), end.min(query_end))).filter(|(start, end)| start <= end))
+        .collect::<Vec<Option<(i64, i64)>>>();
+    let start = ranges.iter().flatten().map(|(start, _)| *start);
+    let end = ranges.iter().flatten().map(|(_, end)| *end);
+
+    let effective = match query.alignment {
+        AlignmentPolicy::Intersection => {
+            if let Some(missing) = ranges.iter().position(|range| range.is_none()) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("query: {}:{} has no bars in the range of the query", coverage[missing].exchange, coverage[missing].symbol)));
+            }
+            match (start.max(), end.min()) {
+                (Some(start), Some(end)) if start <= end => vec![Some((start, end)); ranges.len()],
+                _ => return Err(Error::new(ErrorKind::InvalidInput, "query: the symbols do not have bars in a common range")),
+            }
+        },
+        AlignmentPolicy::Union => match (start.min(), end.max()) {
+            (Some(start), Some(end)) => ranges.iter().map(|range| range.map(|_| (start, end))).collect(),
+            _ => ranges,
+        },
+        AlignmentPolicy::Independent => ranges,
+    };
+
+    let (start, end) = match (effective.iter().flatten().map(|(start, _)| *start).min(), effective.iter().flatten().map(|(_, end)| *end).max()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err(Error::new(ErrorKind::NotFound, "query: no bars in the range of the query")),
+    };
+
+    for ((source, coverage), range) in sources.iter_mut().zip(coverage.iter_mut()).zip(effective) {
+        coverage.effective_start = range.map(|(start, _)| start);
+        coverage.effective_end = range.map(|(_, end)| end);
+        match range {
+            Some((start, end)) => source.range = Some((start, end)),
+            None => source.files.clear(),
+        }
+    }
+
+    Ok((coverage, start, end))
+}
+
+// get the range of the records of the files of a source (none when there are no records)
+fn source_range(source: &QuerySource) -> Result<Option<(i64, i64)>, Error> {
+    let mut range: Option<(i64, i64)> = None;
+    for filename in source.files.iter().filter(|filename| std::path::Path::new(filename).exists()) {
+        let mut reader = Reader::new(filename.clone())?;
+        let count = reader.record_count()?;
+        if count == 0 {
+            continue;
+        }
+
+        // older files do not store their range in the header
+        let header = reader.header()?.clone();
+        let (start, end) = match header.end_timestamp > 0 {
+            true => (header.start_timestamp as i64, header.end_timestamp as i64),
+            false => (reader.read_timestamp_at(0)?, reader.read_timestamp_at(count - 1)?),
+        };
+        range = Some(range.map_or((start, end), |(first, last)| (first.min(start), last.max(end))));
+    }
+
+    Ok(range)
+}
+
+// parse the intervals of a query to consolidate
+// - the base bars are always returned, intervals that are not larger than the base resolution of a symbol are skipped
+fn query_intervals(query: &Query) -> Result<Vec<Interval>, Error> {
+    query.intervals.iter().map(|interval| Interval::from_string(interval)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use super::super::database::Database;
+
+    #[test]
+    fn queries_of_a_damaged_block_fail_under_the_fail_policy() {
+        let path = std::env::temp_dir().join(format!("engine_damaged_{}", std::process::id())).to_string_lossy().to_string();
+        fs::create_dir_all(&path).unwrap();
+        let source = format!("{}/source.csv", path);
+        let mut contents = "timestamp,open,high,low,close,volume\n".to_string();
+        for i in 0..100 {
+            contents.push_str(&format!("{},1,2,0.5,1.5,10\n", 1704067200 + i * 60));
+        }
+        fs::write(&source, contents).unwrap();
+
+        let mut engine = DatabaseEngine::new_with(path.clone(), 2);
+        let summary = engine.update_dataset("TEST".to_string(), "DAMAGED".to_string(), source, ConflictPolicy::PreferNewer).unwrap();
+
+        // damage the close of the first record
+        let filename = summary.files[0].filename.clone();
+        let header = Reader::new(filename.clone()).unwrap().header().unwrap().clone();
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes[header.size() + 40] ^= 0xff;
+        fs::write(&filename, bytes).unwrap();
+
+        let query = Query::new(Database::new()).with_symbols(vec![(Exchange::new_with("TEST".to_string()), Symbol { name: "DAMAGED".to_string(), ..Symbol::new() })]);
+        let query_id = engine.start_query(1, query).unwrap().id;
+
+        // the query fails instead of running without bars
+        let start = Instant::now();
+        let error = loop {
+            match engine.query_chunk(query_id.clone(), HashMap::new()) {
+                Ok(result) => {
+                    assert_eq!(result.status, "running");
+                    assert!(result.bars.is_empty());
+                    assert!(start.elapsed() < Duration::from_secs(10), "the query did not fail");
+                    thread::sleep(Duration::from_millis(10));
+                },
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
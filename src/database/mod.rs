@@ -4,4 +4,5 @@ pub mod models;
 pub mod tasks;
 pub mod storage;
 pub mod engine;
+#[allow(clippy::module_inception)]
 pub mod database;
\ No newline at end of file
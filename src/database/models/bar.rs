@@ -1,13 +1,24 @@
-use super::{bar_map::BarMap, candlestick::Candlestick};
+use std::hash::{Hash, Hasher};
+use super::{bar_map::BarMap, candlestick::Candlestick, order_book::OrderBook, corporate_action::CorporateAction, symbol_table::SymbolId};
 
 
 // represents a bar of candlesticks linked by timestamp across multiple symbols and exchanges
-#[derive(Clone, Debug, Hash, PartialEq)]
+// - symbols are referred to by their interned id (the symbol table of the query result resolves the names)
+#[derive(Clone, Debug, PartialEq)]
 pub struct Bar {
     pub timestamp: i64,
 
-    // map of exchange and symbol to candlestick
+    // candlestick of every symbol
     pub candlesticks: BarMap,
+
+    // latest order book snapshot of every symbol at or before the timestamp
+    pub order_books: BarMap<OrderBook>,
+
+    // corporate actions (splits, dividends) of a symbol with an ex-date reached at this bar
+    pub corporate_actions: Vec<(SymbolId, CorporateAction)>,
+
+    // consolidated candlesticks that completed at this bar by the index of their interval in the query
+    pub consolidated: Vec<BarMap>,
 }
 
 impl Bar {
@@ -15,30 +26,83 @@ impl Bar {
         Self {
             timestamp,
             candlesticks: BarMap::new(),
+            order_books: BarMap::new(),
+            corporate_actions: Vec::new(),
+            consolidated: Vec::new(),
         }
     }
 
+    #[allow(dead_code)]
     pub fn new_with(timestamp: i64, candlesticks: BarMap) -> Self {
         Self {
             timestamp,
             candlesticks,
+            order_books: BarMap::new(),
+            corporate_actions: Vec::new(),
+            consolidated: Vec::new(),
+        }
+    }
+
+    // add the candlestick of a symbol to the bar
+    pub fn add_candlestick(&mut self, id: SymbolId, candlestick: Candlestick) {
+        self.candlesticks.insert(id, candlestick);
+    }
+
+    // check if the bar has a candlestick for a symbol
+    #[allow(dead_code)]
+    pub fn has_candlestick(&self, id: SymbolId) -> bool {
+        self.candlesticks.contains(id)
+    }
+
+    // get the candlestick of a symbol
+    #[allow(dead_code)]
+    pub fn get_candlestick(&self, id: SymbolId) -> Option<&Candlestick> {
+        self.candlesticks.get(id)
+    }
+
+    // set the order book snapshot of a symbol
+    pub fn set_order_book(&mut self, id: SymbolId, order_book: OrderBook) {
+        self.order_books.set(id, order_book);
+    }
+
+    // add a corporate action event of a symbol
+    pub fn add_corporate_action(&mut self, id: SymbolId, corporate_action: CorporateAction) {
+        self.corporate_actions.push((id, corporate_action));
+    }
+
+    // add a consolidated candlestick of an interval that completed at this bar
+    pub fn add_consolidated(&mut self, interval_index: usize, id: SymbolId, candlestick: Candlestick) {
+        if interval_index >= self.consolidated.len() {
+            self.consolidated.resize_with(interval_index + 1, BarMap::new);
         }
+        self.consolidated[interval_index].insert(id, candlestick);
+    }
+
+    // get the consolidated candlestick of an interval for a symbol
+    pub fn get_consolidated(&self, interval_index: usize, id: SymbolId) -> Option<&Candlestick> {
+        self.consolidated.get(interval_index)?.get(id)
     }
 
-    // add a candlestick to the bar using the source id as the key
-    // example source id would be "binance:BTCUSDT"
-    pub fn add_candlestick(&mut self, source_id: String, candlestick: Candlestick) {
-        let key = source_id.split(":").collect::<Vec<&str>>();
-        let exchange = key[0].to_string();
-        let symbol = key[1].to_string();
-        self.candlesticks.insert((exchange, symbol), candlestick);
+    // check if a consolidated candlestick completed at this bar
+    pub fn has_consolidated(&self) -> bool {
+        self.consolidated.iter().any(|candlesticks| !candlesticks.is_empty())
     }
 
-    // check if the bar has a candlestick for the source id
-    pub fn has_candlestick(&self, source_id: String) -> bool {
-        let key = source_id.split(":").collect::<Vec<&str>>();
-        let exchange = key[0].to_string();
-        let symbol = key[1].to_string();
-        self.candlesticks.bars.contains_key(&(exchange, symbol))
+    // get the order book snapshot of a symbol
+    pub fn get_order_book(&self, id: SymbolId) -> Option<&OrderBook> {
+        self.order_books.get(id)
     }
-}
\ No newline at end of file
+}
+
+impl Hash for Bar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp.hash(state);
+        self.candlesticks.hash(state);
+        self.order_books.hash(state);
+
+        for (interval_index, candlesticks) in self.consolidated.iter().enumerate().filter(|(_, candlesticks)| !candlesticks.is_empty()) {
+            interval_index.hash(state);
+            candlesticks.hash(state);
+        }
+    }
+}
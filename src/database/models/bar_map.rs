@@ -1,44 +1,67 @@
-use std::{collections::HashMap, hash::{Hash, Hasher}};
-use super::{bar::Bar, candlestick::Candlestick};
+use std::hash::{Hash, Hasher};
+use super::{candlestick::Candlestick, symbol_table::SymbolId};
 
 
-// represents a map of a single candlestick by exchange and symbol
+// represents a value per symbol of a bar (a candlestick by default)
+// - values are stored in a slot per symbol id so lookups do not hash or allocate
 #[derive(Clone, Debug, PartialEq)]
-pub struct BarMap {
-    // map of exchange and symbol to list of candlestick
-    pub bars: HashMap<(String, String), Candlestick>,
+pub struct BarMap<T = Candlestick> {
+    pub slots: Vec<Option<T>>,
 }
 
-impl BarMap {
+impl<T> BarMap<T> {
     pub fn new() -> Self {
         Self {
-            bars: HashMap::new(),
+            slots: Vec::new(),
         }
     }
 
-    pub fn new_with(bars: HashMap<(String, String), Candlestick>) -> Self {
-        Self {
-            bars,
+    // add the value of a symbol (the first value of a symbol is kept)
+    pub fn insert(&mut self, id: SymbolId, value: T) {
+        if !self.contains(id) {
+            self.set(id, value);
         }
     }
 
-    pub fn insert(&mut self, key: (String, String), candlestick: Candlestick) {
-        let key_copy = key.clone();
-        let exchange = key.0;
-        let symbol = key.1;
-        if !self.bars.contains_key(&key_copy) {
-            self.bars.insert(key_copy, candlestick);
+    // set the value of a symbol (replaces an existing value)
+    pub fn set(&mut self, id: SymbolId, value: T) {
+        let index = id as usize;
+        if index >= self.slots.len() {
+            self.slots.resize_with(index + 1, || None);
         }
+        self.slots[index] = Some(value);
+    }
+
+    // get the value of a symbol
+    pub fn get(&self, id: SymbolId) -> Option<&T> {
+        self.slots.get(id as usize)?.as_ref()
+    }
+
+    // remove the value of a symbol
+    pub fn remove(&mut self, id: SymbolId) -> Option<T> {
+        self.slots.get_mut(id as usize)?.take()
+    }
+
+    // check if the bar has a value for a symbol
+    pub fn contains(&self, id: SymbolId) -> bool {
+        self.get(id).is_some()
+    }
+
+    // iterate over the symbols that have a value in id order
+    pub fn iter(&self) -> impl Iterator<Item = (SymbolId, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, value)| value.as_ref().map(|value| (index as SymbolId, value)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(|value| value.is_none())
     }
 }
 
-impl Hash for BarMap {
+impl<T: Hash> Hash for BarMap<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let mut sorted_keys: Vec<_> = self.bars.keys().collect();
-        sorted_keys.sort();
-        for key in sorted_keys {
-            key.hash(state);
-            self.bars.get(key).unwrap().hash(state);
+        for (id, value) in self.iter() {
+            id.hash(state);
+            value.hash(state);
         }
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,4 @@
-use std::mem::ManuallyDrop;
+use std::{mem::ManuallyDrop, io::{Error, ErrorKind}};
 
 use evmap::ShallowCopy;
 use super::{bar::Bar};
@@ -9,6 +9,9 @@ use super::{bar::Bar};
 pub struct BarSet {
     pub is_last: bool,
     pub bars: Vec<Bar>,
+
+    // the error of a query that failed (the last barset of the query, without bars)
+    pub error: Option<(ErrorKind, String)>,
 }
 
 impl BarSet {
@@ -16,6 +19,7 @@ impl BarSet {
         Self {
             is_last: false,
             bars: Vec::new(),
+            error: None,
         }
     }
 
@@ -23,11 +27,22 @@ impl BarSet {
         Self {
             is_last,
             bars,
+            error: None,
+        }
+    }
+
+    // create the last barset of a query that failed
+    pub fn new_failed(error: &Error) -> Self {
+        Self {
+            is_last: true,
+            bars: Vec::new(),
+            error: Some((error.kind(), error.to_string())),
         }
     }
 
     // add a bar to the barset using the source id as the key
     // example source id would be "binance:BTCUSDT"
+    #[allow(dead_code)]
     pub fn add_bar(&mut self, bar: Bar) {
         self.bars.push(bar);
     }
@@ -37,11 +52,12 @@ impl Eq for BarSet {}
 
 impl ShallowCopy for BarSet {
     unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
-        let cloned_is_last = self.is_last.clone();
+        let cloned_is_last = self.is_last;
         let cloned_bars = self.bars.clone();
         let cloned_barset = Self {
             is_last: cloned_is_last,
             bars: cloned_bars,
+            error: self.error.clone(),
         };
         ManuallyDrop::new(cloned_barset)
     }
@@ -0,0 +1,368 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use super::interval::{Interval, IntervalUnit};
+
+
+// represents a single trading session of an exchange in unix timestamps
+// - the extended open and close include the pre/post-market minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub date: NaiveDate,
+    pub open: i64,
+    pub close: i64,
+    pub extended_open: i64,
+    pub extended_close: i64,
+}
+
+impl Session {
+    // check if a timestamp is within the session
+    pub fn contains(&self, timestamp: i64, extended_hours: bool) -> bool {
+        let (open, close) = self.bounds(extended_hours);
+        timestamp >= open && timestamp < close
+    }
+
+    // get the open and close of the session
+    pub fn bounds(&self, extended_hours: bool) -> (i64, i64) {
+        if extended_hours {
+            return (self.extended_open, self.extended_close);
+        }
+
+        (self.open, self.close)
+    }
+}
+
+// represents the trading calendar of an exchange
+// - session times are minutes since midnight in the timezone of the exchange (1440 is midnight of the next day)
+// - daylight saving time is handled by converting the local session times of each date with the timezone
+// - exchanges without a calendar trade 24/7 in UTC
+#[derive(Debug, Clone)]
+pub struct TradingCalendar {
+    pub timezone: Tz,
+    pub open: u32,
+    pub close: u32,
+    pub pre_market: Option<u32>,
+    pub post_market: Option<u32>,
+    pub weekdays: Vec<Weekday>,
+    pub holidays: HashSet<NaiveDate>,
+    pub early_closes: HashMap<NaiveDate, u32>,
+}
+
+impl TradingCalendar {
+    // create a calendar that trades every minute of every day (crypto exchanges)
+    pub fn continuous() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            open: 0,
+            close: 1440,
+            pre_market: None,
+            post_market: None,
+            weekdays: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun],
+            holidays: HashSet::new(),
+            early_closes: HashMap::new(),
+        }
+    }
+
+    // load a calendar definition from a key=value file
+    // - timezone=America/New_York
+    // - session=09:30-16:00
+    // - pre_market=04:00 (start of the pre-market session)
+    // - post_market=20:00 (end of the post-market session)
+    // - weekdays=mon,tue,wed,thu,fri
+    // - holidays=2020-01-01,2020-01-20
+    // - early_closes=2020-11-27 13:00,2020-12-24 13:00
+    pub fn from_file(filename: &str) -> Result<Self, Error> {
+        let contents = fs::read_to_string(filename)?;
+        let mut calendar = Self::continuous();
+        calendar.weekdays = vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts.next().unwrap_or("").trim();
+
+            match key {
+                "timezone" => {
+                    calendar.timezone = value.parse::<Tz>()
+                        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid timezone: {}", value)))?;
+                },
+                "session" => {
+                    let mut times = value.splitn(2, '-');
+                    calendar.open = parse_time(times.next().unwrap_or(""))?;
+                    calendar.close = parse_time(times.next().unwrap_or(""))?;
+                },
+                "pre_market" => calendar.pre_market = Some(parse_time(value)?),
+                "post_market" => calendar.post_market = Some(parse_time(value)?),
+                "weekdays" => {
+                    calendar.weekdays = Vec::new();
+                    for day in value.split(',').filter(|day| !day.trim().is_empty()) {
+                        let weekday = day.trim().parse::<Weekday>()
+                            .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid weekday: {}", day)))?;
+                        calendar.weekdays.push(weekday);
+                    }
+                },
+                "holidays" => {
+                    for date in value.split(',').filter(|date| !date.trim().is_empty()) {
+                        calendar.holidays.insert(parse_date(date)?);
+                    }
+                },
+                "early_closes" => {
+                    for early_close in value.split(',').filter(|early_close| !early_close.trim().is_empty()) {
+                        let mut parts = early_close.trim().splitn(2, ' ');
+                        let date = parse_date(parts.next().unwrap_or(""))?;
+                        let time = parse_time(parts.next().unwrap_or(""))?;
+                        calendar.early_closes.insert(date, time);
+                    }
+                },
+                _ => println!("calendar: unknown key {} in {}", key, filename),
+            }
+        }
+
+        if calendar.open >= calendar.close {
+            return Err(Error::new(ErrorKind::InvalidData, format!("invalid session in {}", filename)));
+        }
+
+        Ok(calendar)
+    }
+
+    // check if the exchange trades on a date
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        self.weekdays.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    // get the session of a trading day
+    pub fn session(&self, date: NaiveDate) -> Option<Session> {
+        if !self.is_trading_day(date) {
+            return None;
+        }
+
+        let close = *self.early_closes.get(&date).unwrap_or(&self.close);
+        let extended_close = match self.post_market {
+            Some(post_market) if !self.early_closes.contains_key(&date) => post_market.max(close),
+            _ => close,
+        };
+
+        Some(Session {
+            date,
+            open: self.local_timestamp(date, self.open),
+            close: self.local_timestamp(date, close),
+            extended_open: self.local_timestamp(date, self.pre_market.unwrap_or(self.open).min(self.open)),
+            extended_close: self.local_timestamp(date, extended_close),
+        })
+    }
+
+    // get the session that a timestamp belongs to (if it is a trading day)
+    pub fn session_at(&self, timestamp: i64) -> Option<Session> {
+        self.session(self.local_date(timestamp)?)
+    }
+
+    // check if the exchange is open at a timestamp
+    pub fn is_open(&self, timestamp: i64, extended_hours: bool) -> bool {
+        match self.session_at(timestamp) {
+            Some(session) => session.contains(timestamp, extended_hours),
+            None => false,
+        }
+    }
+
+    // get the start and end of the consolidated bar of an interval that a timestamp belongs to
+    // - intraday intervals are aligned to the open of the session and cut off at the close
+    // - daily, weekly and monthly intervals go from the open of the first session to the close of the last session
+    pub fn bucket(&self, timestamp: i64, interval: &Interval, extended_hours: bool) -> Option<(i64, i64)> {
+        let session = self.session_at(timestamp)?;
+        if !session.contains(timestamp, extended_hours) {
+            return None;
+        }
+
+        let (open, close) = session.bounds(extended_hours);
+        let date = session.date;
+        let amount = interval.amount as i64;
+
+        match interval.unit {
+            IntervalUnit::Second | IntervalUnit::Minute | IntervalUnit::Hour => {
+                let seconds = interval.seconds().unwrap();
+                let start = open + ((timestamp - open) / seconds) * seconds;
+                Some((start, (start + seconds).min(close)))
+            },
+            IntervalUnit::Day => {
+                // days are counted in sessions so every period has the same number of sessions
+                let offset = self.session_number(date).rem_euclid(amount);
+                let first = self.shift_sessions(date, -offset);
+                let last = self.shift_sessions(date, amount - 1 - offset);
+                self.period_bounds(first, last, extended_hours)
+            },
+            IntervalUnit::Week => {
+                let monday = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                let week = monday.num_days_from_ce() as i64 / 7;
+                let first = monday - Duration::weeks(week % amount);
+                self.period_bounds(first, first + Duration::days(amount * 7 - 1), extended_hours)
+            },
+            IntervalUnit::Month => {
+                let month = date.year() as i64 * 12 + date.month0() as i64;
+                let first_month = month - month % amount;
+                let first = month_start(first_month);
+                let last = month_start(first_month + amount) - Duration::days(1);
+                self.period_bounds(first, last, extended_hours)
+            },
+        }
+    }
+
+    // get the start and end of the consolidated bar of an interval that a daily (or larger) bar belongs to
+    // - a bar within a session belongs to that session, any other bar belongs to the session of its utc date
+    //   (daily bars are often stamped at midnight utc or at the close)
+    pub fn daily_bucket(&self, timestamp: i64, interval: &Interval, extended_hours: bool) -> Option<(i64, i64)> {
+        if let Some(bucket) = self.bucket(timestamp, interval, extended_hours) {
+            return Some(bucket);
+        }
+
+        let session = self.session(Utc.timestamp_opt(timestamp, 0).single()?.date_naive())?;
+        self.bucket(session.bounds(extended_hours).0, interval, extended_hours)
+    }
+
+    // get the open of the first session and the close of the last session between two dates
+    fn period_bounds(&self, first: NaiveDate, last: NaiveDate, extended_hours: bool) -> Option<(i64, i64)> {
+        let mut start = None;
+        let mut end = None;
+        let mut date = first;
+        while date <= last {
+            if let Some(session) = self.session(date) {
+                let (open, close) = session.bounds(extended_hours);
+                if start.is_none() {
+                    start = Some(open);
+                }
+                end = Some(close);
+            }
+            date += Duration::days(1);
+        }
+
+        Some((start?, end?))
+    }
+
+    // count the trading days between the unix epoch and a date (negative before the epoch)
+    fn session_number(&self, date: NaiveDate) -> i64 {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let (from, to, sign) = if date >= epoch { (epoch, date, 1) } else { (date, epoch, -1) };
+
+        // whole weeks have every weekday once, the remaining days are counted one by one
+        let days = (to - from).num_days();
+        let mut count = days / 7 * self.weekdays.len() as i64;
+        let mut day = from + Duration::days(days / 7 * 7);
+        while day < to {
+            if self.weekdays.contains(&day.weekday()) {
+                count += 1;
+            }
+            day += Duration::days(1);
+        }
+        count -= self.holidays.iter().filter(|holiday| **holiday >= from && **holiday < to && self.weekdays.contains(&holiday.weekday())).count() as i64;
+
+        sign * count
+    }
+
+    // move a trading day by a number of trading days (backward when negative)
+    fn shift_sessions(&self, date: NaiveDate, sessions: i64) -> NaiveDate {
+        let step = Duration::days(sessions.signum());
+        let mut date = date;
+        let mut remaining = sessions.abs();
+        while remaining > 0 {
+            date += step;
+            if self.is_trading_day(date) {
+                remaining -= 1;
+            }
+        }
+
+        date
+    }
+
+    // convert a timestamp into the local date of the exchange (none when the timestamp is out of range)
+    pub fn local_date(&self, timestamp: i64) -> Option<NaiveDate> {
+        Some(Utc.timestamp_opt(timestamp, 0).single()?.with_timezone(&self.timezone).date_naive())
+    }
+
+    // convert a local date and minutes since midnight into a timestamp
+    // - times skipped by daylight saving time are moved forward by an hour
+    fn local_timestamp(&self, date: NaiveDate, minutes: u32) -> i64 {
+        let local: NaiveDateTime = date.and_hms_opt(0, 0, 0).unwrap() + Duration::minutes(minutes as i64);
+        match self.timezone.from_local_datetime(&local).earliest() {
+            Some(datetime) => datetime.timestamp(),
+            None => {
+                let shifted = local + Duration::hours(1);
+                self.timezone.from_local_datetime(&shifted).earliest().map(|datetime| datetime.timestamp()).unwrap_or(Utc.from_utc_datetime(&shifted).timestamp())
+            }
+        }
+    }
+}
+
+// parse a HH:MM time into minutes since midnight (24:00 is allowed)
+fn parse_time(value: &str) -> Result<u32, Error> {
+    let value = value.trim();
+    let mut parts = value.splitn(2, ':');
+    let hours = parts.next().unwrap_or("").parse::<u32>();
+    let minutes = parts.next().unwrap_or("").parse::<u32>();
+
+    match (hours, minutes) {
+        (Ok(hours), Ok(minutes)) if minutes < 60 && hours * 60 + minutes <= 1440 => Ok(hours * 60 + minutes),
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("invalid time: {}", value))),
+    }
+}
+
+// parse a YYYY-MM-DD date
+fn parse_date(value: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid date: {}", value)))
+}
+
+// get the first day of a month counted from year 0
+fn month_start(month: i64) -> NaiveDate {
+    NaiveDate::from_ymd_opt((month / 12) as i32, (month % 12) as u32 + 1, 1).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // weekdays from 10:00 to 16:00 utc with a pre-market from 09:00
+    fn calendar() -> TradingCalendar {
+        let mut calendar = TradingCalendar::continuous();
+        calendar.open = 600;
+        calendar.close = 960;
+        calendar.pre_market = Some(540);
+        calendar.weekdays = vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        calendar.holidays.insert(NaiveDate::from_ymd_opt(2024, 1, 15).unwrap());
+        calendar
+    }
+
+    #[test]
+    fn periods_of_several_days_have_the_same_number_of_sessions() {
+        let calendar = calendar();
+        let interval = Interval::from_string("2d").unwrap();
+
+        // every period has two sessions across weekends and holidays
+        let mut buckets = Vec::new();
+        let mut date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        while date < NaiveDate::from_ymd_opt(2024, 2, 1).unwrap() {
+            if let Some(session) = calendar.session(date) {
+                buckets.push(calendar.bucket(session.open, &interval, false).unwrap());
+            }
+            date += Duration::days(1);
+        }
+        // the first and last periods can cross the range of the test
+        let (first, last) = (buckets[0], buckets[buckets.len() - 1]);
+        for bucket in buckets.iter().filter(|bucket| **bucket != first && **bucket != last) {
+            assert_eq!(buckets.iter().filter(|other| *other == bucket).count(), 2, "{:?}", bucket);
+        }
+    }
+
+    #[test]
+    fn timestamps_out_of_range_have_no_session() {
+        let calendar = calendar();
+        assert_eq!(calendar.local_date(i64::MAX), None);
+        assert_eq!(calendar.bucket(i64::MAX, &Interval::from_string("1d").unwrap(), false), None);
+        assert_eq!(calendar.daily_bucket(i64::MIN, &Interval::from_string("1d").unwrap(), false), None);
+    }
+}
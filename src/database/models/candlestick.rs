@@ -1,9 +1,10 @@
-use std::fs::File;
+use std::collections::HashMap;
 use std::hash::{Hasher, Hash};
-use std::io::{Error, BufReader};
-use byteorder::BigEndian;
+use std::io::{Error, ErrorKind, Read, Write};
+use byteorder::{BigEndian, WriteBytesExt};
 use byteorder::ReadBytesExt;
-use super::field::{Field, FieldType};
+use super::field::{Field, FieldType, FieldValue, FieldDefinition};
+use super::header::MAX_EXTRA_FIELDS;
 
 
 // represents a single bar off a chart for a single symbol
@@ -15,9 +16,13 @@ pub struct Candlestick {
     pub low: f64,
     pub close: f64,
     pub volume: f64,
+
+    // extra named fields declared in the header of the file (open interest, funding rate, etc.)
+    pub extensions: HashMap<String, FieldValue>,
 }
 
 impl Candlestick {
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             timestamp: 0,
@@ -26,6 +31,7 @@ impl Candlestick {
             low: 0.0,
             close: 0.0,
             volume: 0.0,
+            extensions: HashMap::new(),
         }
     }
 
@@ -37,61 +43,70 @@ impl Candlestick {
             low,
             close,
             volume,
+            extensions: HashMap::new(),
         }
     }
 
-    pub fn from_reader(reader: &mut BufReader<File>) -> Result<Self, Error> {
+    // read a single record using the extra fields declared in the header
+    pub fn from_reader<R: Read>(reader: &mut R, definitions: &[FieldDefinition]) -> Result<Self, Error> {
         // detect the end of a file
         let field_count_result = reader.read_u8(); // 1 byte
         if field_count_result.is_err() {
             return Err(Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected end of file"));
         }
 
-        // read the number of fields from u8 - 6 fields
+        // read the number of fields from u8 - 6 default fields and any extra fields
         let field_count = field_count_result.unwrap();
+        if field_count < 6 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("invalid field count: {}", field_count)));
+        }
 
         // read the fields
         let mut fields = vec![];
-        for i in 0..field_count {
+        for _ in 0..field_count {
 
             // read the length of the field
-            let length = reader.read_u8().unwrap(); // 1 byte
-            // println!("length: {}", length);
+            let length = reader.read_u8()?; // 1 byte
 
             // read the field type
-            let field_type = reader.read_u8().unwrap(); // 1 byte
-            // println!("field_type: {}", field_type);
+            let field_type = reader.read_u8()?; // 1 byte
 
             // read the original value
-            let original_value;
-            if field_type == 1 {
-                // read as i64
-                original_value = reader.read_i64::<BigEndian>().unwrap() as f64; // 8 bytes
-            }
-            else if field_type == 2 {
-                // read as f64
-                original_value = reader.read_f64::<BigEndian>().unwrap(); // 8 bytes
-            }
-            else {
-                panic!("invalid field type");
-            }
-
-            // create a new field
-            let field = Field {
-                length,
-                field_type: match field_type {
-                    1 => FieldType::Int64,
-                    2 => FieldType::Float64,
-                    // 2 => FieldType::String,
-                    _ => panic!("invalid field type"),
+            let field = match FieldType::from_u8(field_type) {
+                Some(FieldType::Int64) => {
+                    // read as i64
+                    Field::new(length, FieldType::Int64, reader.read_i64::<BigEndian>()? as f64) // 8 bytes
+                },
+                Some(FieldType::Float64) => {
+                    // read as f64
+                    Field::new(length, FieldType::Float64, reader.read_f64::<BigEndian>()?) // 8 bytes
                 },
-                original_value,
+                Some(FieldType::String) => {
+                    // read as a fixed length string (padded with zeros)
+                    let mut buffer = vec![0; length as usize];
+                    reader.read_exact(&mut buffer)?;
+                    let value = String::from_utf8_lossy(&buffer).trim_end_matches('\0').to_string();
+                    Field::new_string(length, value)
+                },
+                None => {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("invalid field type: {}", field_type)));
+                }
             };
 
             // add the field to the list
             fields.push(field);
         }
 
+        // map the extra fields to the names declared in the header
+        let mut extensions = HashMap::new();
+        for (i, field) in fields.iter().enumerate().skip(6) {
+            let name = match definitions.get(i - 6) {
+                Some(definition) => definition.name.clone(),
+                None => format!("field_{}", i),
+            };
+            extensions.insert(name, field.value());
+        }
+
         // println!("fields: {:?}", fields[0].original_value as i32);
 
         Ok(Self {
@@ -101,29 +116,129 @@ impl Candlestick {
             low:  fields[3].original_value,
             close: fields[4].original_value,
             volume: fields[5].original_value,
+            extensions,
         })
     }
 
+    // write a single record using the extra fields declared in the header
+    // - extra fields missing from the candlestick are written as zero/empty values
+    pub fn write<W: Write>(&self, writer: &mut W, definitions: &[FieldDefinition]) -> Result<(), Error> {
+        let mut buffer = vec![];
+
+        // write the number of fields
+        if definitions.len() > MAX_EXTRA_FIELDS {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("too many extra fields: {} (at most {})", definitions.len(), MAX_EXTRA_FIELDS)));
+        }
+        buffer.write_u8(6 + definitions.len() as u8)?;
+
+        // write the timestamp (size, type, value)
+        buffer.write_u8(8u8)?;
+        buffer.write_u8(FieldType::Int64.to_u8())?;
+        buffer.write_i64::<BigEndian>(self.timestamp)?;
+
+        // write the open, high, low, close and volume (size, type, value)
+        for value in [self.open, self.high, self.low, self.close, self.volume] {
+            buffer.write_u8(8u8)?;
+            buffer.write_u8(FieldType::Float64.to_u8())?;
+            buffer.write_f64::<BigEndian>(value)?;
+        }
+
+        // write the extra fields in the order they are declared
+        for definition in definitions.iter() {
+            let value = self.extensions.get(&definition.name);
+
+            buffer.write_u8(definition.length)?;
+            buffer.write_u8(definition.field_type.to_u8())?;
+            match definition.field_type {
+                FieldType::Int64 => {
+                    let value = value.and_then(|value| value.as_f64()).unwrap_or(0.0);
+                    buffer.write_i64::<BigEndian>(value as i64)?;
+                },
+                FieldType::Float64 => {
+                    let value = value.and_then(|value| value.as_f64()).unwrap_or(0.0);
+                    buffer.write_f64::<BigEndian>(value)?;
+                },
+                FieldType::String => {
+                    let value = value.map(|value| value.as_string()).unwrap_or_default();
+                    let mut bytes = value.into_bytes();
+                    bytes.resize(definition.length as usize, 0);
+                    buffer.write_all(&bytes)?;
+                }
+            }
+        }
+
+        // write the buffer to the file
+        writer.write_all(&buffer)?;
+
+        Ok(())
+    }
+
+    // check if the bytes of a record have the layout declared in the header (field count, lengths and types)
+    // - used to find the start of the next record when a file is damaged
+    pub fn check_layout(bytes: &[u8], definitions: &[FieldDefinition]) -> bool {
+        if definitions.len() > MAX_EXTRA_FIELDS || bytes.first() != Some(&(6 + definitions.len() as u8)) {
+            return false;
+        }
+
+        let mut position = 1;
+        let default_fields = [(8u8, FieldType::Int64)].into_iter().chain([(8u8, FieldType::Float64); 5]);
+        let extra_fields = definitions.iter().map(|definition| (definition.length, definition.field_type));
+        for (length, field_type) in default_fields.chain(extra_fields) {
+            if bytes.get(position) != Some(&length) || bytes.get(position + 1) != Some(&field_type.to_u8()) {
+                return false;
+            }
+
+            position += 2 + length as usize;
+        }
+
+        position == bytes.len()
+    }
+
+    // set the value of an extra field
+    pub fn set_extension(&mut self, name: String, value: FieldValue) {
+        self.extensions.insert(name, value);
+    }
+
+    // adjust the prices and volume of the candlestick (used for corporate actions)
+    pub fn adjust(&mut self, price_factor: f64, volume_factor: f64) {
+        self.open *= price_factor;
+        self.high *= price_factor;
+        self.low *= price_factor;
+        self.close *= price_factor;
+        self.volume *= volume_factor;
+    }
+
+    // only keep the extra fields with the given names
+    pub fn retain_extensions(&mut self, names: &[String]) {
+        self.extensions.retain(|name, _| names.contains(name));
+    }
+
+    #[allow(dead_code)]
     pub fn set_timestamp(&mut self, timestamp: i64) {
         self.timestamp = timestamp;
     }
 
+    #[allow(dead_code)]
     pub fn set_open(&mut self, open: f64) {
         self.open = open;
     }
 
+    #[allow(dead_code)]
     pub fn set_high(&mut self, high: f64) {
         self.high = high;
     }
 
+    #[allow(dead_code)]
     pub fn set_low(&mut self, low: f64) {
         self.low = low;
     }
 
+    #[allow(dead_code)]
     pub fn set_close(&mut self, close: f64) {
         self.close = close;
     }
 
+    #[allow(dead_code)]
     pub fn set_volume(&mut self, volume: f64) {
         self.volume = volume;
     }
@@ -136,7 +251,41 @@ impl Hash for Candlestick {
         self.low.to_bits().hash(state);
         self.close.to_bits().hash(state);
         self.timestamp.hash(state);
+
+        let mut sorted_keys: Vec<_> = self.extensions.keys().collect();
+        sorted_keys.sort();
+        for key in sorted_keys {
+            key.hash(state);
+            self.extensions.get(key).unwrap().hash(state);
+        }
     }
 }
 
-impl Eq for Candlestick {}
\ No newline at end of file
+impl Eq for Candlestick {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definitions(count: usize) -> Vec<FieldDefinition> {
+        (0..count).map(|i| FieldDefinition::new_number(format!("field_{}", i))).collect()
+    }
+
+    #[test]
+    fn records_hold_up_to_the_max_extra_fields() {
+        let candlestick = Candlestick::new_with(60, 1.0, 2.0, 0.5, 1.5, 100.0);
+        let mut buffer = Vec::new();
+        candlestick.write(&mut buffer, &definitions(MAX_EXTRA_FIELDS)).unwrap();
+
+        assert_eq!(buffer[0], u8::MAX);
+        assert!(Candlestick::check_layout(&buffer, &definitions(MAX_EXTRA_FIELDS)));
+    }
+
+    #[test]
+    fn records_with_too_many_extra_fields_are_rejected() {
+        let candlestick = Candlestick::new_with(60, 1.0, 2.0, 0.5, 1.5, 100.0);
+        let mut buffer = Vec::new();
+
+        assert!(candlestick.write(&mut buffer, &definitions(MAX_EXTRA_FIELDS + 1)).is_err());
+        assert!(buffer.is_empty());
+    }
+}
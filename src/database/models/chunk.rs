@@ -1,6 +1,6 @@
-use std::{io::{BufReader, ErrorKind}, fs::File};
+use std::{io::BufReader, fs::File};
 use std::io::Error;
-use super::{bar::Bar, candlestick::Candlestick, barset::BarSet};
+use super::{candlestick::Candlestick, header::Header};
 
 
 pub struct Chunk {
@@ -9,6 +9,7 @@ pub struct Chunk {
 
 impl Chunk {
     // create a new chunk
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             candlesticks: Vec::new(),
@@ -22,29 +23,30 @@ impl Chunk {
         }
     }
 
-    // read a chunk from a reader
-    pub fn from_reader(reader: &mut BufReader<File>, limit: i32, offset: i32) -> Result<Self, Error> {
+    // read a chunk from a reader using the record layout from the header
+    #[allow(dead_code)]
+    pub fn from_reader(reader: &mut BufReader<File>, header: &Header, limit: i32, offset: i32) -> Result<Self, Error> {
         // create a new chunk
         let mut chunk: Vec<Candlestick> = Vec::new();
 
         // seek to the offset
-        let bytes_per_record = 61;
+        let bytes_per_record = header.record_size();
         match reader.seek_relative(offset as i64 * bytes_per_record as i64) {
             Ok(_) => {},
             Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("failed to seek to offset: {}", e)));
+                return Err(Error::new(e.kind(), format!("failed to seek to offset: {}", e)));
             }
         }
 
         // read the chunk
         for _ in 0..limit {
             // read a bar from the reader
-            match Candlestick::from_reader(reader) {
+            match Candlestick::from_reader(reader, &header.fields) {
                 Ok(bar) => {
                     // add the bar to the chunk
                     chunk.push(bar);
                 },
-                Err(e) => {
+                Err(_) => {
                     break;
                 }
             }
@@ -55,6 +57,7 @@ impl Chunk {
     }
 
     // add a candlestick to the chunk
+    #[allow(dead_code)]
     pub fn add_candlestick(&mut self, candlestick: Candlestick) {
         self.candlesticks.push(candlestick);
     }
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+
+// represents the asset class of an instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetClass {
+    Crypto,
+    Equity,
+    Future,
+    Perpetual,
+    Forex,
+    Option,
+}
+
+impl AssetClass {
+    // convert a string into an asset class
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "crypto" | "spot" => Some(AssetClass::Crypto),
+            "equity" | "stock" => Some(AssetClass::Equity),
+            "future" => Some(AssetClass::Future),
+            "perpetual" => Some(AssetClass::Perpetual),
+            "forex" => Some(AssetClass::Forex),
+            "option" => Some(AssetClass::Option),
+            _ => None,
+        }
+    }
+}
+
+// represents the contract specification of an instrument
+// - tick size is the smallest price increment
+// - lot size is the smallest quantity increment (and the minimum quantity of an order)
+// - min notional is the minimum value of an order (price * quantity * multiplier)
+// - listing and delisting dates are unix timestamps (none when unknown or still listed)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContractSpec {
+    pub symbol: String,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub min_notional: f64,
+    pub multiplier: f64,
+    pub quote_currency: String,
+    pub asset_class: AssetClass,
+    pub listing_timestamp: Option<i64>,
+    pub delisting_timestamp: Option<i64>,
+}
+
+impl ContractSpec {
+    pub fn new(symbol: String, quote_currency: String, asset_class: AssetClass) -> Self {
+        Self {
+            symbol,
+            tick_size: 0.0,
+            lot_size: 0.0,
+            min_notional: 0.0,
+            multiplier: 1.0,
+            quote_currency,
+            asset_class,
+            listing_timestamp: None,
+            delisting_timestamp: None,
+        }
+    }
+
+    // round a price to the nearest tick
+    pub fn round_price(&self, price: f64) -> f64 {
+        round_to_step(price, self.tick_size, false)
+    }
+
+    // round a quantity down to a whole number of lots (an order never grows when rounded)
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        round_to_step(quantity, self.lot_size, true)
+    }
+
+    // get the value of an order in the quote currency
+    pub fn notional(&self, price: f64, quantity: f64) -> f64 {
+        price * quantity * self.multiplier
+    }
+
+    // check if the instrument was trading at a timestamp
+    pub fn is_listed(&self, timestamp: i64) -> bool {
+        self.listing_timestamp.is_none_or(|listing| timestamp >= listing)
+            && self.delisting_timestamp.is_none_or(|delisting| timestamp < delisting)
+    }
+
+    // check if the instrument was trading at any time between two timestamps
+    pub fn is_listed_between(&self, start_timestamp: i64, end_timestamp: i64) -> bool {
+        self.listing_timestamp.is_none_or(|listing| end_timestamp >= listing)
+            && self.delisting_timestamp.is_none_or(|delisting| start_timestamp < delisting)
+    }
+
+    // check if an order respects the constraints of the instrument (price and quantity should already be rounded)
+    pub fn validate_order(&self, price: f64, quantity: f64) -> Result<(), Error> {
+        if quantity <= 0.0 || (self.lot_size > 0.0 && quantity < self.lot_size) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("contract: quantity {} of {} is below the lot size {}", quantity, self.symbol, self.lot_size)));
+        }
+
+        if self.notional(price, quantity) < self.min_notional {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("contract: order value of {} is below the min notional {}", self.symbol, self.min_notional)));
+        }
+
+        Ok(())
+    }
+}
+
+// represents the symbol metadata of an exchange
+// - stored as a csv file per exchange ([path]/symbols/[exchange].csv)
+// - columns are symbol, tick_size, lot_size, min_notional, multiplier, quote_currency, asset_class, listing and delisting timestamps
+// - empty columns use the defaults of a contract (no rounding, multiplier of 1, always listed)
+#[derive(Debug, Clone)]
+pub struct SymbolRegistry {
+    pub exchange: String,
+    pub specs: HashMap<String, ContractSpec>,
+}
+
+impl SymbolRegistry {
+    pub fn new(exchange: String) -> Self {
+        Self {
+            exchange,
+            specs: HashMap::new(),
+        }
+    }
+
+    // load the symbol registry of an exchange (an empty registry if the exchange has none)
+    pub fn from_file(exchange: String, filename: &str) -> Result<Self, Error> {
+        let mut registry = Self::new(exchange);
+        if !Path::new(filename).exists() {
+            return Ok(registry);
+        }
+
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+            let symbol = record.get(0).unwrap_or("").trim().to_string();
+            if symbol.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("missing symbol in {}", filename)));
+            }
+
+            let asset_class = record.get(6).unwrap_or("").trim();
+            let asset_class = match asset_class {
+                "" => AssetClass::Crypto,
+                _ => AssetClass::from_string(asset_class)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("invalid asset class {} of {} in {}", asset_class, symbol, filename)))?,
+            };
+
+            let mut spec = ContractSpec::new(symbol.clone(), record.get(5).unwrap_or("").trim().to_string(), asset_class);
+            spec.tick_size = parse_column(&record, 1, filename)?.unwrap_or(0.0);
+            spec.lot_size = parse_column(&record, 2, filename)?.unwrap_or(0.0);
+            spec.min_notional = parse_column(&record, 3, filename)?.unwrap_or(0.0);
+            spec.multiplier = parse_column(&record, 4, filename)?.unwrap_or(1.0);
+            spec.listing_timestamp = parse_column(&record, 7, filename)?;
+            spec.delisting_timestamp = parse_column(&record, 8, filename)?;
+
+            if spec.tick_size < 0.0 || spec.lot_size < 0.0 || spec.multiplier <= 0.0 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid contract specification of {} in {}", symbol, filename)));
+            }
+
+            registry.specs.insert(symbol, spec);
+        }
+
+        Ok(registry)
+    }
+
+    // check if the exchange has no symbol metadata
+    pub fn is_empty(&self) -> bool {
+        self.specs.is_empty()
+    }
+
+    // get the contract specification of a symbol
+    pub fn get(&self, symbol: &str) -> Option<&ContractSpec> {
+        self.specs.get(symbol)
+    }
+
+    // check that a symbol can be queried between two timestamps
+    // - exchanges without symbol metadata are not validated
+    pub fn validate(&self, symbol: &str, start_timestamp: Option<i64>, end_timestamp: Option<i64>) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let spec = match self.get(symbol) {
+            Some(spec) => spec,
+            None => {
+                return Err(Error::new(ErrorKind::NotFound, format!("contract: symbol {} is not listed on {}", symbol, self.exchange)));
+            }
+        };
+
+        let start_timestamp = start_timestamp.unwrap_or(i64::MIN);
+        let end_timestamp = end_timestamp.unwrap_or(i64::MAX);
+        if !spec.is_listed_between(start_timestamp, end_timestamp) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("contract: symbol {} was not listed on {} during the query", symbol, self.exchange)));
+        }
+
+        Ok(())
+    }
+}
+
+// parse an optional numeric column of the registry
+fn parse_column<T: std::str::FromStr>(record: &csv::StringRecord, index: usize, filename: &str) -> Result<Option<T>, Error> {
+    let value = record.get(index).unwrap_or("").trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    match value.parse::<T>() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, format!("invalid value {} in column {} of {}", value, index, filename))),
+    }
+}
+
+// round a value to a multiple of a step (no rounding when the step is zero)
+// - the result is rounded to the decimals of the step to remove floating point noise
+fn round_to_step(value: f64, step: f64, round_down: bool) -> f64 {
+    if step <= 0.0 {
+        return value;
+    }
+
+    // small epsilon so values that are already a multiple of the step are not rounded down
+    let steps = value / step;
+    let steps = if round_down { (steps + 1e-9).floor() } else { steps.round() };
+
+    let mut scale = 1.0;
+    while scale < 1e12 && ((step * scale).round() - step * scale).abs() > 1e-9 {
+        scale *= 10.0;
+    }
+    (steps * step * scale).round() / scale
+}
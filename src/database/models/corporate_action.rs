@@ -0,0 +1,181 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+
+// represents how prices are adjusted for corporate actions
+// - raw: prices as they were traded
+// - split adjusted: prices before a split are divided by the split ratio (volume is multiplied)
+// - total return: split adjusted and prices before a dividend are reduced by the dividend yield
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceAdjustment {
+    Raw,
+    SplitAdjusted,
+    TotalReturn,
+}
+
+impl PriceAdjustment {
+    // convert a string into a price adjustment
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "raw" => Some(PriceAdjustment::Raw),
+            "split" | "split_adjusted" => Some(PriceAdjustment::SplitAdjusted),
+            "total_return" => Some(PriceAdjustment::TotalReturn),
+            _ => None,
+        }
+    }
+
+    // get the name of the price adjustment
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceAdjustment::Raw => "raw",
+            PriceAdjustment::SplitAdjusted => "split_adjusted",
+            PriceAdjustment::TotalReturn => "total_return",
+        }
+    }
+}
+
+// represents the type of a corporate action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorporateActionKind {
+    Split,
+    Dividend,
+}
+
+// represents a single corporate action of an equity
+// - timestamp is the ex-date of the action
+// - value is the split ratio (2.0 for a 2:1 split) or the cash dividend per share
+// - reference price is the close before the ex-date used for total return adjustments
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorporateAction {
+    pub timestamp: i64,
+    pub kind: CorporateActionKind,
+    pub value: f64,
+    pub reference_price: Option<f64>,
+}
+
+impl CorporateAction {
+    pub fn new(timestamp: i64, kind: CorporateActionKind, value: f64, reference_price: Option<f64>) -> Self {
+        Self {
+            timestamp,
+            kind,
+            value,
+            reference_price,
+        }
+    }
+
+    // the factor prices before the ex-date are multiplied by
+    pub fn price_factor(&self, adjustment: PriceAdjustment) -> f64 {
+        match (self.kind, adjustment) {
+            (_, PriceAdjustment::Raw) => 1.0,
+            (CorporateActionKind::Split, _) => 1.0 / self.value,
+            (CorporateActionKind::Dividend, PriceAdjustment::SplitAdjusted) => 1.0,
+            (CorporateActionKind::Dividend, PriceAdjustment::TotalReturn) => {
+                match self.reference_price {
+                    Some(reference_price) if reference_price > 0.0 => 1.0 - self.value / reference_price,
+                    _ => 1.0,
+                }
+            }
+        }
+    }
+
+    // the factor volumes before the ex-date are multiplied by
+    pub fn volume_factor(&self, adjustment: PriceAdjustment) -> f64 {
+        match (self.kind, adjustment) {
+            (CorporateActionKind::Split, PriceAdjustment::SplitAdjusted | PriceAdjustment::TotalReturn) => self.value,
+            _ => 1.0,
+        }
+    }
+}
+
+// represents the corporate actions table of a dataset
+// - stored as a csv file next to the dataset ([exchange]_[symbol].actions.csv)
+// - columns are timestamp, action (split or dividend), value and an optional reference price
+#[derive(Debug, Clone)]
+pub struct CorporateActions {
+    pub actions: Vec<CorporateAction>,
+}
+
+impl CorporateActions {
+    pub fn new() -> Self {
+        Self {
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn new_with(mut actions: Vec<CorporateAction>) -> Self {
+        actions.sort_by_key(|action| action.timestamp);
+
+        Self {
+            actions,
+        }
+    }
+
+    // load the corporate actions table of a dataset (an empty table if the dataset has none)
+    pub fn from_file(filename: &str) -> Result<Self, Error> {
+        if !Path::new(filename).exists() {
+            return Ok(Self::new());
+        }
+
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut actions = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+            let timestamp = record.get(0).unwrap_or("").trim().parse::<i64>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid corporate action timestamp in {}", filename)))?;
+            let kind = match record.get(1).unwrap_or("").trim() {
+                "split" => CorporateActionKind::Split,
+                "dividend" => CorporateActionKind::Dividend,
+                action => {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("invalid corporate action {} in {}", action, filename)));
+                }
+            };
+            let value = record.get(2).unwrap_or("").trim().parse::<f64>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid corporate action value in {}", filename)))?;
+            let reference_price = record.get(3).and_then(|value| value.trim().parse::<f64>().ok());
+
+            if kind == CorporateActionKind::Split && value <= 0.0 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid split ratio {} in {}", value, filename)));
+            }
+
+            actions.push(CorporateAction::new(timestamp, kind, value, reference_price));
+        }
+
+        Ok(Self::new_with(actions))
+    }
+
+    // get the price and volume factors for a bar at a given timestamp
+    // - every action with an ex-date after the timestamp applies to the bar
+    pub fn factors(&self, timestamp: i64, adjustment: PriceAdjustment) -> (f64, f64) {
+        let mut price_factor = 1.0;
+        let mut volume_factor = 1.0;
+        if adjustment == PriceAdjustment::Raw {
+            return (price_factor, volume_factor);
+        }
+
+        for action in self.actions.iter().rev() {
+            if action.timestamp <= timestamp {
+                break;
+            }
+
+            price_factor *= action.price_factor(adjustment);
+            volume_factor *= action.volume_factor(adjustment);
+        }
+
+        (price_factor, volume_factor)
+    }
+
+    // get the actions with an ex-date after one timestamp and at or before another
+    pub fn between(&self, after_timestamp: i64, timestamp: i64) -> Vec<CorporateAction> {
+        self.actions.iter()
+            .filter(|action| action.timestamp > after_timestamp && action.timestamp <= timestamp)
+            .cloned()
+            .collect()
+    }
+
+    // check if there are dividends without a reference price
+    pub fn has_missing_reference_prices(&self) -> bool {
+        self.actions.iter().any(|action| action.kind == CorporateActionKind::Dividend && action.reference_price.is_none())
+    }
+}
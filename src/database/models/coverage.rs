@@ -0,0 +1,98 @@
+// represents how the bars of symbols with different date ranges are aligned in a query
+// - intersection: only the range where every symbol has bars is read
+// - union: the range where any symbol has bars is read, bars only have the symbols that exist at their timestamp
+// - independent: every symbol is read over its own range (clipped to the range of the query)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlignmentPolicy {
+    Intersection,
+    Union,
+    Independent,
+}
+
+impl AlignmentPolicy {
+    // convert a string into an alignment policy
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "intersection" => Some(AlignmentPolicy::Intersection),
+            "union" => Some(AlignmentPolicy::Union),
+            "independent" => Some(AlignmentPolicy::Independent),
+            _ => None,
+        }
+    }
+
+    // get the name of the alignment policy
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlignmentPolicy::Intersection => "intersection",
+            AlignmentPolicy::Union => "union",
+            AlignmentPolicy::Independent => "independent",
+        }
+    }
+}
+
+// represents whether a symbol has bars at a timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingStatus {
+    // before the first bar of the symbol (or the symbol has no bars)
+    NotListed,
+    Listed,
+    // after the last bar of the symbol
+    Delisted,
+}
+
+impl ListingStatus {
+    // get the name of the listing status
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListingStatus::NotListed => "not_listed",
+            ListingStatus::Listed => "listed",
+            ListingStatus::Delisted => "delisted",
+        }
+    }
+}
+
+// represents the range of the bars of a symbol and the range a query reads for it
+// - the data range is none when the symbol has no bars
+// - the effective range is none when the query does not read any bars of the symbol
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    pub exchange: String,
+    pub symbol: String,
+    pub start_timestamp: Option<i64>,
+    pub end_timestamp: Option<i64>,
+    pub effective_start: Option<i64>,
+    pub effective_end: Option<i64>,
+}
+
+impl Coverage {
+    pub fn new(exchange: String, symbol: String, range: Option<(i64, i64)>) -> Self {
+        Self {
+            exchange,
+            symbol,
+            start_timestamp: range.map(|(start, _)| start),
+            end_timestamp: range.map(|(_, end)| end),
+            effective_start: None,
+            effective_end: None,
+        }
+    }
+
+    // get the range of the bars of the symbol
+    pub fn range(&self) -> Option<(i64, i64)> {
+        Some((self.start_timestamp?, self.end_timestamp?))
+    }
+
+    // get the range the query reads for the symbol
+    pub fn effective_range(&self) -> Option<(i64, i64)> {
+        Some((self.effective_start?, self.effective_end?))
+    }
+
+    // get the listing status of the symbol at a timestamp
+    pub fn status(&self, timestamp: i64) -> ListingStatus {
+        match self.range() {
+            Some((start, _)) if timestamp < start => ListingStatus::NotListed,
+            Some((_, end)) if timestamp > end => ListingStatus::Delisted,
+            Some(_) => ListingStatus::Listed,
+            None => ListingStatus::NotListed,
+        }
+    }
+}
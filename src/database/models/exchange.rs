@@ -1,24 +1,89 @@
+use std::path::Path;
+use std::io::Error;
+use super::calendar::TradingCalendar;
+use super::contract::SymbolRegistry;
+use super::symbol::Symbol;
 
 
 #[derive(Debug, Clone)]
 pub struct Exchange {
     pub name: String,
+
+    // trading calendar of the exchange (trades 24/7 in UTC when not set)
+    pub calendar: Option<TradingCalendar>,
+
+    // contract specifications of the symbols listed on the exchange (empty when not loaded)
+    pub symbols: SymbolRegistry,
 }
 
 impl Exchange {
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             name: "".to_string(),
+            calendar: None,
+            symbols: SymbolRegistry::new("".to_string()),
         }
     }
 
     pub fn new_with(name: String) -> Self {
         Self {
+            symbols: SymbolRegistry::new(name.clone()),
             name,
+            calendar: None,
         }
     }
 
+    // attach a trading calendar to the exchange
+    #[allow(dead_code)]
+    pub fn with_calendar(mut self, calendar: TradingCalendar) -> Self {
+        self.calendar = Some(calendar);
+        self
+    }
+
+    // attach the symbol registry to the exchange
+    #[allow(dead_code)]
+    pub fn with_symbols(mut self, symbols: SymbolRegistry) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    #[allow(dead_code)]
     pub fn set_name(&mut self, name: String) {
+        self.symbols.exchange = name.clone();
         self.name = name;
     }
+
+    // load the calendar of the exchange from the calendars folder ([path]/calendars/[exchange].txt)
+    // - exchanges without a calendar file trade 24/7
+    pub fn load_calendar(&mut self, path: &str) -> Result<(), Error> {
+        let filename = format!("{}/calendars/{}.txt", path, self.name);
+        if Path::new(&filename).exists() {
+            self.calendar = Some(TradingCalendar::from_file(&filename)?);
+        }
+
+        Ok(())
+    }
+
+    // get the trading calendar of the exchange
+    pub fn get_calendar(&self) -> TradingCalendar {
+        self.calendar.clone().unwrap_or_else(TradingCalendar::continuous)
+    }
+
+    // load the symbol registry of the exchange from the symbols folder ([path]/symbols/[exchange].csv)
+    // - exchanges without a registry file are not validated
+    pub fn load_symbols(&mut self, path: &str) -> Result<(), Error> {
+        let filename = format!("{}/symbols/{}.csv", path, self.name);
+        self.symbols = SymbolRegistry::from_file(self.name.clone(), &filename)?;
+
+        Ok(())
+    }
+
+    // look up a symbol in the registry and attach its contract specification
+    pub fn get_symbol(&self, symbol: &Symbol) -> Symbol {
+        match self.symbols.get(&symbol.name) {
+            Some(spec) => symbol.clone().with_spec(spec.clone()),
+            None => symbol.clone(),
+        }
+    }
 }
\ No newline at end of file
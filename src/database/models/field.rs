@@ -1,13 +1,79 @@
+use std::hash::{Hash, Hasher};
+
+
+// represents a data type stored in a field
+// - the u8 value is the type byte written in front of every field of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FieldType {
     Int64,
     Float64,
     String,
 }
 
+impl FieldType {
+    // convert a field type byte into a field type
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(FieldType::Int64),
+            2 => Some(FieldType::Float64),
+            3 => Some(FieldType::String),
+            _ => None,
+        }
+    }
+
+    // convert a field type into the byte stored in the file
+    pub fn to_u8(self) -> u8 {
+        match self {
+            FieldType::Int64 => 1,
+            FieldType::Float64 => 2,
+            FieldType::String => 3,
+        }
+    }
+}
+
+// represents the value of a single field
+// - numbers are stored as f64 the same way the original six fields are
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Number(f64),
+    Text(String),
+}
+
+impl FieldValue {
+    // get the value as a number (text fields are parsed if possible)
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Number(value) => Some(*value),
+            FieldValue::Text(value) => value.parse::<f64>().ok(),
+        }
+    }
+
+    // get the value as text
+    pub fn as_string(&self) -> String {
+        match self {
+            FieldValue::Number(value) => value.to_string(),
+            FieldValue::Text(value) => value.clone(),
+        }
+    }
+}
+
+impl Hash for FieldValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            FieldValue::Number(value) => value.to_bits().hash(state),
+            FieldValue::Text(value) => value.hash(state),
+        }
+    }
+}
+
+// represents a single raw field in a record
 pub struct Field {
+    #[allow(dead_code)]
     pub length: u8,
+    #[allow(dead_code)]
     pub field_type: FieldType,
     pub original_value: f64,
+    pub string_value: Option<String>,
 }
 
 impl Field {
@@ -16,6 +82,60 @@ impl Field {
             length,
             field_type,
             original_value,
+            string_value: None,
+        }
+    }
+
+    // create a new string field
+    pub fn new_string(length: u8, string_value: String) -> Self {
+        Self {
+            length,
+            field_type: FieldType::String,
+            original_value: 0.0,
+            string_value: Some(string_value),
+        }
+    }
+
+    // get the value of the field
+    pub fn value(&self) -> FieldValue {
+        match &self.string_value {
+            Some(value) => FieldValue::Text(value.clone()),
+            None => FieldValue::Number(self.original_value),
+        }
+    }
+}
+
+// represents an extra named field declared in the header of a stmdb file
+// - the six default fields (timestamp, open, high, low, close, volume) are never declared
+// - string fields are stored with a fixed length so every record keeps the same size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDefinition {
+    pub name: String,
+    pub field_type: FieldType,
+    pub length: u8,
+}
+
+impl FieldDefinition {
+    pub fn new(name: String, field_type: FieldType, length: u8) -> Self {
+        Self {
+            name,
+            field_type,
+            length,
         }
     }
-}
\ No newline at end of file
+
+    // create a new numeric field definition (8 bytes)
+    pub fn new_number(name: String) -> Self {
+        Self::new(name, FieldType::Float64, 8)
+    }
+
+    // create a new string field definition with a fixed length
+    pub fn new_string(name: String, length: u8) -> Self {
+        Self::new(name, FieldType::String, length)
+    }
+
+    // number of bytes the field takes up in a record (length, type, value)
+    pub fn size(&self) -> usize {
+        2 + self.length as usize
+    }
+}
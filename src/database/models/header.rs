@@ -1,67 +1,344 @@
-use std::{io::{BufReader, Read, BufWriter}, fs::File};
-use std::io::Error;
+use std::{io::{BufReader, Read, BufWriter, Write}, fs::File};
+use std::io::{Error, ErrorKind};
 
-use byteorder::{BigEndian, WriteBytesExt};
+use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+use super::field::{FieldDefinition, FieldType};
 
 
+// number of bytes used by the six default fields of a record (timestamp, open, high, low, close, volume)
+// - 1 byte for the field count and 10 bytes (length, type, value) per field
+pub const DEFAULT_RECORD_SIZE: usize = 61;
 
+// the most extra fields of a record (the field count of a record is a byte that includes the six default fields)
+pub const MAX_EXTRA_FIELDS: usize = u8::MAX as usize - 6;
+
+// number of records in a block of a version 3 file (every block is followed by its crc32 checksum)
+pub const DEFAULT_BLOCK_SIZE: u16 = 1024;
+
+// number of bytes used by the crc32 checksum after every block
+pub const CHECKSUM_SIZE: usize = 4;
+
+// seconds between the records of files that do not declare a resolution
+pub const DEFAULT_RESOLUTION: u32 = 60;
+
+// represents the type of records stored in a stmdb file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordKind {
+    Candlestick,
+    OrderBook,
+}
+
+// header versions
+// - version 1 "STMD" - only the six default fields
+// - version 2 "STM2" - extra named fields declared after the default header
+// - version 3 "STM3" - version 2 with the number of records per block declared before the extra fields
+//   and a crc32 checksum of the record bytes after every block (the last block can be partial)
+// - version 4 "STM4" - version 3 with the base resolution of the records in seconds declared after the block size
+//   (older versions are 1m records)
+// - "STOB" - order book (L2) snapshots with the number of levels declared after the default header
+#[derive(Debug, Clone)]
 pub struct Header {
-    pub identifier: String, // STMDB (4 bytes)
+    #[allow(dead_code)]
+    pub identifier: String, // STMD (4 bytes)
+    pub version: u8,
+    pub record_kind: RecordKind,
     pub dataset_id: u32,
     pub start_timestamp: u32,
     pub end_timestamp: u32,
+
+    // extra fields stored after the six default fields of every record
+    pub fields: Vec<FieldDefinition>,
+
+    // number of bid/ask levels stored in every order book snapshot
+    pub levels: u8,
+
+    // number of records per checksummed block (0 when the records are not checksummed)
+    pub block_size: u16,
+
+    // seconds between the records of the file (the base resolution of the dataset)
+    pub resolution: u32,
 }
 
 impl Header {
     // create a new empty header
+    #[allow(dead_code)]
     pub fn new() -> Self {
+        Self::new_with(0, 0, 0, Vec::new())
+    }
+
+    // create a new header with a set of extra fields
+    pub fn new_with(dataset_id: u32, start_timestamp: u32, end_timestamp: u32, fields: Vec<FieldDefinition>) -> Self {
+        Self {
+            identifier: "STM4".to_string(),
+            version: 4,
+            record_kind: RecordKind::Candlestick,
+            dataset_id,
+            start_timestamp,
+            end_timestamp,
+            fields,
+            levels: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            resolution: DEFAULT_RESOLUTION,
+        }
+    }
+
+    // create a new header for a file of order book snapshots
+    pub fn new_order_book(dataset_id: u32, start_timestamp: u32, end_timestamp: u32, levels: u8) -> Self {
         Self {
-            identifier: "STMDB".to_string(),
-            dataset_id: 0,
-            start_timestamp: 0,
-            end_timestamp: 0,
+            identifier: "STOB".to_string(),
+            version: 2,
+            record_kind: RecordKind::OrderBook,
+            dataset_id,
+            start_timestamp,
+            end_timestamp,
+            fields: Vec::new(),
+            levels,
+            block_size: 0,
+            resolution: DEFAULT_RESOLUTION,
         }
     }
 
     // use reader buffer to read the file header
     pub fn from_reader(reader: &mut BufReader<File>) -> Result<Self, Error> {
         let mut buffer = [0; 16];
-        reader.read_exact(&mut buffer).unwrap();
+        reader.read_exact(&mut buffer)?;
 
         // println!("header buffer: {:?}", buffer);
 
+        let identifier = String::from_utf8_lossy(&buffer[0..4]).to_string();
+        let (version, record_kind) = match identifier.as_str() {
+            "STMD" => (1, RecordKind::Candlestick),
+            "STM2" => (2, RecordKind::Candlestick),
+            "STM3" => (3, RecordKind::Candlestick),
+            "STM4" => (4, RecordKind::Candlestick),
+            "STOB" => (2, RecordKind::OrderBook),
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid file identifier: {}", identifier)));
+            }
+        };
+
+        // read the number of records per checksummed block
+        let mut block_size = 0;
+        if version >= 3 {
+            block_size = reader.read_u16::<BigEndian>()?;
+        }
+
+        // read the base resolution of the records
+        let mut resolution = DEFAULT_RESOLUTION;
+        if version >= 4 {
+            resolution = reader.read_u32::<BigEndian>()?;
+            if resolution == 0 {
+                return Err(Error::new(ErrorKind::InvalidData, "invalid resolution in header: 0"));
+            }
+        }
+
+        // read the extra field definitions
+        let mut fields = Vec::new();
+        if version >= 2 && record_kind == RecordKind::Candlestick {
+            let field_count = reader.read_u8()?;
+            if field_count as usize > MAX_EXTRA_FIELDS {
+                return Err(Error::new(ErrorKind::InvalidData, format!("too many extra fields in header: {} (at most {})", field_count, MAX_EXTRA_FIELDS)));
+            }
+            for _ in 0..field_count {
+                let field_type = reader.read_u8()?;
+                let length = reader.read_u8()?;
+                let name_length = reader.read_u8()?;
+                let mut name = vec![0; name_length as usize];
+                reader.read_exact(&mut name)?;
+
+                let field_type = match FieldType::from_u8(field_type) {
+                    Some(field_type) => field_type,
+                    None => {
+                        return Err(Error::new(ErrorKind::InvalidData, format!("invalid field type in header: {}", field_type)));
+                    }
+                };
+
+                fields.push(FieldDefinition::new(String::from_utf8_lossy(&name).to_string(), field_type, length));
+            }
+        }
+
+        // read the number of order book levels
+        let mut levels = 0;
+        if record_kind == RecordKind::OrderBook {
+            levels = reader.read_u8()?;
+        }
+
         Ok(Self {
-            identifier: u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]).to_string(),
+            identifier,
+            version,
+            record_kind,
             dataset_id: u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]),
             start_timestamp: u32::from_be_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]),
             end_timestamp: u32::from_be_bytes([buffer[12], buffer[13], buffer[14], buffer[15]]),
+            fields,
+            levels,
+            block_size,
+            resolution,
         })
     }
 
     // write the header to a file buffer
-    pub fn into_writer(writer: &mut BufWriter<File>, dataset_id: i32, start_timestamp: i32, end_timestamp: i32) -> Result<Self, Error> {
+    #[allow(dead_code)]
+    pub fn into_writer(writer: &mut BufWriter<File>, dataset_id: i32, start_timestamp: i32, end_timestamp: i32, fields: Vec<FieldDefinition>) -> Result<Self, Error> {
+        let mut header = Self::new_with(dataset_id as u32, start_timestamp as u32, end_timestamp as u32, fields);
+        header.write(writer)?;
+
+        Ok(header)
+    }
+
+    // write this header to a file buffer
+    // - version 1 headers are written as a later version (with a field count), the version is updated so the size matches the written bytes
+    pub fn write(&mut self, writer: &mut BufWriter<File>) -> Result<(), Error> {
         // write file identifier (4 bytes)
-        let filetype_id = "STMD";
+        let filetype_id = match self.record_kind {
+            RecordKind::Candlestick if self.has_resolution() => "STM4",
+            RecordKind::Candlestick if self.block_size > 0 => "STM3",
+            RecordKind::Candlestick => "STM2",
+            RecordKind::OrderBook => "STOB",
+        };
+        if self.version < 2 {
+            self.version = if self.block_size > 0 { 3 } else { 2 };
+        }
+        if self.record_kind == RecordKind::Candlestick && !self.has_resolution() && self.resolution != DEFAULT_RESOLUTION {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("a resolution of {}s needs a version 4 header", self.resolution)));
+        }
         let filetype_id_bytes: [u8; 4] = match *filetype_id.as_bytes() {
             [b1, b2, b3, b4, ..] => [b1, b2, b3, b4],
             _ => panic!("filetype_id must be exactly 4 characters"),
         };
-        writer.write_u32::<BigEndian>(u32::from_be_bytes(filetype_id_bytes)).unwrap();
+        writer.write_u32::<BigEndian>(u32::from_be_bytes(filetype_id_bytes))?;
 
         // write dataset id (4 bytes)
-        writer.write_u32::<BigEndian>(dataset_id as u32).unwrap();
+        writer.write_u32::<BigEndian>(self.dataset_id)?;
 
         // write start timestamp (4 bytes)
-        writer.write_u32::<BigEndian>(start_timestamp as u32).unwrap();
+        writer.write_u32::<BigEndian>(self.start_timestamp)?;
 
         // write end timestamp (4 bytes)
-        writer.write_u32::<BigEndian>(end_timestamp as u32).unwrap();
+        writer.write_u32::<BigEndian>(self.end_timestamp)?;
 
-        Ok(Self {
-            identifier: filetype_id.to_string(),
-            dataset_id: dataset_id as u32,
-            start_timestamp: start_timestamp as u32,
-            end_timestamp: end_timestamp as u32,
-        })
+        // write the number of order book levels (1 byte)
+        if self.record_kind == RecordKind::OrderBook {
+            writer.write_u8(self.levels)?;
+            return Ok(());
+        }
+
+        // write the number of records per checksummed block (2 bytes)
+        if self.block_size > 0 {
+            writer.write_u16::<BigEndian>(self.block_size)?;
+        }
+
+        // write the base resolution of the records (4 bytes)
+        if self.has_resolution() {
+            writer.write_u32::<BigEndian>(self.resolution)?;
+        }
+
+        // write the extra field definitions (type, length, name length, name)
+        if self.fields.len() > MAX_EXTRA_FIELDS {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("too many extra fields in header: {} (at most {})", self.fields.len(), MAX_EXTRA_FIELDS)));
+        }
+        writer.write_u8(self.fields.len() as u8)?;
+        for field in self.fields.iter() {
+            let name = field.name.as_bytes();
+            if name.len() > u8::MAX as usize {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("field name is too long: {}", field.name)));
+            }
+
+            writer.write_u8(field.field_type.to_u8())?;
+            writer.write_u8(field.length)?;
+            writer.write_u8(name.len() as u8)?;
+            writer.write_all(name)?;
+        }
+
+        Ok(())
+    }
+
+    // number of bytes the header takes up in the file
+    pub fn size(&self) -> usize {
+        if self.version < 2 {
+            return 16;
+        }
+
+        if self.record_kind == RecordKind::OrderBook {
+            return 17;
+        }
+
+        let block_size = if self.block_size > 0 { 2 } else { 0 };
+        let resolution = if self.has_resolution() { 4 } else { 0 };
+        17 + block_size + resolution + self.fields.iter().map(|field| 3 + field.name.len()).sum::<usize>()
+    }
+
+    // number of bytes a single record takes up in the file
+    pub fn record_size(&self) -> usize {
+        // level count, timestamp and a bid/ask price and size per level
+        if self.record_kind == RecordKind::OrderBook {
+            return 9 + self.levels as usize * 32;
+        }
+
+        DEFAULT_RECORD_SIZE + self.fields.iter().map(|field| field.size()).sum::<usize>()
+    }
+
+    // number of bytes a full block takes up in the file (records and checksum)
+    pub fn block_bytes(&self) -> usize {
+        self.block_size as usize * self.record_size() + CHECKSUM_SIZE
+    }
+
+    // position of a record from the start of the file (skips the checksums of the blocks before it)
+    pub fn record_position(&self, index: u64) -> u64 {
+        if self.block_size == 0 {
+            return self.size() as u64 + index * self.record_size() as u64;
+        }
+
+        let block_size = self.block_size as u64;
+        self.size() as u64 + (index / block_size) * self.block_bytes() as u64 + (index % block_size) * self.record_size() as u64
+    }
+
+    // number of records in a file of a given length
+    pub fn record_count(&self, file_length: u64) -> u64 {
+        let length = file_length.saturating_sub(self.size() as u64);
+        let record_size = self.record_size() as u64;
+        if self.block_size == 0 {
+            return length / record_size;
+        }
+
+        let block_bytes = self.block_bytes() as u64;
+        let remainder = length % block_bytes;
+        (length / block_bytes) * self.block_size as u64 + remainder.saturating_sub(CHECKSUM_SIZE as u64) / record_size
+    }
+
+    // check if the resolution is stored in the header (version 4 files with checksummed blocks)
+    fn has_resolution(&self) -> bool {
+        self.version >= 4 && self.block_size > 0
+    }
+
+    // check if an extra field is declared in the header
+    pub fn has_field(&self, name: &str) -> bool {
+        self.fields.iter().any(|field| field.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_1_headers_are_written_with_the_size_of_version_2() {
+        let filename = std::env::temp_dir().join(format!("header_version_1_{}.stmdb", std::process::id()));
+        let mut header = Header::new_with(1, 0, 60, Vec::new());
+        header.version = 1;
+        header.block_size = 0;
+
+        let mut writer = BufWriter::new(File::create(&filename).unwrap());
+        header.write(&mut writer).unwrap();
+        drop(writer);
+
+        let length = std::fs::metadata(&filename).unwrap().len() as usize;
+        let read = Header::from_reader(&mut BufReader::new(File::open(&filename).unwrap())).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!(header.version, 2);
+        assert_eq!(header.size(), length);
+        assert_eq!(read.version, 2);
+        assert_eq!(read.size(), length);
     }
 }
\ No newline at end of file
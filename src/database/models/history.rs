@@ -0,0 +1,24 @@
+use super::candlestick::Candlestick;
+
+
+// represents the bars of a symbol and interval that end at or before a timestamp (oldest first)
+// - used to seed the history of strategies and indicators before the first bar of a backtest
+// - there are fewer bars than requested when the dataset does not go back far enough
+#[derive(Debug, Clone)]
+pub struct History {
+    pub exchange: String,
+    pub symbol: String,
+    pub interval: String,
+    pub candlesticks: Vec<Candlestick>,
+}
+
+impl History {
+    pub fn new(exchange: String, symbol: String, interval: String) -> Self {
+        Self {
+            exchange,
+            symbol,
+            interval,
+            candlesticks: Vec::new(),
+        }
+    }
+}
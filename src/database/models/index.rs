@@ -1,5 +1,8 @@
 use std::collections::HashMap;
-use super::query::Query;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use super::{header::RecordKind, interval::Interval};
 
 
 // represents an indice of all the files in the corpus
@@ -12,6 +15,12 @@ pub struct DatabaseIndex {
 
     // map of symbol and exchange combos to corpus files
     pub corpus_map: HashMap<String, Corpus>,
+
+    // versions of the datasets in the order they were written
+    pub snapshots: Vec<Snapshot>,
+
+    // materialized higher timeframe bars of the datasets
+    pub rollups: Vec<Rollup>,
 }
 impl DatabaseIndex {
 
@@ -21,37 +30,221 @@ impl DatabaseIndex {
             filename,
             root_dir,
             corpus_map: HashMap::new(),
+            snapshots: Vec::new(),
+            rollups: Vec::new(),
         }
     }
 
     // create new index from existing map
+    #[allow(dead_code)]
     pub fn new_from(filename: String, root_dir: String, corpus_map: HashMap<String, Corpus>) -> Self {
         Self {
             filename,
             root_dir,
             corpus_map,
+            snapshots: Vec::new(),
+            rollups: Vec::new(),
         }
     }
 
-    // convert a database query into a list of files that need to be read
-    pub fn from_query(filename: String, root_dir: String, query: Query) -> Self {
+    // load the catalog of the database from a csv file (an empty catalog if there is none)
+    // - columns are dataset_id, exchange, symbol, filename, record_kind, start_timestamp, end_timestamp, record_count, last_updated,
+    //   partition, version, content_hash and resolution (files without a resolution have 1m bars)
+    // - the snapshots are loaded first, files are added to the catalog before the snapshot that uses them is saved
+    //   so every file of a loaded snapshot is in the catalog
+    pub fn from_file(filename: String, root_dir: String) -> Result<Self, Error> {
+        let mut index = Self::new(filename.clone(), root_dir);
+        index.snapshots = Snapshot::from_file(&index.snapshots_filename())?;
+        index.rollups = Rollup::from_file(&index.rollups_filename())?;
+        if !Path::new(&filename).exists() {
+            return Ok(index);
+        }
+
+        let mut reader = csv::Reader::from_path(&filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if record.len() < 9 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid catalog record in {}", filename)));
+            }
+
+            let invalid = |column: &str| Error::new(ErrorKind::InvalidData, format!("invalid {} in catalog {}", column, filename));
+            let mut corpus = Corpus::new(
+                record[0].parse::<u8>().map_err(|_| invalid("dataset id"))?,
+                record[1].to_string(),
+                record[2].to_string(),
+                record[5].parse::<i32>().map_err(|_| invalid("start timestamp"))?,
+                record[6].parse::<i32>().map_err(|_| invalid("end timestamp"))?,
+                record[3].to_string(),
+                record[8].parse::<i32>().map_err(|_| invalid("last updated"))?,
+            );
+            corpus.record_kind = match &record[4] {
+                "candlestick" => RecordKind::Candlestick,
+                "order_book" => RecordKind::OrderBook,
+                _ => return Err(invalid("record kind")),
+            };
+            corpus.record_count = record[7].parse::<u64>().map_err(|_| invalid("record count"))?;
+            corpus.partition = record.get(9).filter(|partition| !partition.is_empty()).map(|partition| partition.to_string());
+            corpus.version = match record.get(10).filter(|version| !version.is_empty()) {
+                Some(version) => version.parse::<u32>().map_err(|_| invalid("version"))?,
+                None => 0,
+            };
+            corpus.content_hash = record.get(11).unwrap_or("").to_string();
+            if let Some(resolution) = record.get(12).filter(|resolution| !resolution.is_empty()) {
+                corpus.resolution = Interval::from_string(resolution).map_err(|_| invalid("resolution"))?;
+            }
+
+            index.corpus_map.insert(corpus.filename.clone(), corpus);
+        }
 
-        let search_query = query.clone();
-        let mut corpus_map = HashMap::new();
+        Ok(index)
+    }
 
-        // get every file for every symbol and exchange combo
-        for (exchange, symbol) in search_query.symbols {
+    // save the catalog of the database to its csv files
+    // - every file is written to a temporary file and renamed so readers never see a partial catalog
+    // - the files are saved before the snapshots so a new snapshot is only visible once its files are
+    pub fn save(&self) -> Result<(), Error> {
+        let temporary = format!("{}.tmp", self.filename);
+        let mut writer = csv::Writer::from_path(&temporary).map_err(Error::from)?;
+        writer.write_record(["dataset_id", "exchange", "symbol", "filename", "record_kind", "start_timestamp", "end_timestamp", "record_count", "last_updated", "partition", "version", "content_hash", "resolution"])
+            .map_err(Error::from)?;
 
-            let symbol_name = format!("{}{}", symbol.target_currency, symbol.base_currency);
-            let data_name = format!("{}_{}", exchange.name, symbol_name);
-            // let data_filename = format!("{}.json", data_name);
+        // keep the file in a stable order so it can be diffed
+        let mut corpora = self.corpus_map.values().collect::<Vec<&Corpus>>();
+        corpora.sort_by(|a, b| a.filename.cmp(&b.filename));
+        for corpus in corpora {
+            let record_kind = match corpus.record_kind {
+                RecordKind::Candlestick => "candlestick",
+                RecordKind::OrderBook => "order_book",
+            };
 
-            let corpus = Corpus::new(0, exchange.name.clone(), symbol_name, 0, 0, data_name.clone(), 0);
-            
-            corpus_map.insert(data_name, corpus);
+            writer.write_record([
+                corpus.dataset_id.to_string(),
+                corpus.exchange.clone(),
+                corpus.symbol.clone(),
+                corpus.filename.clone(),
+                record_kind.to_string(),
+                corpus.start_timestamp.to_string(),
+                corpus.end_timestamp.to_string(),
+                corpus.record_count.to_string(),
+                corpus.last_updated.to_string(),
+                corpus.partition.clone().unwrap_or_default(),
+                corpus.version.to_string(),
+                corpus.content_hash.clone(),
+                corpus.resolution.name.clone(),
+            ]).map_err(Error::from)?;
         }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(temporary, &self.filename)?;
+
+        Snapshot::save(&self.snapshots_filename(), &self.snapshots)?;
+        Rollup::save(&self.rollups_filename(), &self.rollups)
+    }
+
+    // name of the file that stores the snapshots of the catalog
+    pub fn snapshots_filename(&self) -> String {
+        format!("{}/snapshots.csv", self.root_dir)
+    }
 
-        DatabaseIndex::new_from(filename, root_dir, corpus_map)
+    // name of the file that stores the rollups of the catalog
+    pub fn rollups_filename(&self) -> String {
+        format!("{}/rollups.csv", self.root_dir)
+    }
+
+    // get the rollup of an interval of a symbol
+    pub fn rollup(&self, exchange: &str, symbol: &str, interval: &str) -> Option<&Rollup> {
+        self.rollups.iter().find(|rollup| rollup.exchange == exchange && rollup.symbol == symbol && rollup.interval == interval)
+    }
+
+    // add or replace the rollup of an interval of a symbol
+    pub fn update_rollup(&mut self, rollup: Rollup) {
+        self.rollups.retain(|existing| !(existing.exchange == rollup.exchange && existing.symbol == rollup.symbol && existing.interval == rollup.interval));
+        self.rollups.push(rollup);
+    }
+
+    // add or replace the catalog entry of a file
+    pub fn update_corpus(&mut self, corpus: Corpus) {
+        self.corpus_map.insert(corpus.filename.clone(), corpus);
+    }
+
+    // check if the candlesticks of a symbol are stored in partitions
+    pub fn is_partitioned(&self, exchange: &str, symbol: &str) -> bool {
+        self.files(exchange, symbol, None).iter().any(|corpus| corpus.partition.is_some())
+    }
+
+    // get the candlestick partitions of a symbol that overlap a time range (inclusive) in timestamp order
+    // - a pinned snapshot id reads the partitions of the symbol as they were at that snapshot
+    pub fn partitions(&self, exchange: &str, symbol: &str, start_timestamp: i64, end_timestamp: i64, snapshot_id: Option<u32>) -> Vec<&Corpus> {
+        let mut partitions = self.files(exchange, symbol, snapshot_id).into_iter()
+            .filter(|corpus| corpus.partition.is_some())
+            .filter(|corpus| (corpus.start_timestamp as i64) <= end_timestamp && (corpus.end_timestamp as i64) >= start_timestamp)
+            .collect::<Vec<&Corpus>>();
+        partitions.sort_by_key(|corpus| corpus.start_timestamp);
+
+        partitions
+    }
+
+    // get the candlestick files of a symbol at a snapshot (the latest snapshot when none is pinned)
+    // - files written before the symbol had any snapshot (version 0) are used when there is no snapshot at or before the pinned id
+    pub fn files(&self, exchange: &str, symbol: &str, snapshot_id: Option<u32>) -> Vec<&Corpus> {
+        match self.snapshot(exchange, symbol, snapshot_id) {
+            Some(snapshot) => snapshot.files.iter().filter_map(|filename| self.corpus_map.get(filename)).collect(),
+            None => self.corpus_map.values()
+                .filter(|corpus| corpus.record_kind == RecordKind::Candlestick && corpus.version == 0)
+                .filter(|corpus| corpus.exchange == exchange && corpus.symbol == symbol)
+                .collect(),
+        }
+    }
+
+    // get the latest snapshot of a symbol at or before a snapshot id (the latest snapshot when none is pinned)
+    pub fn snapshot(&self, exchange: &str, symbol: &str, snapshot_id: Option<u32>) -> Option<&Snapshot> {
+        self.snapshots.iter()
+            .filter(|snapshot| snapshot.exchange == exchange && snapshot.symbol == symbol)
+            .filter(|snapshot| snapshot_id.is_none_or(|snapshot_id| snapshot.snapshot_id <= snapshot_id))
+            .max_by_key(|snapshot| snapshot.snapshot_id)
+    }
+
+    // get the next unused snapshot id (ids are shared by every symbol so an id pins the whole database)
+    pub fn next_snapshot_id(&self) -> u32 {
+        self.snapshots.iter().map(|snapshot| snapshot.snapshot_id).max().map_or(1, |id| id + 1)
+    }
+
+    // add a snapshot of a symbol made of files that are already in the catalog
+    // - the content hash combines the hashes of the files so identical data has the same hash
+    pub fn add_snapshot(&mut self, snapshot_id: u32, exchange: String, symbol: String, source_hash: String, files: Vec<String>) -> Snapshot {
+        let hashes = files.iter()
+            .map(|filename| self.corpus_map.get(filename).map_or("", |corpus| corpus.content_hash.as_str()))
+            .collect::<Vec<&str>>()
+            .join(";");
+
+        let snapshot = Snapshot {
+            snapshot_id,
+            exchange,
+            symbol,
+            created: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64),
+            content_hash: format!("{:08x}", crc32fast::hash(hashes.as_bytes())),
+            source_hash,
+            files,
+        };
+        self.snapshots.push(snapshot.clone());
+
+        snapshot
+    }
+
+    // get the dataset id of a symbol (a new id if the symbol is not in the catalog)
+    pub fn dataset_id(&self, exchange: &str, symbol: &str) -> Result<u8, Error> {
+        match self.corpus_map.values().find(|corpus| corpus.exchange == exchange && corpus.symbol == symbol) {
+            Some(corpus) => Ok(corpus.dataset_id),
+            None => self.next_dataset_id(),
+        }
+    }
+
+    // get the next unused dataset id (fails when every id is taken)
+    pub fn next_dataset_id(&self) -> Result<u8, Error> {
+        match self.corpus_map.values().map(|corpus| corpus.dataset_id).max() {
+            Some(id) => id.checked_add(1).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("index: no dataset id left in {} ({} is the last id)", self.filename, id))),
+            None => Ok(1),
+        }
     }
 }
 
@@ -59,7 +252,8 @@ impl DatabaseIndex {
 // stores a list of files and their corresponding bar information
 // provides a query way to convert a query into a list of files that need to be read
 
-// represents a single file within the corpus (could be more than one file for efficiency later)
+// represents a single file within the corpus
+// - a symbol is either stored in a single file or split into time partitions ([exchange]_[symbol]/[partition])
 #[derive(Debug, Clone)]
 pub struct Corpus {
     pub dataset_id: u8,
@@ -69,6 +263,19 @@ pub struct Corpus {
     pub start_timestamp: i32,
     pub end_timestamp: i32,
     pub filename: String,
+    pub record_kind: RecordKind,
+    pub record_count: u64,
+
+    // name of the time partition of the file (none when the symbol is stored in a single file)
+    pub partition: Option<String>,
+
+    // snapshot that wrote the file (0 when the file was written before snapshots) and the crc32 of its contents
+    // - files are never changed once they are in a snapshot, a change writes a new version of the file
+    pub version: u32,
+    pub content_hash: String,
+
+    // seconds between the bars of the file (1m unless the dataset declares another base resolution)
+    pub resolution: Interval,
 }
 impl Corpus {
     
@@ -82,6 +289,195 @@ impl Corpus {
             start_timestamp,
             end_timestamp,
             filename,
+            record_kind: RecordKind::Candlestick,
+            record_count: 0,
+            partition: None,
+            version: 0,
+            content_hash: String::new(),
+            resolution: Interval::from_seconds(60),
         }
     }
-}
\ No newline at end of file
+}
+// represents a version of the candlesticks of a symbol
+// - stored as a csv file next to the catalog ([path]/snapshots.csv)
+// - columns are snapshot_id, exchange, symbol, created, content_hash, source_hash and the catalog filenames of the files (separated by ;)
+// - the source hash is the crc32 of the files that were imported to create the snapshot
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub snapshot_id: u32,
+    pub exchange: String,
+    pub symbol: String,
+    pub created: i64,
+    pub content_hash: String,
+    pub source_hash: String,
+    pub files: Vec<String>,
+}
+
+impl Snapshot {
+    // load the snapshots of the database (none if there is no snapshot file)
+    pub fn from_file(filename: &str) -> Result<Vec<Self>, Error> {
+        let mut snapshots = Vec::new();
+        if !Path::new(filename).exists() {
+            return Ok(snapshots);
+        }
+
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if record.len() < 7 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid snapshot record in {}", filename)));
+            }
+
+            let invalid = |column: &str| Error::new(ErrorKind::InvalidData, format!("invalid {} in snapshots {}", column, filename));
+            snapshots.push(Self {
+                snapshot_id: record[0].parse::<u32>().map_err(|_| invalid("snapshot id"))?,
+                exchange: record[1].to_string(),
+                symbol: record[2].to_string(),
+                created: record[3].parse::<i64>().map_err(|_| invalid("created"))?,
+                content_hash: record[4].to_string(),
+                source_hash: record[5].to_string(),
+                files: record[6].split(';').filter(|filename| !filename.is_empty()).map(|filename| filename.to_string()).collect(),
+            });
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.snapshot_id);
+
+        Ok(snapshots)
+    }
+
+    // save the snapshots of the database through a temporary file
+    pub fn save(filename: &str, snapshots: &[Self]) -> Result<(), Error> {
+        let temporary = format!("{}.tmp", filename);
+        let mut writer = csv::Writer::from_path(&temporary).map_err(Error::from)?;
+        writer.write_record(["snapshot_id", "exchange", "symbol", "created", "content_hash", "source_hash", "files"]).map_err(Error::from)?;
+
+        for snapshot in snapshots.iter() {
+            writer.write_record([
+                snapshot.snapshot_id.to_string(),
+                snapshot.exchange.clone(),
+                snapshot.symbol.clone(),
+                snapshot.created.to_string(),
+                snapshot.content_hash.clone(),
+                snapshot.source_hash.clone(),
+                snapshot.files.join(";"),
+            ]).map_err(Error::from)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(temporary, filename)
+    }
+}
+
+// represents the bars of a symbol consolidated into a larger interval ahead of time
+// - stored as a csv file next to the catalog ([path]/rollups.csv)
+// - columns are exchange, symbol, interval, filename, snapshot_id, start_timestamp, end_timestamp, record_count and content_hash
+// - a rollup is built from the files of one snapshot of the symbol and only replaces the 1m bars of queries that read that snapshot
+// - bars are aligned to the regular sessions of the exchange (pre/post-market bars are not rolled up)
+#[derive(Debug, Clone)]
+pub struct Rollup {
+    pub exchange: String,
+    pub symbol: String,
+    pub interval: String,
+    pub filename: String,
+    pub snapshot_id: u32,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub record_count: u64,
+    pub content_hash: String,
+}
+
+impl Rollup {
+    // load the rollups of the database (none if there is no rollup file)
+    pub fn from_file(filename: &str) -> Result<Vec<Self>, Error> {
+        let mut rollups = Vec::new();
+        if !Path::new(filename).exists() {
+            return Ok(rollups);
+        }
+
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if record.len() < 9 {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid rollup record in {}", filename)));
+            }
+
+            let invalid = |column: &str| Error::new(ErrorKind::InvalidData, format!("invalid {} in rollups {}", column, filename));
+            rollups.push(Self {
+                exchange: record[0].to_string(),
+                symbol: record[1].to_string(),
+                interval: record[2].to_string(),
+                filename: record[3].to_string(),
+                snapshot_id: record[4].parse::<u32>().map_err(|_| invalid("snapshot id"))?,
+                start_timestamp: record[5].parse::<i64>().map_err(|_| invalid("start timestamp"))?,
+                end_timestamp: record[6].parse::<i64>().map_err(|_| invalid("end timestamp"))?,
+                record_count: record[7].parse::<u64>().map_err(|_| invalid("record count"))?,
+                content_hash: record[8].to_string(),
+            });
+        }
+
+        Ok(rollups)
+    }
+
+    // save the rollups of the database through a temporary file
+    pub fn save(filename: &str, rollups: &[Self]) -> Result<(), Error> {
+        let temporary = format!("{}.tmp", filename);
+        let mut writer = csv::Writer::from_path(&temporary).map_err(Error::from)?;
+        writer.write_record(["exchange", "symbol", "interval", "filename", "snapshot_id", "start_timestamp", "end_timestamp", "record_count", "content_hash"]).map_err(Error::from)?;
+
+        // keep the file in a stable order so it can be diffed
+        let mut rollups = rollups.iter().collect::<Vec<&Self>>();
+        rollups.sort_by(|a, b| a.filename.cmp(&b.filename));
+        for rollup in rollups {
+            writer.write_record([
+                rollup.exchange.clone(),
+                rollup.symbol.clone(),
+                rollup.interval.clone(),
+                rollup.filename.clone(),
+                rollup.snapshot_id.to_string(),
+                rollup.start_timestamp.to_string(),
+                rollup.end_timestamp.to_string(),
+                rollup.record_count.to_string(),
+                rollup.content_hash.clone(),
+            ]).map_err(Error::from)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(temporary, filename)
+    }
+}
+
+// get the crc32 of the contents of a file as a hex string
+pub fn hash_file(filename: &str) -> Result<String, Error> {
+    let mut file = File::open(filename)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let length = file.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        hasher.update(&buffer[..length]);
+    }
+
+    Ok(format!("{:08x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dataset_ids_are_not_reused_when_they_run_out() {
+        let mut index = DatabaseIndex::new("index.csv".to_string(), "data".to_string());
+        assert_eq!(index.next_dataset_id().unwrap(), 1);
+
+        index.update_corpus(Corpus::new(254, "TEST".to_string(), "A".to_string(), 0, 0, "TEST_A".to_string(), 0));
+        assert_eq!(index.dataset_id("TEST", "A").unwrap(), 254);
+        assert_eq!(index.dataset_id("TEST", "B").unwrap(), 255);
+
+        index.update_corpus(Corpus::new(255, "TEST".to_string(), "B".to_string(), 0, 0, "TEST_B".to_string(), 0));
+        assert_eq!(index.dataset_id("TEST", "B").unwrap(), 255);
+        assert_eq!(index.dataset_id("TEST", "C").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{Error, ErrorKind};
+
+
+// represents the unit of an interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntervalUnit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+// represents an interval that bars are consolidated into (or the base resolution of a dataset)
+// - [x]s - any number of seconds
+// - [x]m - any number of minutes
+// - [x]h - any number of hours
+// - [x]d - any number of days
+// - [x]w - any number of weeks
+// - [x]M - any number of months
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Interval {
+    pub name: String,
+    pub amount: u32,
+    pub unit: IntervalUnit,
+}
+
+impl Interval {
+    pub fn new(amount: u32, unit: IntervalUnit) -> Self {
+        let suffix = match unit {
+            IntervalUnit::Second => "s",
+            IntervalUnit::Minute => "m",
+            IntervalUnit::Hour => "h",
+            IntervalUnit::Day => "d",
+            IntervalUnit::Week => "w",
+            IntervalUnit::Month => "M",
+        };
+
+        Self {
+            name: format!("{}{}", amount, suffix),
+            amount,
+            unit,
+        }
+    }
+
+    // parse an interval from a string (ex: 5m, 4h, 1d)
+    pub fn from_string(value: &str) -> Result<Self, Error> {
+        let value = value.trim();
+        if value.len() < 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid interval: {}", value)));
+        }
+
+        let (amount, suffix) = value.split_at(value.len() - 1);
+        let amount = match amount.parse::<u32>() {
+            Ok(amount) if amount > 0 => amount,
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("invalid interval: {}", value)));
+            }
+        };
+
+        let unit = match suffix {
+            "s" => IntervalUnit::Second,
+            "m" => IntervalUnit::Minute,
+            "h" => IntervalUnit::Hour,
+            "d" => IntervalUnit::Day,
+            "w" => IntervalUnit::Week,
+            "M" => IntervalUnit::Month,
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("invalid interval: {}", value)));
+            }
+        };
+
+        Ok(Self::new(amount, unit))
+    }
+
+    // get the largest unit that a number of seconds can be written in (ex: 86400 is 1d, 90 is 90s)
+    pub fn from_seconds(seconds: i64) -> Self {
+        let seconds = seconds.max(1);
+        match seconds {
+            _ if seconds % 86400 == 0 => Self::new((seconds / 86400) as u32, IntervalUnit::Day),
+            _ if seconds % 3600 == 0 => Self::new((seconds / 3600) as u32, IntervalUnit::Hour),
+            _ if seconds % 60 == 0 => Self::new((seconds / 60) as u32, IntervalUnit::Minute),
+            _ => Self::new(seconds as u32, IntervalUnit::Second),
+        }
+    }
+
+    // number of seconds in an intraday interval (none for days, weeks and months)
+    pub fn seconds(&self) -> Option<i64> {
+        match self.unit {
+            IntervalUnit::Second => Some(self.amount as i64),
+            IntervalUnit::Minute => Some(self.amount as i64 * 60),
+            IntervalUnit::Hour => Some(self.amount as i64 * 3600),
+            _ => None,
+        }
+    }
+
+    // approximate length of the interval in seconds (used to order intervals, months are 31 days)
+    pub fn length(&self) -> i64 {
+        let amount = self.amount as i64;
+        match self.unit {
+            IntervalUnit::Second => amount,
+            IntervalUnit::Minute => amount * 60,
+            IntervalUnit::Hour => amount * 3600,
+            IntervalUnit::Day => amount * 86400,
+            IntervalUnit::Week => amount * 7 * 86400,
+            IntervalUnit::Month => amount * 31 * 86400,
+        }
+    }
+
+    // check if bars of this interval can be consolidated into bars of another interval
+    // - intraday periods start at the session open so an intraday interval must divide the other interval
+    // - days, weeks and months are made of whole sessions so they can be built from 1d bars or intraday bars that divide a day
+    pub fn divides(&self, other: &Interval) -> bool {
+        if self == other {
+            return true;
+        }
+
+        match (self.seconds(), other.seconds()) {
+            (Some(seconds), Some(other_seconds)) => other_seconds % seconds == 0,
+            (Some(seconds), None) => 86400 % seconds == 0,
+            (None, None) => self.unit == IntervalUnit::Day && self.amount == 1,
+            (None, Some(_)) => false,
+        }
+    }
+}
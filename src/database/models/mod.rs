@@ -8,5 +8,14 @@ pub mod candlestick;
 pub mod bar;
 pub mod barset;
 pub mod bar_map;
+pub mod symbol_table;
+pub mod history;
 pub mod query;
-pub mod query_result;
\ No newline at end of file
+pub mod query_result;
+pub mod coverage;
+pub mod order_book;
+pub mod corporate_action;
+pub mod interval;
+pub mod calendar;
+pub mod contract;
+pub mod partition;
@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, BufReader, BufWriter, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+
+// represents a single price level of an order book
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderBookLevel {
+    pub price: f64,
+    pub size: f64,
+}
+
+impl OrderBookLevel {
+    pub fn new(price: f64, size: f64) -> Self {
+        Self {
+            price,
+            size,
+        }
+    }
+}
+
+// represents a snapshot of the top levels of an order book (L2) for a single symbol
+// - bids are ordered from the best (highest) price down
+// - asks are ordered from the best (lowest) price up
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBook {
+    pub timestamp: i64,
+    pub bids: Vec<OrderBookLevel>,
+    pub asks: Vec<OrderBookLevel>,
+}
+
+impl OrderBook {
+    pub fn new_with(timestamp: i64, bids: Vec<OrderBookLevel>, asks: Vec<OrderBookLevel>) -> Self {
+        Self {
+            timestamp,
+            bids,
+            asks,
+        }
+    }
+
+    // read the snapshots of a csv file and the number of levels of its columns
+    // - every row is a timestamp followed by the bid price, bid size, ask price and ask size of every level (best level first)
+    // - empty cells and sizes of zero are empty levels, rows have to be in timestamp order
+    pub fn from_csv(filename: &str) -> Result<(Vec<Self>, u8), Error> {
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let columns = reader.headers().map_err(|e| Error::new(ErrorKind::InvalidData, e))?.len();
+        let levels = match columns.checked_sub(1) {
+            Some(values) if values > 0 && values % 4 == 0 && values / 4 <= u8::MAX as usize => (values / 4) as u8,
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("{} needs a timestamp column and 4 columns per level (bid price, bid size, ask price, ask size)", filename))),
+        };
+
+        let mut order_books: Vec<Self> = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            let value = |index: usize| -> Result<f64, Error> {
+                match record.get(index).unwrap_or("").trim() {
+                    "" => Ok(0.0),
+                    value => value.parse::<f64>().map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid value {} in {}", value, filename))),
+                }
+            };
+
+            let timestamp = record.get(0).unwrap_or("").trim().parse::<i64>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("invalid order book timestamp in {}", filename)))?;
+            if order_books.last().is_some_and(|last| last.timestamp >= timestamp) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("order book snapshot at {} is out of timestamp order in {}", timestamp, filename)));
+            }
+
+            let mut bids = Vec::new();
+            let mut asks = Vec::new();
+            for level in 0..levels as usize {
+                let bid = OrderBookLevel::new(value(1 + level * 4)?, value(2 + level * 4)?);
+                let ask = OrderBookLevel::new(value(3 + level * 4)?, value(4 + level * 4)?);
+                if bid.size > 0.0 {
+                    bids.push(bid);
+                }
+                if ask.size > 0.0 {
+                    asks.push(ask);
+                }
+            }
+            order_books.push(Self::new_with(timestamp, bids, asks));
+        }
+
+        Ok((order_books, levels))
+    }
+
+    // read a single snapshot
+    // - level count (1 byte), timestamp (8 bytes), bid price, bid size, ask price, ask size (32 bytes per level)
+    // - empty levels are stored with a size of zero and are dropped when read
+    pub fn from_reader(reader: &mut BufReader<File>) -> Result<Self, Error> {
+        // detect the end of a file
+        let level_count = match reader.read_u8() {
+            Ok(level_count) => level_count,
+            Err(_) => {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "unexpected end of file"));
+            }
+        };
+
+        let timestamp = reader.read_i64::<BigEndian>()?;
+
+        let mut bids = Vec::with_capacity(level_count as usize);
+        let mut asks = Vec::with_capacity(level_count as usize);
+        for _ in 0..level_count {
+            let bid = OrderBookLevel::new(reader.read_f64::<BigEndian>()?, reader.read_f64::<BigEndian>()?);
+            let ask = OrderBookLevel::new(reader.read_f64::<BigEndian>()?, reader.read_f64::<BigEndian>()?);
+
+            if bid.size > 0.0 {
+                bids.push(bid);
+            }
+            if ask.size > 0.0 {
+                asks.push(ask);
+            }
+        }
+
+        Ok(Self::new_with(timestamp, bids, asks))
+    }
+
+    // write a single snapshot with a fixed number of levels
+    pub fn write(&self, writer: &mut BufWriter<File>, levels: u8) -> Result<(), Error> {
+        let mut buffer = vec![];
+
+        buffer.write_u8(levels)?;
+        buffer.write_i64::<BigEndian>(self.timestamp)?;
+
+        let empty = OrderBookLevel::new(0.0, 0.0);
+        for i in 0..levels as usize {
+            let bid = self.bids.get(i).unwrap_or(&empty);
+            let ask = self.asks.get(i).unwrap_or(&empty);
+
+            buffer.write_f64::<BigEndian>(bid.price)?;
+            buffer.write_f64::<BigEndian>(bid.size)?;
+            buffer.write_f64::<BigEndian>(ask.price)?;
+            buffer.write_f64::<BigEndian>(ask.size)?;
+        }
+
+        writer.write_all(&buffer)?;
+
+        Ok(())
+    }
+
+    // only keep the top levels of the book
+    pub fn truncate(&mut self, levels: usize) {
+        self.bids.truncate(levels);
+        self.asks.truncate(levels);
+    }
+
+    // get the best bid price
+    pub fn best_bid(&self) -> Option<f64> {
+        self.bids.first().map(|level| level.price)
+    }
+
+    // get the best ask price
+    pub fn best_ask(&self) -> Option<f64> {
+        self.asks.first().map(|level| level.price)
+    }
+
+    // get the price between the best bid and best ask
+    pub fn mid_price(&self) -> Option<f64> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+            _ => None,
+        }
+    }
+}
+
+impl Hash for OrderBook {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.timestamp.hash(state);
+        for level in self.bids.iter().chain(self.asks.iter()) {
+            level.price.to_bits().hash(state);
+            level.size.to_bits().hash(state);
+        }
+    }
+}
+
+impl Eq for OrderBook {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_file(name: &str, contents: &str) -> String {
+        let filename = std::env::temp_dir().join(format!("order_book_{}_{}.csv", name, std::process::id())).to_string_lossy().to_string();
+        std::fs::write(&filename, contents).unwrap();
+        filename
+    }
+
+    #[test]
+    fn snapshots_are_read_from_csv() {
+        let filename = csv_file("levels", "timestamp,bid,bid_size,ask,ask_size,bid,bid_size,ask,ask_size\n0,99,5,101,3,98,7,,\n60,99.5,0,100.5,2,,,,\n");
+        let (order_books, levels) = OrderBook::from_csv(&filename).unwrap();
+        assert_eq!(levels, 2);
+        assert_eq!(order_books[0].bids, vec![OrderBookLevel::new(99.0, 5.0), OrderBookLevel::new(98.0, 7.0)]);
+        assert_eq!(order_books[0].asks, vec![OrderBookLevel::new(101.0, 3.0)]);
+        assert_eq!(order_books[0].mid_price(), Some(100.0));
+        assert_eq!((order_books[1].best_bid(), order_books[1].best_ask(), order_books[1].mid_price()), (None, Some(100.5), None));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn invalid_csv_files_fail() {
+        for (name, contents) in [("columns", "timestamp,bid,bid_size,ask\n0,99,5,101\n"), ("order", "timestamp,bid,bid_size,ask,ask_size\n60,99,5,101,3\n0,99,5,101,3\n")] {
+            let filename = csv_file(name, contents);
+            assert_eq!(OrderBook::from_csv(&filename).unwrap_err().kind(), ErrorKind::InvalidData);
+            std::fs::remove_file(filename).unwrap();
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use super::interval::{Interval, IntervalUnit};
+
+
+// represents a time range of a dataset that is stored in its own file
+// - partitions are aligned to UTC periods of the partition interval (1M is a file per month)
+// - the name is the start of the period and is used as the filename of the partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub name: String,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+}
+
+impl Partition {
+    // get the partition that a timestamp belongs to (the end is exclusive)
+    pub fn from_timestamp(timestamp: i64, interval: &Interval) -> Self {
+        let amount = interval.amount as i64;
+
+        let (start, end, format) = match interval.unit {
+            IntervalUnit::Second => {
+                let start = timestamp - timestamp.rem_euclid(amount);
+                (start, start + amount, "%Y-%m-%dT%H%M%S")
+            },
+            IntervalUnit::Minute | IntervalUnit::Hour => {
+                let seconds = interval.seconds().unwrap();
+                let start = timestamp - timestamp.rem_euclid(seconds);
+                (start, start + seconds, "%Y-%m-%dT%H%M")
+            },
+            IntervalUnit::Day => {
+                let seconds = amount * 86400;
+                let start = timestamp - timestamp.rem_euclid(seconds);
+                (start, start + seconds, "%Y-%m-%d")
+            },
+            IntervalUnit::Week => {
+                // unix time started on a thursday so weeks are shifted to start on mondays
+                let seconds = amount * 7 * 86400;
+                let monday = 4 * 86400;
+                let start = timestamp - (timestamp - monday).rem_euclid(seconds);
+                (start, start + seconds, "%Y-%m-%d")
+            },
+            IntervalUnit::Month => {
+                let date = Utc.timestamp_opt(timestamp, 0).unwrap().date_naive();
+                let month = date.year() as i64 * 12 + date.month0() as i64;
+                let first_month = month - month.rem_euclid(amount);
+                (month_timestamp(first_month), month_timestamp(first_month + amount), "%Y-%m")
+            },
+        };
+
+        Self {
+            name: Utc.timestamp_opt(start, 0).unwrap().format(format).to_string(),
+            start_timestamp: start,
+            end_timestamp: end,
+        }
+    }
+}
+
+// get the timestamp of the first day of a month counted from year 0
+fn month_timestamp(month: i64) -> i64 {
+    let date = NaiveDate::from_ymd_opt((month / 12) as i32, (month % 12) as u32 + 1, 1).unwrap();
+    Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).timestamp()
+}
+
+// the default partition interval of new datasets
+pub fn default_partition_interval() -> Interval {
+    Interval::new(1, IntervalUnit::Month)
+}
+
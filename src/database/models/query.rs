@@ -1,7 +1,10 @@
 use std::{io::{Error, ErrorKind}, collections::HashMap};
 use crate::database::database::Database;
-use super::{exchange::Exchange, symbol::Symbol, query_result::QueryResult};
+use crate::database::storage::VerifyPolicy;
+use super::{exchange::Exchange, symbol::Symbol, query_result::QueryResult, history::History, corporate_action::PriceAdjustment, coverage::{AlignmentPolicy, Coverage}, symbol_table::SymbolId};
 
+// the most intervals a query consolidates (more would slow down every chunk of the query)
+pub const MAX_INTERVALS: usize = 5;
 
 // represents a query to the database
 #[derive(Clone)]
@@ -11,6 +14,16 @@ pub struct Query {
     pub database: Database,
     pub symbols: Vec<(Exchange, Symbol)>,
     pub intervals: Vec<String>,
+    pub fields: Vec<String>,
+    pub order_book_levels: Option<u8>,
+    pub adjustment: PriceAdjustment,
+    pub extended_hours: bool,
+    pub verify: VerifyPolicy,
+    pub snapshot_id: Option<u32>,
+    pub alignment: AlignmentPolicy,
+    pub coverage: Vec<Coverage>,
+    pub rollups: bool,
+    pub resolutions: HashMap<SymbolId, i64>,
     pub start_timestamp: Option<i64>,
     pub end_timestamp: Option<i64>,
     pub limit: i32
@@ -25,6 +38,16 @@ impl Query {
             database,
             symbols: Vec::new(),
             intervals: Vec::new(),
+            fields: Vec::new(),
+            order_book_levels: None,
+            adjustment: PriceAdjustment::Raw,
+            extended_hours: false,
+            verify: VerifyPolicy::Fail,
+            snapshot_id: None,
+            alignment: AlignmentPolicy::Union,
+            coverage: Vec::new(),
+            rollups: false,
+            resolutions: HashMap::new(),
             start_timestamp: None,
             end_timestamp: None,
             limit: 1000
@@ -39,6 +62,16 @@ impl Query {
             database,
             symbols: Vec::new(),
             intervals: Vec::new(),
+            fields: Vec::new(),
+            order_book_levels: None,
+            adjustment: PriceAdjustment::Raw,
+            extended_hours: false,
+            verify: VerifyPolicy::Fail,
+            snapshot_id: None,
+            alignment: AlignmentPolicy::Union,
+            coverage: Vec::new(),
+            rollups: false,
+            resolutions: HashMap::new(),
             start_timestamp: None,
             end_timestamp: None,
             limit: 1000
@@ -46,6 +79,7 @@ impl Query {
     }
 
     // sets the limit of the query (default is 1000, max is 10000)
+    #[allow(dead_code)]
     pub fn with_limit(mut self, limit: i32) -> Self {
 
         // limit to 10000 for performance
@@ -85,7 +119,7 @@ impl Query {
     pub fn with_intervals(mut self, intervals: Vec<String>) -> Self {
 
         // limit to 5 intervals for performance
-        if intervals.len() > 5 {
+        if intervals.len() > MAX_INTERVALS {
             panic!("Limit to 5 intervals for performance");
         }
 
@@ -93,8 +127,62 @@ impl Query {
         self
     }
 
+    // sets the extra fields of the query by name (defaults to every field declared in the datasets)
+    // - the query will fail if a dataset does not declare one of the fields
+    pub fn with_fields(mut self, fields: Vec<String>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    // adds the order book snapshot (top levels) as of each bar to the results
+    // - the query will fail if there are no order book snapshots for a symbol
+    pub fn with_order_book(mut self, levels: u8) -> Self {
+        self.order_book_levels = Some(levels);
+        self
+    }
+
+    // sets how prices are adjusted for corporate actions (defaults to raw prices)
+    pub fn with_adjustment(mut self, adjustment: PriceAdjustment) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    // includes pre/post-market bars when consolidating intervals (defaults to regular sessions only)
+    pub fn with_extended_hours(mut self, extended_hours: bool) -> Self {
+        self.extended_hours = extended_hours;
+        self
+    }
+
+    // sets what happens when a block of records does not match its checksum (defaults to failing the query)
+    pub fn with_verify(mut self, verify: VerifyPolicy) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    // reads the datasets as they were at a snapshot of the catalog (defaults to the latest data)
+    // - the same snapshot id always returns the same bars so backtests can be reproduced after the data is changed
+    pub fn with_snapshot(mut self, snapshot_id: u32) -> Self {
+        self.snapshot_id = Some(snapshot_id);
+        self
+    }
+
+    // sets how the bars of symbols with different date ranges are aligned (defaults to the union of the ranges)
+    // - the coverage of every symbol is set when the query is started
+    pub fn with_alignment(mut self, alignment: AlignmentPolicy) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    // reads the coarsest rollup that can be consolidated into every interval instead of the base bars (defaults to the base bars)
+    // - the base bars of the results are then the bars of the rollup, the resolution of every symbol is set when the query is started
+    pub fn with_rollups(mut self, rollups: bool) -> Self {
+        self.rollups = rollups;
+        self
+    }
+
     // starts the query with the database instance
-    pub fn start(mut self) -> Self {
+    #[allow(dead_code)]
+    pub fn start(self) -> Self {
 
         // get results from the database instance
         let query = self.clone();
@@ -104,10 +192,20 @@ impl Query {
 
         Self {
             id: Some(results.id), // set the query id
-            client_id: client_id,
-            database: database,
+            client_id,
+            database,
             symbols: self.symbols,
             intervals: self.intervals,
+            fields: self.fields,
+            order_book_levels: self.order_book_levels,
+            adjustment: self.adjustment,
+            extended_hours: self.extended_hours,
+            verify: self.verify,
+            snapshot_id: self.snapshot_id,
+            alignment: self.alignment,
+            coverage: results.coverage, // set the coverage of the symbols
+            rollups: self.rollups,
+            resolutions: results.resolutions, // set the base resolution of the symbols
             start_timestamp: self.start_timestamp,
             end_timestamp: self.end_timestamp,
             limit: self.limit
@@ -119,7 +217,7 @@ impl Query {
 
         // a query id is required to get the next results
         if self.id.is_none() {
-            return Err(Error::new(ErrorKind::Other, "Query id is required to get next results"));
+            return Err(Error::new(ErrorKind::InvalidInput, "Query id is required to get next results"));
         }
         let query_id = self.id.clone().unwrap();
 
@@ -132,4 +230,21 @@ impl Query {
 
         Ok(results.unwrap())
     }
-}
\ No newline at end of file
+
+    // stop the query that was started and disconnect its client from the database
+    pub fn stop(&mut self) -> Result<(), Error> {
+        if let Some(client_id) = self.client_id {
+            self.database.disconnect_client(client_id);
+        }
+        match self.id.take() {
+            Some(query_id) => self.database.stop_query(query_id),
+            None => Ok(()),
+        }
+    }
+
+    // get the last bars of every symbol and interval that end at or before a timestamp
+    // - used to seed indicators before the first bar of the query (the query does not have to be started)
+    pub fn history(&self, count: usize, end_timestamp: i64) -> Result<Vec<History>, Error> {
+        self.database.history(self.clone(), count, end_timestamp)
+    }
+}
@@ -1,14 +1,21 @@
-use super::{barset::BarSet, bar::Bar};
+use std::collections::HashMap;
+use super::{bar::Bar, coverage::Coverage, symbol_table::{SymbolTable, SymbolId}};
 
 
 // represents a query result from the database
 // stores an id to use for the next query
 // stores a vector of bar sets of many different symbols and exchanges
+// stores the symbol table used to resolve the symbol ids of the bars into names
+// stores the coverage of every symbol (only in the first result of a query)
+// stores the seconds between the base bars of every symbol (only in the first result of a query)
 #[derive(Clone, Debug)]
 pub struct QueryResult {
     pub id: String,
     pub status: String,
     pub bars: Vec<Bar>,
+    pub symbols: SymbolTable,
+    pub coverage: Vec<Coverage>,
+    pub resolutions: HashMap<SymbolId, i64>,
 }
 
 impl QueryResult {
@@ -17,6 +24,9 @@ impl QueryResult {
             id,
             status,
             bars: vec![],
+            symbols: SymbolTable::new(),
+            coverage: Vec::new(),
+            resolutions: HashMap::new(),
         }
     }
 
@@ -25,6 +35,27 @@ impl QueryResult {
             id,
             status,
             bars,
+            symbols: SymbolTable::new(),
+            coverage: Vec::new(),
+            resolutions: HashMap::new(),
         }
     }
-}
\ No newline at end of file
+
+    // set the symbol table of the bars
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    // set the coverage of the symbols of the query
+    pub fn with_coverage(mut self, coverage: Vec<Coverage>) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    // set the seconds between the base bars of the symbols of the query
+    pub fn with_resolutions(mut self, resolutions: HashMap<SymbolId, i64>) -> Self {
+        self.resolutions = resolutions;
+        self
+    }
+}
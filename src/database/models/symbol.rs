@@ -1,10 +1,16 @@
+use std::hash::{Hash, Hasher};
+use super::contract::ContractSpec;
+
 
 // represents a ticker symbol
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct Symbol {
     pub name: String,
     pub target_currency: String,
     pub base_currency: String,
+
+    // contract specification from the symbol registry of the exchange (not part of the identity of the symbol)
+    pub spec: Option<ContractSpec>,
 }
 
 impl Symbol {
@@ -13,22 +19,51 @@ impl Symbol {
             name: "".to_string(),
             target_currency: "".to_string(),
             base_currency: "".to_string(),
+            spec: None,
         }
     }
 
+    #[allow(dead_code)]
     pub fn new_with(target_currency: String, base_currency: String) -> Self {
         Self {
             name: format!("{}{}", target_currency, base_currency),
             target_currency,
             base_currency,
+            spec: None,
         }
     }
 
+    // attach a contract specification to the symbol
+    pub fn with_spec(mut self, spec: ContractSpec) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    #[allow(dead_code)]
     pub fn set_target_currency(&mut self, target_currency: String) {
         self.target_currency = target_currency;
     }
 
+    #[allow(dead_code)]
     pub fn set_base_currency(&mut self, base_currency: String) {
         self.base_currency = base_currency;
     }
-}
\ No newline at end of file
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.target_currency.hash(state);
+        self.base_currency.hash(state);
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.target_currency == other.target_currency
+            && self.base_currency == other.base_currency
+    }
+}
+
+impl Eq for Symbol {}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+
+// small integer id of an exchange and symbol pair
+pub type SymbolId = u16;
+
+// interns exchange and symbol pairs so bars refer to symbols by id instead of by name
+// - ids are given out in the order the symbols are first seen and never change for the life of the engine
+// - names are only resolved at the edge of the database (query results)
+// - ids are looked up by exchange then symbol so a lookup borrows the names instead of allocating a key
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    names: Vec<(String, String)>,
+    ids: HashMap<String, HashMap<String, SymbolId>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            names: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    // get the id of an exchange and symbol (a new id the first time the pair is seen)
+    // - fails when every id is taken
+    pub fn intern(&mut self, exchange: &str, symbol: &str) -> Result<SymbolId, Error> {
+        if let Some(id) = self.id(exchange, symbol) {
+            return Ok(id);
+        }
+
+        let id = SymbolId::try_from(self.names.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("symbol table: no id left for {}:{} ({} symbols)", exchange, symbol, self.names.len())))?;
+        self.names.push((exchange.to_string(), symbol.to_string()));
+        self.ids.entry(exchange.to_string()).or_default().insert(symbol.to_string(), id);
+
+        Ok(id)
+    }
+
+    // get the id of an exchange and symbol that was already interned
+    pub fn id(&self, exchange: &str, symbol: &str) -> Option<SymbolId> {
+        self.ids.get(exchange)?.get(symbol).copied()
+    }
+
+    // get the exchange and symbol of an id
+    pub fn name(&self, id: SymbolId) -> Option<(&str, &str)> {
+        self.names.get(id as usize).map(|(exchange, symbol)| (exchange.as_str(), symbol.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_keep_the_id_they_were_first_given() {
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.intern("NYSE", "SPY").unwrap(), 0);
+        assert_eq!(symbols.intern("NYSE", "QQQ").unwrap(), 1);
+        assert_eq!(symbols.intern("NYSE", "SPY").unwrap(), 0);
+        assert_eq!(symbols.id("NYSE", "QQQ"), Some(1));
+        assert_eq!(symbols.id("BINANCE", "SPY"), None);
+        assert_eq!(symbols.name(1), Some(("NYSE", "QQQ")));
+    }
+
+    #[test]
+    fn interning_fails_when_every_id_is_taken() {
+        let mut symbols = SymbolTable::new();
+        for i in 0..=SymbolId::MAX as usize {
+            symbols.intern("TEST", &i.to_string()).unwrap();
+        }
+
+        assert!(symbols.intern("TEST", "0").is_ok());
+        assert_eq!(symbols.intern("TEST", "NEW").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(symbols.id("TEST", "NEW"), None);
+    }
+}
@@ -1,72 +1,434 @@
-use std::{fs::File, io::BufReader};
+use std::{fs::{File, OpenOptions}, io::{BufReader, BufWriter, Cursor, Read, Write, Seek, SeekFrom}};
 use std::io::{Error, ErrorKind};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::models::candlestick::Candlestick;
 use super::models::chunk::Chunk;
-use super::models::header::Header;
+use super::models::header::{Header, RecordKind};
+use super::models::order_book::OrderBook;
 
 
+// represents what happens when the checksum of a block does not match its records
+// - fail: the read returns an error
+// - skip block: the records of the block are left out
+// - report: the mismatch is logged and the records are read anyway
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyPolicy {
+    Fail,
+    SkipBlock,
+    Report,
+}
+
+impl VerifyPolicy {
+    // convert a string into a verify policy
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "fail" => Some(VerifyPolicy::Fail),
+            "skip" | "skip_block" => Some(VerifyPolicy::SkipBlock),
+            "report" => Some(VerifyPolicy::Report),
+            _ => None,
+        }
+    }
+}
+
 // will read a chunk of data from a file using our .stmdb format
 pub struct Reader {
+    filename: String,
     reader_buffer: BufReader<File>,
-    has_header: bool,
+    header: Option<Header>,
+    verify: VerifyPolicy,
 }
 
 impl Reader {
-    // create a new reader
-    pub fn new(filename: String) -> Self {
+    // create a new reader (fails when the file cannot be opened)
+    pub fn new(filename: String) -> Result<Self, Error> {
         // open the file
-        let file = match File::open(filename) {
+        let file = match File::open(filename.clone()) {
             Ok(file) => file,
             Err(e) => {
-                panic!("failed to open file: {:?}", e);
+                return Err(Error::new(e.kind(), format!("failed to open file {}: {}", filename, e)));
             }
         };
 
         // create a new reader
-        let mut reader = BufReader::new(file);
+        let reader = BufReader::new(file);
 
-        Self {
-            has_header: false,
+        Ok(Self {
+            filename,
+            header: None,
             reader_buffer: reader,
-        }
+            verify: VerifyPolicy::Fail,
+        })
+    }
+
+    // set what happens when the checksum of a block does not match
+    pub fn with_verify(mut self, verify: VerifyPolicy) -> Self {
+        self.verify = verify;
+        self
     }
 
     // read the header of a stmdb file
-    pub fn read_header(&mut self) -> Result<Header, Error> {
+    pub fn read_header(&mut self) -> Result<&Header, Error> {
         // get a reference to the reader
-        let mut reader = &mut self.reader_buffer;
+        let reader = &mut self.reader_buffer;
 
         // seek to the beginning of the file
-        match reader.seek_relative(0) {
+        match reader.seek(SeekFrom::Start(0)) {
             Ok(_) => {},
             Err(e) => {
-                return Err(Error::new(ErrorKind::Other, format!("failed to seek to beginning of file: {}", e)));
+                return Err(Error::new(e.kind(), format!("failed to seek to beginning of file: {}", e)));
             }
         }
 
         // read the header
-        let header = Header::from_reader(&mut reader).unwrap();
+        let header = Header::from_reader(reader)?;
 
-        // set the has_header flag
-        self.has_header = true;
+        // keep the header for reading the records
+        self.header = Some(header);
 
         // return the header
-        Ok(header)
+        Ok(self.header.as_ref().unwrap())
     }
-    
+
+    // get the header of the file (reads it if it has not been read yet)
+    pub fn header(&mut self) -> Result<&Header, Error> {
+        if self.header.is_none() {
+            return self.read_header();
+        }
+
+        Ok(self.header.as_ref().unwrap())
+    }
+
     // read a chunk of data from a stmdb file
     pub fn read_chunk(&mut self, limit: i32, offset: i32) -> Result<Chunk, Error> {
-        // if header is not read, read it
-        if !self.has_header {
-            let header = self.read_header().unwrap();   
+        let count = self.record_count()?;
+        let first = (offset.max(0) as u64).min(count);
+        let last = (first + limit.max(0) as u64).min(count);
+
+        Ok(Chunk::new_with(self.read_records(first, last)?))
+    }
+
+    // number of records stored in the file
+    pub fn record_count(&mut self) -> Result<u64, Error> {
+        let length = self.reader_buffer.get_ref().metadata()?.len();
+
+        Ok(self.header()?.record_count(length))
+    }
+
+    // move the cursor to the start of a record
+    pub fn seek_record(&mut self, index: u64) -> Result<(), Error> {
+        let position = self.header()?.record_position(index);
+        self.reader_buffer.seek(SeekFrom::Start(position))?;
+
+        Ok(())
+    }
+
+    // read the candlestick records from one index up to (not including) another
+    // - files with checksums are read a block at a time and every block is verified
+    pub fn read_records(&mut self, first: u64, last: u64) -> Result<Vec<Candlestick>, Error> {
+        let header = self.header()?.clone();
+        if header.record_kind != RecordKind::Candlestick {
+            return Err(Error::new(ErrorKind::InvalidData, "file does not contain candlesticks"));
         }
 
-        // get a reference to the reader
-        let reader = &mut self.reader_buffer;
+        let mut candlesticks = Vec::with_capacity(last.saturating_sub(first) as usize);
+        if first >= last {
+            return Ok(candlesticks);
+        }
+
+        // files without checksums are read record by record
+        if header.block_size == 0 {
+            self.seek_record(first)?;
+            for _ in first..last {
+                candlesticks.push(Candlestick::from_reader(&mut self.reader_buffer, &header.fields)?);
+            }
+
+            return Ok(candlesticks);
+        }
+
+        let count = self.record_count()?;
+        let block_size = header.block_size as u64;
+        let record_size = header.record_size();
+        for block in (first / block_size)..=((last - 1) / block_size) {
+            // read the records and the checksum of the whole block
+            let block_first = block * block_size;
+            let block_last = (block_first + block_size).min(count);
+            let mut bytes = vec![0; (block_last - block_first) as usize * record_size];
+            self.seek_record(block_first)?;
+            self.reader_buffer.read_exact(&mut bytes)?;
+            let checksum = self.reader_buffer.read_u32::<BigEndian>()?;
+
+            if crc32fast::hash(&bytes) != checksum {
+                match self.verify {
+                    VerifyPolicy::Fail => {
+                        return Err(Error::new(ErrorKind::InvalidData, format!("checksum mismatch in block {} of file {}", block, self.filename)));
+                    },
+                    VerifyPolicy::SkipBlock => {
+                        println!("storage: skipping block {} of file {} (checksum mismatch)", block, self.filename);
+                        continue;
+                    },
+                    VerifyPolicy::Report => {
+                        println!("storage: checksum mismatch in block {} of file {}", block, self.filename);
+                    }
+                }
+            }
+
+            // read the requested records of the block
+            let start = first.max(block_first);
+            let end = last.min(block_last);
+            let mut cursor = Cursor::new(&bytes[((start - block_first) as usize * record_size)..((end - block_first) as usize * record_size)]);
+            for _ in start..end {
+                match Candlestick::from_reader(&mut cursor, &header.fields) {
+                    Ok(candlestick) => candlesticks.push(candlestick),
+                    Err(e) if self.verify == VerifyPolicy::Report => {
+                        println!("storage: unreadable record in block {} of file {}: {}", block, self.filename, e);
+                        break;
+                    },
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(candlesticks)
+    }
+
+    // read the timestamp of a record without reading the whole record
+    pub fn read_timestamp_at(&mut self, index: u64) -> Result<i64, Error> {
+        // candlesticks start with the field count, length and type bytes
+        // order books start with the level count byte
+        let timestamp_offset = match self.header()?.record_kind {
+            RecordKind::Candlestick => 3,
+            RecordKind::OrderBook => 1,
+        };
+
+        self.seek_record(index)?;
+        self.reader_buffer.seek_relative(timestamp_offset)?;
+
+        self.reader_buffer.read_i64::<BigEndian>()
+    }
+
+    // find the index of the first record with a timestamp at or after the given timestamp
+    // - records are stored in timestamp order so we can binary search the file
+    pub fn find_timestamp(&mut self, timestamp: i64) -> Result<u64, Error> {
+        let mut low = 0;
+        let mut high = self.record_count()?;
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.read_timestamp_at(middle)? < timestamp {
+                low = middle + 1;
+            }
+            else {
+                high = middle;
+            }
+        }
+
+        Ok(low)
+    }
+
+    // read up to a number of candlesticks with a timestamp at or before a timestamp (oldest first)
+    // - the end is found with the timestamp index and the records are read backwards from it
+    pub fn read_before(&mut self, end_timestamp: i64, count: u64) -> Result<Vec<Candlestick>, Error> {
+        let last = self.find_timestamp(end_timestamp.saturating_add(1))?;
 
-        // read a chunk from 
-        let chunk = Chunk::from_reader(reader, limit, offset).unwrap();
+        self.read_records(last.saturating_sub(count), last)
+    }
 
-        // return the chunk of data
-        Ok(chunk)
+    // read a single candlestick record by index
+    pub fn read_candlestick_at(&mut self, index: u64) -> Result<Candlestick, Error> {
+        match self.read_records(index, index + 1)?.pop() {
+            Some(candlestick) => Ok(candlestick),
+            None => Err(Error::new(ErrorKind::InvalidData, format!("record {} of file {} could not be read", index, self.filename))),
+        }
     }
-}
\ No newline at end of file
+
+    // read every candlestick record in the file
+    pub fn read_candlesticks(&mut self) -> Result<Vec<Candlestick>, Error> {
+        let count = self.record_count()?;
+
+        self.read_records(0, count)
+    }
+
+    // read the candlesticks with a timestamp at or after the start and before the end
+    pub fn read_range(&mut self, start_timestamp: i64, end_timestamp: i64) -> Result<Vec<Candlestick>, Error> {
+        let first = self.find_timestamp(start_timestamp)?;
+        let last = self.find_timestamp(end_timestamp)?;
+
+        self.read_records(first, last)
+    }
+
+    // read the next order book snapshot from the cursor position
+    pub fn read_order_book(&mut self) -> Result<OrderBook, Error> {
+        if self.header()?.record_kind != RecordKind::OrderBook {
+            return Err(Error::new(ErrorKind::InvalidData, "file does not contain order book snapshots"));
+        }
+
+        OrderBook::from_reader(&mut self.reader_buffer)
+    }
+}
+
+// will write records to a file using our .stmdb format
+// - records of files with checksums are buffered until a block is full
+// - the last block can be partial and is written when the writer is flushed (no records can be written after it)
+pub struct Writer {
+    writer_buffer: BufWriter<File>,
+    header: Option<Header>,
+    block: Vec<u8>,
+    block_records: u16,
+    closed: bool,
+}
+
+impl Writer {
+    // create a new writer (truncates any existing file)
+    pub fn new(filename: String) -> Result<Self, Error> {
+        let file = File::create(filename)?;
+
+        Ok(Self {
+            writer_buffer: BufWriter::new(file),
+            header: None,
+            block: Vec::new(),
+            block_records: 0,
+            closed: false,
+        })
+    }
+
+    // open an existing file to append records after its last record
+    // - the header must be the one already stored in the file
+    // - a partial last block is verified and reopened so new records fill it up
+    pub fn append(filename: String, header: Header) -> Result<Self, Error> {
+        let mut file = OpenOptions::new().read(true).write(true).open(filename.clone())?;
+        let count = header.record_count(file.metadata()?.len());
+
+        let mut block = Vec::new();
+        let mut block_records = 0;
+        let mut end = header.record_position(count);
+        if header.block_size > 0 {
+            block_records = (count % header.block_size as u64) as u16;
+            end = header.record_position(count - block_records as u64);
+
+            if block_records > 0 {
+                block = vec![0; block_records as usize * header.record_size()];
+                file.seek(SeekFrom::Start(end))?;
+                file.read_exact(&mut block)?;
+                if crc32fast::hash(&block) != file.read_u32::<BigEndian>()? {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("checksum mismatch in the last block of file {} (repair it first)", filename)));
+                }
+            }
+        }
+
+        // drop the checksum of the partial block and anything after the last record
+        file.set_len(end)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self {
+            writer_buffer: BufWriter::new(file),
+            header: Some(header),
+            block,
+            block_records,
+            closed: false,
+        })
+    }
+
+    // write the header of a stmdb file
+    pub fn write_header(&mut self, mut header: Header) -> Result<(), Error> {
+        header.write(&mut self.writer_buffer)?;
+        self.header = Some(header);
+
+        Ok(())
+    }
+
+    // write a set of candlesticks using the record layout from the header
+    pub fn write_candlesticks(&mut self, candlesticks: &[Candlestick]) -> Result<(), Error> {
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                return Err(Error::new(ErrorKind::InvalidInput, "header must be written before any records"));
+            }
+        };
+
+        if self.closed {
+            return Err(Error::new(ErrorKind::InvalidInput, "the last block has been written, reopen the file to append records"));
+        }
+
+        // files without checksums are written record by record
+        if header.block_size == 0 {
+            for candlestick in candlesticks.iter() {
+                candlestick.write(&mut self.writer_buffer, &header.fields)?;
+            }
+
+            return Ok(());
+        }
+
+        let block_size = header.block_size;
+        let fields = header.fields.clone();
+        for candlestick in candlesticks.iter() {
+            candlestick.write(&mut self.block, &fields)?;
+            self.block_records += 1;
+
+            if self.block_records == block_size {
+                self.write_block()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // write the buffered records of a block followed by their checksum
+    fn write_block(&mut self) -> Result<(), Error> {
+        self.writer_buffer.write_all(&self.block)?;
+        self.writer_buffer.write_u32::<BigEndian>(crc32fast::hash(&self.block))?;
+        self.block.clear();
+        self.block_records = 0;
+
+        Ok(())
+    }
+
+    // write a set of order book snapshots using the number of levels from the header
+    pub fn write_order_books(&mut self, order_books: &[OrderBook]) -> Result<(), Error> {
+        let levels = match &self.header {
+            Some(header) if header.record_kind == RecordKind::OrderBook => header.levels,
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidInput, "an order book header must be written before any snapshots"));
+            }
+        };
+
+        for order_book in order_books.iter() {
+            order_book.write(&mut self.writer_buffer, levels)?;
+        }
+
+        Ok(())
+    }
+
+    // update the start and end timestamps stored in the header
+    // - the cursor is moved back to the end of the file afterwards
+    pub fn update_timestamps(&mut self, start_timestamp: u32, end_timestamp: u32) -> Result<(), Error> {
+        self.writer_buffer.seek(SeekFrom::Start(8))?;
+        self.writer_buffer.write_u32::<BigEndian>(start_timestamp)?;
+        self.writer_buffer.write_u32::<BigEndian>(end_timestamp)?;
+        self.writer_buffer.seek(SeekFrom::End(0))?;
+
+        if let Some(header) = self.header.as_mut() {
+            header.start_timestamp = start_timestamp;
+            header.end_timestamp = end_timestamp;
+        }
+
+        Ok(())
+    }
+
+    // flush any buffered records to the file (a partial block ends the file)
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.block_records > 0 {
+            self.write_block()?;
+            self.closed = true;
+        }
+
+        self.writer_buffer.flush()
+    }
+}
+
+impl Drop for Writer {
+    // write the pending block so records are not lost when the writer is not flushed
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            println!("storage: error flushing writer: {}", e);
+        }
+    }
+}
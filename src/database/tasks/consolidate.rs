@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use crate::database::models::{bar::Bar, calendar::TradingCalendar, candlestick::Candlestick, interval::Interval, symbol_table::SymbolId};
+
+
+// a consolidated candlestick that is still being built
+struct PartialCandlestick {
+    start: i64,
+    end: i64,
+    candlestick: Candlestick,
+}
+
+// consolidates bars of the base resolution into bars of larger intervals
+// - every symbol can have its own base resolution (intervals that are not larger than it are the base bars
+//   and are skipped)
+// - bars are aligned to the sessions of the trading calendar of each exchange
+// - daily base bars are aligned to the session of their date
+// - intraday bars outside of the sessions are removed from the bars (pre/post-market bars are optional)
+// - a consolidated candlestick is added to the bar that completes it (or the last bar when the query ends)
+// - calendars and partial candlesticks are looked up by symbol id and interval index
+pub struct ConsolidateTask {
+    intervals: Vec<Interval>,
+    calendars: HashMap<SymbolId, TradingCalendar>,
+    default_calendar: TradingCalendar,
+    extended_hours: bool,
+    base_seconds: i64,
+    resolutions: HashMap<SymbolId, i64>,
+    partials: HashMap<(SymbolId, usize), PartialCandlestick>,
+}
+
+impl ConsolidateTask {
+    // create a new consolidate task (the calendar of every symbol is the calendar of its exchange)
+    pub fn new(intervals: Vec<Interval>, calendars: HashMap<SymbolId, TradingCalendar>, extended_hours: bool, base_seconds: i64) -> Self {
+        Self {
+            intervals,
+            calendars,
+            default_calendar: TradingCalendar::continuous(),
+            extended_hours,
+            base_seconds,
+            resolutions: HashMap::new(),
+            partials: HashMap::new(),
+        }
+    }
+
+    // set the base resolution of the symbols that do not use the default base resolution (seconds by symbol id)
+    pub fn with_resolutions(mut self, resolutions: HashMap<SymbolId, i64>) -> Self {
+        self.resolutions = resolutions;
+        self
+    }
+
+    // update the consolidated candlesticks with the next bar (bars must be in timestamp order)
+    pub fn update(&mut self, bar: &mut Bar) {
+        // daily base bars are not stamped within their session so they are kept
+        let outside = bar.candlesticks.iter()
+            .filter(|(id, candlestick)| match self.calendars.get(id) {
+                Some(calendar) => self.resolutions.get(id).copied().unwrap_or(self.base_seconds) < 86400 && !calendar.is_open(candlestick.timestamp, self.extended_hours),
+                None => false,
+            })
+            .map(|(id, _)| id)
+            .collect::<Vec<SymbolId>>();
+        for id in outside {
+            bar.candlesticks.remove(id);
+        }
+
+        if self.intervals.is_empty() {
+            return;
+        }
+
+        let mut completed = Vec::new();
+        for (id, candlestick) in bar.candlesticks.iter() {
+            let calendar = self.calendars.get(&id).unwrap_or(&self.default_calendar);
+            let base_seconds = self.resolutions.get(&id).copied().unwrap_or(self.base_seconds);
+
+            for (interval_index, interval) in self.intervals.iter().enumerate() {
+                if interval.length() <= base_seconds {
+                    continue;
+                }
+
+                let bucket = match base_seconds >= 86400 {
+                    true => calendar.daily_bucket(candlestick.timestamp, interval, self.extended_hours),
+                    false => calendar.bucket(candlestick.timestamp, interval, self.extended_hours),
+                };
+                let (start, end) = match bucket {
+                    Some(bucket) => bucket,
+                    None => continue,
+                };
+
+                let key = (id, interval_index);
+
+                // a bar of a new period completes the previous one (missing bars at the end of a period)
+                if let Some(partial) = self.partials.get(&key) {
+                    if partial.start != start {
+                        let partial = self.partials.remove(&key).unwrap();
+                        completed.push((key, partial.candlestick));
+                    }
+                }
+
+                let partial = self.partials.entry(key).or_insert_with(|| {
+                    let mut consolidated = candlestick.clone();
+                    consolidated.timestamp = start;
+                    consolidated.volume = 0.0;
+                    PartialCandlestick {
+                        start,
+                        end,
+                        candlestick: consolidated,
+                    }
+                });
+
+                // merge the bar into the consolidated candlestick
+                partial.candlestick.high = partial.candlestick.high.max(candlestick.high);
+                partial.candlestick.low = partial.candlestick.low.min(candlestick.low);
+                partial.candlestick.close = candlestick.close;
+                partial.candlestick.volume += candlestick.volume;
+                partial.candlestick.extensions = candlestick.extensions.clone();
+
+                // the last bar of the period completes it
+                if candlestick.timestamp + base_seconds >= partial.end {
+                    let partial = self.partials.remove(&key).unwrap();
+                    completed.push((key, partial.candlestick));
+                }
+            }
+        }
+
+        for ((id, interval_index), candlestick) in completed {
+            bar.add_consolidated(interval_index, id, candlestick);
+        }
+    }
+
+    // add the consolidated candlesticks that are still being built to the last bar (at the end of a query)
+    pub fn flush(&mut self, bar: &mut Bar) {
+        let mut partials = self.partials.drain().collect::<Vec<((SymbolId, usize), PartialCandlestick)>>();
+        partials.sort_by_key(|(key, _)| *key);
+        for ((id, interval_index), partial) in partials {
+            bar.add_consolidated(interval_index, id, partial.candlestick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Weekday;
+
+    // tuesday 2024-01-02 10:00 utc
+    const OPEN: i64 = 1704189600;
+
+    fn calendar() -> TradingCalendar {
+        let mut calendar = TradingCalendar::continuous();
+        calendar.open = 600;
+        calendar.close = 960;
+        calendar.pre_market = Some(540);
+        calendar.weekdays = vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        calendar
+    }
+
+    fn bar(timestamp: i64, close: f64) -> Bar {
+        let mut bar = Bar::new(timestamp);
+        bar.add_candlestick(0, Candlestick::new_with(timestamp, close, close, close, close, 1.0));
+        bar
+    }
+
+    #[test]
+    fn bars_outside_of_the_sessions_are_removed() {
+        for extended_hours in [false, true] {
+            let mut task = ConsolidateTask::new(vec![], HashMap::from([(0, calendar())]), extended_hours, 60);
+            let mut pre_market = bar(OPEN - 1800, 1.0);
+            let mut regular = bar(OPEN, 1.0);
+            let mut closed = bar(OPEN + 7 * 3600, 1.0);
+            task.update(&mut pre_market);
+            task.update(&mut regular);
+            task.update(&mut closed);
+
+            assert_eq!(pre_market.candlesticks.is_empty(), !extended_hours);
+            assert!(!regular.candlesticks.is_empty());
+            assert!(closed.candlesticks.is_empty());
+        }
+    }
+
+    #[test]
+    fn partial_periods_are_flushed_into_the_last_bar() {
+        let mut task = ConsolidateTask::new(vec![Interval::from_string("5m").unwrap()], HashMap::from([(0, calendar())]), false, 60);
+        let mut bars = (0..3).map(|i| bar(OPEN + i * 60, 1.0 + i as f64)).collect::<Vec<Bar>>();
+        for bar in bars.iter_mut() {
+            task.update(bar);
+        }
+        assert!(bars.iter().all(|bar| !bar.has_consolidated()));
+
+        let last = bars.last_mut().unwrap();
+        task.flush(last);
+        let consolidated = last.get_consolidated(0, 0).unwrap();
+        assert_eq!((consolidated.timestamp, consolidated.open, consolidated.close, consolidated.volume), (OPEN, 1.0, 3.0, 3.0));
+
+        // nothing is left to flush
+        let mut next = bar(OPEN + 180, 4.0);
+        task.flush(&mut next);
+        assert!(!next.has_consolidated());
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::io::Error;
+use crate::database::{models::{bar::Bar, calendar::TradingCalendar, candlestick::Candlestick, corporate_action::{CorporateActions, PriceAdjustment}, history::History, interval::Interval, symbol_table::SymbolId}, storage::{Reader, VerifyPolicy}};
+use super::{Task, consolidate::ConsolidateTask, query::{QuerySource, load_corporate_actions}};
+
+
+// most times the base bars that are read are doubled to find enough consolidated bars (gaps and closed sessions)
+const MAX_HISTORY_ATTEMPTS: u32 = 8;
+
+// a task that reads the last bars of every symbol and interval that end at or before a timestamp
+// - base bars (at the base resolution of every symbol) are read backwards from the end using the timestamp index of the files (newest file first),
+//   a base bar ends base seconds after its timestamp so the bar that starts less than base seconds before the end is still open
+// - larger intervals are consolidated from the base bars using the calendar of the exchange,
+//   the range that is read grows until there are enough complete bars or the start of the dataset is reached
+// - a consolidated bar that is still open at the end timestamp is not included
+pub struct HistoryTask {
+    pub sources: Vec<QuerySource>,
+    pub intervals: Vec<Interval>,
+    pub calendars: HashMap<SymbolId, TradingCalendar>,
+    pub count: usize,
+    pub end_timestamp: i64,
+    pub extended_hours: bool,
+    pub adjustment: PriceAdjustment,
+    pub verify: VerifyPolicy,
+    pub history: Vec<History>,
+}
+
+impl HistoryTask {
+    // create a new history task (the base interval is always included)
+    pub fn new(sources: Vec<QuerySource>, intervals: Vec<Interval>, count: usize, end_timestamp: i64) -> Self {
+        Self {
+            sources,
+            intervals,
+            calendars: HashMap::new(),
+            count,
+            end_timestamp,
+            extended_hours: false,
+            adjustment: PriceAdjustment::Raw,
+            verify: VerifyPolicy::Fail,
+            history: Vec::new(),
+        }
+    }
+
+    // align the consolidated bars to the sessions of the calendar of every symbol
+    pub fn with_calendars(mut self, calendars: HashMap<SymbolId, TradingCalendar>, extended_hours: bool) -> Self {
+        self.calendars = calendars;
+        self.extended_hours = extended_hours;
+        self
+    }
+
+    // adjust the prices of the bars for corporate actions
+    pub fn with_adjustment(mut self, adjustment: PriceAdjustment) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    // set what happens when a block of records does not match its checksum
+    pub fn with_verify(mut self, verify: VerifyPolicy) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    // read the history of every symbol and interval
+    pub fn run(&mut self) -> Result<Vec<History>, Error> {
+        let mut history = Vec::new();
+        for source in self.sources.iter() {
+            let corporate_actions = match self.adjustment {
+                PriceAdjustment::Raw => None,
+                _ => Some(load_corporate_actions(source, self.adjustment)?),
+            };
+
+            // the base interval (intervals that are not larger than the base resolution are the base bars)
+            let mut base = History::new(source.exchange.clone(), source.symbol.clone(), Interval::from_seconds(source.base_seconds).name);
+            base.candlesticks = self.read_base(source, &corporate_actions)?;
+            history.push(base);
+
+            for interval in self.intervals.iter().filter(|interval| interval.length() > source.base_seconds) {
+                let mut consolidated = History::new(source.exchange.clone(), source.symbol.clone(), interval.name.clone());
+                consolidated.candlesticks = self.consolidate(source, interval, &corporate_actions)?;
+                history.push(consolidated);
+            }
+        }
+
+        self.history = history.clone();
+        Ok(history)
+    }
+
+    // read the last base bars that are within the sessions of the symbol (like the base bars of a query)
+    fn read_base(&self, source: &QuerySource, corporate_actions: &Option<CorporateActions>) -> Result<Vec<Candlestick>, Error> {
+        let calendar = match self.calendars.get(&source.symbol_id) {
+            Some(calendar) if source.base_seconds < 86400 => calendar,
+            _ => return self.read_before(source, self.count as u64, corporate_actions),
+        };
+
+        let mut base_count = self.count as u64;
+        let mut candlesticks = Vec::new();
+        for _ in 0..MAX_HISTORY_ATTEMPTS {
+            let base = self.read_before(source, base_count, corporate_actions)?;
+            candlesticks = base.iter().filter(|candlestick| calendar.is_open(candlestick.timestamp, self.extended_hours)).cloned().collect();
+
+            // stop when there are enough bars or there is no more data
+            if candlesticks.len() >= self.count || (base.len() as u64) < base_count {
+                break;
+            }
+            base_count *= 2;
+        }
+
+        let skip = candlesticks.len().saturating_sub(self.count);
+        Ok(candlesticks.split_off(skip))
+    }
+
+    // consolidate enough base bars to get the last bars of an interval
+    fn consolidate(&self, source: &QuerySource, interval: &Interval, corporate_actions: &Option<CorporateActions>) -> Result<Vec<Candlestick>, Error> {
+        // months do not have a fixed length so the longest month is used
+        let mut base_count = (self.count as i64 * interval.length() / source.base_seconds).max(1) as u64;
+
+        let mut candlesticks = Vec::new();
+        for _ in 0..MAX_HISTORY_ATTEMPTS {
+            let base = self.read_before(source, base_count, corporate_actions)?;
+
+            let calendars = self.calendars.get(&source.symbol_id)
+                .map(|calendar| HashMap::from([(source.symbol_id, calendar.clone())]))
+                .unwrap_or_default();
+            let mut consolidate_task = ConsolidateTask::new(vec![interval.clone()], calendars, self.extended_hours, source.base_seconds);
+
+            // the first consolidated bar can be missing the start of its period so it is dropped
+            candlesticks.clear();
+            for candlestick in base.iter() {
+                let mut bar = Bar::new(candlestick.timestamp);
+                bar.add_candlestick(source.symbol_id, candlestick.clone());
+                consolidate_task.update(&mut bar);
+                if let Some(consolidated) = bar.get_consolidated(0, source.symbol_id) {
+                    candlesticks.push(consolidated.clone());
+                }
+            }
+            if (base.len() as u64) == base_count && !candlesticks.is_empty() {
+                candlesticks.remove(0);
+            }
+
+            // stop when there are enough bars or there is no more data
+            if candlesticks.len() >= self.count || (base.len() as u64) < base_count {
+                break;
+            }
+            base_count *= 2;
+        }
+
+        let skip = candlesticks.len().saturating_sub(self.count);
+        Ok(candlesticks.split_off(skip))
+    }
+
+    // read up to a number of base bars that end at or before the end timestamp (oldest first)
+    fn read_before(&self, source: &QuerySource, count: u64, corporate_actions: &Option<CorporateActions>) -> Result<Vec<Candlestick>, Error> {
+        let end_timestamp = self.end_timestamp.saturating_sub(source.base_seconds);
+        let mut chunks = Vec::new();
+        let mut remaining = count;
+        for filename in source.files.iter().rev() {
+            if remaining == 0 {
+                break;
+            }
+
+            let chunk = Reader::new(filename.clone())?.with_verify(self.verify).read_before(end_timestamp, remaining)?;
+            remaining -= chunk.len() as u64;
+            chunks.push(chunk);
+        }
+
+        let mut candlesticks = chunks.into_iter().rev().flatten().collect::<Vec<Candlestick>>();
+
+        // adjust the prices for splits and dividends
+        if let Some(corporate_actions) = corporate_actions {
+            for candlestick in candlesticks.iter_mut() {
+                let (price_factor, volume_factor) = corporate_actions.factors(candlestick.timestamp, self.adjustment);
+                candlestick.adjust(price_factor, volume_factor);
+            }
+        }
+
+        Ok(candlesticks)
+    }
+}
+
+impl Task for HistoryTask {
+    // execute the history task
+    fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
+        let result = match self.run() {
+            Ok(_) => true,
+            Err(e) => {
+                println!("thread.tasks.history: error reading history: {}", e);
+                false
+            }
+        };
+
+        // call the on exit callback
+        if let Some(callback) = on_exit {
+            callback(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{models::header::Header, storage::Writer};
+
+    // a file of 1m bars every minute from 0
+    fn base_file(name: &str, count: i64) -> String {
+        let filename = std::env::temp_dir().join(format!("{}_{}.stmdb", name, std::process::id())).to_string_lossy().to_string();
+        let candlesticks = (0..count).map(|i| Candlestick::new_with(i * 60, 1.0, 2.0, 0.5, i as f64, 10.0)).collect::<Vec<Candlestick>>();
+
+        let mut writer = Writer::new(filename.clone()).unwrap();
+        writer.write_header(Header::new_with(0, 0, 0, Vec::new())).unwrap();
+        writer.write_candlesticks(&candlesticks).unwrap();
+        writer.flush().unwrap();
+
+        filename
+    }
+
+    fn history(filename: &str, intervals: Vec<Interval>, count: usize, end_timestamp: i64) -> Vec<History> {
+        let source = QuerySource::new(0, "TEST".to_string(), "BASE".to_string(), vec![filename.to_string()], format!("{}.actions.csv", filename)).with_base_seconds(60);
+        HistoryTask::new(vec![source], intervals, count, end_timestamp).run().unwrap()
+    }
+
+    #[test]
+    fn base_bars_that_are_still_open_are_not_read() {
+        let filename = base_file("history_open_base", 10);
+
+        let timestamps = |history: &History| history.candlesticks.iter().map(|candlestick| candlestick.timestamp).collect::<Vec<i64>>();
+        assert_eq!(timestamps(&history(&filename, Vec::new(), 2, 600)[0]), vec![480, 540]);
+        assert_eq!(timestamps(&history(&filename, Vec::new(), 2, 599)[0]), vec![420, 480]);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn consolidated_bars_end_at_or_before_the_end() {
+        let filename = base_file("history_consolidated", 10);
+
+        let history = history(&filename, vec![Interval::from_string("5m").unwrap()], 2, 600);
+        let consolidated = history.iter().find(|history| history.interval == "5m").unwrap();
+        assert_eq!(consolidated.candlesticks.iter().map(|candlestick| (candlestick.timestamp, candlestick.close)).collect::<Vec<(i64, f64)>>(), vec![(0, 4.0), (300, 9.0)]);
+
+        let history = self::history(&filename, vec![Interval::from_string("5m").unwrap()], 2, 599);
+        let consolidated = history.iter().find(|history| history.interval == "5m").unwrap();
+        assert_eq!(consolidated.candlesticks.iter().map(|candlestick| candlestick.timestamp).collect::<Vec<i64>>(), vec![0]);
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,915 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use crate::database::{models::{interval::Interval, partition::Partition, candlestick::Candlestick, field::{FieldDefinition, FieldType, FieldValue}, header::{Header, RecordKind}}, storage::{Reader, Writer}};
+use super::Task;
+
+
+// represents how records with the same timestamp but different values are resolved
+// - prefer newer: the record from the most recently modified source wins
+// - prefer volume: the record with the higher volume wins
+// - fail: the merge is aborted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    PreferNewer,
+    PreferVolume,
+    Fail,
+}
+
+impl ConflictPolicy {
+    // convert a string into a conflict policy
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "newer" | "prefer_newer" => Some(ConflictPolicy::PreferNewer),
+            "volume" | "prefer_volume" => Some(ConflictPolicy::PreferVolume),
+            "fail" => Some(ConflictPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
+// represents a file that was written by an update or merge
+#[derive(Debug, Clone)]
+pub struct MergedFile {
+    pub filename: String,
+    pub partition: Option<String>,
+    pub record_count: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+}
+
+// represents the outcome of an update or merge
+// - the record count and range cover the files that were written
+#[derive(Debug, Clone, Default)]
+pub struct MergeSummary {
+    pub added: u64,
+    pub replaced: u64,
+    pub duplicates: u64,
+    pub skipped: u64,
+    pub record_count: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub files: Vec<MergedFile>,
+
+    // timestamp of the first record that was merged (records before it did not change)
+    pub changed_from: Option<i64>,
+
+    // seconds between the bars of the dataset
+    pub resolution: i64,
+
+    // snapshot of the dataset that was created by the change (set once the catalog is updated)
+    pub snapshot_id: u32,
+}
+
+// a task that will update a stmdb file with the records of other files
+// - update mode only appends records newer than the last record of the target (existing records are not rewritten)
+// - merge mode combines the records of the target and the sources and rewrites the target
+// - sources can be stmdb files or csv files with timestamp, open, high, low, close and volume columns
+// - a partitioned target is a folder with a file per partition and only the partitions that receive records are written
+// - a versioned task never changes the current files, the files that receive records are written as a new version
+//   ([name].v[version].stmdb) so readers of the current files are not disturbed
+pub struct MergeTask {
+    pub target: String,
+    pub sources: Vec<String>,
+    pub policy: ConflictPolicy,
+    pub append_only: bool,
+    pub dataset_id: u32,
+    pub partition_interval: Option<Interval>,
+    pub summary: Option<MergeSummary>,
+
+    // version of the written files and the current files of the target (partition, filename)
+    pub version: Option<u32>,
+    pub current_files: Vec<(Option<String>, String)>,
+}
+
+impl MergeTask {
+    // create a new merge task
+    pub fn new(target: String, sources: Vec<String>, policy: ConflictPolicy) -> Self {
+        Self {
+            target,
+            sources,
+            policy,
+            append_only: false,
+            dataset_id: 0,
+            partition_interval: None,
+            summary: None,
+            version: None,
+            current_files: Vec::new(),
+        }
+    }
+
+    // create a new update task that only appends newer records
+    pub fn new_update(target: String, source: String, policy: ConflictPolicy) -> Self {
+        Self {
+            append_only: true,
+            ..Self::new(target, vec![source], policy)
+        }
+    }
+
+    // dataset id used when the target file does not exist yet
+    pub fn with_dataset_id(mut self, dataset_id: u32) -> Self {
+        self.dataset_id = dataset_id;
+        self
+    }
+
+    // split the target folder into a file per partition of an interval
+    pub fn with_partitions(mut self, interval: Interval) -> Self {
+        self.partition_interval = Some(interval);
+        self
+    }
+
+    // write the files that receive records as a new version instead of changing the current files
+    pub fn with_version(mut self, version: u32, current_files: Vec<(Option<String>, String)>) -> Self {
+        self.version = Some(version);
+        self.current_files = current_files;
+        self
+    }
+
+    // run the update or merge and return a summary of the changes
+    // - the records of the sources are merged by timestamp while they are read so a merge never holds every record
+    pub fn run(&mut self) -> Result<MergeSummary, Error> {
+        // use the record layout of the target (or the first stmdb source when the target is new)
+        let target_files = self.target_files()?;
+        let mut header = None;
+        if let Some(filename) = target_files.first() {
+            header = Some(read_header(filename)?);
+        }
+        for source in self.sources.iter().filter(|source| source.ends_with(".stmdb")) {
+            let source_header = read_header(source)?;
+            match &header {
+                Some(header) if header.fields != source_header.fields => {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("merge: fields of {} do not match {}", source, self.target)));
+                },
+                Some(header) if header.resolution != source_header.resolution => {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("merge: resolution of {} ({}s) does not match {} ({}s)", source, source_header.resolution, self.target, header.resolution)));
+                },
+                Some(_) => {},
+                None => header = Some(source_header),
+            }
+        }
+        let inferred = header.is_none();
+        let mut header = match header {
+            Some(header) => header,
+            None => Header::new_with(self.dataset_id, 0, 0, csv_fields(&self.sources)?),
+        };
+        if header.record_kind != RecordKind::Candlestick {
+            return Err(Error::new(ErrorKind::InvalidData, "merge: only candlestick files can be merged"));
+        }
+        if target_files.is_empty() {
+            header.dataset_id = self.dataset_id;
+        }
+
+        // the newest source is merged last
+        let mut sources = Vec::new();
+        for source in self.sources.iter() {
+            sources.push((fs::metadata(source)?.modified()?, source.clone()));
+        }
+        if self.policy == ConflictPolicy::PreferNewer {
+            sources.sort_by_key(|(modified, _)| *modified);
+        }
+        let sources = sources.into_iter().map(|(_, source)| source).collect::<Vec<String>>();
+
+        // a new dataset imported from csv files has the resolution of the smallest gap between its records
+        // - the sources are read once more to find it before any record is written
+        if inferred {
+            let mut streams = open_streams(&sources, &header.fields)?;
+            let mut previous = None;
+            let mut resolution = None;
+            while let Some(candlestick) = self.next_record(&mut streams, &mut MergeSummary::default())? {
+                if let Some(previous) = previous {
+                    resolution = Some(resolution.map_or(candlestick.timestamp - previous, |resolution: i64| resolution.min(candlestick.timestamp - previous)));
+                }
+                previous = Some(candlestick.timestamp);
+            }
+            header.resolution = resolution.unwrap_or(60).clamp(1, u32::MAX as i64) as u32;
+        }
+
+        // records at or before the end of the target are left alone when updating
+        let mut last_timestamp = None;
+        if self.append_only {
+            if let Some(filename) = target_files.last() {
+                let mut reader = Reader::new(filename.clone())?;
+                let count = reader.record_count()?;
+                if count > 0 {
+                    last_timestamp = Some(reader.read_timestamp_at(count - 1)?);
+                }
+            }
+        }
+
+        // write the merged records into the files they belong to (a file is finished when the records move to the next one)
+        let mut summary = MergeSummary {
+            resolution: header.resolution as i64,
+            ..Default::default()
+        };
+        let mut streams = open_streams(&sources, &header.fields)?;
+        let mut output: Option<OutputFile> = None;
+        while let Some(candlestick) = self.next_record(&mut streams, &mut summary)? {
+            if last_timestamp.is_some_and(|last_timestamp| candlestick.timestamp <= last_timestamp) {
+                summary.skipped += 1;
+                continue;
+            }
+            summary.changed_from.get_or_insert(candlestick.timestamp);
+
+            let (filename, partition) = self.file_of(candlestick.timestamp);
+            if output.as_ref().is_none_or(|output| output.partition != partition) {
+                if let Some(output) = output.take() {
+                    self.finish(output, &mut summary)?;
+                }
+                output = Some(self.open_output(filename, partition, &header)?);
+            }
+            if let Some(output) = output.as_mut() {
+                self.write(output, candlestick, &mut summary)?;
+            }
+        }
+        if let Some(output) = output.take() {
+            self.finish(output, &mut summary)?;
+        }
+
+        println!("thread.tasks.merge: {} records added, {} replaced, {} duplicates, {} skipped in {} files of {}", summary.added, summary.replaced, summary.duplicates, summary.skipped, summary.files.len(), self.target);
+
+        self.summary = Some(summary.clone());
+        Ok(summary)
+    }
+
+    // take the next record of the sources by timestamp (records of every source with the same timestamp are combined)
+    fn next_record(&self, streams: &mut [RecordStream], summary: &mut MergeSummary) -> Result<Option<Candlestick>, Error> {
+        let timestamp = match streams.iter().filter_map(|stream| stream.head.as_ref()).map(|candlestick| candlestick.timestamp).min() {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+
+        let mut record: Option<Candlestick> = None;
+        for stream in streams.iter_mut() {
+            while stream.head.as_ref().is_some_and(|candlestick| candlestick.timestamp == timestamp) {
+                let candlestick = stream.advance()?.unwrap();
+                record = Some(match record {
+                    Some(existing) => self.combine(existing, candlestick, &stream.filename, summary)?,
+                    None => candlestick,
+                });
+            }
+        }
+
+        Ok(record)
+    }
+
+    // resolve two records with the same timestamp using the conflict policy (the second record is the newer one)
+    fn combine(&self, existing: Candlestick, candlestick: Candlestick, filename: &str, summary: &mut MergeSummary) -> Result<Candlestick, Error> {
+        if existing == candlestick {
+            summary.duplicates += 1;
+            return Ok(existing);
+        }
+
+        let replace = match self.policy {
+            ConflictPolicy::PreferNewer => true,
+            ConflictPolicy::PreferVolume => candlestick.volume > existing.volume,
+            ConflictPolicy::Fail => {
+                return Err(Error::new(ErrorKind::InvalidData, format!("merge: conflicting records at {} in {}", candlestick.timestamp, filename)));
+            }
+        };
+
+        if replace {
+            summary.replaced += 1;
+            return Ok(candlestick);
+        }
+
+        Ok(existing)
+    }
+
+    // open the file that the records of a partition are written to
+    // - updates append to the current file (a new version starts as a copy of it)
+    // - merges write the records of the current file and the sources into a temporary file that replaces the file
+    fn open_output(&self, filename: String, partition: Option<String>, header: &Header) -> Result<OutputFile, Error> {
+        let current = self.current_file(&filename, &partition);
+        let filename = match self.version {
+            Some(version) => versioned_filename(&filename, version),
+            None => filename,
+        };
+
+        let mut output = OutputFile {
+            filename: filename.clone(),
+            temporary: None,
+            partition,
+            writer: None,
+            existing: None,
+            buffer: Vec::new(),
+            record_count: 0,
+            start_timestamp: None,
+            end_timestamp: 0,
+        };
+
+        match current {
+            Some(current) if self.append_only => {
+                if current != filename {
+                    fs::copy(&current, &filename)?;
+                }
+                let mut reader = Reader::new(filename.clone())?;
+                let header = reader.read_header()?.clone();
+                output.record_count = reader.record_count()?;
+                if output.record_count > 0 {
+                    output.start_timestamp = Some(reader.read_timestamp_at(0)?);
+                    output.end_timestamp = reader.read_timestamp_at(output.record_count - 1)?;
+                }
+                output.writer = Some(Writer::append(filename, header)?);
+            },
+            current => {
+                if let Some(current) = current {
+                    output.existing = Some(RecordStream::open(&current, &header.fields)?);
+                }
+                if let Some(folder) = Path::new(&filename).parent() {
+                    fs::create_dir_all(folder)?;
+                }
+
+                let temporary = format!("{}.tmp", filename);
+                let mut writer = Writer::new(temporary.clone())?;
+                writer.write_header(header.clone())?;
+                output.writer = Some(writer);
+                output.temporary = Some(temporary);
+            }
+        }
+
+        Ok(output)
+    }
+
+    // write a record of the sources into a file after the records of the current file that come before it
+    fn write(&self, output: &mut OutputFile, candlestick: Candlestick, summary: &mut MergeSummary) -> Result<(), Error> {
+        let mut existing_record = None;
+        if let Some(mut existing) = output.existing.take() {
+            while existing.head.as_ref().is_some_and(|existing| existing.timestamp < candlestick.timestamp) {
+                let record = existing.advance()?.unwrap();
+                output.push(record)?;
+            }
+            if existing.head.as_ref().is_some_and(|existing| existing.timestamp == candlestick.timestamp) {
+                existing_record = Some((existing.advance()?.unwrap(), existing.filename.clone()));
+            }
+            output.existing = Some(existing);
+        }
+
+        match existing_record {
+            Some((existing, filename)) => {
+                let record = self.combine(existing, candlestick, &filename, summary)?;
+                output.push(record)
+            },
+            None => {
+                summary.added += 1;
+                output.push(candlestick)
+            }
+        }
+    }
+
+    // write the rest of the current file and add the written file to the summary
+    fn finish(&self, mut output: OutputFile, summary: &mut MergeSummary) -> Result<(), Error> {
+        if let Some(mut existing) = output.existing.take() {
+            while let Some(record) = existing.advance()? {
+                output.push(record)?;
+            }
+        }
+        output.write_buffer()?;
+
+        let start_timestamp = output.start_timestamp.unwrap_or(0);
+        if let Some(mut writer) = output.writer.take() {
+            writer.update_timestamps(start_timestamp as u32, output.end_timestamp as u32)?;
+            writer.flush()?;
+        }
+        if let Some(temporary) = output.temporary.as_ref() {
+            fs::rename(temporary, &output.filename)?;
+        }
+
+        let merged = MergedFile {
+            filename: output.filename,
+            partition: output.partition,
+            record_count: output.record_count,
+            start_timestamp,
+            end_timestamp: output.end_timestamp,
+        };
+        summary.record_count += merged.record_count;
+        if summary.files.is_empty() || merged.start_timestamp < summary.start_timestamp {
+            summary.start_timestamp = merged.start_timestamp;
+        }
+        summary.end_timestamp = summary.end_timestamp.max(merged.end_timestamp);
+        summary.files.push(merged);
+
+        Ok(())
+    }
+
+    // get the existing files of the target in timestamp order
+    fn target_files(&self) -> Result<Vec<String>, Error> {
+        if self.version.is_some() {
+            let mut files = self.current_files.clone();
+            files.sort();
+
+            return Ok(files.into_iter().map(|(_, filename)| filename).collect());
+        }
+
+        if self.partition_interval.is_none() {
+            if Path::new(&self.target).exists() {
+                return Ok(vec![self.target.clone()]);
+            }
+
+            return Ok(Vec::new());
+        }
+
+        let mut files = Vec::new();
+        if Path::new(&self.target).is_dir() {
+            for entry in fs::read_dir(&self.target)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|extension| extension == "stmdb") {
+                    files.push(path.to_string_lossy().to_string());
+                }
+            }
+        }
+
+        // partition names sort in timestamp order
+        files.sort();
+
+        Ok(files)
+    }
+
+    // get the current file of a partition of the target (none when the records go into a new file)
+    fn current_file(&self, filename: &str, partition: &Option<String>) -> Option<String> {
+        if self.version.is_none() {
+            return Path::new(filename).exists().then(|| filename.to_string());
+        }
+
+        self.current_files.iter()
+            .find(|(current_partition, _)| current_partition == partition)
+            .map(|(_, current)| current.clone())
+    }
+
+    // get the file and partition that a record is written to
+    fn file_of(&self, timestamp: i64) -> (String, Option<String>) {
+        match &self.partition_interval {
+            Some(interval) => {
+                let partition = Partition::from_timestamp(timestamp, interval);
+                (format!("{}/{}.stmdb", self.target, partition.name), Some(partition.name))
+            },
+            None => (self.target.clone(), None),
+        }
+    }
+}
+
+// get the filename of a version of a file
+pub fn versioned_filename(filename: &str, version: u32) -> String {
+    format!("{}.v{}.stmdb", filename.strip_suffix(".stmdb").unwrap_or(filename), version)
+}
+
+// write every record into a temporary file and replace the file with it
+pub fn rewrite(filename: &str, partition: Option<String>, header: &Header, records: BTreeMap<i64, Candlestick>) -> Result<MergedFile, Error> {
+    let records = records.into_values().collect::<Vec<Candlestick>>();
+    let start_timestamp = records.first().map_or(0, |candlestick| candlestick.timestamp);
+    let end_timestamp = records.last().map_or(0, |candlestick| candlestick.timestamp);
+
+    let mut header = header.clone();
+    header.start_timestamp = start_timestamp as u32;
+    header.end_timestamp = end_timestamp as u32;
+
+    let temporary = format!("{}.tmp", filename);
+    let mut writer = Writer::new(temporary.clone())?;
+    writer.write_header(header)?;
+    writer.write_candlesticks(&records)?;
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(temporary, filename)?;
+
+    Ok(MergedFile {
+        filename: filename.to_string(),
+        partition,
+        record_count: records.len() as u64,
+        start_timestamp,
+        end_timestamp,
+    })
+}
+
+impl Task for MergeTask {
+    // execute the merge task
+    fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
+        let result = match self.run() {
+            Ok(_) => true,
+            Err(e) => {
+                println!("thread.tasks.merge: error merging into {}: {}", self.target, e);
+                false
+            }
+        };
+
+        // call the on exit callback
+        if let Some(callback) = on_exit {
+            callback(result);
+        }
+    }
+}
+
+// read the header of a stmdb file
+fn read_header(filename: &str) -> Result<Header, Error> {
+    if !Path::new(filename).exists() {
+        return Err(Error::new(ErrorKind::NotFound, format!("merge: file {} does not exist", filename)));
+    }
+
+    Ok(Reader::new(filename.to_string())?.read_header()?.clone())
+}
+
+// the columns of a csv file read into the default fields of a record
+const DEFAULT_COLUMNS: [&str; 7] = ["timestamp", "time", "open", "high", "low", "close", "volume"];
+
+// get the extra fields of a new dataset from the other columns of its csv sources
+// - a column is a number when every value is a number, otherwise a string as long as its longest value
+fn csv_fields(sources: &[String]) -> Result<Vec<FieldDefinition>, Error> {
+    let mut columns: Vec<(String, bool, usize)> = Vec::new();
+    for source in sources.iter().filter(|source| source.ends_with(".csv")) {
+        let mut reader = csv::Reader::from_path(source).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut indexes = Vec::new();
+        for (index, header) in reader.headers().map_err(|e| Error::new(ErrorKind::InvalidData, e))?.iter().enumerate() {
+            if DEFAULT_COLUMNS.contains(&header.trim().to_lowercase().as_str()) {
+                continue;
+            }
+            let position = match columns.iter().position(|(name, _, _)| name == header.trim()) {
+                Some(position) => position,
+                None => {
+                    columns.push((header.trim().to_string(), true, 0));
+                    columns.len() - 1
+                }
+            };
+            indexes.push((index, position));
+        }
+
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            for (index, position) in indexes.iter() {
+                let value = record.get(*index).unwrap_or("").trim();
+                let (_, number, length) = &mut columns[*position];
+                *number &= value.parse::<f64>().is_ok();
+                *length = (*length).max(value.len());
+            }
+        }
+    }
+
+    Ok(columns.into_iter().map(|(name, number, length)| match number {
+        true => FieldDefinition::new_number(name),
+        false => FieldDefinition::new_string(name, length.clamp(1, u8::MAX as usize) as u8),
+    }).collect())
+}
+
+// number of records read from a stmdb file at a time
+const STREAM_RECORDS: u64 = 4096;
+
+// represents a file that receives the merged records of a partition
+// - records are buffered and written a batch at a time
+struct OutputFile {
+    filename: String,
+    temporary: Option<String>,
+    partition: Option<String>,
+    writer: Option<Writer>,
+
+    // records of the current file that are merged with the records of the sources
+    existing: Option<RecordStream>,
+
+    buffer: Vec<Candlestick>,
+    record_count: u64,
+    start_timestamp: Option<i64>,
+    end_timestamp: i64,
+}
+
+impl OutputFile {
+    // add a record after the last record of the file
+    fn push(&mut self, candlestick: Candlestick) -> Result<(), Error> {
+        self.start_timestamp.get_or_insert(candlestick.timestamp);
+        self.end_timestamp = candlestick.timestamp;
+        self.record_count += 1;
+        self.buffer.push(candlestick);
+
+        if self.buffer.len() as u64 >= STREAM_RECORDS {
+            self.write_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    // write the buffered records
+    fn write_buffer(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.write_candlesticks(&self.buffer)?;
+        }
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+// represents the records of a stmdb or csv file in timestamp order
+// - the next record is kept so the records of several files can be merged by timestamp
+struct RecordStream {
+    filename: String,
+    records: Box<dyn Iterator<Item = Result<Candlestick, Error>>>,
+    head: Option<Candlestick>,
+}
+
+impl RecordStream {
+    // open a stmdb or csv file and read its first record
+    fn open(filename: &str, definitions: &[FieldDefinition]) -> Result<Self, Error> {
+        let records: Box<dyn Iterator<Item = Result<Candlestick, Error>>> = match filename.ends_with(".csv") {
+            true => Box::new(CsvRecords::open(filename, definitions)?),
+            false => Box::new(StmdbRecords::open(filename)?),
+        };
+
+        let mut stream = Self {
+            filename: filename.to_string(),
+            records,
+            head: None,
+        };
+        stream.advance()?;
+
+        Ok(stream)
+    }
+
+    // take the next record and read the one after it (a file fails when its records are not in timestamp order)
+    fn advance(&mut self) -> Result<Option<Candlestick>, Error> {
+        let next = self.records.next().transpose()?;
+        if let (Some(head), Some(next)) = (self.head.as_ref(), next.as_ref()) {
+            if next.timestamp < head.timestamp {
+                return Err(Error::new(ErrorKind::InvalidData, format!("merge: records of {} are not in timestamp order at {}", self.filename, next.timestamp)));
+            }
+        }
+
+        Ok(std::mem::replace(&mut self.head, next))
+    }
+}
+
+// open a stream for every source
+fn open_streams(sources: &[String], definitions: &[FieldDefinition]) -> Result<Vec<RecordStream>, Error> {
+    sources.iter().map(|source| RecordStream::open(source, definitions)).collect()
+}
+
+// reads the records of a stmdb file a batch at a time
+struct StmdbRecords {
+    reader: Reader,
+    next: u64,
+    count: u64,
+    batch: std::vec::IntoIter<Candlestick>,
+}
+
+impl StmdbRecords {
+    fn open(filename: &str) -> Result<Self, Error> {
+        let mut reader = Reader::new(filename.to_string())?;
+        let count = reader.record_count()?;
+
+        Ok(Self {
+            reader,
+            next: 0,
+            count,
+            batch: Vec::new().into_iter(),
+        })
+    }
+}
+
+impl Iterator for StmdbRecords {
+    type Item = Result<Candlestick, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(candlestick) = self.batch.next() {
+                return Some(Ok(candlestick));
+            }
+            if self.next >= self.count {
+                return None;
+            }
+
+            // blocks that are skipped by the verify policy leave a batch empty
+            let last = (self.next + STREAM_RECORDS).min(self.count);
+            match self.reader.read_records(self.next, last) {
+                Ok(batch) => self.batch = batch.into_iter(),
+                Err(e) => {
+                    self.next = self.count;
+                    return Some(Err(e));
+                }
+            }
+            self.next = last;
+        }
+    }
+}
+
+// reads the records of a csv file a row at a time using the column names of the first row
+// - extra columns that match a field of the target are kept as extensions
+struct CsvRecords {
+    filename: String,
+    records: csv::StringRecordsIntoIter<fs::File>,
+    columns: [usize; 6],
+    extensions: Vec<(usize, FieldDefinition)>,
+}
+
+impl CsvRecords {
+    fn open(filename: &str, definitions: &[FieldDefinition]) -> Result<Self, Error> {
+        let mut reader = csv::Reader::from_path(filename).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let headers = reader.headers().map_err(|e| Error::new(ErrorKind::InvalidData, e))?.clone();
+
+        let column = |names: &[&str]| -> Result<usize, Error> {
+            headers.iter().position(|header| names.contains(&header.trim().to_lowercase().as_str()))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("merge: missing {} column in {}", names[0], filename)))
+        };
+        let columns = [
+            column(&DEFAULT_COLUMNS[0..2])?,
+            column(&DEFAULT_COLUMNS[2..3])?,
+            column(&DEFAULT_COLUMNS[3..4])?,
+            column(&DEFAULT_COLUMNS[4..5])?,
+            column(&DEFAULT_COLUMNS[5..6])?,
+            column(&DEFAULT_COLUMNS[6..7])?,
+        ];
+        let extensions = definitions.iter()
+            .filter_map(|definition| headers.iter().position(|header| header.trim() == definition.name).map(|index| (index, definition.clone())))
+            .collect::<Vec<(usize, FieldDefinition)>>();
+
+        Ok(Self {
+            filename: filename.to_string(),
+            records: reader.into_records(),
+            columns,
+            extensions,
+        })
+    }
+
+    // convert a row into a candlestick
+    fn parse(&self, record: csv::StringRecord) -> Result<Candlestick, Error> {
+        let value = |index: usize| -> Result<f64, Error> {
+            record.get(index).unwrap_or("").trim().parse::<f64>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("merge: invalid value in column {} of {}", index, self.filename)))
+        };
+
+        let mut candlestick = Candlestick::new_with(
+            value(self.columns[0])? as i64,
+            value(self.columns[1])?,
+            value(self.columns[2])?,
+            value(self.columns[3])?,
+            value(self.columns[4])?,
+            value(self.columns[5])?,
+        );
+
+        for (index, definition) in self.extensions.iter() {
+            let extension = match definition.field_type {
+                FieldType::String => FieldValue::Text(record.get(*index).unwrap_or("").to_string()),
+                FieldType::Int64 | FieldType::Float64 => FieldValue::Number(value(*index)?),
+            };
+            candlestick.set_extension(definition.name.clone(), extension);
+        }
+
+        Ok(candlestick)
+    }
+}
+
+impl Iterator for CsvRecords {
+    type Item = Result<Candlestick, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?.map_err(|e| Error::new(ErrorKind::InvalidData, e));
+        Some(record.and_then(|record| self.parse(record)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn temporary(name: &str, extension: &str) -> String {
+        std::env::temp_dir().join(format!("merge_{}_{}{}", name, std::process::id(), extension)).to_string_lossy().to_string()
+    }
+
+    // a csv source of (timestamp, close, volume) records modified some seconds ago (older files are merged first)
+    fn csv_file(name: &str, records: &[(i64, f64, f64)], age: u64) -> String {
+        let filename = temporary(name, ".csv");
+        let mut contents = "timestamp,open,high,low,close,volume\n".to_string();
+        for (timestamp, close, volume) in records {
+            contents.push_str(&format!("{},1,2,0.5,{},{}\n", timestamp, close, volume));
+        }
+        fs::write(&filename, contents).unwrap();
+        fs::File::options().write(true).open(&filename).unwrap().set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+
+        filename
+    }
+
+    fn stmdb_file(name: &str, records: &[(i64, f64, f64)]) -> String {
+        let filename = temporary(name, ".stmdb");
+        let candlesticks = records.iter().map(|(timestamp, close, volume)| Candlestick::new_with(*timestamp, 1.0, 2.0, 0.5, *close, *volume)).collect::<Vec<Candlestick>>();
+
+        let mut writer = Writer::new(filename.clone()).unwrap();
+        writer.write_header(Header::new_with(0, 0, 0, Vec::new())).unwrap();
+        writer.write_candlesticks(&candlesticks).unwrap();
+        writer.flush().unwrap();
+
+        filename
+    }
+
+    fn records(filename: &str) -> Vec<(i64, f64, f64)> {
+        Reader::new(filename.to_string()).unwrap().read_candlesticks().unwrap().iter()
+            .map(|candlestick| (candlestick.timestamp, candlestick.close, candlestick.volume))
+            .collect()
+    }
+
+    fn remove(filenames: &[&str]) {
+        for filename in filenames {
+            fs::remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn the_record_of_the_newest_source_wins() {
+        let older = csv_file("newer_older", &[(0, 1.0, 10.0), (60, 1.0, 10.0), (120, 1.0, 10.0)], 60);
+        let newer = csv_file("newer_newer", &[(60, 2.0, 5.0), (120, 1.0, 10.0), (180, 2.0, 5.0)], 0);
+        let target = temporary("newer", ".stmdb");
+
+        let summary = MergeTask::new(target.clone(), vec![newer.clone(), older.clone()], ConflictPolicy::PreferNewer).run().unwrap();
+        assert_eq!(records(&target), vec![(0, 1.0, 10.0), (60, 2.0, 5.0), (120, 1.0, 10.0), (180, 2.0, 5.0)]);
+        assert_eq!((summary.added, summary.replaced, summary.duplicates, summary.record_count, summary.resolution), (4, 1, 1, 4, 60));
+        assert_eq!((summary.start_timestamp, summary.end_timestamp, summary.changed_from), (0, 180, Some(0)));
+        remove(&[&older, &newer, &target]);
+    }
+
+    #[test]
+    fn the_record_with_the_higher_volume_wins() {
+        let first = csv_file("volume_first", &[(0, 1.0, 10.0), (60, 1.0, 10.0)], 0);
+        let second = csv_file("volume_second", &[(0, 2.0, 20.0), (60, 2.0, 5.0)], 60);
+        let target = temporary("volume", ".stmdb");
+
+        let summary = MergeTask::new(target.clone(), vec![first.clone(), second.clone()], ConflictPolicy::PreferVolume).run().unwrap();
+        assert_eq!(records(&target), vec![(0, 2.0, 20.0), (60, 1.0, 10.0)]);
+        assert_eq!((summary.added, summary.replaced), (2, 1));
+        remove(&[&first, &second, &target]);
+    }
+
+    #[test]
+    fn conflicting_records_fail_the_merge() {
+        let first = csv_file("fail_first", &[(0, 1.0, 10.0), (60, 1.0, 10.0)], 0);
+        let same = csv_file("fail_same", &[(0, 1.0, 10.0)], 0);
+        let conflict = csv_file("fail_conflict", &[(60, 2.0, 10.0)], 0);
+        let target = temporary("fail", ".stmdb");
+
+        // identical records are duplicates and not conflicts
+        let summary = MergeTask::new(target.clone(), vec![first.clone(), same.clone()], ConflictPolicy::Fail).run().unwrap();
+        assert_eq!((summary.added, summary.duplicates), (2, 1));
+
+        let error = MergeTask::new(target.clone(), vec![conflict.clone()], ConflictPolicy::Fail).run().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(records(&target), vec![(0, 1.0, 10.0), (60, 1.0, 10.0)]);
+        remove(&[&first, &same, &conflict, &target]);
+        let _ = fs::remove_file(format!("{}.tmp", target));
+    }
+
+    #[test]
+    fn sources_are_merged_into_the_records_of_the_target() {
+        let target = stmdb_file("existing", &[(0, 1.0, 10.0), (60, 1.0, 10.0), (120, 1.0, 10.0)]);
+        let source = csv_file("existing_source", &[(60, 2.0, 10.0), (90, 2.0, 10.0), (300, 2.0, 10.0)], 0);
+
+        let summary = MergeTask::new(target.clone(), vec![source.clone()], ConflictPolicy::PreferNewer).run().unwrap();
+        assert_eq!(records(&target), vec![(0, 1.0, 10.0), (60, 2.0, 10.0), (90, 2.0, 10.0), (120, 1.0, 10.0), (300, 2.0, 10.0)]);
+        assert_eq!((summary.added, summary.replaced, summary.record_count, summary.changed_from), (2, 1, 5, Some(60)));
+        remove(&[&target, &source]);
+    }
+
+    #[test]
+    fn updates_only_append_newer_records() {
+        let target = stmdb_file("update", &[(0, 1.0, 10.0), (60, 1.0, 10.0)]);
+        let source = csv_file("update_source", &[(0, 2.0, 10.0), (60, 2.0, 10.0), (120, 2.0, 10.0)], 0);
+
+        let summary = MergeTask::new_update(target.clone(), source.clone(), ConflictPolicy::Fail).run().unwrap();
+        assert_eq!(records(&target), vec![(0, 1.0, 10.0), (60, 1.0, 10.0), (120, 2.0, 10.0)]);
+        assert_eq!((summary.added, summary.skipped, summary.record_count), (1, 2, 3));
+        assert_eq!((summary.start_timestamp, summary.end_timestamp), (0, 120));
+        remove(&[&target, &source]);
+    }
+
+    #[test]
+    fn records_are_written_into_their_partitions() {
+        let source = csv_file("partitions_source", &[(0, 1.0, 10.0), (3540, 1.0, 10.0), (3600, 2.0, 10.0)], 0);
+        let target = temporary("partitions", "");
+
+        let summary = MergeTask::new(target.clone(), vec![source.clone()], ConflictPolicy::Fail).with_partitions(Interval::from_string("1h").unwrap()).run().unwrap();
+        assert_eq!(summary.files.iter().map(|file| file.record_count).collect::<Vec<u64>>(), vec![2, 1]);
+        assert_eq!(records(&summary.files[1].filename), vec![(3600, 2.0, 10.0)]);
+        remove(&[&source]);
+        fs::remove_dir_all(target).unwrap();
+    }
+
+    #[test]
+    fn sources_out_of_timestamp_order_fail_the_merge() {
+        let source = csv_file("unordered", &[(60, 1.0, 10.0), (0, 1.0, 10.0)], 0);
+        let target = temporary("unordered", ".stmdb");
+
+        let error = MergeTask::new(target.clone(), vec![source.clone()], ConflictPolicy::Fail).run().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        remove(&[&source]);
+        let _ = fs::remove_file(format!("{}.tmp", target));
+    }
+
+    #[test]
+    fn extra_columns_become_fields_of_a_new_dataset() {
+        let source = temporary("extra", ".csv");
+        fs::write(&source, "time,open,high,low,close,volume,funding_rate,flag\n0,1,2,0.5,1.5,10,0.01,up\n60,1,2,0.5,1.5,10,-0.02,down\n").unwrap();
+        let target = temporary("extra", ".stmdb");
+
+        MergeTask::new(target.clone(), vec![source.clone()], ConflictPolicy::Fail).run().unwrap();
+        let mut reader = Reader::new(target.clone()).unwrap();
+        let fields = reader.header().unwrap().fields.iter().map(|field| (field.name.clone(), field.field_type, field.length)).collect::<Vec<(String, FieldType, u8)>>();
+        assert_eq!(fields, vec![("funding_rate".to_string(), FieldType::Float64, 8), ("flag".to_string(), FieldType::String, 4)]);
+        let candlesticks = reader.read_candlesticks().unwrap();
+        assert_eq!(candlesticks[1].extensions.get("funding_rate"), Some(&FieldValue::Number(-0.02)));
+        assert_eq!(candlesticks[1].extensions.get("flag").map(|value| value.as_string()), Some("down".to_string()));
+        remove(&[&source, &target]);
+    }
+}
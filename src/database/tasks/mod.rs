@@ -2,6 +2,10 @@ pub mod read_chunk;
 pub mod write_chunk;
 pub mod consolidate;
 pub mod query;
+pub mod merge;
+pub mod repair;
+pub mod history;
+pub mod rollup;
 
 
 pub trait Task {
@@ -1,17 +1,148 @@
-use std::{sync::{Mutex, Arc}, collections::HashMap, time::Instant};
+use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, collections::HashMap, io::{Error, ErrorKind}, time::Instant};
 use crossbeam::channel::Sender;
-use crate::database::{models::{barset::BarSet, candlestick::Candlestick, bar::Bar}, tasks::read_chunk::ReadChunkTask, storage::Reader, threads::ThreadPool};
+use crate::database::{models::{barset::BarSet, candlestick::Candlestick, bar::Bar, order_book::OrderBook, corporate_action::{CorporateActions, CorporateActionKind, PriceAdjustment}}, tasks::{read_chunk::ReadChunkTask, consolidate::ConsolidateTask}, storage::{Reader, VerifyPolicy}, threads::ThreadPool};
+use crate::database::models::{header::DEFAULT_RESOLUTION, symbol_table::SymbolId};
 use super::Task;
 
 
+// streams order book snapshots from a file alongside the bars of a query
+// - keeps the latest snapshot at or before the current bar and peeks at the next one
+struct OrderBookStream {
+    symbol_id: SymbolId,
+    reader: Reader,
+    current: Option<OrderBook>,
+    next: Option<OrderBook>,
+}
+
+impl OrderBookStream {
+    // open a stream of snapshots starting at a timestamp
+    // - the stream starts at the snapshot before the timestamp so the first bars have the book as of their time
+    fn new(symbol_id: SymbolId, filename: String, start_timestamp: i64) -> Result<Self, Error> {
+        let mut reader = Reader::new(filename)?;
+        let index = reader.find_timestamp(start_timestamp.saturating_add(1))?.saturating_sub(1);
+        reader.seek_record(index)?;
+        let next = reader.read_order_book().ok();
+
+        Ok(Self {
+            symbol_id,
+            reader,
+            current: None,
+            next,
+        })
+    }
+
+    // get the latest snapshot at or before a timestamp
+    fn as_of(&mut self, timestamp: i64) -> Option<&OrderBook> {
+        while let Some(next) = &self.next {
+            if next.timestamp > timestamp {
+                break;
+            }
+
+            self.current = self.next.take();
+            self.next = self.reader.read_order_book().ok();
+        }
+
+        self.current.as_ref()
+    }
+}
+
+
+// represents the files of a symbol that are read by a query
+// - a symbol is stored in a single file or in time partitions (files are in timestamp order)
+// - the bars of the symbol use its interned id
+#[derive(Debug, Clone)]
+pub struct QuerySource {
+    pub symbol_id: SymbolId,
+    pub exchange: String,
+    pub symbol: String,
+    pub files: Vec<String>,
+    pub actions_file: String,
+
+    // seconds between the records of the files
+    pub base_seconds: i64,
+
+    // range of the records that are read (inclusive, none reads the range of the query)
+    pub range: Option<(i64, i64)>,
+}
+
+impl QuerySource {
+    pub fn new(symbol_id: SymbolId, exchange: String, symbol: String, files: Vec<String>, actions_file: String) -> Self {
+        Self {
+            symbol_id,
+            exchange,
+            symbol,
+            files,
+            actions_file,
+            base_seconds: DEFAULT_RESOLUTION as i64,
+            range: None,
+        }
+    }
+
+    // set the seconds between the records of the files
+    pub fn with_base_seconds(mut self, base_seconds: i64) -> Self {
+        self.base_seconds = base_seconds;
+        self
+    }
+}
+
 // query task - starts the tasks for reading file chunks, synchronizing the data, and consolidating the data
 pub struct QueryTask {
     thread_pool: Arc<ThreadPool>,
     channel: Arc<Sender<BarSet>>,
-    files: Vec<String>,
+    sources: Vec<QuerySource>,
     limit: i32,
     start_timestamp: i64,
     end_timestamp: i64,
+    fields: Vec<String>,
+
+    // order book files to synchronize with the bars (symbol id, filename)
+    order_books: Vec<(SymbolId, String)>,
+    order_book_levels: usize,
+
+    // how prices are adjusted for corporate actions
+    adjustment: PriceAdjustment,
+
+    // what happens when a block of records does not match its checksum
+    verify: VerifyPolicy,
+
+    // consolidates the bars into the intervals of the query
+    consolidate_task: Option<ConsolidateTask>,
+
+    // set when the query is stopped before every page has been read
+    stop: Arc<AtomicBool>,
+}
+
+// load the corporate actions table of a symbol
+// - dividends without a reference price use the close of the last record before the ex-date
+pub fn load_corporate_actions(source: &QuerySource, adjustment: PriceAdjustment) -> Result<CorporateActions, Error> {
+    let mut corporate_actions = CorporateActions::from_file(&source.actions_file)?;
+    if adjustment != PriceAdjustment::TotalReturn || !corporate_actions.has_missing_reference_prices() {
+        return Ok(corporate_actions);
+    }
+
+    for action in corporate_actions.actions.iter_mut() {
+        if action.kind != CorporateActionKind::Dividend || action.reference_price.is_some() {
+            continue;
+        }
+
+        // the files are in timestamp order so the last record before the ex-date is in the last file that has one
+        for filename in source.files.iter() {
+            let mut reader = Reader::new(filename.to_string())?;
+            let index = reader.find_timestamp(action.timestamp)?;
+            if index > 0 {
+                action.reference_price = Some(reader.read_candlestick_at(index - 1)?.close);
+            }
+            if index < reader.record_count()? {
+                break;
+            }
+        }
+
+        if action.reference_price.is_none() {
+            println!("thread.tasks.query: no reference price for the dividend at {} of {}:{}", action.timestamp, source.exchange, source.symbol);
+        }
+    }
+
+    Ok(corporate_actions)
 }
 
 impl QueryTask {
@@ -19,35 +150,96 @@ impl QueryTask {
     pub fn new(
         thread_pool: Arc<ThreadPool>,
         channel: Arc<Sender<BarSet>>,
-        files: Vec<String>,
+        sources: Vec<QuerySource>,
         start_timestamp: i64,
         end_timestamp: i64,
-        limit: i32
+        limit: i32,
+        fields: Vec<String>
     ) -> Self {
         Self {
             thread_pool,
             channel,
-            files,
+            sources,
             start_timestamp,
             end_timestamp,
-            limit
+            limit,
+            fields,
+            order_books: Vec::new(),
+            order_book_levels: 0,
+            adjustment: PriceAdjustment::Raw,
+            verify: VerifyPolicy::Fail,
+            consolidate_task: None,
+            stop: Arc::new(AtomicBool::new(false)),
         }
     }
+
+    // consolidate the bars into larger intervals
+    pub fn with_consolidation(mut self, consolidate_task: ConsolidateTask) -> Self {
+        self.consolidate_task = Some(consolidate_task);
+        self
+    }
+
+    // adjust the prices of the bars for corporate actions
+    pub fn with_adjustment(mut self, adjustment: PriceAdjustment) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    // set what happens when a block of records does not match its checksum
+    pub fn with_verify(mut self, verify: VerifyPolicy) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    // stop reading pages once a flag is set
+    pub fn with_stop(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    // synchronize order book snapshots with the bars of the query
+    pub fn with_order_books(mut self, order_books: Vec<(SymbolId, String)>, levels: u8) -> Self {
+        self.order_books = order_books;
+        self.order_book_levels = levels as usize;
+        self
+    }
 }
 
 impl Task for QueryTask {
     // execute the query task
+    // - a query that fails sends a last page with its error so the client does not wait for the pages that are left
     fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
+        let result = self.run();
+        if let Err(e) = &result {
+            println!("thread.tasks.query: {}", e);
+            if let Err(e) = self.channel.send(BarSet::new_failed(e)) {
+                println!("thread.tasks.query: error sending the error of the query to main thread: {}", e);
+            }
+        }
+
+        // call the on exit function
+        if let Some(on_exit) = on_exit {
+            on_exit(result.is_ok());
+        }
+    }
+}
+
+impl QueryTask {
+    // read, synchronize and consolidate every page of the query and send them to the main thread
+    fn run(&mut self) -> Result<(), Error> {
         println!("thread.tasks.query: querying files");
-        println!("thread.tasks.query: files: {:?}", self.files);
+        println!("thread.tasks.query: files: {:?}", self.sources.iter().flat_map(|source| source.files.iter()).collect::<Vec<&String>>());
 
-        // define pagination
-        let total_bars = (self.end_timestamp - self.start_timestamp) / 60;
+        // define pagination (pages are windows of time so every file of a symbol is read by timestamp)
+        // - pages hold a limit of bars of the finest base resolution of the sources
+        let base_seconds = self.sources.iter().map(|source| source.base_seconds).min().unwrap_or(DEFAULT_RESOLUTION as i64);
+        let total_bars = (self.end_timestamp - self.start_timestamp) / base_seconds + 1;
         let page_size = self.limit as i64;
         let mut page_count = total_bars / page_size;
         if total_bars % page_size != 0 {
             page_count += 1;
         }
+        let page_seconds = page_size * base_seconds;
 
         println!("thread.tasks.query: start timestamp: {}", self.start_timestamp);
         println!("thread.tasks.query: end timestamp: {}", self.end_timestamp);
@@ -55,107 +247,179 @@ impl Task for QueryTask {
         println!("thread.tasks.query: page size: {}", page_size);
         println!("thread.tasks.query: page count: {}", page_count);
 
+        // open the order book streams at the start of the query
+        let mut order_book_streams = Vec::new();
+        for (symbol_id, filename) in self.order_books.iter() {
+            let stream = OrderBookStream::new(*symbol_id, filename.clone(), self.start_timestamp)
+                .map_err(|e| Error::new(e.kind(), format!("error opening order book file {}: {}", filename, e)))?;
+            order_book_streams.push(stream);
+        }
+
+        // load the corporate actions table of every symbol
+        let mut corporate_actions = Vec::new();
+        for source in self.sources.iter() {
+            let actions = load_corporate_actions(source, self.adjustment)
+                .map_err(|e| Error::new(e.kind(), format!("error loading corporate actions for {}:{}: {}", source.exchange, source.symbol, e)))?;
+            corporate_actions.push(Arc::new(actions));
+        }
+
+        // read the range of every file from its header so pages only read the files they overlap
+        let mut file_ranges = HashMap::new();
+        for filename in self.sources.iter().flat_map(|source| source.files.iter()) {
+            let header = Reader::new(filename.to_string()).and_then(|mut reader| reader.read_header().cloned())
+                .map_err(|e| Error::new(e.kind(), format!("error reading header of file {}: {}", filename, e)))?;
+            match header.end_timestamp {
+                0 => file_ranges.insert(filename.to_string(), (i64::MIN, i64::MAX)),
+                _ => file_ranges.insert(filename.to_string(), (header.start_timestamp as i64, header.end_timestamp as i64)),
+            };
+        }
+
+        // timestamp of the last bar of every symbol used to detect ex-dates between bars
+        let mut last_timestamps: HashMap<usize, i64> = HashMap::new();
+
         // loop through all the pages using the start_timestamp and the end_timestamp
         // setup all pages before waiting for results on any page
-        // create a read chunk task for each file
+        // create a read chunk task for each file that overlaps the page
         let mut receivers = HashMap::new();
         for page in 0..page_count {
             println!("thread.tasks.query: page {}", page);
+            let page_start = self.start_timestamp + page * page_seconds;
+            let page_end = (page_start + page_seconds).min(self.end_timestamp + 1);
 
             // loop through all the files for the query
-            for filename in self.files.iter() {
-                let start = Instant::now();
-
-                // create a new reader for the file
-                let reader = Reader::new(filename.to_string());
-
-                // create a channel for the read chunk task to send the bars back to the query task
-                let (sender, receiver) = crossbeam::channel::unbounded();
-                receivers.insert(format!("{}{}", page, filename), receiver);
-                let channel = Arc::new(sender);
-
-                // create a new read chunk task
-                let offset = page * page_size;
-                let mut read_task = ReadChunkTask::new(Arc::clone(&channel), filename.to_string(), reader, page_size as i32, offset as i32);
-
-                // start the read chunk task
-                let filename_thread = filename.clone().to_string();
-                self.thread_pool.execute(move || {
-                    read_task.execute(Some(Box::new(move |result: bool| {
-                        println!("thread.tasks.query: read chunk task {} finished in {}ms", filename_thread, (start.elapsed().as_nanos() as f64 / 1_000_000.0));
-                    })));
-                });
+            for (source_index, source) in self.sources.iter().enumerate() {
+                // the part of the page in the range of the source
+                let (read_start, read_end) = match source.range {
+                    Some((start, end)) => (page_start.max(start), page_end.min(end.saturating_add(1))),
+                    None => (page_start, page_end),
+                };
+
+                let mut source_receivers = Vec::new();
+                for filename in source.files.iter() {
+                    let (file_start, file_end) = file_ranges[filename];
+                    if read_start >= read_end || file_start >= read_end || file_end < read_start {
+                        continue;
+                    }
+
+                    let start = Instant::now();
+
+                    // create a new reader for the file
+                    let reader = Reader::new(filename.to_string())
+                        .map_err(|e| Error::new(e.kind(), format!("error opening file {}: {}", filename, e)))?
+                        .with_verify(self.verify);
+
+                    // create a channel for the read chunk task to send the bars back to the query task
+                    let (sender, receiver) = crossbeam::channel::unbounded();
+                    source_receivers.push(receiver);
+                    let channel = Arc::new(sender);
+
+                    // create a new read chunk task
+                    let mut read_task = ReadChunkTask::new(Arc::clone(&channel), filename.to_string(), reader, page_size as i32, 0, self.fields.clone())
+                        .with_range(read_start, read_end)
+                        .with_adjustment(Arc::clone(&corporate_actions[source_index]), self.adjustment);
+
+                    // start the read chunk task
+                    let filename_thread = filename.clone().to_string();
+                    self.thread_pool.execute(move || {
+                        read_task.execute(Some(Box::new(move |result: bool| {
+                            let status = if result { "finished" } else { "failed" };
+                            println!("thread.tasks.query: read chunk task {} {} in {}ms", filename_thread, status, (start.elapsed().as_nanos() as f64 / 1_000_000.0));
+                        })));
+                    });
+                }
+                receivers.insert((page, source_index), source_receivers);
             }
         }
 
         for page in 0..page_count {
-            let mut file_bars: HashMap<String, Vec<Candlestick>> = HashMap::new();
-            
-            // loop through all files to get the read chunk tasks results
-            for filename in self.files.iter() {
-                // get the receiver for the read chunk task
-                let receiver = match receivers.get(&format!("{}{}", page, filename)) {
-                    Some(receiver) => receiver,
-                    None => {
-                        println!("thread.tasks.query: error getting receiver for file {}", filename);
-                        return;
-                    }
-                };
+            if self.stop.load(Ordering::SeqCst) {
+                println!("thread.tasks.query: query stopped at page {}", page);
+                return Ok(());
+            }
 
-                // wait for the read chunk task to finish
-                let bars = match receiver.recv() {
-                    Ok(bars) => bars,
-                    Err(e) => {
-                        println!("thread.tasks.query: error receiving bars from read chunk task: {}", e);
-                        return;
-                    }
-                };
+            let mut source_bars: Vec<Vec<Candlestick>> = Vec::new();
 
-                // add the bars to the file_bars map
-                file_bars.insert(filename.to_string(), bars);
+            // loop through all symbols to get the read chunk tasks results (in the order of their files)
+            for source_index in 0..self.sources.len() {
+                let mut bars = Vec::new();
+                for receiver in receivers.get(&(page, source_index)).unwrap() {
+                    // wait for the read chunk task to finish
+                    let chunk = receiver.recv()
+                        .map_err(|e| Error::new(ErrorKind::BrokenPipe, format!("error receiving bars from read chunk task: {}", e)))?;
+                    bars.extend(chunk?);
+                }
+
+                source_bars.push(bars);
             }
 
             // synchronize the bars from the files into a set of bars a barset
             let start = Instant::now();
             let mut barset = BarSet::new();
-            for (filename, candlesticks) in file_bars.iter() {
+            let mut bar_indexes: HashMap<i64, usize> = HashMap::new();
+            for (source_index, candlesticks) in source_bars.into_iter().enumerate() {
                 // println!("thread.tasks.query: candlesticks: {:?}", candlesticks);
 
-                let parts: Vec<&str> = filename.split('/').collect();
-                let exchange_symbol: Vec<&str> = parts[2].split('_').collect();
-                let exchange = exchange_symbol[0];
-                let symbol = exchange_symbol[1].replace(".stmdb", "");
+                let symbol_id = self.sources[source_index].symbol_id;
+                let actions = &corporate_actions[source_index];
 
-                for candlestick in candlesticks.iter() {
+                for candlestick in candlesticks {
                     let timestamp = candlestick.timestamp;
 
-                    // check if the barset has a bar representing the timestamp
-                    let bar = barset.bars.iter_mut().find(|b| b.timestamp == timestamp);
-                    match bar {
-                        Some(bar) => {
-                            // has a bar for the timestamp
-                            // check if we have the candlestick in that bar
-                            let source_id = format!("{}:{}", exchange, symbol);
-                            if !bar.has_candlestick(source_id) {
-                                // add the candlestick to the bar
-                                bar.add_candlestick(format!("{}:{}", exchange, symbol), candlestick.clone());
-                            }
-                        },
-                        None => {
-                            // does not have a bar for the timestamp
-                            // create a new bar
-                            let mut bar = Bar::new(timestamp);
-
-                            // add the candlestick to the bar
-                            bar.add_candlestick(format!("{}:{}", exchange, symbol), candlestick.clone());
-
-                            // add the bar to the barset
-                            barset.bars.push(bar);
+                    // corporate actions with an ex-date since the last bar of the symbol
+                    let last_timestamp = last_timestamps.insert(source_index, timestamp).unwrap_or(self.start_timestamp - 1);
+                    let events = if actions.actions.is_empty() { vec![] } else { actions.between(last_timestamp, timestamp) };
+
+                    // get the bar representing the timestamp (a new bar if there is none yet)
+                    let index = *bar_indexes.entry(timestamp).or_insert_with(|| {
+                        barset.bars.push(Bar::new(timestamp));
+                        barset.bars.len() - 1
+                    });
+                    let bar = &mut barset.bars[index];
+
+                    // the first candlestick of the symbol at the timestamp is kept
+                    bar.add_candlestick(symbol_id, candlestick);
+                    for event in events {
+                        bar.add_corporate_action(symbol_id, event);
+                    }
+                }
+            }
+
+            // bars are created in the order the files are read
+            barset.bars.sort_by_key(|bar| bar.timestamp);
+
+            // attach the latest order book snapshot as of each bar
+            if !order_book_streams.is_empty() {
+                for bar in barset.bars.iter_mut() {
+                    for stream in order_book_streams.iter_mut() {
+                        let symbol_id = stream.symbol_id;
+                        let levels = self.order_book_levels;
+                        if let Some(order_book) = stream.as_of(bar.timestamp) {
+                            let mut order_book = order_book.clone();
+                            order_book.truncate(levels);
+                            bar.set_order_book(symbol_id, order_book);
                         }
                     }
                 }
             }
 
+            // consolidate the bars into the intervals of the query
+            // - bars left without candlesticks (outside of the sessions) are dropped
+            // - the periods that are still being built at the end of the query are added to its last bar
+            if let Some(consolidate_task) = self.consolidate_task.as_mut() {
+                for bar in barset.bars.iter_mut() {
+                    consolidate_task.update(bar);
+                }
+                barset.bars.retain(|bar| !bar.candlesticks.is_empty() || !bar.corporate_actions.is_empty());
+
+                if page == page_count - 1 {
+                    let mut bar = barset.bars.pop().unwrap_or_else(|| Bar::new(self.end_timestamp));
+                    consolidate_task.flush(&mut bar);
+                    if !bar.candlesticks.is_empty() || !bar.corporate_actions.is_empty() || bar.has_consolidated() {
+                        barset.bars.push(bar);
+                    }
+                }
+            }
+
             println!("thread.tasks.query: barset of {} bars synchronized in {}ms", barset.bars.len(), (start.elapsed().as_nanos() as f64 / 1_000_000.0));
 
             // last page of results for the query task
@@ -174,9 +438,50 @@ impl Task for QueryTask {
             }
         }
 
-        // call the on exit function
-        if let Some(on_exit) = on_exit {
-            on_exit(true);
-        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{models::{header::Header, order_book::OrderBookLevel}, storage::Writer};
+
+    fn order_book_file(name: &str, timestamps: &[i64]) -> String {
+        let filename = std::env::temp_dir().join(format!("{}_{}.stmdb", name, std::process::id())).to_string_lossy().to_string();
+        let order_books = timestamps.iter().map(|timestamp| {
+            OrderBook::new_with(*timestamp, vec![OrderBookLevel::new(99.0, 1.0)], vec![OrderBookLevel::new(101.0, 1.0)])
+        }).collect::<Vec<OrderBook>>();
+
+        let mut writer = Writer::new(filename.clone()).unwrap();
+        writer.write_header(Header::new_order_book(0, 0, 0, 1)).unwrap();
+        writer.write_order_books(&order_books).unwrap();
+        writer.flush().unwrap();
+
+        filename
+    }
+
+    #[test]
+    fn order_book_stream_starts_at_the_snapshot_before_the_query() {
+        let filename = order_book_file("query_order_book_before", &[100, 200, 300]);
+        let mut stream = OrderBookStream::new(0, filename.clone(), 250).unwrap();
+
+        assert_eq!(stream.as_of(250).map(|order_book| order_book.timestamp), Some(200));
+        assert_eq!(stream.as_of(300).map(|order_book| order_book.timestamp), Some(300));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn order_book_stream_has_no_snapshot_before_the_first_one() {
+        let filename = order_book_file("query_order_book_first", &[100, 200]);
+        let mut stream = OrderBookStream::new(0, filename.clone(), 50).unwrap();
+
+        assert!(stream.as_of(50).is_none());
+        assert_eq!(stream.as_of(150).map(|order_book| order_book.timestamp), Some(100));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn order_book_stream_fails_without_a_file() {
+        assert!(OrderBookStream::new(0, "missing_L2.stmdb".to_string(), 0).is_err());
+    }
+}
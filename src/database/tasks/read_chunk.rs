@@ -1,46 +1,67 @@
-use std::sync::{Arc};
+use std::{sync::Arc, io::{Error, ErrorKind}};
 use crossbeam::channel::Sender;
-use crate::database::{models::{candlestick::Candlestick}, storage::Reader};
+use crate::database::{models::{candlestick::Candlestick, corporate_action::{CorporateActions, PriceAdjustment}}, storage::Reader};
 use super::Task;
 
 
 // a task that will read a chunk of data from a file
 pub struct ReadChunkTask {
-    pub channel: Arc<Sender<Vec<Candlestick>>>,
+    pub channel: Arc<Sender<Result<Vec<Candlestick>, Error>>>,
     pub filename: String,
     pub reader: Reader,
     pub limit: i32,
     pub offset: i32,
+
+    // extra fields to keep on the candlesticks (empty keeps every field)
+    pub fields: Vec<String>,
+
+    // corporate actions used to adjust the prices of the candlesticks
+    pub corporate_actions: Option<Arc<CorporateActions>>,
+    pub adjustment: PriceAdjustment,
+
+    // time range to read instead of the limit and offset (start inclusive, end exclusive)
+    pub range: Option<(i64, i64)>,
 }
 impl ReadChunkTask {
     // create a new read chunk task
-    pub fn new(channel: Arc<Sender<Vec<Candlestick>>>, filename: String, reader: Reader, limit: i32, offset: i32) -> Self {
+    pub fn new(channel: Arc<Sender<Result<Vec<Candlestick>, Error>>>, filename: String, reader: Reader, limit: i32, offset: i32, fields: Vec<String>) -> Self {
         Self {
             channel,
             filename,
             reader,
             limit,
             offset,
+            fields,
+            corporate_actions: None,
+            adjustment: PriceAdjustment::Raw,
+            range: None,
         }
     }
+
+    // read the records of a time range (start inclusive, end exclusive)
+    pub fn with_range(mut self, start_timestamp: i64, end_timestamp: i64) -> Self {
+        self.range = Some((start_timestamp, end_timestamp));
+        self
+    }
+
+    // adjust the prices of the candlesticks for corporate actions
+    pub fn with_adjustment(mut self, corporate_actions: Arc<CorporateActions>, adjustment: PriceAdjustment) -> Self {
+        self.corporate_actions = Some(corporate_actions);
+        self.adjustment = adjustment;
+        self
+    }
 }
 
 impl Task for ReadChunkTask {
     // execute the read chunk task
+    // - the bars or the error of the read are sent back to the query thread
     fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
         // println!("thread.tasks.read_chunk: reading chunk of file {}", self.filename);
-
-        // read the chunk of data from the file
-        let bars: Vec<Candlestick> = match self.reader.read_chunk(self.limit, self.offset) {
-            Ok(chunk) => chunk.candlesticks,
-            Err(e) => {
-                println!("thread.tasks.read_chunk: error reading chunk of file {}: {}", self.filename, e);
-                return;
-            }
-        };
+        let result = self.read();
+        let is_ok = result.is_ok();
 
         // send the chunk of bars back to the query thread
-        match self.channel.send(bars) {
+        match self.channel.send(result) {
             Ok(_) => {
                 // println!("thread.tasks.read_chunk: chunk of bars sent to query thread");
             },
@@ -51,7 +72,45 @@ impl Task for ReadChunkTask {
 
         // call the on exit callback
         if let Some(callback) = on_exit {
-            callback(true);
+            callback(is_ok);
         }
     }
+}
+
+impl ReadChunkTask {
+    // read the chunk of bars from the file, keep the requested fields and adjust their prices
+    fn read(&mut self) -> Result<Vec<Candlestick>, Error> {
+        // make sure the file declares every field that was requested
+        let header = self.reader.header()
+            .map_err(|e| Error::new(e.kind(), format!("error reading header of file {}: {}", self.filename, e)))?;
+        if let Some(field) = self.fields.iter().find(|field| !header.has_field(field)) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("file {} does not have field {}", self.filename, field)));
+        }
+
+        // read the chunk of data from the file
+        let chunk = match self.range {
+            Some((start_timestamp, end_timestamp)) => self.reader.read_range(start_timestamp, end_timestamp),
+            None => self.reader.read_chunk(self.limit, self.offset).map(|chunk| chunk.candlesticks),
+        };
+        let mut bars = chunk.map_err(|e| Error::new(e.kind(), format!("error reading chunk of file {}: {}", self.filename, e)))?;
+
+        // only keep the extra fields that were requested
+        if !self.fields.is_empty() {
+            for bar in bars.iter_mut() {
+                bar.retain_extensions(&self.fields);
+            }
+        }
+
+        // adjust the prices for splits and dividends
+        if let Some(corporate_actions) = &self.corporate_actions {
+            if self.adjustment != PriceAdjustment::Raw {
+                for bar in bars.iter_mut() {
+                    let (price_factor, volume_factor) = corporate_actions.factors(bar.timestamp, self.adjustment);
+                    bar.adjust(price_factor, volume_factor);
+                }
+            }
+        }
+
+        Ok(bars)
+    }
 }
\ No newline at end of file
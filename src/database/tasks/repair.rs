@@ -0,0 +1,422 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Error, ErrorKind};
+use super::Task;
+use super::super::models::candlestick::Candlestick;
+use super::super::models::header::{Header, RecordKind, CHECKSUM_SIZE};
+use super::super::storage::Writer;
+
+
+// represents what was salvaged from a damaged stmdb file
+// - lost ranges are the timestamps of the last good record before and the first good record after each gap
+//   (none when the gap is at the start or the end of the file)
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub filename: String,
+    pub output: String,
+    pub blocks_checked: u64,
+    pub blocks_salvaged: u64,
+    pub blocks_lost: u64,
+    pub records_salvaged: u64,
+    pub records_lost: u64,
+    pub lost_ranges: Vec<(Option<i64>, Option<i64>)>,
+}
+
+impl RepairReport {
+    // write the report as key=value lines
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("file={}", self.filename),
+            format!("output={}", self.output),
+            format!("blocks_checked={}", self.blocks_checked),
+            format!("blocks_salvaged={}", self.blocks_salvaged),
+            format!("blocks_lost={}", self.blocks_lost),
+            format!("records_salvaged={}", self.records_salvaged),
+            format!("records_lost={}", self.records_lost),
+        ];
+
+        for (start, end) in self.lost_ranges.iter() {
+            let start = start.map_or("start".to_string(), |timestamp| timestamp.to_string());
+            let end = end.map_or("end".to_string(), |timestamp| timestamp.to_string());
+            lines.push(format!("lost_range={}-{}", start, end));
+        }
+
+        lines.join("\n") + "\n"
+    }
+}
+
+// scans a damaged stmdb file and writes the records that can still be read into a new file
+// - files with checksums (version 3 and later) are checked block by block, a damaged block is skipped and the scan resynchronises
+//   on the next offset where a block matches its checksum
+// - older files have no checksums so every record is checked against the layout bytes of the header
+//   (field count, length and type of every field) and the scan resynchronises on the next valid record
+//   (records are not written with the 0x1337 terminator of the conversion format so it can not be used here)
+// - records that go back in time are treated as damaged
+// - the salvaged copy is written as a version 4 file with the resolution of the original next to it with a report of what was lost
+pub struct RepairTask {
+    pub filename: String,
+    pub output: String,
+    pub report_filename: String,
+    pub report: Option<RepairReport>,
+}
+
+impl RepairTask {
+    pub fn new(filename: String) -> Self {
+        let base = filename.strip_suffix(".stmdb").unwrap_or(&filename).to_string();
+
+        Self {
+            filename,
+            output: format!("{}.repaired.stmdb", base),
+            report_filename: format!("{}.repair.txt", base),
+            report: None,
+        }
+    }
+
+    // scan the file, write the salvaged copy and the report
+    pub fn run(&mut self) -> Result<RepairReport, Error> {
+        let header = Header::from_reader(&mut BufReader::new(File::open(&self.filename)?))?;
+        if header.record_kind != RecordKind::Candlestick {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("repair: {} does not contain candlesticks", self.filename)));
+        }
+
+        let bytes = fs::read(&self.filename)?;
+        let data = &bytes[header.size().min(bytes.len())..];
+
+        let mut scan = Scan::new(self.filename.clone(), self.output.clone());
+        match header.block_size {
+            0 => scan.records(&header, data),
+            _ => scan.blocks(&header, data),
+        }
+        scan.close();
+
+        // write the salvaged records
+        let records = scan.records;
+        let mut output_header = Header::new_with(
+            header.dataset_id,
+            records.first().map_or(0, |candlestick| candlestick.timestamp as u32),
+            records.last().map_or(0, |candlestick| candlestick.timestamp as u32),
+            header.fields.clone(),
+        );
+        output_header.block_size = if header.block_size > 0 { header.block_size } else { output_header.block_size };
+        output_header.resolution = header.resolution;
+
+        let mut writer = Writer::new(self.output.clone())?;
+        writer.write_header(output_header)?;
+        writer.write_candlesticks(&records)?;
+        writer.flush()?;
+
+        fs::write(&self.report_filename, scan.report.to_text())?;
+        self.report = Some(scan.report.clone());
+
+        Ok(scan.report)
+    }
+}
+
+// state of a scan through the records of a damaged file
+struct Scan {
+    report: RepairReport,
+    records: Vec<Candlestick>,
+    last_timestamp: Option<i64>,
+    in_gap: bool,
+}
+
+impl Scan {
+    fn new(filename: String, output: String) -> Self {
+        Self {
+            report: RepairReport {
+                filename,
+                output,
+                ..RepairReport::default()
+            },
+            records: Vec::new(),
+            last_timestamp: None,
+            in_gap: false,
+        }
+    }
+
+    // check every block against its checksum
+    fn blocks(&mut self, header: &Header, data: &[u8]) {
+        let record_size = header.record_size();
+        let block_bytes = header.block_bytes();
+
+        let mut position = 0;
+        while position < data.len() {
+            self.report.blocks_checked += 1;
+
+            if let Some(length) = valid_block(data, position, block_bytes, record_size) {
+                if self.salvage(header, &data[position..(position + length - CHECKSUM_SIZE)]) {
+                    self.report.blocks_salvaged += 1;
+                }
+                else {
+                    self.report.blocks_lost += 1;
+                }
+                position += length;
+                continue;
+            }
+
+            // resynchronise on the next block boundary (damaged bytes) or the next offset that starts a valid block (missing bytes)
+            let aligned = position + block_bytes;
+            let next = match aligned >= data.len() || valid_block(data, aligned, block_bytes, record_size).is_some() {
+                true => aligned.min(data.len()),
+                false => ((position + 1)..data.len())
+                    .find(|offset| valid_block(data, *offset, block_bytes, record_size).is_some())
+                    .unwrap_or(data.len()),
+            };
+
+            println!("thread.tasks.repair: damaged block at byte {} of {}", header.size() + position, self.report.filename);
+            self.report.blocks_lost += 1;
+            self.lose((((next - position) as f64) / record_size as f64).round() as u64);
+            position = next;
+        }
+    }
+
+    // check every record against the layout of the header
+    fn records(&mut self, header: &Header, data: &[u8]) {
+        let record_size = header.record_size();
+        let valid = |offset: usize| -> bool {
+            offset + record_size <= data.len() && Candlestick::check_layout(&data[offset..(offset + record_size)], &header.fields)
+        };
+
+        let mut position = 0;
+        while position + record_size <= data.len() {
+            if valid(position) {
+                self.salvage(header, &data[position..(position + record_size)]);
+                position += record_size;
+                continue;
+            }
+
+            // resynchronise on the next offset where a record (and the one after it) has a valid layout
+            let next = ((position + 1)..data.len())
+                .find(|offset| valid(*offset) && (offset + record_size * 2 > data.len() || valid(offset + record_size)))
+                .unwrap_or(data.len());
+
+            println!("thread.tasks.repair: damaged record at byte {} of {}", header.size() + position, self.report.filename);
+            self.lose(((next - position) as f64 / record_size as f64).ceil() as u64);
+            position = next;
+        }
+
+        // trailing bytes of an incomplete record
+        if position < data.len() {
+            self.lose(1);
+        }
+    }
+
+    // read the records of a valid byte range (returns false if nothing could be salvaged)
+    fn salvage(&mut self, header: &Header, bytes: &[u8]) -> bool {
+        let mut cursor = Cursor::new(bytes);
+        let mut salvaged = false;
+
+        for _ in 0..(bytes.len() / header.record_size()) {
+            let candlestick = match Candlestick::from_reader(&mut cursor, &header.fields) {
+                Ok(candlestick) => candlestick,
+                Err(_) => {
+                    self.lose(1);
+                    break;
+                }
+            };
+
+            if self.last_timestamp.is_some_and(|timestamp| candlestick.timestamp <= timestamp) {
+                self.lose(1);
+                continue;
+            }
+
+            if self.in_gap {
+                self.report.lost_ranges.push((self.last_timestamp, Some(candlestick.timestamp)));
+                self.in_gap = false;
+            }
+
+            self.last_timestamp = Some(candlestick.timestamp);
+            self.report.records_salvaged += 1;
+            self.records.push(candlestick);
+            salvaged = true;
+        }
+
+        salvaged
+    }
+
+    // count records that could not be salvaged
+    fn lose(&mut self, count: u64) {
+        self.report.records_lost += count.max(1);
+        self.in_gap = true;
+    }
+
+    // record a gap that reaches the end of the file
+    fn close(&mut self) {
+        if self.in_gap {
+            self.report.lost_ranges.push((self.last_timestamp, None));
+            self.in_gap = false;
+        }
+    }
+}
+
+// get the length of the block at an offset if it matches its checksum
+// - only the last block of a file can be partial
+fn valid_block(data: &[u8], position: usize, block_bytes: usize, record_size: usize) -> Option<usize> {
+    let remaining = data.len() - position;
+    let length = match remaining >= block_bytes {
+        true => block_bytes,
+        false if remaining > CHECKSUM_SIZE && (remaining - CHECKSUM_SIZE).is_multiple_of(record_size) => remaining,
+        false => return None,
+    };
+
+    let records = &data[position..(position + length - CHECKSUM_SIZE)];
+    let checksum = &data[(position + length - CHECKSUM_SIZE)..(position + length)];
+    match crc32fast::hash(records) == u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+        true => Some(length),
+        false => None,
+    }
+}
+
+impl Task for RepairTask {
+    // execute the repair task
+    fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
+        let result = match self.run() {
+            Ok(report) => {
+                print!("{}", report.to_text());
+                true
+            },
+            Err(e) => {
+                println!("thread.tasks.repair: error repairing {}: {}", self.filename, e);
+                false
+            }
+        };
+
+        // call the on exit callback
+        if let Some(callback) = on_exit {
+            callback(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::storage::{Reader, VerifyPolicy};
+
+    // a file of 12 records (one minute apart) in blocks of 4 records, or without checksums when the block size is 0
+    fn file(name: &str, block_size: u16) -> (String, Header) {
+        let filename = std::env::temp_dir().join(format!("repair_{}_{}.stmdb", name, std::process::id())).to_string_lossy().to_string();
+        let candlesticks = (0..12).map(|i| Candlestick::new_with(i * 60, 1.0, 2.0, 0.5, 1.5, 10.0)).collect::<Vec<Candlestick>>();
+        let mut header = Header::new_with(0, 0, 660, Vec::new());
+        header.block_size = block_size;
+
+        let mut writer = Writer::new(filename.clone()).unwrap();
+        writer.write_header(header.clone()).unwrap();
+        writer.write_candlesticks(&candlesticks).unwrap();
+        writer.flush().unwrap();
+
+        (filename, header)
+    }
+
+    // the offset of a record in a file
+    fn offset(header: &Header, index: usize) -> usize {
+        match header.block_size {
+            0 => header.size() + index * header.record_size(),
+            block_size => header.size() + (index / block_size as usize) * header.block_bytes() + (index % block_size as usize) * header.record_size(),
+        }
+    }
+
+    fn timestamps(candlesticks: &[Candlestick]) -> Vec<i64> {
+        candlesticks.iter().map(|candlestick| candlestick.timestamp).collect()
+    }
+
+    fn remove(task: &RepairTask) {
+        for filename in [&task.filename, &task.output, &task.report_filename] {
+            fs::remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_follow_the_verify_policy() {
+        let (filename, header) = file("verify", 4);
+        let mut bytes = fs::read(&filename).unwrap();
+        // damage the close of the 6th record (the second block)
+        bytes[offset(&header, 5) + 40] ^= 0xff;
+        fs::write(&filename, bytes).unwrap();
+
+        let read = |verify: VerifyPolicy| Reader::new(filename.clone()).unwrap().with_verify(verify).read_candlesticks();
+        assert_eq!(read(VerifyPolicy::Fail).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(timestamps(&read(VerifyPolicy::SkipBlock).unwrap()), vec![0, 60, 120, 180, 480, 540, 600, 660]);
+        assert_eq!(read(VerifyPolicy::Report).unwrap().len(), 12);
+        // blocks that are not read are not checked
+        assert_eq!(timestamps(&Reader::new(filename.clone()).unwrap().read_records(8, 12).unwrap()), vec![480, 540, 600, 660]);
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn repairs_a_damaged_block() {
+        let (filename, header) = file("damaged", 4);
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes[offset(&header, 5) + 40] ^= 0xff;
+        fs::write(&filename, bytes).unwrap();
+
+        let mut task = RepairTask::new(filename);
+        let report = task.run().unwrap();
+        assert_eq!((report.blocks_checked, report.blocks_salvaged, report.blocks_lost), (3, 2, 1));
+        assert_eq!((report.records_salvaged, report.records_lost), (8, 4));
+        assert_eq!(report.lost_ranges, vec![(Some(180), Some(480))]);
+        assert_eq!(timestamps(&Reader::new(task.output.clone()).unwrap().read_candlesticks().unwrap()), vec![0, 60, 120, 180, 480, 540, 600, 660]);
+        assert!(fs::read_to_string(&task.report_filename).unwrap().contains("lost_range=180-480"));
+        remove(&task);
+    }
+
+    #[test]
+    fn repairs_missing_bytes() {
+        let (filename, header) = file("missing", 4);
+        let mut bytes = fs::read(&filename).unwrap();
+        // cut the last record of the file and half of the second block
+        bytes.truncate(bytes.len() - header.record_size() - CHECKSUM_SIZE);
+        bytes.drain(offset(&header, 4)..offset(&header, 6));
+        fs::write(&filename, bytes).unwrap();
+
+        let mut task = RepairTask::new(filename);
+        let report = task.run().unwrap();
+        assert_eq!(report.records_salvaged, 4);
+        assert_eq!(report.lost_ranges, vec![(Some(180), None)]);
+        remove(&task);
+    }
+
+    #[test]
+    fn repairs_keep_the_resolution_of_the_file() {
+        let filename = std::env::temp_dir().join(format!("repair_resolution_{}.stmdb", std::process::id())).to_string_lossy().to_string();
+        let candlesticks = (0..12).map(|i| Candlestick::new_with(i * 5, 1.0, 2.0, 0.5, 1.5, 10.0)).collect::<Vec<Candlestick>>();
+        let mut header = Header::new_with(0, 0, 55, Vec::new());
+        header.block_size = 4;
+        header.resolution = 5;
+        let mut writer = Writer::new(filename.clone()).unwrap();
+        writer.write_header(header.clone()).unwrap();
+        writer.write_candlesticks(&candlesticks).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes[offset(&header, 5) + 40] ^= 0xff;
+        fs::write(&filename, bytes).unwrap();
+
+        let mut task = RepairTask::new(filename);
+        task.run().unwrap();
+        let mut reader = Reader::new(task.output.clone()).unwrap();
+        let output_header = reader.header().unwrap();
+        assert_eq!((output_header.version, output_header.resolution), (4, 5));
+        assert_eq!(timestamps(&reader.read_candlesticks().unwrap()), vec![0, 5, 10, 15, 40, 45, 50, 55]);
+        remove(&task);
+    }
+
+    #[test]
+    fn repairs_a_file_without_checksums() {
+        let (filename, header) = file("unchecked", 0);
+        let mut bytes = fs::read(&filename).unwrap();
+        // damage the layout bytes of the 3rd record
+        bytes[offset(&header, 2)] = 0;
+        fs::write(&filename, bytes).unwrap();
+
+        let mut task = RepairTask::new(filename);
+        let report = task.run().unwrap();
+        assert_eq!((report.records_salvaged, report.records_lost), (11, 1));
+        assert_eq!(report.lost_ranges, vec![(Some(60), Some(180))]);
+        // the salvaged copy has checksums
+        let mut reader = Reader::new(task.output.clone()).unwrap();
+        assert!(reader.header().unwrap().block_size > 0);
+        assert_eq!(reader.read_candlesticks().unwrap().len(), 11);
+        remove(&task);
+    }
+}
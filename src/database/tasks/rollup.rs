@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use crate::database::{models::{bar::Bar, calendar::TradingCalendar, candlestick::Candlestick, header::Header, interval::Interval}, storage::Reader};
+use super::{Task, consolidate::ConsolidateTask, merge::{MergedFile, rewrite}};
+
+
+// a task that consolidates the base bars of a dataset into a rollup file of a larger interval
+// - bars are aligned to the regular sessions of the calendar of the exchange
+// - a resumed rollup keeps the bars of the existing rollup that end at or before the first changed record
+//   and only consolidates the base bars after them
+// - the last period of the dataset is only written once it is complete (or the next period has started)
+//   so it is consolidated again when newer records are added
+pub struct RollupTask {
+    pub files: Vec<String>,
+    pub target: String,
+    pub interval: Interval,
+    pub calendar: TradingCalendar,
+    pub base_seconds: i64,
+    pub changed_from: Option<i64>,
+    pub summary: Option<MergedFile>,
+}
+
+impl RollupTask {
+    // create a new rollup task from the base files of a dataset in timestamp order
+    pub fn new(files: Vec<String>, target: String, interval: Interval, calendar: TradingCalendar) -> Self {
+        Self {
+            files,
+            target,
+            interval,
+            calendar,
+            base_seconds: 60,
+            changed_from: None,
+            summary: None,
+        }
+    }
+
+    // set the seconds between the base bars of the dataset (1m by default)
+    pub fn with_base_seconds(mut self, base_seconds: i64) -> Self {
+        self.base_seconds = base_seconds;
+        self
+    }
+
+    // keep the bars of the existing rollup that end at or before the first changed record
+    pub fn with_resume(mut self, changed_from: i64) -> Self {
+        self.changed_from = Some(changed_from);
+        self
+    }
+
+    // consolidate the base bars and write the rollup file
+    pub fn run(&mut self) -> Result<MergedFile, Error> {
+        let header = match self.files.first() {
+            Some(filename) => Reader::new(filename.clone())?.header()?.clone(),
+            None => return Err(Error::new(ErrorKind::NotFound, format!("rollup: no files to roll up into {}", self.target))),
+        };
+
+        // bars of the existing rollup that are not affected by the change
+        let mut records: BTreeMap<i64, Candlestick> = BTreeMap::new();
+        if let Some(changed_from) = self.changed_from.filter(|_| Path::new(&self.target).exists()) {
+            for candlestick in Reader::new(self.target.clone())?.read_candlesticks()? {
+                match self.calendar.bucket(candlestick.timestamp, &self.interval, false) {
+                    Some((_, end)) if end <= changed_from => records.insert(candlestick.timestamp, candlestick),
+                    _ => break,
+                };
+            }
+        }
+        let resume_from = match records.last_key_value() {
+            Some((timestamp, _)) => self.calendar.bucket(*timestamp, &self.interval, false).map_or(i64::MIN, |(_, end)| end),
+            None => i64::MIN,
+        };
+
+        // consolidate the base bars of every file after the kept bars
+        let calendars = HashMap::from([(0, self.calendar.clone())]);
+        let mut consolidate_task = ConsolidateTask::new(vec![self.interval.clone()], calendars, false, self.base_seconds);
+        for filename in self.files.iter() {
+            let mut reader = Reader::new(filename.clone())?;
+            if reader.header()?.end_timestamp > 0 && (reader.header()?.end_timestamp as i64) < resume_from {
+                continue;
+            }
+
+            for candlestick in reader.read_range(resume_from, i64::MAX)? {
+                let mut bar = Bar::new(candlestick.timestamp);
+                bar.add_candlestick(0, candlestick);
+                consolidate_task.update(&mut bar);
+                if let Some(consolidated) = bar.get_consolidated(0, 0) {
+                    records.insert(consolidated.timestamp, consolidated.clone());
+                }
+            }
+        }
+
+        let mut header = Header::new_with(header.dataset_id, 0, 0, header.fields.clone());
+        header.resolution = self.interval.length() as u32;
+        let summary = rewrite(&self.target, None, &header, records)?;
+        println!("thread.tasks.rollup: {} {} bars written to {}", summary.record_count, self.interval.name, self.target);
+
+        self.summary = Some(summary.clone());
+        Ok(summary)
+    }
+}
+
+impl Task for RollupTask {
+    // execute the rollup task
+    fn execute(&mut self, on_exit: Option<Box<dyn FnOnce(bool) + Send + 'static>>) {
+        let result = match self.run() {
+            Ok(_) => true,
+            Err(e) => {
+                println!("thread.tasks.rollup: error rolling up {}: {}", self.target, e);
+                false
+            }
+        };
+
+        // call the on exit callback
+        if let Some(callback) = on_exit {
+            callback(result);
+        }
+    }
+}
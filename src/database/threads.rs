@@ -10,6 +10,7 @@ type Job = Box<dyn FnOnce() + Send + 'static>;
 // represents a thread pool
 // manages a set of threads that can be used to perform individualized units or separations of work
 pub struct ThreadPool {
+    #[allow(dead_code)]
     workers: Vec<Worker>,
     sender: Sender<Message>,
 }
@@ -51,6 +52,7 @@ impl ThreadPool {
     }
 
     // wait for all threads to finish executing
+    #[allow(dead_code)]
     pub fn join(&mut self) {
         for worker in &mut self.workers {
             println!("thread_pool: hutting down worker {}", worker.id);
@@ -66,12 +68,15 @@ impl ThreadPool {
 // represents a message sent to a worker
 enum Message {
     NewJob(Job),
+    #[allow(dead_code)]
     Terminate,
 }
 
 // represents a worker thread
 struct Worker {
+    #[allow(dead_code)]
     id: usize,
+    #[allow(dead_code)]
     thread: Option<thread::JoinHandle<()>>,
 }
 
@@ -79,7 +84,11 @@ impl Worker {
     // create a new worker thread with id and receiver channel
     fn new(id: usize, receiver: Arc<Mutex<Receiver<Message>>>) -> Worker {
         let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+            // the worker stops once the thread pool is dropped
+            let message = match receiver.lock().unwrap().recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            };
             match message {
                 Message::NewJob(job) => {
                     // println!("Worker {} got a job; executing.", id);
@@ -15,9 +15,9 @@ impl Dataset {
         let symbol = parts[1].to_string();
 
         Dataset {
-            name: name,
-            exchange: exchange,
-            symbol: symbol,
+            name,
+            exchange,
+            symbol,
             files: Vec::new(),
         }
     }
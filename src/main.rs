@@ -1,100 +1,71 @@
-use std::{io::stdin, time::Instant};
-use crate::database::{database::Database, models::{symbol::Symbol, exchange::Exchange, query::Query}};
+use commands::{Command, handle_command};
+use rustyline::Editor;
+
+// bring modules into scope
+mod utils;
+mod models;
+mod commands;
+mod threads;
+mod datasets;
+mod plugins;
+mod system;
+mod simulator;
 mod database;
 
-
-// since we now have a filesystem with our own database file format
-// we can create a custom database for accessing our market data from our filesystem
-// our queries are all exclusive
-// - the query will fail if there's no market data for a given exchange/symbol combination
-// -- we want to take care not to load any data that cannot be sync'd as this whole database if for backtesting strategies
-// -- we would rather the system fail than have a strategy run with bad data
-// -- we can always add more data later
-// - our queries consist of a limit of bars, start_time, end_time, intervals, symbols, and exchanges
-// -- this is a very powerful query system because it allows us to get exactly what we need in the format we need it
-// - we can perform optimizations behind the scenes to speed up the system
-// - we can use multi-threading and specific seek/read optimizations to speed up the system
-// we can cache our data in memory for faster access
-// we can also support a live data feed from the database
-
-
 fn main() {
+    // Get the path to the config file from the command line
+    let config_path = std::env::args().nth(1).unwrap_or("config.txt".to_string());
 
-    // create a new database instance
-    let database = Database::new();
-
-    // create a new client
-    let client_id = 1;
-
-    // variables for performance testing
-    let mut record_count = 0;
-    let start = Instant::now();
-
-    // generate custom query to database
-    let mut query = Query::new_with(client_id, database)
-        // defaults to 1000
-        .with_limit(2000)
-
-        // defaults to full datasets
-        .with_start_time(1577836860)
-        .with_end_time(1609459140)
-
-        // limited to 5 intervals for now
-        .with_intervals(Vec::from([
-            "5m".to_string(),
-            "15m".to_string(),
-            "1h".to_string(),
-            "4h".to_string(),
-            "1d".to_string()
-        ]))
-
-        // limited to 5 symbols for now
-        .with_symbols(Vec::from([
-            (Exchange::new_with("KuCoin".to_string()), Symbol::new_with("BTC".to_string(), "USDT".to_string())), 
-            (Exchange::new_with("KuCoin".to_string()), Symbol::new_with("ADA".to_string(), "USDT".to_string())),
-            (Exchange::new_with("KuCoin".to_string()), Symbol::new_with("DASH".to_string(), "USDT".to_string()))
-        ]))
+    // Read the config file into a Config struct
+    let config = utils::read_config(config_path);
 
-        // initialize the query (generate the id and start the tasks)
-        .start();
+    // create a REPL editor using rustyline
+    let mut rl = Editor::<()>::new().unwrap();
 
-    println!("starting query: {}", query.id.clone().unwrap());
-
-    // get historical data from database
-    // - when at least one barset is stored in the cache, the query will return
-    // - next() can be called multiple times to get the next barset
-    let mut last_timestamp = 0;
-    while let Ok(result) = query.next() {
-        if result.bars.len() == 0 {
-            continue;
-        }
-
-        // track total records
-        record_count += result.bars.len();
+    // load REPL history
+    if rl.load_history("./.engine-history.txt").is_err() {
+        println!("No history found");
+    }
 
-        // loop through the bars
-        for bar in result.bars {
-            if bar.timestamp < last_timestamp {
-                println!("main: timestamp error: {} < {}", bar.timestamp, last_timestamp);
-                return;
+    // Print the welcome message
+    println!("-----------------------------------------------------------------");
+    println!("Trade Engine version 0.1 (type 'exit' to quit or 'help' for help)");
+    println!("-----------------------------------------------------------------");
+
+    // create a core system
+    let mut core = system::Core::new(&config.clone());
+
+    // initialize the system by load plugins
+    core.initialize();
+
+    // update loop
+    loop {
+        // output a prompt and wait for input
+        let input = rl.readline("engine> ");
+
+        // handle the result
+        match input {
+            Ok(line) => {
+                let raw_line = line;
+                // add the input to our REPL history
+                rl.add_history_entry(raw_line.clone());
+
+                // parse the command
+                let command = Command::new(raw_line.clone());
+
+                // handle REPL commands and exit if we need to
+                let should_exit = handle_command(&mut core, command);
+                if should_exit {
+                    break;
+                }
+                
+            },
+            Err(_) => {
+                break;
             }
-
-            last_timestamp = bar.timestamp;
-        }
-
-        if result.status == "complete" {
-            break;
         }
     }
 
-    println!("main: query id: {}", query.id.clone().unwrap());
-    println!("main: symbols: {}", query.symbols.clone().len());
-    println!("main: total results: {}", record_count);
-    println!("main: query completed in {:?}s", (start.elapsed().as_millis() as f64 / 1000.0));
-
-    // get live data from database
-
-    // wait to exit
-    println!("Press any key to continue");
-    stdin().read_line(&mut String::new()).unwrap();
+    // save our REPL history to a file
+    rl.save_history("./.engine-history.txt").unwrap();
 }
\ No newline at end of file
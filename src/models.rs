@@ -1,26 +1,86 @@
+use crate::database::models::contract::ContractSpec;
 
 
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderStatus {
     Submitted,
+    #[allow(dead_code)]
     PartiallyFilled,
     Filled,
     Invalid,
 }
 
+impl OrderStatus {
+    // get the name of the value used in results files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Submitted => "submitted",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Invalid => "invalid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderSide {
     Buy,
     Sell
 }
 
+impl OrderSide {
+    // convert a string into an order side
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "buy" => Some(OrderSide::Buy),
+            "sell" => Some(OrderSide::Sell),
+            _ => None,
+        }
+    }
+
+    // get the name of the value used in results files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderSide::Buy => "buy",
+            OrderSide::Sell => "sell",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderType {
     Limit,
     Market
 }
 
+impl OrderType {
+    // convert a string into an order type
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "limit" => Some(OrderType::Limit),
+            "market" => Some(OrderType::Market),
+            _ => None,
+        }
+    }
+
+    // get the name of the value used in results files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Limit => "limit",
+            OrderType::Market => "market",
+        }
+    }
+}
+
+// represents an order of a strategy
+// - the timestamp is the bar the order was submitted at, the price is the fill price once it is filled
+#[derive(Debug, Clone)]
 pub struct Order {
     pub exchange: String,
     pub symbol: String,
     pub id: i32,
+    pub timestamp: i64,
     pub label: Option<String>,
     pub status: OrderStatus,
     pub side: OrderSide,
@@ -30,7 +90,46 @@ pub struct Order {
     pub price: f64,
 }
 
+impl Order {
+    // create a new market or limit order (the id is set when the order is submitted)
+    pub fn new(exchange: String, symbol: String, side: OrderSide, fill_type: OrderType, quantity: f64, limit: Option<f64>) -> Self {
+        Self {
+            exchange,
+            symbol,
+            id: 0,
+            timestamp: 0,
+            label: None,
+            status: OrderStatus::Submitted,
+            side,
+            fill_type,
+            quantity,
+            limit,
+            price: 0.0,
+        }
+    }
+
+    // round the order to the tick and lot size of the instrument and check its constraints
+    // - the order is marked invalid when it is below the lot size or min notional after rounding
+    pub fn apply_spec(&mut self, spec: &ContractSpec) -> bool {
+        self.quantity = spec.round_quantity(self.quantity);
+        self.price = spec.round_price(self.price);
+        if let Some(limit) = self.limit {
+            self.limit = Some(spec.round_price(limit));
+        }
+
+        let price = self.limit.unwrap_or(self.price);
+        if let Err(e) = spec.validate_order(price, self.quantity) {
+            println!("order: {}", e);
+            self.status = OrderStatus::Invalid;
+            return false;
+        }
+
+        true
+    }
+}
+
 
+#[allow(dead_code)]
 pub struct Metric<T> {
     pub name: String,
     pub value: T
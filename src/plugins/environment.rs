@@ -0,0 +1,229 @@
+use std::{io::{Error, Write}, fs};
+use rlua::Value;
+
+use crate::database::{models::{corporate_action::PriceAdjustment, coverage::AlignmentPolicy, interval::Interval, query::MAX_INTERVALS}, storage::VerifyPolicy};
+use super::strategies::HISTORY_LIMIT;
+
+// the most bars of every symbol and interval a strategy can keep in its history
+pub const MAX_HISTORY_LIMIT: usize = 5000;
+
+// the longest run of missing bars treated as missing data (longer gaps are market closures)
+pub const MAX_GAP_BARS: i64 = 60;
+
+// represents how a strategy handles bars missing from its datasets
+// - ignore: the bars are passed as they were read
+// - smoothing: missing bars are filled with flat bars at the last close (no volume)
+// - stop: the backtest stops at the first missing bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataMissingMode {
+    Ignore,
+    Smoothing,
+    Stop,
+}
+
+impl DataMissingMode {
+    // convert a string into a missing data mode
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "ignore" | "none" => Some(DataMissingMode::Ignore),
+            "smoothing" | "smooth" => Some(DataMissingMode::Smoothing),
+            "stop" => Some(DataMissingMode::Stop),
+            _ => None,
+        }
+    }
+
+    // get the name of the mode
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataMissingMode::Ignore => "ignore",
+            DataMissingMode::Smoothing => "smoothing",
+            DataMissingMode::Stop => "stop",
+        }
+    }
+}
+
+// the runtime settings a strategy declares with env(name, value)
+// - problems are collected so they are reported with the problems of the inputs
+#[derive(Debug, Clone)]
+pub struct StrategyEnv {
+    pub logging: bool,
+    pub verify: VerifyPolicy,
+    pub data_missing_mode: DataMissingMode,
+    pub history_limit: usize,
+    pub intervals: Vec<String>,
+    // read the rollups of the datasets instead of their base bars when they can build every interval
+    pub rollups: bool,
+    // the levels of the order book passed with the base bars (none when the order book is not read)
+    pub order_book: Option<u8>,
+    // the extra fields of the datasets passed with the bars (every declared field when empty)
+    pub fields: Vec<String>,
+    // how the prices of the datasets are adjusted for their corporate actions
+    pub price_adjustment: PriceAdjustment,
+    // pass the pre/post-market bars of exchanges with a calendar and consolidate them into the intervals
+    pub extended_hours: bool,
+    // the snapshot of the catalog the datasets are read at (the latest data when none is pinned)
+    pub snapshot: Option<u32>,
+    // how the bars of datasets with different date ranges are aligned
+    pub alignment: AlignmentPolicy,
+    // the commission of a fill as a fraction of its value
+    pub commission: f64,
+    pub errors: Vec<String>,
+}
+
+impl Default for StrategyEnv {
+    fn default() -> Self {
+        Self {
+            logging: false,
+            verify: VerifyPolicy::Fail,
+            data_missing_mode: DataMissingMode::Ignore,
+            history_limit: HISTORY_LIMIT,
+            intervals: Vec::new(),
+            rollups: false,
+            order_book: None,
+            fields: Vec::new(),
+            price_adjustment: PriceAdjustment::Raw,
+            extended_hours: false,
+            snapshot: None,
+            alignment: AlignmentPolicy::Union,
+            commission: 0.0,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl StrategyEnv {
+    // set a runtime setting from the arguments of the env hook
+    pub fn set(&mut self, name: &str, value: Value) {
+        if let Err(e) = self.parse(name, value) {
+            self.errors.push(format!("env {}: {}", name, e));
+        }
+    }
+
+    fn parse(&mut self, name: &str, value: Value) -> Result<(), String> {
+        match (name, value) {
+            ("logging", Value::Boolean(logging)) => self.logging = logging,
+
+            // checks either stop the backtest or are only reported, a verify policy can also be named
+            ("data_integrity_checks", Value::Boolean(checks)) => self.verify = if checks { VerifyPolicy::Fail } else { VerifyPolicy::Report },
+            ("data_integrity_checks", Value::String(policy)) => {
+                let policy = policy.to_str().unwrap_or_default();
+                self.verify = VerifyPolicy::from_string(policy).ok_or_else(|| format!("expected true, false, fail, skip or report, got '{}'", policy))?;
+            },
+
+            ("data_missing_mode", Value::String(mode)) => {
+                let mode = mode.to_str().unwrap_or_default();
+                self.data_missing_mode = DataMissingMode::from_string(mode).ok_or_else(|| format!("expected ignore, smoothing or stop, got '{}'", mode))?;
+            },
+
+            ("history_limit", Value::Integer(limit)) if limit >= 1 && limit as usize <= MAX_HISTORY_LIMIT => self.history_limit = limit as usize,
+            ("history_limit", Value::Integer(_)) => return Err(format!("expected 1 to {} bars", MAX_HISTORY_LIMIT)),
+
+            // intervals are a list or a single interval (ex: {"5m", "1h"} or "5m")
+            ("intervals", Value::String(interval)) => self.intervals = parse_intervals(vec![interval.to_str().unwrap_or_default().to_string()])?,
+            ("intervals", Value::Table(intervals)) => {
+                let intervals = intervals.sequence_values::<String>().collect::<rlua::Result<Vec<String>>>().map_err(|_| "expected a list of intervals".to_string())?;
+                self.intervals = parse_intervals(intervals)?;
+            },
+
+            ("rollups", Value::Boolean(rollups)) => self.rollups = rollups,
+
+            // the top levels of the order book as of every base bar (every dataset needs order book snapshots)
+            ("order_book", Value::Integer(levels)) if levels >= 1 && levels <= u8::MAX as i64 => self.order_book = Some(levels as u8),
+            ("order_book", Value::Integer(_)) => return Err(format!("expected 1 to {} levels", u8::MAX)),
+
+            // fields are a list or a single name (ex: {"open_interest", "funding_rate"}), every dataset needs to declare them
+            ("fields", Value::String(field)) => self.fields = vec![field.to_str().unwrap_or_default().to_string()],
+            ("fields", Value::Table(fields)) => {
+                self.fields = fields.sequence_values::<String>().collect::<rlua::Result<Vec<String>>>().map_err(|_| "expected a list of field names".to_string())?;
+            },
+
+            ("price_adjustment", Value::String(adjustment)) => {
+                let adjustment = adjustment.to_str().unwrap_or_default();
+                self.price_adjustment = PriceAdjustment::from_string(adjustment).ok_or_else(|| format!("expected raw, split_adjusted or total_return, got '{}'", adjustment))?;
+            },
+
+            ("extended_hours", Value::Boolean(extended_hours)) => self.extended_hours = extended_hours,
+
+            // a pinned snapshot reads the same bars after the datasets are updated so the backtest can be reproduced
+            ("snapshot", Value::Integer(snapshot_id)) if snapshot_id >= 0 && snapshot_id <= u32::MAX as i64 => self.snapshot = Some(snapshot_id as u32),
+            ("snapshot", Value::Integer(_)) => return Err(format!("expected a snapshot id from 0 to {}", u32::MAX)),
+
+            ("alignment", Value::String(alignment)) => {
+                let alignment = alignment.to_str().unwrap_or_default();
+                self.alignment = AlignmentPolicy::from_string(alignment).ok_or_else(|| format!("expected intersection, union or independent, got '{}'", alignment))?;
+            },
+
+            ("commission", Value::Number(rate)) if (0.0..1.0).contains(&rate) => self.commission = rate,
+            ("commission", Value::Integer(0)) => self.commission = 0.0,
+            ("commission", Value::Number(_)) | ("commission", Value::Integer(_)) => return Err("expected a fraction of the value of fills from 0 to 1".to_string()),
+
+            ("logging", _) | ("data_integrity_checks", _) | ("data_missing_mode", _) | ("history_limit", _) | ("intervals", _) | ("rollups", _) | ("order_book", _)
+            | ("fields", _) | ("price_adjustment", _) | ("extended_hours", _) | ("snapshot", _) | ("alignment", _) | ("commission", _) => return Err("unsupported value".to_string()),
+            _ => return Err("unknown setting".to_string()),
+        }
+
+        Ok(())
+    }
+}
+
+// check a list of intervals (duplicates are removed)
+fn parse_intervals(values: Vec<String>) -> Result<Vec<String>, String> {
+    let mut intervals: Vec<String> = Vec::new();
+    for value in values {
+        let interval = Interval::from_string(&value).map_err(|e| e.to_string())?;
+        if !intervals.contains(&interval.name) {
+            intervals.push(interval.name);
+        }
+    }
+
+    if intervals.len() > MAX_INTERVALS {
+        return Err(format!("a strategy can consolidate up to {} intervals ({} given)", MAX_INTERVALS, intervals.len()));
+    }
+
+    Ok(intervals)
+}
+
+// the log of a strategy (orders, fills, errors and print calls of the script)
+// - lines are kept until the log file of the backtest is opened
+// - nothing is written when the strategy does not enable logging
+// - lines start with the timestamp of the bar being processed
+#[derive(Default)]
+pub struct StrategyLog {
+    pub timestamp: Option<i64>,
+    lines: Vec<String>,
+    file: Option<fs::File>,
+    closed: bool,
+}
+
+impl StrategyLog {
+    // add a line to the log
+    pub fn write(&mut self, line: String) {
+        let line = format!("{} {}", self.timestamp.map_or("-".to_string(), |timestamp| timestamp.to_string()), line);
+        match self.file.as_mut() {
+            Some(file) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    println!("Error writing strategy log: {}", e);
+                }
+            },
+            None if !self.closed => self.lines.push(line),
+            None => {},
+        }
+    }
+
+    // open the log file and write the lines logged so far
+    pub fn open(&mut self, filename: &str) -> Result<(), Error> {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(filename)?;
+        for line in self.lines.drain(..) {
+            writeln!(file, "{}", line)?;
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    // stop logging (the lines logged so far are dropped)
+    pub fn close(&mut self) {
+        self.lines.clear();
+        self.file = None;
+        self.closed = true;
+    }
+}
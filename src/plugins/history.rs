@@ -0,0 +1,226 @@
+use std::sync::{Arc, Mutex};
+use rlua::{Context, MetaMethod, Table, UserData, UserDataMethods, Value};
+
+use crate::database::models::candlestick::Candlestick;
+
+use super::series::{add_series_methods, AsSource, Source};
+
+// represents a column of the history of a symbol and interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryField {
+    Timestamp,
+    Open,
+    High,
+    Low,
+    Close,
+    Volume,
+}
+
+impl HistoryField {
+    // convert a string into a history field
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "timestamp" => Some(HistoryField::Timestamp),
+            "open" => Some(HistoryField::Open),
+            "high" => Some(HistoryField::High),
+            "low" => Some(HistoryField::Low),
+            "close" => Some(HistoryField::Close),
+            "volume" => Some(HistoryField::Volume),
+            _ => None,
+        }
+    }
+
+    // get the name of the field
+    pub fn name(&self) -> &'static str {
+        match self {
+            HistoryField::Timestamp => "timestamp",
+            HistoryField::Open => "open",
+            HistoryField::High => "high",
+            HistoryField::Low => "low",
+            HistoryField::Close => "close",
+            HistoryField::Volume => "volume",
+        }
+    }
+}
+
+// the latest candlesticks of a symbol and interval kept in columns of a ring buffer
+// - index 1 is the most recent candlestick, the oldest candlestick is dropped once the buffer is full
+pub struct HistoryBuffer {
+    pub exchange: String,
+    pub symbol: String,
+    pub interval: String,
+    capacity: usize,
+    len: usize,
+    // the position of the next candlestick in the columns
+    head: usize,
+    // the number of candlesticks pushed since the buffer was created
+    pushes: u64,
+    timestamps: Vec<i64>,
+    columns: [Vec<f64>; 5],
+}
+
+impl HistoryBuffer {
+    pub fn new(exchange: String, symbol: String, interval: String, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            exchange,
+            symbol,
+            interval,
+            capacity,
+            len: 0,
+            head: 0,
+            pushes: 0,
+            timestamps: vec![0; capacity],
+            columns: std::array::from_fn(|_| vec![0.0; capacity]),
+        }
+    }
+
+    // add the most recent candlestick
+    pub fn push(&mut self, candlestick: &Candlestick) {
+        self.timestamps[self.head] = candlestick.timestamp;
+        for (column, value) in self.columns.iter_mut().zip([candlestick.open, candlestick.high, candlestick.low, candlestick.close, candlestick.volume]) {
+            column[self.head] = value;
+        }
+
+        self.head = (self.head + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+        self.pushes += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn pushes(&self) -> u64 {
+        self.pushes
+    }
+
+    // get the position in the columns of an index (1 is the most recent candlestick)
+    fn position(&self, index: i64) -> Option<usize> {
+        if index < 1 || index as usize > self.len {
+            return None;
+        }
+
+        Some((self.head + self.capacity - index as usize) % self.capacity)
+    }
+
+    // get a value of a column at an index (1 is the most recent candlestick)
+    pub fn get(&self, field: HistoryField, index: i64) -> Option<f64> {
+        let position = self.position(index)?;
+        Some(match field {
+            HistoryField::Timestamp => self.timestamps[position] as f64,
+            HistoryField::Open => self.columns[0][position],
+            HistoryField::High => self.columns[1][position],
+            HistoryField::Low => self.columns[2][position],
+            HistoryField::Close => self.columns[3][position],
+            HistoryField::Volume => self.columns[4][position],
+        })
+    }
+
+    // get the candlestick at an index (1 is the most recent candlestick)
+    pub fn candlestick(&self, index: i64) -> Option<Candlestick> {
+        let position = self.position(index)?;
+        Some(Candlestick::new_with(
+            self.timestamps[position],
+            self.columns[0][position],
+            self.columns[1][position],
+            self.columns[2][position],
+            self.columns[3][position],
+            self.columns[4][position],
+        ))
+    }
+
+    // clamp a slice of indexes to the candlesticks in the buffer (the end defaults to the oldest candlestick)
+    fn slice_range(&self, from: i64, to: Option<i64>) -> std::ops::RangeInclusive<i64> {
+        from.max(1)..=to.unwrap_or(self.len as i64).min(self.len as i64)
+    }
+}
+
+// the history of a symbol and interval passed to on_bar (shared with the runtime, nothing is copied per bar)
+// - history[i] is a bar table, history.close (or any field) is a column and #history is the number of bars
+// - history:slice(from, to) returns the bar tables from index from to index to (most recent first)
+#[derive(Clone)]
+pub struct History(pub Arc<Mutex<HistoryBuffer>>);
+
+// a column of a history
+// - column[i] is a value, #column is the number of values
+// - column:slice(from, to) returns the values from index from to index to (most recent first)
+// - a column is a series (ex: history.close - history.open, history.high:highest(20))
+#[derive(Clone)]
+pub struct HistoryColumn(Arc<Mutex<HistoryBuffer>>, HistoryField);
+
+impl AsSource for HistoryColumn {
+    fn source(&self) -> Source {
+        Source::History(self.0.clone(), self.1)
+    }
+}
+
+impl UserData for History {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("len", |_, history, ()| Ok(history.0.lock().unwrap().len()));
+        methods.add_method("slice", |lua_ctx, history, (from, to): (i64, Option<i64>)| {
+            let buffer = history.0.lock().unwrap();
+            let bars = buffer.slice_range(from, to)
+                .filter_map(|index| buffer.candlestick(index))
+                .map(|candlestick| create_history_bar_table(lua_ctx, &candlestick))
+                .collect::<rlua::Result<Vec<Table>>>()?;
+            lua_ctx.create_sequence_from(bars)
+        });
+        methods.add_meta_method(MetaMethod::Len, |_, history, ()| Ok(history.0.lock().unwrap().len()));
+        methods.add_meta_method(MetaMethod::Index, |lua_ctx, history, key: Value| {
+            match key {
+                Value::Integer(index) => match history.0.lock().unwrap().candlestick(index) {
+                    Some(candlestick) => Ok(Value::Table(create_history_bar_table(lua_ctx, &candlestick)?)),
+                    None => Ok(Value::Nil),
+                },
+                Value::String(name) => {
+                    let buffer = history.0.lock().unwrap();
+                    match name.to_str()? {
+                        "exchange" => Ok(Value::String(lua_ctx.create_string(&buffer.exchange)?)),
+                        "symbol" => Ok(Value::String(lua_ctx.create_string(&buffer.symbol)?)),
+                        "interval" => Ok(Value::String(lua_ctx.create_string(&buffer.interval)?)),
+                        name => match HistoryField::from_string(name) {
+                            Some(field) => Ok(Value::UserData(lua_ctx.create_userdata(HistoryColumn(history.0.clone(), field))?)),
+                            None => Ok(Value::Nil),
+                        },
+                    }
+                },
+                _ => Ok(Value::Nil),
+            }
+        });
+    }
+}
+
+impl UserData for HistoryColumn {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("len", |_, column, ()| Ok(column.0.lock().unwrap().len()));
+        methods.add_method("slice", |lua_ctx, column, (from, to): (i64, Option<i64>)| {
+            let buffer = column.0.lock().unwrap();
+            let values = buffer.slice_range(from, to).filter_map(|index| buffer.get(column.1, index)).collect::<Vec<f64>>();
+            lua_ctx.create_sequence_from(values)
+        });
+        add_series_methods(methods);
+        methods.add_meta_method(MetaMethod::Len, |_, column, ()| Ok(column.0.lock().unwrap().len()));
+        methods.add_meta_method(MetaMethod::Index, |_, column, index: i64| {
+            let value = column.0.lock().unwrap().get(column.1, index);
+            Ok(match (column.1, value) {
+                (HistoryField::Timestamp, Some(value)) => Value::Integer(value as i64),
+                (_, Some(value)) => Value::Number(value),
+                (_, None) => Value::Nil,
+            })
+        });
+    }
+}
+
+// convert a candlestick of a history into a bar table
+fn create_history_bar_table<'lua>(lua_ctx: Context<'lua>, candlestick: &Candlestick) -> rlua::Result<Table<'lua>> {
+    let bar_t = lua_ctx.create_table()?;
+    bar_t.set("timestamp", candlestick.timestamp)?;
+    bar_t.set("open", candlestick.open)?;
+    bar_t.set("high", candlestick.high)?;
+    bar_t.set("low", candlestick.low)?;
+    bar_t.set("close", candlestick.close)?;
+    bar_t.set("volume", candlestick.volume)?;
+
+    Ok(bar_t)
+}
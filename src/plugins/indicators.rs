@@ -1,88 +1,407 @@
-use std::{io::{Error, Read}, fs, collections::HashMap};
+use std::{io::{Error, ErrorKind, Read}, fs, collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
 
-use rlua::Lua;
+use rlua::{Lua, UserData, UserDataMethods, ToLuaMulti};
 
-use crate::datasets::Dataset;
+use crate::database::models::candlestick::Candlestick;
 
-// use super::datasets::Dataset;
+use super::{history::{History, HistoryBuffer}, inputs::{InputDefinition, Inputs}, lua_hooks::LuaHook, sandbox::Sandbox, native::{self, NativeIndicator}, series::{add_series_methods, register, AsSource, Series, SeriesSet, Source}, strategies::create_bar_table};
 
 // holds details about an available indicator plugin
+// - the directory of the plugin holds the modules its script can require
 #[derive(Clone)]
 pub struct IndicatorPlugin {
     pub name: String,
     pub lua_script: String,
+    pub directory: String,
 }
 impl IndicatorPlugin {
-    pub fn new(name: String, lua_script: String) -> IndicatorPlugin {
+    pub fn new(name: String, lua_script: String, directory: String) -> IndicatorPlugin {
         IndicatorPlugin {
-            name: name,
-            lua_script: lua_script,
+            name,
+            lua_script,
+            directory,
         }
     }
 }
 
-// an instance of a indicator that has been started
+// the values an indicator produced with update({value = ...}), most recent first
+// - value is the main output, other fields are named outputs (ex: the signal of a macd)
+pub struct IndicatorValues {
+    capacity: usize,
+    series: HashMap<String, VecDeque<f64>>,
+    // the number of bars the indicator produced values for
+    updates: u64,
+}
+
+impl IndicatorValues {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            series: HashMap::new(),
+            updates: 0,
+        }
+    }
+
+    // add the outputs of a bar
+    pub fn push<'a>(&mut self, values: impl IntoIterator<Item = (&'a str, f64)>) {
+        self.updates += 1;
+        for (name, value) in values {
+            if !self.series.contains_key(name) {
+                self.series.insert(name.to_string(), VecDeque::with_capacity(self.capacity + 1));
+            }
+            if let Some(series) = self.series.get_mut(name) {
+                series.push_front(value);
+                series.truncate(self.capacity);
+            }
+        }
+    }
+
+    // get an output at an index (1 is the most recent value)
+    pub fn get(&self, name: &str, index: usize) -> Option<f64> {
+        self.series.get(name)?.get(index.checked_sub(1)?).copied()
+    }
+
+    // get the number of values of an output
+    pub fn len(&self, name: &str) -> usize {
+        self.series.get(name).map_or(0, |series| series.len())
+    }
+
+    pub fn updates(&self) -> u64 {
+        self.updates
+    }
+
+    // check whether the indicator produced its main output (other outputs like the lagging line of ichimoku can come first)
+    pub fn is_ready(&self) -> bool {
+        self.len("value") > 0
+    }
+
+    // get the latest values of an output (most recent first)
+    pub fn history(&self, name: &str, count: usize) -> Vec<f64> {
+        self.series.get(name).map_or(Vec::new(), |series| series.iter().take(count).copied().collect())
+    }
+}
+
+// the handle of an indicator returned to a strategy by indicator(...)
+// - val() is the latest value (nil while the indicator warms up), val("signal") is the latest value of a named output
+// - history(n) is the latest n values (most recent first), history(n, "signal") the latest values of a named output
+// - an indicator is a series of its main output, output("signal") is the series of a named output
+#[derive(Clone)]
+pub struct IndicatorHandle {
+    pub key: String,
+    pub values: Arc<Mutex<IndicatorValues>>,
+}
+
+impl AsSource for IndicatorHandle {
+    fn source(&self) -> Source {
+        Source::Indicator {
+            key: self.key.clone(),
+            output: "value".to_string(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+impl UserData for IndicatorHandle {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("val", |_, indicator, name: Option<String>| {
+            Ok(indicator.values.lock().unwrap().get(name.as_deref().unwrap_or("value"), 1))
+        });
+        methods.add_method("history", |_, indicator, (count, name): (usize, Option<String>)| {
+            Ok(indicator.values.lock().unwrap().history(name.as_deref().unwrap_or("value"), count))
+        });
+        methods.add_method("output", |_, indicator, name: String| {
+            Ok(Series(Source::Indicator {
+                key: indicator.key.clone(),
+                output: name,
+                values: indicator.values.clone(),
+            }))
+        });
+        add_series_methods(methods);
+    }
+}
+
+// the implementation of an indicator
+// - a lua script runs in its own sandboxed state with the input and update hooks
+enum IndicatorKind {
+    Lua {
+        lua_state: Lua,
+        sandbox: Sandbox,
+        history: History,
+        series: Arc<Mutex<SeriesSet>>,
+        update: Arc<Mutex<Option<HashMap<String, f64>>>>,
+    },
+    Native(Box<dyn NativeIndicator>),
+}
+
+// an instance of an indicator fed the bars of a symbol and interval
+// - the inputs are the arguments the strategy passed to indicator(...) in the order the indicator declares them
 pub struct Indicator {
     pub name: String,
-    pub lua_state: Lua,
-    pub settings: HashMap<String, String>,
-    pub datasets: HashMap<String, Dataset>,
+    pub interval: String,
+    pub exchange: String,
+    pub symbol: String,
+    pub values: Arc<Mutex<IndicatorValues>>,
+    kind: IndicatorKind,
 }
 
 impl Indicator {
     pub fn new(
+        plugin: &IndicatorPlugin,
+        inputs: Vec<String>,
+        interval: String,
+        exchange: String,
+        symbol: String,
+        history_limit: usize,
+    ) -> Result<Indicator, Error> {
+        let arguments = Arc::new(Mutex::new(inputs.into_iter().collect::<VecDeque<String>>()));
+        let declared = Arc::new(Mutex::new(Inputs::default()));
+        let update = Arc::new(Mutex::new(None));
+        let series = Arc::new(Mutex::new(SeriesSet::new(history_limit)));
+
+        // create new Lua state
+        let sandbox = Sandbox::new(plugin.directory.clone());
+        let lua = sandbox.create_lua()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("indicator {}: error creating the Lua state: {}", plugin.name, e)))?;
+        let result = lua.context(|lua_ctx| {
+            register(lua_ctx, series.clone())?;
+
+            // every input takes the next argument of indicator(...) (the default when there are no arguments left)
+            let arguments_hook = arguments.clone();
+            let declared_hook = declared.clone();
+            LuaHook::new_external(&lua_ctx, "input", move |_, (name, data_type, default_value, options): (String, String, rlua::Value, Option<rlua::Table>)| {
+                let definition = InputDefinition::from_lua(name, data_type, default_value, options);
+                let argument = arguments_hook.lock().unwrap().pop_front();
+                Ok(declared_hook.lock().unwrap().declare_with(definition, argument))
+            });
+            lua_ctx.globals().set("get_setting", lua_ctx.globals().get::<_, rlua::Function>("input")?)?;
+
+            // the outputs of the indicator for the bar passed to on_bar
+            let update_hook = update.clone();
+            LuaHook::new_external(&lua_ctx, "update", move |_, values: HashMap<String, f64>| {
+                *update_hook.lock().unwrap() = Some(values);
+                Ok(true)
+            });
+
+            sandbox.reset();
+            lua_ctx.load(&plugin.lua_script).set_name(&plugin.name)?.exec()?;
+            if !LuaHook::exists(&lua_ctx, "on_bar") {
+                return Err(rlua::Error::RuntimeError("missing on_bar function".to_string()));
+            }
+
+            Ok(())
+        });
+        if let Err(e) = result {
+            return Err(Error::new(ErrorKind::InvalidData, format!("indicator {}: error loading Lua script: {}", plugin.name, sandbox.describe(&e))));
+        }
+
+        let mut errors = declared.lock().unwrap().errors.clone();
+        let unused = arguments.lock().unwrap().len();
+        if unused > 0 {
+            errors.push(format!("too many arguments ({} more than the inputs)", unused));
+        }
+        if !errors.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("indicator {}: {}", plugin.name, errors.join(", "))));
+        }
+
+        let history = History(Arc::new(Mutex::new(HistoryBuffer::new(exchange.clone(), symbol.clone(), interval.clone(), history_limit))));
+        Ok(Indicator {
+            name: plugin.name.clone(),
+            interval,
+            exchange,
+            symbol,
+            values: Arc::new(Mutex::new(IndicatorValues::new(history_limit))),
+            kind: IndicatorKind::Lua {
+                lua_state: lua,
+                sandbox,
+                history,
+                series,
+                update,
+            },
+        })
+    }
+
+    // create an instance of a native indicator
+    pub fn new_native(
         name: String,
-        settings: HashMap<String, String>,
-        datasets: HashMap<String, Dataset>,
-        // metrics: Option<Vec<Metric<()>>>,
+        native: Box<dyn NativeIndicator>,
+        interval: String,
+        exchange: String,
+        symbol: String,
+        history_limit: usize,
     ) -> Indicator {
+        Indicator {
+            name,
+            interval,
+            exchange,
+            symbol,
+            values: Arc::new(Mutex::new(IndicatorValues::new(history_limit))),
+            kind: IndicatorKind::Native(native),
+        }
+    }
 
-        // setup wrapper to control method calls from Rust into LUA or vice versa
-        let lua = Lua::new();
+    // pass the next candlestick to the indicator and keep the values it updated
+    pub fn update(&mut self, candlestick: &Candlestick) -> Result<(), Error> {
+        let (lua_state, sandbox, history, series, update) = match &mut self.kind {
+            IndicatorKind::Native(native) => {
+                self.values.lock().unwrap().push(native.update(candlestick));
+                return Ok(());
+            },
+            IndicatorKind::Lua { lua_state, sandbox, history, series, update } => (lua_state, sandbox, history, series, update),
+        };
+        history.0.lock().unwrap().push(candlestick);
+        series.lock().unwrap().update();
 
-        // verify that the lua script has the required functions
-        // start up lua context
-        // extract the settings required from the LUA script
-        // verify the required settings match up with the supplied settings from settings.txt
-        // perform initialization tests (run through some dummy data to see how the script responds)
-        // prepare synchronized datasets (by timestamp)
+        sandbox.reset();
+        let result = lua_state.context(|lua_ctx| {
+            let bar_t = create_bar_table(lua_ctx, &self.exchange, &self.symbol, &self.interval, candlestick)?;
+            let history_u = lua_ctx.create_userdata(history.clone())?;
+            LuaHook::call(&lua_ctx, "on_bar", (bar_t, history_u).to_lua_multi(lua_ctx)?)?;
+            Ok(())
+        });
+        result.map_err(|e: rlua::Error| Error::new(ErrorKind::InvalidData, format!("indicator {}: error in on_bar: {}", self.name, sandbox.describe(&e))))?;
 
-        Indicator {
-            name: name,
-            lua_state: lua,
-            settings: settings,
-            datasets
+        if let Some(values) = update.lock().unwrap().take() {
+            self.values.lock().unwrap().push(values.iter().map(|(name, value)| (name.as_str(), *value)));
         }
+        Ok(())
     }
+}
+
+// the indicators instantiated by a strategy
+// - an indicator is created once for every name, inputs, interval and symbol and shared by every call of indicator(...)
+// - an indicator plugin is used instead of the native indicator with the same name
+pub struct Indicators {
+    pub plugins: HashMap<String, IndicatorPlugin>,
+    pub history_limit: usize,
 
-    // 
-    pub fn update(&self,) {
-        println!("updating indicator: {}", self.name);
+    // the intervals the strategy receives bars for (none until the backtest is started)
+    pub intervals: Option<Vec<String>>,
+
+    instances: HashMap<String, Indicator>,
+}
+
+impl Indicators {
+    pub fn new(plugins: HashMap<String, IndicatorPlugin>, history_limit: usize) -> Self {
+        Self {
+            plugins,
+            history_limit,
+            intervals: None,
+            instances: HashMap::new(),
+        }
+    }
+
+    // get the handle of an indicator (created and warmed up with the history of its symbol and interval the first time)
+    pub fn get_or_create(
+        &mut self,
+        name: &str,
+        inputs: Vec<String>,
+        interval: &str,
+        exchange: &str,
+        symbol: &str,
+        history: Option<&HistoryBuffer>,
+    ) -> Result<IndicatorHandle, Error> {
+        let key = format!("{}({}) {} {}:{}", name, inputs.join(","), interval, exchange, symbol);
+        if let Some(indicator) = self.instances.get(&key) {
+            return Ok(IndicatorHandle { key, values: indicator.values.clone() });
+        }
+
+        if let Some(intervals) = self.intervals.as_ref() {
+            if !intervals.iter().any(|available| available == interval) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("indicator {}: the strategy does not receive {} bars (add the interval to env intervals)", name, interval)));
+            }
+        }
+
+        let mut indicator = match (self.plugins.get(name), native::create(name, inputs.clone())) {
+            (Some(plugin), _) => Indicator::new(plugin, inputs, interval.to_string(), exchange.to_string(), symbol.to_string(), self.history_limit)?,
+            (None, Some(Ok(native))) => Indicator::new_native(name.to_string(), native, interval.to_string(), exchange.to_string(), symbol.to_string(), self.history_limit),
+            (None, Some(Err(e))) => return Err(Error::new(ErrorKind::InvalidInput, format!("indicator {}: {}", name, e))),
+            (None, None) => return Err(Error::new(ErrorKind::NotFound, format!("indicator {} not found", name))),
+        };
+
+        // the oldest candlestick is passed first
+        if let Some(history) = history {
+            for index in (1..=history.len() as i64).rev() {
+                if let Some(candlestick) = history.candlestick(index) {
+                    indicator.update(&candlestick)?;
+                }
+            }
+        }
+
+        let values = indicator.values.clone();
+        self.instances.insert(key.clone(), indicator);
+        Ok(IndicatorHandle { key, values })
+    }
+
+    // pass a candlestick to the indicators of its symbol and interval
+    pub fn update(&mut self, exchange: &str, symbol: &str, interval: &str, candlestick: &Candlestick) -> Result<(), Error> {
+        for indicator in self.instances.values_mut() {
+            if indicator.interval == interval && indicator.exchange == exchange && indicator.symbol == symbol {
+                indicator.update(candlestick)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // check whether every indicator produced a value
+    pub fn is_ready(&self) -> bool {
+        self.instances.values().all(|indicator| indicator.values.lock().unwrap().is_ready())
+    }
+
+    // get the intervals of the indicators
+    pub fn intervals(&self) -> Vec<String> {
+        let mut intervals = Vec::new();
+        for indicator in self.instances.values() {
+            if !intervals.contains(&indicator.interval) {
+                intervals.push(indicator.interval.clone());
+            }
+        }
+
+        intervals
     }
 }
 
 
 // loads all indicators from the filesystem using the given path
+// - every lua file is an indicator named after the file (ex: rsi.lua is the rsi indicator)
 pub fn load_indicators(indicators_path: String) -> Result<HashMap<String, IndicatorPlugin>, Error> {
     let mut indicators = HashMap::new();
     for entry in fs::read_dir(&indicators_path)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_dir() {
-            let indicator_name = path.file_name().unwrap().to_str().unwrap().to_string();
-            let lua_file = format!("{}/{}{}", &indicators_path, indicator_name, ".lua");
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "lua") {
+            let indicator_name = match path.file_stem().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
 
             // load lua script file
-            // println!("loading lua script: {}", lua_file);
-            let mut file = fs::File::open(lua_file).unwrap();
+            let mut file = fs::File::open(&path)?;
             let mut lua_contents = String::new();
-            file.read_to_string(&mut lua_contents).unwrap();
+            file.read_to_string(&mut lua_contents)?;
 
-            let indicator = IndicatorPlugin::new(indicator_name.clone(), lua_contents);
+            let indicator = IndicatorPlugin::new(indicator_name.clone(), lua_contents, indicators_path.clone());
 
             indicators.insert(indicator_name, indicator);
         }
     }
 
     Ok(indicators)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_ready_once_the_main_output_is_produced() {
+        let mut values = IndicatorValues::new(10);
+        values.push([("lagging", 1.0)]);
+        assert!(!values.is_ready());
+
+        values.push([("lagging", 2.0), ("value", 3.0)]);
+        assert!(values.is_ready());
+        assert_eq!(values.get("value", 1), Some(3.0));
+        assert_eq!(values.history("lagging", 5), vec![2.0, 1.0]);
+    }
+}
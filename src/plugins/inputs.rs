@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use rlua::{Context, Table, ToLua, Value};
+
+
+// represents the type of an input of a plugin
+// - enums only accept one of their values, lists are comma separated values of an item type
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputType {
+    Integer,
+    Float,
+    String,
+    Bool,
+    Enum(Vec<String>),
+    List(Box<InputType>),
+}
+
+impl InputType {
+    // convert a type name into an input type (enums and lists take their values and item type from the options)
+    pub fn from_string(value: &str, values: Vec<String>, item: Option<InputType>) -> Option<Self> {
+        match value {
+            "integer" | "int" => Some(InputType::Integer),
+            "float" | "number" => Some(InputType::Float),
+            "string" => Some(InputType::String),
+            "bool" | "boolean" => Some(InputType::Bool),
+            "enum" if !values.is_empty() => Some(InputType::Enum(values)),
+            "list" => Some(InputType::List(Box::new(item.unwrap_or(InputType::String)))),
+            _ => None,
+        }
+    }
+
+    // get the name of the type used in error messages
+    pub fn name(&self) -> String {
+        match self {
+            InputType::Integer => "an integer".to_string(),
+            InputType::Float => "a float".to_string(),
+            InputType::String => "a string".to_string(),
+            InputType::Bool => "a bool".to_string(),
+            InputType::Enum(values) => format!("one of {}", values.join(", ")),
+            InputType::List(item) => format!("a list of {}", item.name().trim_start_matches("a ").trim_start_matches("an ")),
+        }
+    }
+}
+
+// represents the value of an input
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputValue {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    List(Vec<InputValue>),
+}
+
+impl InputValue {
+    // get the number of an integer or float value
+    fn number(&self) -> Option<f64> {
+        match self {
+            InputValue::Integer(value) => Some(*value as f64),
+            InputValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // write the value the way it is written in a settings file
+    pub fn to_text(&self) -> String {
+        match self {
+            InputValue::Integer(value) => value.to_string(),
+            InputValue::Float(value) => value.to_string(),
+            InputValue::String(value) => value.clone(),
+            InputValue::Bool(value) => value.to_string(),
+            InputValue::List(values) => values.iter().map(|value| value.to_text()).collect::<Vec<String>>().join(","),
+        }
+    }
+}
+
+impl<'lua> ToLua<'lua> for InputValue {
+    fn to_lua(self, lua_ctx: Context<'lua>) -> rlua::Result<Value<'lua>> {
+        match self {
+            InputValue::Integer(value) => Ok(Value::Integer(value)),
+            InputValue::Float(value) => Ok(Value::Number(value)),
+            InputValue::String(value) => value.to_lua(lua_ctx),
+            InputValue::Bool(value) => Ok(Value::Boolean(value)),
+            InputValue::List(values) => Ok(Value::Table(lua_ctx.create_sequence_from(values)?)),
+        }
+    }
+}
+
+// represents an input declared by a plugin with input(name, type, default, options)
+// - an input without a default is required in the settings
+// - options is a table with min and max (numbers and the items of lists), values (enums) and item (the type of list items)
+#[derive(Debug, Clone)]
+pub struct InputDefinition {
+    pub name: String,
+    pub input_type: InputType,
+    pub default: Option<InputValue>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl InputDefinition {
+    // create an input definition from the arguments of the input hook
+    pub fn from_lua(name: String, type_name: String, default: Value, options: Option<Table>) -> Result<Self, String> {
+        let mut values = Vec::new();
+        let mut item = None;
+        let mut min = None;
+        let mut max = None;
+        if let Some(options) = options {
+            let option_error = |e: rlua::Error| format!("input {}: invalid options: {}", name, e);
+            values = options.get::<_, Option<Vec<String>>>("values").map_err(option_error)?.unwrap_or_default();
+            min = options.get::<_, Option<f64>>("min").map_err(option_error)?;
+            max = options.get::<_, Option<f64>>("max").map_err(option_error)?;
+            if let Some(item_name) = options.get::<_, Option<String>>("item").map_err(option_error)? {
+                item = Some(InputType::from_string(&item_name, Vec::new(), None).ok_or_else(|| format!("input {}: unsupported list item type {}", name, item_name))?);
+            }
+        }
+
+        let input_type = InputType::from_string(&type_name, values, item)
+            .ok_or_else(|| format!("input {}: unsupported type {}", name, type_name))?;
+        let mut definition = Self {
+            name,
+            input_type,
+            default: None,
+            min,
+            max,
+        };
+
+        definition.default = match default {
+            Value::Nil => None,
+            Value::Table(table) => {
+                let items = table.sequence_values::<Value>().collect::<rlua::Result<Vec<Value>>>().map_err(|e| format!("input {}: invalid default: {}", definition.name, e))?;
+                let text = items.into_iter().filter_map(value_to_text).collect::<Vec<String>>().join(",");
+                Some(definition.parse(&text).map_err(|e| format!("{} (default)", e))?)
+            },
+            value => match value_to_text(value) {
+                Some(text) => Some(definition.parse(&text).map_err(|e| format!("{} (default)", e))?),
+                None => return Err(format!("input {}: unsupported default value", definition.name)),
+            },
+        };
+
+        Ok(definition)
+    }
+
+    // parse the value of a setting into the type of the input and check its constraints
+    pub fn parse(&self, text: &str) -> Result<InputValue, String> {
+        let value = parse_value(&self.input_type, text.trim())
+            .ok_or_else(|| format!("input {}: expected {}, got '{}'", self.name, self.input_type.name(), text))?;
+
+        // the constraints apply to numbers and to every item of a list
+        let numbers = match &value {
+            InputValue::List(values) => values.iter().filter_map(|value| value.number()).collect::<Vec<f64>>(),
+            value => value.number().into_iter().collect(),
+        };
+        for number in numbers {
+            let range = match (self.min, self.max) {
+                (Some(min), Some(max)) if number < min || number > max => format!("between {} and {}", min, max),
+                (Some(min), None) if number < min => format!("at least {}", min),
+                (None, Some(max)) if number > max => format!("at most {}", max),
+                _ => continue,
+            };
+            return Err(format!("input {}: expected a value {}, got {}", self.name, range, number));
+        }
+
+        Ok(value)
+    }
+}
+
+// the inputs declared by a plugin and the problems found with its settings
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    pub definitions: Vec<InputDefinition>,
+    pub values: HashMap<String, InputValue>,
+    pub errors: Vec<String>,
+}
+
+impl Inputs {
+    // declare an input and resolve its value from the settings (the default when there is no setting)
+    // - problems are collected so every problem of the settings is reported at once
+    pub fn declare(&mut self, definition: Result<InputDefinition, String>, settings: &HashMap<String, String>) -> Option<InputValue> {
+        let setting = definition.as_ref().ok().and_then(|definition| settings.get(&definition.name)).cloned();
+        self.declare_with(definition, setting)
+    }
+
+    // declare an input and resolve its value from a setting (the default when there is no setting)
+    pub fn declare_with(&mut self, definition: Result<InputDefinition, String>, setting: Option<String>) -> Option<InputValue> {
+        let definition = match definition {
+            Ok(definition) => definition,
+            Err(e) => {
+                self.errors.push(e);
+                return None;
+            }
+        };
+
+        let value = match setting {
+            Some(text) => match definition.parse(&text) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    self.errors.push(e);
+                    definition.default.clone()
+                }
+            },
+            None if definition.default.is_none() => {
+                self.errors.push(format!("input {}: missing required setting", definition.name));
+                None
+            },
+            None => definition.default.clone(),
+        };
+
+        if let Some(value) = value.clone() {
+            self.values.insert(definition.name.clone(), value);
+        }
+        self.definitions.push(definition);
+        value
+    }
+
+    // check the settings for keys that are not inputs (reserved keys are used by the runtime)
+    pub fn validate(&mut self, settings: &HashMap<String, String>, reserved: &[&str]) {
+        let mut unknown = settings.keys()
+            .filter(|key| !reserved.contains(&key.as_str()) && !self.definitions.iter().any(|definition| &definition.name == *key))
+            .collect::<Vec<&String>>();
+        unknown.sort();
+        for key in unknown {
+            self.errors.push(format!("setting {}: unknown key", key));
+        }
+    }
+}
+
+// parse a value of a type (lists are comma separated)
+fn parse_value(input_type: &InputType, text: &str) -> Option<InputValue> {
+    match input_type {
+        InputType::Integer => text.parse::<i64>().ok().map(InputValue::Integer),
+        InputType::Float => text.parse::<f64>().ok().filter(|value| value.is_finite()).map(InputValue::Float),
+        InputType::String => Some(InputValue::String(text.to_string())),
+        InputType::Bool => match text.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Some(InputValue::Bool(true)),
+            "false" | "no" | "0" => Some(InputValue::Bool(false)),
+            _ => None,
+        },
+        InputType::Enum(values) => values.iter().find(|value| value.as_str() == text).map(|value| InputValue::String(value.clone())),
+        InputType::List(item) => text.split(',')
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| parse_value(item, value))
+            .collect::<Option<Vec<InputValue>>>()
+            .map(InputValue::List),
+    }
+}
+
+// convert a scalar lua value into the text of a setting
+pub fn value_to_text(value: Value) -> Option<String> {
+    match value {
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Boolean(value) => Some(value.to_string()),
+        Value::String(value) => value.to_str().ok().map(|value| value.to_string()),
+        _ => None,
+    }
+}
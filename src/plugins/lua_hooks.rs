@@ -1,4 +1,4 @@
-use rlua::{Function, Lua, Context, ToLuaMulti, Value, Result, ToLua, MultiValue};
+use rlua::{Function, Context, FromLuaMulti, ToLuaMulti, Value, Result, MultiValue};
 
 
 
@@ -9,17 +9,19 @@ pub struct LuaHook {
 impl LuaHook {
 
     // create a new rust hook called from within lua
-    pub fn new_external(lua_ctx: &Context, name: &str, f: F)
-    // where
-        // F: FnMut(&Lua, MultiValue) -> Result<Value<'lua>> + 'static
+    pub fn new_external<'lua, A, R, F>(lua_ctx: &Context<'lua>, name: &str, f: F)
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
     {
-        let create_f_result = lua_ctx.create_function(f);
-
-        if let Err(e) = create_f_result {
-            println!("Error loading global from Lua script: {}", e);
-        }
-
-        let function = create_f_result.unwrap();
+        let function = match lua_ctx.create_function(f) {
+            Ok(function) => function,
+            Err(e) => {
+                println!("Error creating Lua hook {}: {}", name, e);
+                return;
+            }
+        };
 
         let set_result = lua_ctx.globals().set(name, function);
         if let Err(e) = set_result {
@@ -27,21 +29,15 @@ impl LuaHook {
         }
     }
 
-    // call a lua function from rust
-    pub fn call<'lua>(lua_ctx: &'lua Context, name: &str, params: MultiValue<'lua>) -> Result<Value<'lua>> {
-        let get_result = lua_ctx.globals().get(name);
-        if let Err(e) = get_result {
-            println!("Error loading global from Lua script: {}", e);
-        }
-        let f: Function = get_result.unwrap();
-
-        let ret: Result<Value<'lua>> = f.call(params);
-        if let Err(e) = ret {
-            println!("Error executing Lua function: {}", e);
-        }
+    // check if a lua script defines a function
+    pub fn exists(lua_ctx: &Context, name: &str) -> bool {
+        matches!(lua_ctx.globals().get::<_, Value>(name), Ok(Value::Function(_)))
+    }
 
-        let result: _ = ret.unwrap();
+    // call a lua function from rust
+    pub fn call<'lua>(lua_ctx: &Context<'lua>, name: &str, params: MultiValue<'lua>) -> Result<Value<'lua>> {
+        let f: Function = lua_ctx.globals().get(name)?;
 
-        Ok(result.to_lua(*lua_ctx).unwrap())
+        f.call(params)
     }
-}
\ No newline at end of file
+}
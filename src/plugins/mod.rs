@@ -1,3 +1,10 @@
+pub mod environment;
+pub mod history;
 pub mod indicators;
+pub mod inputs;
+pub mod native;
+pub mod sandbox;
+pub mod schedule;
+pub mod series;
 pub mod strategies;
 pub mod lua_hooks;
\ No newline at end of file
@@ -0,0 +1,721 @@
+use std::collections::VecDeque;
+
+use crate::database::models::candlestick::Candlestick;
+
+use super::{environment::MAX_HISTORY_LIMIT, inputs::{InputDefinition, InputType, InputValue, Inputs}};
+
+// the indicators computed in rust (a lua indicator plugin with the same name is used instead)
+// - the arguments of indicator(...) are the inputs in the order below, every input has a default
+// - value is the main output, the other outputs are read with val(name) and history(n, name)
+// - periods are 1 to MAX_HISTORY_LIMIT bars
+//
+// sma(periods=14), ema(periods=14), wma(periods=14), rsi(periods=14)
+// macd(fast=12, slow=26, signal=9): value (macd line), signal, histogram
+// bb(periods=20, deviations=2): value (middle band), upper, lower
+// atr(periods=14)
+// adx(periods=14): value (adx), plus_di, minus_di
+// stochastic(k=14, d=3): value (%k), d
+// vwap(): value (reset every utc day)
+// obv()
+// donchian(periods=20): value (middle), upper, lower
+// keltner(periods=20, multiplier=2, atr_periods=10): value (middle), upper, lower
+// ichimoku(conversion=9, base=26, span_b=52, displacement=26): value (conversion line), base, span_a, span_b, lagging
+
+// an indicator computed in rust, updated once per candlestick
+pub trait NativeIndicator: Send {
+    // add the next candlestick and return the outputs (nothing while the indicator warms up)
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)>;
+}
+
+// create a native indicator from the arguments of indicator(...) (none when there is no native indicator with the name)
+pub fn create(name: &str, arguments: Vec<String>) -> Option<Result<Box<dyn NativeIndicator>, String>> {
+    let mut arguments = Arguments::new(arguments);
+    let indicator: Box<dyn NativeIndicator> = match name {
+        "sma" => Box::new(SmaIndicator(Sma::new(arguments.periods("periods", 14)))),
+        "ema" => Box::new(EmaIndicator(Ema::new(arguments.periods("periods", 14)))),
+        "wma" => Box::new(Wma::new(arguments.periods("periods", 14))),
+        "rsi" => Box::new(Rsi::new(arguments.periods("periods", 14))),
+        "macd" => Box::new(Macd::new(arguments.periods("fast", 12), arguments.periods("slow", 26), arguments.periods("signal", 9))),
+        "bb" => Box::new(BollingerBands::new(arguments.periods("periods", 20), arguments.float("deviations", 2.0))),
+        "atr" => Box::new(Atr::new(arguments.periods("periods", 14))),
+        "adx" => Box::new(Adx::new(arguments.periods("periods", 14))),
+        "stochastic" => Box::new(Stochastic::new(arguments.periods("k", 14), arguments.periods("d", 3))),
+        "vwap" => Box::new(Vwap::default()),
+        "obv" => Box::new(Obv::default()),
+        "donchian" => Box::new(Donchian::new(arguments.periods("periods", 20))),
+        "keltner" => Box::new(Keltner::new(arguments.periods("periods", 20), arguments.float("multiplier", 2.0), arguments.periods("atr_periods", 10))),
+        "ichimoku" => Box::new(Ichimoku::new(arguments.periods("conversion", 9), arguments.periods("base", 26), arguments.periods("span_b", 52), arguments.periods("displacement", 26))),
+        _ => return None,
+    };
+
+    Some(arguments.finish().map(|_| indicator))
+}
+
+// the arguments of a native indicator checked like the inputs of a lua indicator
+// - an invalid argument is reported and the default is used so the indicator can still be created
+struct Arguments {
+    values: VecDeque<String>,
+    inputs: Inputs,
+}
+
+impl Arguments {
+    fn new(values: Vec<String>) -> Self {
+        Self {
+            values: values.into(),
+            inputs: Inputs::default(),
+        }
+    }
+
+    fn declare(&mut self, name: &str, input_type: InputType, default: InputValue, min: f64, max: Option<f64>) -> Option<InputValue> {
+        let definition = InputDefinition {
+            name: name.to_string(),
+            input_type,
+            default: Some(default),
+            min: Some(min),
+            max,
+        };
+        let argument = self.values.pop_front();
+        self.inputs.declare_with(Ok(definition), argument)
+    }
+
+    // a number of bars (1 to MAX_HISTORY_LIMIT)
+    fn periods(&mut self, name: &str, default: usize) -> usize {
+        match self.declare(name, InputType::Integer, InputValue::Integer(default as i64), 1.0, Some(MAX_HISTORY_LIMIT as f64)) {
+            Some(InputValue::Integer(value)) => value as usize,
+            _ => default,
+        }
+    }
+
+    // a positive number
+    fn float(&mut self, name: &str, default: f64) -> f64 {
+        match self.declare(name, InputType::Float, InputValue::Float(default), 0.0, None) {
+            Some(InputValue::Float(value)) => value,
+            _ => default,
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        let mut errors = self.inputs.errors;
+        if !self.values.is_empty() {
+            errors.push(format!("too many arguments ({} more than the inputs)", self.values.len()));
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join(", ")),
+        }
+    }
+}
+
+// the sum (and sum of squares) of the latest values
+struct Window {
+    periods: usize,
+    values: VecDeque<f64>,
+    sum: f64,
+    sum_squares: f64,
+}
+
+impl Window {
+    fn new(periods: usize) -> Self {
+        Self {
+            periods,
+            values: VecDeque::new(),
+            sum: 0.0,
+            sum_squares: 0.0,
+        }
+    }
+
+    // add a value and return true once the window is full
+    fn push(&mut self, value: f64) -> bool {
+        self.values.push_back(value);
+        self.sum += value;
+        self.sum_squares += value * value;
+        if self.values.len() > self.periods {
+            let oldest = self.values.pop_front().unwrap_or_default();
+            self.sum -= oldest;
+            self.sum_squares -= oldest * oldest;
+        }
+
+        self.values.len() == self.periods
+    }
+
+    fn mean(&self) -> f64 {
+        self.sum / self.values.len().max(1) as f64
+    }
+
+    fn deviation(&self) -> f64 {
+        let mean = self.mean();
+        (self.sum_squares / self.values.len().max(1) as f64 - mean * mean).max(0.0).sqrt()
+    }
+}
+
+// simple moving average
+struct Sma(Window);
+
+impl Sma {
+    fn new(periods: usize) -> Self {
+        Self(Window::new(periods))
+    }
+
+    fn update(&mut self, value: f64) -> Option<f64> {
+        self.0.push(value).then(|| self.0.mean())
+    }
+}
+
+// exponential moving average seeded with the simple average of the first values
+// - wilder's smoothing is the same average with a smoothing of 1 / periods
+struct Ema {
+    alpha: f64,
+    seed: Sma,
+    value: Option<f64>,
+}
+
+impl Ema {
+    fn new(periods: usize) -> Self {
+        Self::new_with(periods, 2.0 / (periods as f64 + 1.0))
+    }
+
+    fn new_wilder(periods: usize) -> Self {
+        Self::new_with(periods, 1.0 / periods as f64)
+    }
+
+    fn new_with(periods: usize, alpha: f64) -> Self {
+        Self {
+            alpha,
+            seed: Sma::new(periods),
+            value: None,
+        }
+    }
+
+    fn update(&mut self, value: f64) -> Option<f64> {
+        self.value = match self.value {
+            Some(average) => Some(average + self.alpha * (value - average)),
+            None => self.seed.update(value),
+        };
+
+        self.value
+    }
+}
+
+// the highest or lowest of the latest values (a monotonic queue of the candidates)
+pub(super) struct Extreme {
+    periods: usize,
+    highest: bool,
+    count: usize,
+    candidates: VecDeque<(usize, f64)>,
+}
+
+impl Extreme {
+    pub(super) fn new(periods: usize, highest: bool) -> Self {
+        Self {
+            periods,
+            highest,
+            count: 0,
+            candidates: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn update(&mut self, value: f64) -> Option<f64> {
+        while let Some(&(_, last)) = self.candidates.back() {
+            if (self.highest && last <= value) || (!self.highest && last >= value) {
+                self.candidates.pop_back();
+            }
+            else {
+                break;
+            }
+        }
+        self.candidates.push_back((self.count, value));
+        self.count += 1;
+
+        while self.candidates.front().is_some_and(|&(index, _)| index + self.periods < self.count) {
+            self.candidates.pop_front();
+        }
+
+        (self.count >= self.periods).then(|| self.candidates.front().map_or(value, |&(_, value)| value))
+    }
+}
+
+// the highest high and the lowest low of the latest candlesticks
+struct Range {
+    highest: Extreme,
+    lowest: Extreme,
+}
+
+impl Range {
+    fn new(periods: usize) -> Self {
+        Self {
+            highest: Extreme::new(periods, true),
+            lowest: Extreme::new(periods, false),
+        }
+    }
+
+    // the highest high and the lowest low once there are enough candlesticks
+    fn update(&mut self, candlestick: &Candlestick) -> Option<(f64, f64)> {
+        let (highest, lowest) = (self.highest.update(candlestick.high), self.lowest.update(candlestick.low));
+        highest.zip(lowest)
+    }
+}
+
+// the true range of a candlestick (the range including the gap from the previous close)
+#[derive(Default)]
+struct TrueRange {
+    previous_close: Option<f64>,
+}
+
+impl TrueRange {
+    fn update(&mut self, candlestick: &Candlestick) -> f64 {
+        let range = match self.previous_close {
+            Some(close) => (candlestick.high - candlestick.low).max((candlestick.high - close).abs()).max((candlestick.low - close).abs()),
+            None => candlestick.high - candlestick.low,
+        };
+
+        self.previous_close = Some(candlestick.close);
+        range
+    }
+}
+
+struct SmaIndicator(Sma);
+
+impl NativeIndicator for SmaIndicator {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        self.0.update(candlestick.close).map(|value| ("value", value)).into_iter().collect()
+    }
+}
+
+struct EmaIndicator(Ema);
+
+impl NativeIndicator for EmaIndicator {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        self.0.update(candlestick.close).map(|value| ("value", value)).into_iter().collect()
+    }
+}
+
+// weighted moving average (the most recent close has a weight of periods, the oldest a weight of 1)
+struct Wma {
+    window: Window,
+    weighted_sum: f64,
+}
+
+impl Wma {
+    fn new(periods: usize) -> Self {
+        Self {
+            window: Window::new(periods),
+            weighted_sum: 0.0,
+        }
+    }
+}
+
+impl NativeIndicator for Wma {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        // every value already in a full window loses a weight of 1
+        let periods = self.window.periods as f64;
+        match self.window.values.len() == self.window.periods {
+            true => self.weighted_sum += periods * candlestick.close - self.window.sum,
+            false => self.weighted_sum += (self.window.values.len() + 1) as f64 * candlestick.close,
+        }
+
+        match self.window.push(candlestick.close) {
+            true => vec![("value", self.weighted_sum / (periods * (periods + 1.0) / 2.0))],
+            false => Vec::new(),
+        }
+    }
+}
+
+// relative strength index (wilder's smoothing of the gains and losses)
+struct Rsi {
+    previous_close: Option<f64>,
+    gains: Ema,
+    losses: Ema,
+}
+
+impl Rsi {
+    fn new(periods: usize) -> Self {
+        Self {
+            previous_close: None,
+            gains: Ema::new_wilder(periods),
+            losses: Ema::new_wilder(periods),
+        }
+    }
+}
+
+impl NativeIndicator for Rsi {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let previous_close = match self.previous_close.replace(candlestick.close) {
+            Some(close) => close,
+            None => return Vec::new(),
+        };
+
+        let change = candlestick.close - previous_close;
+        match (self.gains.update(change.max(0.0)), self.losses.update((-change).max(0.0))) {
+            (Some(_), Some(0.0)) => vec![("value", 100.0)],
+            (Some(gain), Some(loss)) => vec![("value", 100.0 - 100.0 / (1.0 + gain / loss))],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// moving average convergence divergence
+struct Macd {
+    fast: Ema,
+    slow: Ema,
+    signal: Ema,
+}
+
+impl Macd {
+    fn new(fast: usize, slow: usize, signal: usize) -> Self {
+        Self {
+            fast: Ema::new(fast),
+            slow: Ema::new(slow),
+            signal: Ema::new(signal),
+        }
+    }
+}
+
+impl NativeIndicator for Macd {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let (fast, slow) = (self.fast.update(candlestick.close), self.slow.update(candlestick.close));
+        let macd = match (fast, slow) {
+            (Some(fast), Some(slow)) => fast - slow,
+            _ => return Vec::new(),
+        };
+
+        match self.signal.update(macd) {
+            Some(signal) => vec![("value", macd), ("signal", signal), ("histogram", macd - signal)],
+            None => vec![("value", macd)],
+        }
+    }
+}
+
+// bollinger bands (a simple average and the standard deviation of the closes)
+struct BollingerBands {
+    window: Window,
+    deviations: f64,
+}
+
+impl BollingerBands {
+    fn new(periods: usize, deviations: f64) -> Self {
+        Self {
+            window: Window::new(periods),
+            deviations,
+        }
+    }
+}
+
+impl NativeIndicator for BollingerBands {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        if !self.window.push(candlestick.close) {
+            return Vec::new();
+        }
+
+        let (middle, width) = (self.window.mean(), self.window.deviation() * self.deviations);
+        vec![("value", middle), ("upper", middle + width), ("lower", middle - width)]
+    }
+}
+
+// average true range (wilder's smoothing)
+struct Atr {
+    true_range: TrueRange,
+    average: Ema,
+}
+
+impl Atr {
+    fn new(periods: usize) -> Self {
+        Self {
+            true_range: TrueRange::default(),
+            average: Ema::new_wilder(periods),
+        }
+    }
+
+    fn average(&mut self, candlestick: &Candlestick) -> Option<f64> {
+        let true_range = self.true_range.update(candlestick);
+        self.average.update(true_range)
+    }
+}
+
+impl NativeIndicator for Atr {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        self.average(candlestick).map(|value| ("value", value)).into_iter().collect()
+    }
+}
+
+// average directional index with the directional indicators
+struct Adx {
+    previous: Option<(f64, f64)>,
+    true_range: TrueRange,
+    ranges: Ema,
+    plus: Ema,
+    minus: Ema,
+    adx: Ema,
+}
+
+impl Adx {
+    fn new(periods: usize) -> Self {
+        Self {
+            previous: None,
+            true_range: TrueRange::default(),
+            ranges: Ema::new_wilder(periods),
+            plus: Ema::new_wilder(periods),
+            minus: Ema::new_wilder(periods),
+            adx: Ema::new_wilder(periods),
+        }
+    }
+}
+
+impl NativeIndicator for Adx {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let true_range = self.true_range.update(candlestick);
+        let (high, low) = match self.previous.replace((candlestick.high, candlestick.low)) {
+            Some(previous) => previous,
+            None => return Vec::new(),
+        };
+
+        let (up, down) = (candlestick.high - high, low - candlestick.low);
+        let plus = if up > down && up > 0.0 { up } else { 0.0 };
+        let minus = if down > up && down > 0.0 { down } else { 0.0 };
+        let (range, plus, minus) = match (self.ranges.update(true_range), self.plus.update(plus), self.minus.update(minus)) {
+            (Some(range), Some(plus), Some(minus)) => (range, plus, minus),
+            _ => return Vec::new(),
+        };
+
+        let plus_di = if range > 0.0 { 100.0 * plus / range } else { 0.0 };
+        let minus_di = if range > 0.0 { 100.0 * minus / range } else { 0.0 };
+        let dx = if plus_di + minus_di > 0.0 { 100.0 * (plus_di - minus_di).abs() / (plus_di + minus_di) } else { 0.0 };
+        match self.adx.update(dx) {
+            Some(adx) => vec![("value", adx), ("plus_di", plus_di), ("minus_di", minus_di)],
+            None => vec![("plus_di", plus_di), ("minus_di", minus_di)],
+        }
+    }
+}
+
+// stochastic oscillator (%k is the close within the range of the latest candlesticks, %d is the average of %k)
+struct Stochastic {
+    range: Range,
+    d: Sma,
+}
+
+impl Stochastic {
+    fn new(k: usize, d: usize) -> Self {
+        Self {
+            range: Range::new(k),
+            d: Sma::new(d),
+        }
+    }
+}
+
+impl NativeIndicator for Stochastic {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let (highest, lowest) = match self.range.update(candlestick) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+
+        let k = if highest > lowest { 100.0 * (candlestick.close - lowest) / (highest - lowest) } else { 50.0 };
+        match self.d.update(k) {
+            Some(d) => vec![("value", k), ("d", d)],
+            None => vec![("value", k)],
+        }
+    }
+}
+
+// volume weighted average price of the typical prices since the start of the utc day
+#[derive(Default)]
+struct Vwap {
+    day: Option<i64>,
+    price_volume: f64,
+    volume: f64,
+}
+
+impl NativeIndicator for Vwap {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let day = candlestick.timestamp.div_euclid(86400);
+        if self.day.replace(day) != Some(day) {
+            self.price_volume = 0.0;
+            self.volume = 0.0;
+        }
+
+        let typical = (candlestick.high + candlestick.low + candlestick.close) / 3.0;
+        self.price_volume += typical * candlestick.volume;
+        self.volume += candlestick.volume;
+        vec![("value", if self.volume > 0.0 { self.price_volume / self.volume } else { typical })]
+    }
+}
+
+// on balance volume
+#[derive(Default)]
+struct Obv {
+    previous_close: Option<f64>,
+    value: f64,
+}
+
+impl NativeIndicator for Obv {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        match self.previous_close.replace(candlestick.close) {
+            Some(close) if candlestick.close > close => self.value += candlestick.volume,
+            Some(close) if candlestick.close < close => self.value -= candlestick.volume,
+            _ => {},
+        }
+
+        vec![("value", self.value)]
+    }
+}
+
+// donchian channel (the highest high and the lowest low of the latest candlesticks)
+struct Donchian(Range);
+
+impl Donchian {
+    fn new(periods: usize) -> Self {
+        Self(Range::new(periods))
+    }
+}
+
+impl NativeIndicator for Donchian {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        match self.0.update(candlestick) {
+            Some((upper, lower)) => vec![("value", (upper + lower) / 2.0), ("upper", upper), ("lower", lower)],
+            None => Vec::new(),
+        }
+    }
+}
+
+// keltner channel (an exponential average of the closes with bands at a multiple of the average true range)
+struct Keltner {
+    middle: Ema,
+    atr: Atr,
+    multiplier: f64,
+}
+
+impl Keltner {
+    fn new(periods: usize, multiplier: f64, atr_periods: usize) -> Self {
+        Self {
+            middle: Ema::new(periods),
+            atr: Atr::new(atr_periods),
+            multiplier,
+        }
+    }
+}
+
+impl NativeIndicator for Keltner {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        match (self.middle.update(candlestick.close), self.atr.average(candlestick)) {
+            (Some(middle), Some(atr)) => vec![("value", middle), ("upper", middle + self.multiplier * atr), ("lower", middle - self.multiplier * atr)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// ichimoku cloud
+// - the spans of a candlestick are the spans calculated displacement candlesticks before it
+// - lagging is the close that is plotted displacement candlesticks back
+struct Ichimoku {
+    conversion: Range,
+    base: Range,
+    span_b: Range,
+    displacement: usize,
+    spans: VecDeque<(Option<f64>, Option<f64>)>,
+}
+
+impl Ichimoku {
+    fn new(conversion: usize, base: usize, span_b: usize, displacement: usize) -> Self {
+        Self {
+            conversion: Range::new(conversion),
+            base: Range::new(base),
+            span_b: Range::new(span_b),
+            displacement,
+            spans: VecDeque::new(),
+        }
+    }
+}
+
+impl NativeIndicator for Ichimoku {
+    fn update(&mut self, candlestick: &Candlestick) -> Vec<(&'static str, f64)> {
+        let midpoint = |range: Option<(f64, f64)>| range.map(|(highest, lowest)| (highest + lowest) / 2.0);
+        let conversion = midpoint(self.conversion.update(candlestick));
+        let base = midpoint(self.base.update(candlestick));
+        let span_b = midpoint(self.span_b.update(candlestick));
+        let span_a = conversion.zip(base).map(|(conversion, base)| (conversion + base) / 2.0);
+
+        self.spans.push_back((span_a, span_b));
+        let (span_a, span_b) = match self.spans.len() > self.displacement {
+            true => self.spans.pop_front().unwrap_or_default(),
+            false => (None, None),
+        };
+
+        let mut values = vec![("lagging", candlestick.close)];
+        for (name, value) in [("value", conversion), ("base", base), ("span_a", span_a), ("span_b", span_b)] {
+            if let Some(value) = value {
+                values.push((name, value));
+            }
+        }
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candlesticks(closes: &[f64]) -> Vec<Candlestick> {
+        closes.iter().enumerate().map(|(i, close)| Candlestick::new_with(i as i64 * 60, *close, *close + 1.0, *close - 1.0, *close, 100.0)).collect()
+    }
+
+    // the value output of an indicator after every candlestick
+    fn values(indicator: &mut Box<dyn NativeIndicator>, closes: &[f64]) -> Vec<Option<f64>> {
+        candlesticks(closes).iter().map(|candlestick| {
+            indicator.update(candlestick).into_iter().find(|(name, _)| *name == "value").map(|(_, value)| value)
+        }).collect()
+    }
+
+    fn indicator(name: &str, arguments: &[&str]) -> Box<dyn NativeIndicator> {
+        create(name, arguments.iter().map(|argument| argument.to_string()).collect()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn sma_is_the_mean_of_the_last_periods() {
+        let mut sma = indicator("sma", &["3"]);
+        assert_eq!(values(&mut sma, &[1.0, 2.0, 3.0, 4.0, 8.0]), vec![None, None, Some(2.0), Some(3.0), Some(5.0)]);
+    }
+
+    #[test]
+    fn ema_is_seeded_with_the_sma() {
+        let mut ema = indicator("ema", &["3"]);
+        assert_eq!(values(&mut ema, &[1.0, 2.0, 3.0, 6.0]), vec![None, None, Some(2.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn wma_weights_the_latest_close_the_most() {
+        let mut wma = indicator("wma", &["3"]);
+        assert_eq!(values(&mut wma, &[1.0, 2.0, 3.0, 6.0]), vec![None, None, Some(14.0 / 6.0), Some(26.0 / 6.0)]);
+    }
+
+    #[test]
+    fn rsi_is_100_without_losses() {
+        let mut rsi = indicator("rsi", &["2"]);
+        assert_eq!(values(&mut rsi, &[1.0, 2.0, 3.0, 4.0]), vec![None, None, Some(100.0), Some(100.0)]);
+    }
+
+    #[test]
+    fn ichimoku_spans_are_displaced() {
+        let mut ichimoku = indicator("ichimoku", &["1", "1", "1", "2"]);
+        let outputs = candlesticks(&[10.0, 20.0, 30.0]).iter().map(|candlestick| ichimoku.update(candlestick)).collect::<Vec<_>>();
+        let span_a = |outputs: &Vec<(&'static str, f64)>| outputs.iter().find(|(name, _)| *name == "span_a").map(|(_, value)| *value);
+
+        assert_eq!(span_a(&outputs[1]), None);
+        assert_eq!(span_a(&outputs[2]), Some(10.0));
+    }
+
+    #[test]
+    fn periods_are_capped_at_the_history_limit() {
+        let periods = (MAX_HISTORY_LIMIT + 1).to_string();
+        assert!(create("sma", vec![periods]).unwrap().is_err());
+        assert!(create("ichimoku", vec!["9".to_string(), "26".to_string(), "52".to_string(), usize::MAX.to_string()]).unwrap().is_err());
+        assert!(create("sma", vec![MAX_HISTORY_LIMIT.to_string()]).unwrap().is_ok());
+    }
+
+    #[test]
+    fn arguments_are_checked() {
+        assert!(create("sma", vec!["0".to_string()]).unwrap().is_err());
+        assert!(create("sma", vec!["14".to_string(), "2".to_string()]).unwrap().is_err());
+        assert!(create("unknown", Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fs, path::Path, sync::{Arc, atomic::{AtomicU64, Ordering}}};
+use rlua::{Function, HookTriggers, Lua, StdLib, Table, Value};
+
+// the most lua instructions a plugin can run in one call from the runtime (loading the script, on_bar, ...)
+pub const INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+// the most memory the lua state of a plugin can use
+pub const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+// the number of instructions between two checks of the instruction limit
+const HOOK_INTERVAL: u32 = 1000;
+
+// the globals a plugin can use (everything else of the loaded libraries is removed)
+const GLOBALS: [&str; 26] = [
+    "_G", "_VERSION", "assert", "error", "getmetatable", "ipairs", "next", "pairs", "pcall", "print", "rawequal", "rawget", "rawlen",
+    "rawset", "select", "setmetatable", "tonumber", "tostring", "type", "xpcall", "coroutine", "math", "os", "string", "table", "utf8",
+];
+
+// the functions of the os library a plugin can use (no files, processes or environment)
+const OS_FUNCTIONS: [&str; 4] = ["clock", "date", "difftime", "time"];
+
+// the name of the modules loaded with require in the registry
+const MODULES_KEY: &str = "sandbox_modules";
+
+// errors of the instruction limit are raised again by pcall and xpcall so a script cannot keep running past the limit
+const PROTECTED_CALLS: &str = r#"
+local protected_call, protected_xcall, raise, exceeded = pcall, xpcall, error, ...
+local function check(ok, ...)
+    if not ok and exceeded() then
+        raise((...), 0)
+    end
+    return ok, ...
+end
+pcall = function(...) return check(protected_call(...)) end
+xpcall = function(...) return check(protected_xcall(...)) end
+"#;
+
+// the sandbox profile of the lua state of a plugin
+// - only the whitelisted standard libraries are available (no io, debug, package, load or dofile, os is limited to time functions)
+// - require(name) loads name.lua from the directory of the plugin (ex: require("utils.math") loads utils/math.lua)
+// - every call from the runtime can run up to INSTRUCTION_LIMIT instructions and the state can use up to MEMORY_LIMIT bytes
+pub struct Sandbox {
+    pub directory: String,
+    instructions: Arc<AtomicU64>,
+}
+
+impl Sandbox {
+    pub fn new(directory: String) -> Self {
+        Self {
+            directory,
+            instructions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // create a lua state with the profile
+    pub fn create_lua(&self) -> rlua::Result<Lua> {
+        let lua = Lua::new_with(StdLib::BASE | StdLib::COROUTINE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH | StdLib::OS);
+
+        lua.context(|lua_ctx| {
+            let globals = lua_ctx.globals();
+            let removed = globals.clone().pairs::<String, Value>()
+                .filter_map(|pair| pair.ok().map(|(name, _)| name))
+                .filter(|name| !GLOBALS.contains(&name.as_str()))
+                .collect::<Vec<String>>();
+            for name in removed {
+                globals.set(name, Value::Nil)?;
+            }
+
+            let os: Table = globals.get("os")?;
+            let limited_os = lua_ctx.create_table()?;
+            for name in OS_FUNCTIONS {
+                limited_os.set(name, os.get::<_, Function>(name)?)?;
+            }
+            globals.set("os", limited_os)?;
+
+            let instructions = self.instructions.clone();
+            let exceeded = lua_ctx.create_function(move |_, ()| Ok(instructions.load(Ordering::Relaxed) > INSTRUCTION_LIMIT))?;
+            lua_ctx.load(PROTECTED_CALLS).set_name("sandbox")?.call::<_, ()>(exceeded)?;
+
+            // modules are loaded once and shared by every require of the same name
+            lua_ctx.set_named_registry_value(MODULES_KEY, lua_ctx.create_table()?)?;
+            let directory = self.directory.clone();
+            let require = lua_ctx.create_function(move |lua_ctx, name: String| {
+                let modules: Table = lua_ctx.named_registry_value(MODULES_KEY)?;
+                if let Some(module) = modules.get::<_, Option<Value>>(name.as_str())? {
+                    return Ok(module);
+                }
+
+                let valid = !name.is_empty() && !name.starts_with('.') && !name.ends_with('.') && !name.contains("..")
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                if !valid {
+                    return Err(rlua::Error::RuntimeError(format!("require {}: invalid module name (expected names like utils or utils.math)", name)));
+                }
+
+                // the module has to be inside the directory of the plugin (links are resolved)
+                let path = Path::new(&directory).join(format!("{}.lua", name.replace('.', "/")));
+                let inside = match (path.canonicalize(), Path::new(&directory).canonicalize()) {
+                    (Ok(path), Ok(directory)) => path.starts_with(directory),
+                    _ => false,
+                };
+                let script = match inside {
+                    true => fs::read_to_string(&path).ok(),
+                    false => None,
+                };
+                let script = script.ok_or_else(|| rlua::Error::RuntimeError(format!("require {}: module not found in {}", name, directory)))?;
+
+                let module = match lua_ctx.load(&script).set_name(&name)?.call::<_, Value>(())? {
+                    Value::Nil => Value::Boolean(true),
+                    module => module,
+                };
+                modules.set(name.as_str(), module.clone())?;
+                Ok(module)
+            })?;
+            globals.set("require", require)?;
+
+            Ok(())
+        })?;
+
+        let instructions = self.instructions.clone();
+        lua.set_hook(HookTriggers { every_nth_instruction: Some(HOOK_INTERVAL), ..Default::default() }, move |_, _| {
+            if instructions.fetch_add(HOOK_INTERVAL as u64, Ordering::Relaxed) + HOOK_INTERVAL as u64 > INSTRUCTION_LIMIT {
+                return Err(rlua::Error::RuntimeError(format!("instruction limit exceeded ({} instructions in one call)", INSTRUCTION_LIMIT)));
+            }
+            Ok(())
+        });
+        lua.set_memory_limit(Some(MEMORY_LIMIT));
+
+        Ok(lua)
+    }
+
+    // start counting the instructions of a new call from the runtime
+    pub fn reset(&self) {
+        self.instructions.store(0, Ordering::Relaxed);
+    }
+
+    // describe an error of the script (limits are named so they are not mistaken for bugs of the script)
+    pub fn describe(&self, error: &rlua::Error) -> String {
+        if self.instructions.load(Ordering::Relaxed) > INSTRUCTION_LIMIT {
+            return format!("instruction limit exceeded ({} instructions in one call)", INSTRUCTION_LIMIT);
+        }
+        if is_memory_error(error) {
+            return format!("memory limit exceeded ({} MB)", MEMORY_LIMIT / 1024 / 1024);
+        }
+
+        // errors of rust callbacks (require, hooks) are described by their cause and where they were called
+        match error {
+            rlua::Error::CallbackError { cause, traceback } => format!("{}\n{}", self.describe(cause), traceback),
+            error => error.to_string(),
+        }
+    }
+}
+
+fn is_memory_error(error: &rlua::Error) -> bool {
+    match error {
+        rlua::Error::MemoryError(_) => true,
+        rlua::Error::CallbackError { cause, .. } => is_memory_error(cause),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> String {
+        let directory = std::env::temp_dir().join(format!("sandbox_{}_{}", name, std::process::id()));
+        fs::create_dir_all(directory.join("utils")).unwrap();
+        directory.to_string_lossy().to_string()
+    }
+
+    fn run(sandbox: &Sandbox, lua: &Lua, script: &str) -> Result<Value<'static>, String> {
+        sandbox.reset();
+        lua.context(|lua_ctx| {
+            lua_ctx.load(script).eval::<Value>().map(|value| match value {
+                Value::Boolean(value) => Value::Boolean(value),
+                Value::Integer(value) => Value::Integer(value),
+                Value::Number(value) => Value::Number(value),
+                _ => Value::Nil,
+            })
+        }).map_err(|error| sandbox.describe(&error))
+    }
+
+    #[test]
+    fn removes_unsafe_globals() {
+        let sandbox = Sandbox::new(directory("globals"));
+        let lua = sandbox.create_lua().unwrap();
+        let script = "return io == nil and debug == nil and load == nil and dofile == nil and os.execute == nil and os.getenv == nil and os.time ~= nil";
+        assert!(matches!(run(&sandbox, &lua, script), Ok(Value::Boolean(true))));
+    }
+
+    #[test]
+    fn requires_modules_of_the_directory() {
+        let directory = directory("require");
+        fs::write(Path::new(&directory).join("utils/math.lua"), "loads = (loads or 0) + 1\nreturn { double = function(x) return x * 2 end }").unwrap();
+        let sandbox = Sandbox::new(directory.clone());
+        let lua = sandbox.create_lua().unwrap();
+
+        // modules are loaded once
+        assert!(matches!(run(&sandbox, &lua, "return require('utils.math').double(require('utils.math').double(2)) + loads"), Ok(Value::Integer(9))));
+        let error = run(&sandbox, &lua, "return require('../secret')").unwrap_err();
+        assert!(error.contains("invalid module name"), "{}", error);
+        assert!(run(&sandbox, &lua, "return require('missing')").unwrap_err().contains("module not found"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn limits_instructions() {
+        let sandbox = Sandbox::new(directory("instructions"));
+        let lua = sandbox.create_lua().unwrap();
+        assert!(run(&sandbox, &lua, "while true do end").unwrap_err().contains("instruction limit exceeded"));
+        // pcall cannot catch the limit
+        assert!(run(&sandbox, &lua, "while true do pcall(function() while true do end end) end").unwrap_err().contains("instruction limit exceeded"));
+        // the count starts again with every call
+        assert!(matches!(run(&sandbox, &lua, "local n = 0 for i = 1, 1000 do n = n + i end return n"), Ok(Value::Integer(500500))));
+    }
+
+    #[test]
+    fn limits_memory() {
+        let sandbox = Sandbox::new(directory("memory"));
+        let lua = sandbox.create_lua().unwrap();
+        let error = run(&sandbox, &lua, "local t = {} for i = 1, 100 do t[i] = string.rep('x', 1024 * 1024) .. i end").unwrap_err();
+        assert!(error.contains("memory limit exceeded"), "{}", error);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, Duration, Weekday};
+
+use crate::database::models::calendar::TradingCalendar;
+
+// the most days searched for the next time of a schedule (a 29th of february can be years away)
+const SEARCH_DAYS: i64 = 366 * 8;
+
+// represents an event of the trading calendar of an exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalendarEvent {
+    Open,
+    Close,
+}
+
+impl CalendarEvent {
+    pub fn from_string(value: &str) -> Option<Self> {
+        match value {
+            "market_open" => Some(CalendarEvent::Open),
+            "market_close" => Some(CalendarEvent::Close),
+            _ => None,
+        }
+    }
+}
+
+// represents when a timer of a strategy fires
+// - cron specs are "minute hour day month weekday" in utc (ex: "59 23 * * *" is daily at 23:59, "0 */4 * * mon-fri" every 4 hours on weekdays)
+// - fields are *, numbers, ranges (1-5), steps (*/15, 0-30/10) and comma separated lists, weekdays are 0-7 (0 and 7 are sunday) or names
+// - @hourly, @daily, @weekly (monday at 00:00) and @monthly are shortcuts of cron specs
+// - calendar specs follow the sessions of an exchange: market_open or market_close with an optional offset (+30m, -5m, +1h, -30s),
+//   weekdays (mon or mon,fri) and exchange (needed when the strategy has datasets of more than one exchange)
+//   ex: "market_open mon" is every monday at the open, "market_close -5m" is 5 minutes before every close
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduleSpec {
+    Cron {
+        minutes: Vec<u32>,
+        hours: Vec<u32>,
+        days: Vec<u32>,
+        months: Vec<u32>,
+        weekdays: Vec<u32>,
+        // a day matches the days or the weekdays when both are restricted (like cron)
+        any_day: bool,
+        any_weekday: bool,
+    },
+    Calendar {
+        event: CalendarEvent,
+        exchange: String,
+        offset: i64,
+        weekdays: Vec<Weekday>,
+    },
+}
+
+impl ScheduleSpec {
+    // parse a spec (the exchanges are the exchanges of the datasets of the strategy)
+    pub fn from_string(spec: &str, exchanges: &[String]) -> Result<Self, String> {
+        let spec = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * mon",
+            "@monthly" => "0 0 1 * *",
+            spec => spec,
+        };
+        let tokens = spec.split_whitespace().collect::<Vec<&str>>();
+        let first = tokens.first().ok_or_else(|| "empty spec".to_string())?;
+
+        if let Some(event) = CalendarEvent::from_string(first) {
+            let mut offset = 0;
+            let mut weekdays = Vec::new();
+            let mut exchange = None;
+            for token in tokens[1..].iter() {
+                if token.starts_with('+') || token.starts_with('-') {
+                    offset = parse_offset(token).ok_or_else(|| format!("invalid offset {} (expected +30m, -5m, +1h, ...)", token))?;
+                } else if let Ok(days) = token.split(',').map(|day| day.parse::<Weekday>()).collect::<Result<Vec<Weekday>, _>>() {
+                    weekdays.extend(days);
+                } else if exchanges.iter().any(|name| name == token) {
+                    exchange = Some(token.to_string());
+                } else {
+                    return Err(format!("unknown weekday or exchange {}", token));
+                }
+            }
+
+            let exchange = match (exchange, exchanges) {
+                (Some(exchange), _) => exchange,
+                (None, [exchange]) => exchange.clone(),
+                (None, _) => return Err("an exchange is needed with datasets of more than one exchange".to_string()),
+            };
+            return Ok(ScheduleSpec::Calendar { event, exchange, offset, weekdays });
+        }
+
+        let fields = match tokens.as_slice() {
+            [minute, hour, day, month, weekday] => [*minute, *hour, *day, *month, *weekday],
+            _ => return Err("expected a cron spec (minute hour day month weekday), a shortcut (@daily) or a calendar spec (market_open mon)".to_string()),
+        };
+        // sunday is 0 and 7
+        let mut weekdays = parse_field(fields[4], 0, 7, true).ok_or_else(|| format!("invalid weekday field {}", fields[4]))?
+            .into_iter().map(|weekday| weekday % 7).collect::<Vec<u32>>();
+        weekdays.sort();
+        weekdays.dedup();
+        let days = parse_field(fields[2], 1, 31, false).ok_or_else(|| format!("invalid day field {}", fields[2]))?;
+        // a field is unrestricted when it covers its whole range (*, */1, 1-31, 0-6, ...)
+        Ok(ScheduleSpec::Cron {
+            minutes: parse_field(fields[0], 0, 59, false).ok_or_else(|| format!("invalid minute field {}", fields[0]))?,
+            hours: parse_field(fields[1], 0, 23, false).ok_or_else(|| format!("invalid hour field {}", fields[1]))?,
+            any_day: days.len() == 31,
+            any_weekday: weekdays.len() == 7,
+            days,
+            months: parse_field(fields[3], 1, 12, false).ok_or_else(|| format!("invalid month field {}", fields[3]))?,
+            weekdays,
+        })
+    }
+
+    // get the exchange of a calendar spec
+    pub fn exchange(&self) -> Option<&str> {
+        match self {
+            ScheduleSpec::Calendar { exchange, .. } => Some(exchange),
+            ScheduleSpec::Cron { .. } => None,
+        }
+    }
+
+    // get the first time of the spec after a timestamp
+    // - exchanges without a calendar trade 24/7 in utc
+    pub fn next(&self, after: i64, calendars: &HashMap<String, TradingCalendar>) -> Option<i64> {
+        match self {
+            ScheduleSpec::Cron { minutes, hours, days, months, weekdays, any_day, any_weekday } => {
+                let first = DateTime::from_timestamp(after.div_euclid(60) * 60 + 60, 0)?.date_naive();
+                for i in 0..SEARCH_DAYS {
+                    let date = first + Duration::days(i);
+                    let day = days.contains(&date.day());
+                    let weekday = weekdays.contains(&date.weekday().num_days_from_sunday());
+                    let matches = match (any_day, any_weekday) {
+                        (false, false) => day || weekday,
+                        _ => day && weekday,
+                    };
+                    if !matches || !months.contains(&date.month()) {
+                        continue;
+                    }
+
+                    let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+                    for hour in hours.iter() {
+                        for minute in minutes.iter() {
+                            let timestamp = midnight + *hour as i64 * 3600 + *minute as i64 * 60;
+                            if timestamp > after {
+                                return Some(timestamp);
+                            }
+                        }
+                    }
+                }
+
+                None
+            },
+            ScheduleSpec::Calendar { event, exchange, offset, weekdays } => {
+                let continuous = TradingCalendar::continuous();
+                let calendar = calendars.get(exchange).unwrap_or(&continuous);
+
+                // the day before is searched too since an offset can move a time to another day
+                let first = calendar.local_date(after)? - Duration::days(1);
+                for i in 0..SEARCH_DAYS {
+                    let date = first + Duration::days(i);
+                    if !weekdays.is_empty() && !weekdays.contains(&date.weekday()) {
+                        continue;
+                    }
+                    let session = match calendar.session(date) {
+                        Some(session) => session,
+                        None => continue,
+                    };
+
+                    let timestamp = match event {
+                        CalendarEvent::Open => session.open,
+                        CalendarEvent::Close => session.close,
+                    } + offset;
+                    if timestamp > after {
+                        return Some(timestamp);
+                    }
+                }
+
+                None
+            },
+        }
+    }
+}
+
+// represents a timer scheduled by a strategy
+pub struct Timer {
+    pub id: u64,
+    pub spec: String,
+    pub schedule: ScheduleSpec,
+    // the next time of the timer (none until the clock of the strategy has started or once the spec has no more times)
+    pub next: Option<i64>,
+    started: bool,
+}
+
+// represents a time a timer fired at
+// - missed is the number of earlier times of the timer that passed without bars (only the latest one fires)
+#[derive(Debug, Clone)]
+pub struct TimerEvent {
+    pub id: u64,
+    pub spec: String,
+    pub exchange: Option<String>,
+    pub time: i64,
+    pub missed: u64,
+}
+
+// the timers of a strategy and its clock in simulated time
+// - the clock only moves with the bars of the strategy (never with the wall clock) so backtests, replays and live runs fire the same timers at the same bars
+// - a timer added while the strategy runs fires after the current clock, a timer added before the first bar can fire at its timestamp
+#[derive(Default)]
+pub struct Timers {
+    pub timers: Vec<Timer>,
+    pub clock: Option<i64>,
+    next_id: u64,
+}
+
+impl Timers {
+    // add a timer and get its id
+    pub fn add(&mut self, spec: String, schedule: ScheduleSpec) -> u64 {
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id: self.next_id,
+            spec,
+            schedule,
+            next: None,
+            started: false,
+        });
+        self.next_id
+    }
+
+    // remove a timer (returns false when there is no timer with the id)
+    pub fn remove(&mut self, id: u64) -> bool {
+        let count = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() != count
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.timers.iter().any(|timer| timer.id == id)
+    }
+
+    // move the clock to a timestamp and get the timers that are due, in order of their times (then of their ids)
+    pub fn advance(&mut self, clock: i64, calendars: &HashMap<String, TradingCalendar>) -> Vec<TimerEvent> {
+        let start = self.clock.unwrap_or(clock - 1);
+        let mut events = Vec::new();
+        for timer in self.timers.iter_mut() {
+            if !timer.started {
+                timer.started = true;
+                timer.next = timer.schedule.next(start, calendars);
+            }
+
+            let mut time = None;
+            let mut missed = 0;
+            while let Some(next) = timer.next.filter(|next| *next <= clock) {
+                if time.is_some() {
+                    missed += 1;
+                }
+                time = Some(next);
+                timer.next = timer.schedule.next(next, calendars);
+            }
+
+            if let Some(time) = time {
+                events.push(TimerEvent {
+                    id: timer.id,
+                    spec: timer.spec.clone(),
+                    exchange: timer.schedule.exchange().map(|exchange| exchange.to_string()),
+                    time,
+                    missed,
+                });
+            }
+        }
+        self.clock = Some(clock);
+
+        events.sort_by_key(|event| (event.time, event.id));
+        events
+    }
+}
+
+// parse a field of a cron spec into its sorted values
+fn parse_field(field: &str, min: u32, max: u32, weekdays: bool) -> Option<Vec<u32>> {
+    let value = |text: &str| -> Option<u32> {
+        let number = match text.parse::<u32>() {
+            Ok(number) => number,
+            Err(_) if weekdays => text.parse::<Weekday>().ok()?.num_days_from_sunday(),
+            Err(_) => return None,
+        };
+        Some(number).filter(|number| *number >= min && *number <= max)
+    };
+
+    let mut values = Vec::new();
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            return None;
+        }
+        values.extend((first..=last).step_by(step as usize));
+    }
+    values.sort();
+    values.dedup();
+
+    Some(values)
+}
+
+// parse an offset of a calendar spec into seconds (+30m, -5m, +1h, -30s, a number without unit is minutes)
+fn parse_offset(text: &str) -> Option<i64> {
+    let (sign, text) = match text.split_at(1) {
+        ("+", text) => (1, text),
+        ("-", text) => (-1, text),
+        _ => return None,
+    };
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => text.split_at(position),
+        None => (text, "m"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return None,
+    };
+
+    number.parse::<i64>().ok().map(|number| sign * number * seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn timestamp(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp()
+    }
+
+    fn cron(spec: &str) -> ScheduleSpec {
+        ScheduleSpec::from_string(spec, &[]).unwrap()
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse_field("*/15", 0, 59, false), Some(vec![0, 15, 30, 45]));
+        assert_eq!(parse_field("0-30/10", 0, 59, false), Some(vec![0, 10, 20, 30]));
+        assert_eq!(parse_field("5/20", 0, 59, false), Some(vec![5, 25, 45]));
+        assert_eq!(parse_field("3,1,2-3", 1, 31, false), Some(vec![1, 2, 3]));
+        assert_eq!(parse_field("mon-fri", 0, 7, true), Some(vec![1, 2, 3, 4, 5]));
+        assert_eq!(parse_field("60", 0, 59, false), None);
+        assert_eq!(parse_field("5-1", 0, 59, false), None);
+        assert_eq!(parse_field("*/0", 0, 59, false), None);
+        assert_eq!(parse_field("mon", 0, 59, false), None);
+    }
+
+    #[test]
+    fn parse_specs() {
+        assert_eq!(cron("@daily"), cron("0 0 * * *"));
+        match cron("0 0 * * 7,0") {
+            ScheduleSpec::Cron { weekdays, .. } => assert_eq!(weekdays, vec![0]),
+            spec => panic!("unexpected spec {:?}", spec),
+        }
+        assert!(ScheduleSpec::from_string("0 0 * *", &[]).is_err());
+        assert!(ScheduleSpec::from_string("", &[]).is_err());
+        assert_eq!(parse_offset("+30m"), Some(1800));
+        assert_eq!(parse_offset("-1h"), Some(-3600));
+        assert_eq!(parse_offset("+5"), Some(300));
+        assert_eq!(parse_offset("5m"), None);
+    }
+
+    #[test]
+    fn next_cron() {
+        let calendars = HashMap::new();
+        let after = timestamp(2024, 1, 3, 23, 59);
+        assert_eq!(cron("59 23 * * *").next(after, &calendars), Some(timestamp(2024, 1, 4, 23, 59)));
+        assert_eq!(cron("0 */4 * * mon-fri").next(timestamp(2024, 1, 5, 21, 0), &calendars), Some(timestamp(2024, 1, 8, 0, 0)));
+        assert_eq!(cron("@monthly").next(after, &calendars), Some(timestamp(2024, 2, 1, 0, 0)));
+        assert_eq!(cron("0 0 29 2 *").next(after, &calendars), Some(timestamp(2024, 2, 29, 0, 0)));
+        assert_eq!(cron("0 0 30 2 *").next(after, &calendars), None);
+    }
+
+    #[test]
+    fn next_cron_day_and_weekday() {
+        let calendars = HashMap::new();
+        let after = timestamp(2024, 1, 1, 0, 0);
+        // restricted days and weekdays match either (the 10th or the next monday)
+        assert_eq!(cron("0 0 10 * mon").next(after, &calendars), Some(timestamp(2024, 1, 8, 0, 0)));
+        // a field that covers its whole range is unrestricted, so the other one decides alone
+        assert_eq!(cron("0 0 10 * */1").next(after, &calendars), Some(timestamp(2024, 1, 10, 0, 0)));
+        assert_eq!(cron("0 0 */1 * mon").next(after, &calendars), Some(timestamp(2024, 1, 8, 0, 0)));
+        assert_eq!(cron("0 0 1-31 * 0-6").next(after, &calendars), Some(timestamp(2024, 1, 2, 0, 0)));
+    }
+
+    #[test]
+    fn next_calendar() {
+        let mut calendar = TradingCalendar::continuous();
+        calendar.open = 600;
+        calendar.close = 960;
+        calendar.weekdays = vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        let calendars = HashMap::from([("EX".to_string(), calendar)]);
+        let exchanges = vec!["EX".to_string()];
+
+        // friday 2024-01-05 after the close
+        let after = timestamp(2024, 1, 5, 17, 0);
+        let open = ScheduleSpec::from_string("market_open", &exchanges).unwrap();
+        assert_eq!(open.exchange(), Some("EX"));
+        assert_eq!(open.next(after, &calendars), Some(timestamp(2024, 1, 8, 10, 0)));
+        let close = ScheduleSpec::from_string("market_close -5m wed", &exchanges).unwrap();
+        assert_eq!(close.next(after, &calendars), Some(timestamp(2024, 1, 10, 15, 55)));
+        // an offset can move a time before the timestamp of its session
+        let before = ScheduleSpec::from_string("market_open -30m", &exchanges).unwrap();
+        assert_eq!(before.next(timestamp(2024, 1, 8, 9, 0), &calendars), Some(timestamp(2024, 1, 8, 9, 30)));
+
+        assert!(ScheduleSpec::from_string("market_open", &["EX".to_string(), "EY".to_string()]).is_err());
+        assert!(ScheduleSpec::from_string("market_open XX", &exchanges).is_err());
+        assert!(ScheduleSpec::from_string("market_open +5x", &exchanges).is_err());
+    }
+
+    #[test]
+    fn advance_timers() {
+        let calendars = HashMap::new();
+        let mut timers = Timers::default();
+        let id = timers.add("@hourly".to_string(), cron("@hourly"));
+        let start = timestamp(2024, 1, 1, 0, 0);
+        // a timer added before the first bar fires at its timestamp
+        let events = timers.advance(start, &calendars);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].time, start);
+
+        // only the latest of the missed times fires
+        let events = timers.advance(start + 3 * 3600 + 60, &calendars);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].time, events[0].missed), (start + 3 * 3600, 2));
+        assert!(timers.remove(id));
+        assert!(!timers.contains(id));
+        assert!(timers.advance(start + 5 * 3600, &calendars).is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+use rlua::{AnyUserData, Context, MetaMethod, UserData, UserDataMethods, Value};
+
+use super::{history::{HistoryBuffer, HistoryColumn, HistoryField}, indicators::{IndicatorHandle, IndicatorValues}, native::Extreme};
+
+// the name of the series of a lua state in its registry
+const REGISTRY_KEY: &str = "series";
+
+// a series of values read most recent first (index 1 is the latest value)
+// - indicators and history columns are read from their own buffers, derived series from their node
+#[derive(Clone)]
+pub enum Source {
+    Indicator {
+        key: String,
+        output: String,
+        values: Arc<Mutex<IndicatorValues>>,
+    },
+    History(Arc<Mutex<HistoryBuffer>>, HistoryField),
+    Derived(usize),
+    Constant(f64),
+}
+
+// represents an arithmetic operator of derived series
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+        }
+    }
+
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Operator::Add => a + b,
+            Operator::Subtract => a - b,
+            Operator::Multiply => a * b,
+            Operator::Divide => a / b,
+        }
+    }
+}
+
+// how the value of a node is computed from its sources
+enum Operation {
+    Binary(Operator, Source, Source),
+    // the highest or lowest value of the latest values of a source
+    Extreme(Source, Extreme),
+    // the number of consecutive rising (or falling) values of a source
+    Streak(Source, bool),
+}
+
+// a series computed from other series, one value every time one of its sources has a new value
+struct Node {
+    key: String,
+    operation: Operation,
+    values: VecDeque<f64>,
+    // the sum of the versions of the sources when the node was last computed
+    version: u64,
+    pushes: u64,
+}
+
+impl Node {
+    fn sources(&self) -> Vec<&Source> {
+        match &self.operation {
+            Operation::Binary(_, a, b) => vec![a, b],
+            Operation::Extreme(source, _) | Operation::Streak(source, _) => vec![source],
+        }
+    }
+
+    // compute the next value from the values of the sources an offset of bars ago (0 is the latest bar)
+    fn advance(&mut self, nodes: &[Node], offset: usize, capacity: usize) {
+        let value = |source: &Source, index: usize| read(nodes, source, index + offset);
+        let next = match &mut self.operation {
+            Operation::Binary(operator, a, b) => value(a, 1).zip(value(b, 1)).map(|(a, b)| operator.apply(a, b)),
+            Operation::Extreme(source, extreme) => value(source, 1).and_then(|current| extreme.update(current)),
+            Operation::Streak(source, rising) => value(source, 1).map(|current| match value(source, 2) {
+                Some(previous) if (*rising && current > previous) || (!*rising && current < previous) => self.values.front().map_or(1.0, |streak| streak + 1.0),
+                _ => 0.0,
+            }),
+        };
+
+        if let Some(next) = next {
+            self.values.push_front(next);
+            self.values.truncate(capacity);
+            self.pushes += 1;
+        }
+    }
+}
+
+// read a value of a source at an index (1 is the latest value)
+fn read(nodes: &[Node], source: &Source, index: usize) -> Option<f64> {
+    match source {
+        Source::Indicator { output, values, .. } => values.lock().unwrap().get(output, index),
+        Source::History(buffer, field) => buffer.lock().unwrap().get(*field, index as i64),
+        Source::Derived(node) => nodes.get(*node)?.values.get(index.checked_sub(1)?).copied(),
+        Source::Constant(value) => Some(*value),
+    }
+}
+
+// get the number of values of a source
+fn len(nodes: &[Node], source: &Source) -> usize {
+    match source {
+        Source::Indicator { output, values, .. } => values.lock().unwrap().len(output),
+        Source::History(buffer, _) => buffer.lock().unwrap().len(),
+        Source::Derived(node) => nodes.get(*node).map_or(0, |node| node.values.len()),
+        Source::Constant(_) => usize::MAX,
+    }
+}
+
+// get a number that changes every time a source has a new value
+fn version(nodes: &[Node], source: &Source) -> u64 {
+    match source {
+        Source::Indicator { values, .. } => values.lock().unwrap().updates(),
+        Source::History(buffer, _) => buffer.lock().unwrap().pushes(),
+        Source::Derived(node) => nodes.get(*node).map_or(0, |node| node.pushes),
+        Source::Constant(_) => 0,
+    }
+}
+
+// the derived series of a lua state
+// - a node is created once for every expression and kept up to date by update(), so every node costs O(1) per bar
+// - a new node is warmed up with the values its sources already have
+// - the values of sources updated by different intervals are combined as of their latest values
+pub struct SeriesSet {
+    pub capacity: usize,
+    nodes: Vec<Node>,
+    keys: HashMap<String, usize>,
+}
+
+impl SeriesSet {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            nodes: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    // get the text that identifies a source (equal sources share their nodes)
+    fn key(&self, source: &Source) -> String {
+        match source {
+            Source::Indicator { key, output, .. } => format!("{}.{}", key, output),
+            Source::History(buffer, field) => {
+                let buffer = buffer.lock().unwrap();
+                format!("{}:{} {} {}", buffer.exchange, buffer.symbol, buffer.interval, field.name())
+            },
+            Source::Derived(node) => self.nodes.get(*node).map_or(String::new(), |node| node.key.clone()),
+            Source::Constant(value) => value.to_string(),
+        }
+    }
+
+    // get the node of an operation (created and warmed up the first time)
+    fn node(&mut self, key: String, operation: Operation) -> usize {
+        if let Some(index) = self.keys.get(&key) {
+            return *index;
+        }
+
+        let mut node = Node {
+            key: key.clone(),
+            operation,
+            values: VecDeque::with_capacity(self.capacity + 1),
+            version: 0,
+            pushes: 0,
+        };
+
+        // the oldest values are passed first
+        let available = node.sources().iter().map(|source| len(&self.nodes, source)).min().unwrap_or(0).min(self.capacity);
+        for offset in (0..available).rev() {
+            node.advance(&self.nodes, offset, self.capacity);
+        }
+        node.version = node.sources().iter().map(|source| version(&self.nodes, source)).sum();
+
+        self.nodes.push(node);
+        self.keys.insert(key, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    // compute the nodes whose sources have new values (sources are always older nodes, so one pass is enough)
+    pub fn update(&mut self) {
+        for index in 0..self.nodes.len() {
+            let (nodes, rest) = self.nodes.split_at_mut(index);
+            let node = &mut rest[0];
+            let version = node.sources().iter().map(|source| version(nodes, source)).sum::<u64>();
+            if version != node.version {
+                node.version = version;
+                node.advance(nodes, 0, self.capacity);
+            }
+        }
+    }
+
+    // get a value of a source at an index (1 is the latest value)
+    pub fn get(&self, source: &Source, index: usize) -> Option<f64> {
+        read(&self.nodes, source, index)
+    }
+
+    // get the latest values of a source (most recent first)
+    pub fn history(&self, source: &Source, count: usize) -> Vec<f64> {
+        (1..=count).map_while(|index| self.get(source, index)).collect()
+    }
+
+    // get the series of an arithmetic operation of two sources
+    pub fn binary(&mut self, operator: Operator, a: Source, b: Source) -> Source {
+        let key = format!("({} {} {})", self.key(&a), operator.symbol(), self.key(&b));
+        Source::Derived(self.node(key, Operation::Binary(operator, a, b)))
+    }
+
+    // get the highest (or lowest) of the latest values of a source (none until there are enough values)
+    pub fn extreme(&mut self, source: Source, periods: usize, highest: bool) -> Option<f64> {
+        let key = format!("{}({}, {})", if highest { "highest" } else { "lowest" }, self.key(&source), periods);
+        let node = self.node(key, Operation::Extreme(source, Extreme::new(periods, highest)));
+        self.nodes[node].values.front().copied()
+    }
+
+    // get the number of consecutive rising (or falling) values of a source
+    pub fn streak(&mut self, source: Source, rising: bool) -> usize {
+        let key = format!("{}({})", if rising { "rising" } else { "falling" }, self.key(&source));
+        let node = self.node(key, Operation::Streak(source, rising));
+        self.nodes[node].values.front().map_or(0, |streak| *streak as usize)
+    }
+
+    // check whether a source crossed over (or under) another source with its latest value
+    pub fn cross(&self, a: &Source, b: &Source, over: bool) -> bool {
+        match (self.get(a, 1), self.get(a, 2), self.get(b, 1), self.get(b, 2)) {
+            (Some(a1), Some(a2), Some(b1), Some(b2)) if over => a2 <= b2 && a1 > b1,
+            (Some(a1), Some(a2), Some(b1), Some(b2)) => a2 >= b2 && a1 < b1,
+            _ => false,
+        }
+    }
+
+    // get the difference between the latest value of a source and its value a number of values ago
+    pub fn change(&self, source: &Source, periods: usize) -> Option<f64> {
+        Some(self.get(source, 1)? - self.get(source, periods + 1)?)
+    }
+}
+
+// the series of a lua state kept in its registry (so every series userdata reaches it)
+#[derive(Clone)]
+pub struct SeriesRegistry(pub Arc<Mutex<SeriesSet>>);
+
+impl UserData for SeriesRegistry {}
+
+// make series available to the scripts of a lua state
+pub fn register(lua_ctx: Context, series: Arc<Mutex<SeriesSet>>) -> rlua::Result<()> {
+    lua_ctx.set_named_registry_value(REGISTRY_KEY, SeriesRegistry(series))
+}
+
+fn series_set(lua_ctx: Context) -> rlua::Result<Arc<Mutex<SeriesSet>>> {
+    let registry = lua_ctx.named_registry_value::<_, AnyUserData>(REGISTRY_KEY)
+        .map_err(|_| rlua::Error::RuntimeError("series are not available in this script".to_string()))?;
+    let registry = registry.borrow::<SeriesRegistry>()?;
+    Ok(registry.0.clone())
+}
+
+// a userdata that can be used as a series (indicators, history columns and derived series)
+pub trait AsSource {
+    fn source(&self) -> Source;
+}
+
+// convert a lua value into a source (numbers are constant series)
+fn to_source(value: Value) -> rlua::Result<Source> {
+    match value {
+        Value::Integer(value) => Ok(Source::Constant(value as f64)),
+        Value::Number(value) => Ok(Source::Constant(value)),
+        Value::UserData(data) => {
+            if let Ok(indicator) = data.borrow::<IndicatorHandle>() {
+                return Ok(indicator.source());
+            }
+            if let Ok(column) = data.borrow::<HistoryColumn>() {
+                return Ok(column.source());
+            }
+            if let Ok(series) = data.borrow::<Series>() {
+                return Ok(series.source());
+            }
+            Err(rlua::Error::RuntimeError("expected a number, an indicator, a history column or a series".to_string()))
+        },
+        _ => Err(rlua::Error::RuntimeError("expected a number, an indicator, a history column or a series".to_string())),
+    }
+}
+
+fn binary<'lua>(lua_ctx: Context<'lua>, operator: Operator, a: Value<'lua>, b: Value<'lua>) -> rlua::Result<Series> {
+    let (a, b) = (to_source(a)?, to_source(b)?);
+    Ok(Series(series_set(lua_ctx)?.lock().unwrap().binary(operator, a, b)))
+}
+
+fn check_periods(name: &str, periods: usize) -> rlua::Result<usize> {
+    if periods < 1 {
+        return Err(rlua::Error::RuntimeError(format!("{}: expected at least 1 period", name)));
+    }
+
+    Ok(periods)
+}
+
+// add the arithmetic operators and the series helpers to a userdata
+// - a + b, a - b, a * b, a / b and -a are derived series (numbers can be used on either side)
+// - crossover(other) and crossunder(other) check the latest two values against another series or a number (0 by default)
+// - highest(n) and lowest(n) are the highest and lowest of the latest n values (nil until there are n values)
+// - change(n) is the latest value minus the value n values ago (n defaults to 1)
+// - rising(n) and falling(n) check whether the latest n values rose (or fell) one after another (n defaults to 1)
+pub fn add_series_methods<'lua, T: AsSource + UserData, M: UserDataMethods<'lua, T>>(methods: &mut M) {
+    methods.add_method("crossover", |lua_ctx, series, other: Option<Value>| {
+        let other = other.map_or(Ok(Source::Constant(0.0)), to_source)?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().cross(&series.source(), &other, true))
+    });
+    methods.add_method("crossunder", |lua_ctx, series, other: Option<Value>| {
+        let other = other.map_or(Ok(Source::Constant(0.0)), to_source)?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().cross(&series.source(), &other, false))
+    });
+    methods.add_method("highest", |lua_ctx, series, periods: usize| {
+        let periods = check_periods("highest", periods)?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().extreme(series.source(), periods, true))
+    });
+    methods.add_method("lowest", |lua_ctx, series, periods: usize| {
+        let periods = check_periods("lowest", periods)?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().extreme(series.source(), periods, false))
+    });
+    methods.add_method("change", |lua_ctx, series, periods: Option<usize>| {
+        let periods = check_periods("change", periods.unwrap_or(1))?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().change(&series.source(), periods))
+    });
+    methods.add_method("rising", |lua_ctx, series, periods: Option<usize>| {
+        let periods = check_periods("rising", periods.unwrap_or(1))?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().streak(series.source(), true) >= periods)
+    });
+    methods.add_method("falling", |lua_ctx, series, periods: Option<usize>| {
+        let periods = check_periods("falling", periods.unwrap_or(1))?;
+        Ok(series_set(lua_ctx)?.lock().unwrap().streak(series.source(), false) >= periods)
+    });
+
+    methods.add_meta_function(MetaMethod::Add, |lua_ctx, (a, b): (Value, Value)| binary(lua_ctx, Operator::Add, a, b));
+    methods.add_meta_function(MetaMethod::Sub, |lua_ctx, (a, b): (Value, Value)| binary(lua_ctx, Operator::Subtract, a, b));
+    methods.add_meta_function(MetaMethod::Mul, |lua_ctx, (a, b): (Value, Value)| binary(lua_ctx, Operator::Multiply, a, b));
+    methods.add_meta_function(MetaMethod::Div, |lua_ctx, (a, b): (Value, Value)| binary(lua_ctx, Operator::Divide, a, b));
+    methods.add_meta_function(MetaMethod::Unm, |lua_ctx, a: Value| binary(lua_ctx, Operator::Subtract, Value::Integer(0), a));
+}
+
+// a series derived from indicators, history columns and numbers (ex: indicator("ema", 9, "1m") - indicator("ema", 21, "1m"))
+// - val() is the latest value (nil until every source has a value), history(n) is the latest n values (most recent first)
+#[derive(Clone)]
+pub struct Series(pub Source);
+
+impl AsSource for Series {
+    fn source(&self) -> Source {
+        self.0.clone()
+    }
+}
+
+impl UserData for Series {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("val", |lua_ctx, series, ()| Ok(series_set(lua_ctx)?.lock().unwrap().get(&series.0, 1)));
+        methods.add_method("history", |lua_ctx, series, count: usize| Ok(series_set(lua_ctx)?.lock().unwrap().history(&series.0, count)));
+        add_series_methods(methods);
+    }
+}
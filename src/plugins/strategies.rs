@@ -1,163 +1,1151 @@
-use std::{io::{Error, Read}, fs, collections::HashMap, any::Any};
+use std::{io::{Error, ErrorKind, Read}, fs, collections::HashMap, sync::{Arc, Mutex}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use rlua::{Lua, Function, Variadic, MultiValue, Context, FromLuaMulti};
+use rlua::{Lua, Context, Function, MultiValue, Table, Value, Variadic, ToLuaMulti};
 
-use crate::{models::{Order, Metric}, datasets::Dataset};
+use crate::{models::{Order, OrderSide, OrderType}, datasets::Dataset, simulator::{OrderSimulator, BacktestSummary, BacktestStatus, Fill, Position}, utils::parse_time};
+use crate::database::{database::Database, models::{bar::Bar, bar_map::BarMap, calendar::{Session, TradingCalendar}, candlestick::Candlestick, exchange::Exchange, symbol::Symbol, interval::Interval, query::{Query, MAX_INTERVALS}, symbol_table::SymbolTable, field::FieldValue, order_book::OrderBook, corporate_action::{CorporateAction, CorporateActionKind}, coverage::Coverage}};
 
-use super::{indicators::Indicator, lua_hooks::LuaHook};
+use super::{environment::{StrategyEnv, StrategyLog, DataMissingMode, MAX_GAP_BARS}, history::{History, HistoryBuffer, HistoryField}, indicators::{IndicatorPlugin, Indicators}, inputs::{InputDefinition, Inputs, value_to_text}, lua_hooks::LuaHook, sandbox::Sandbox, schedule::{ScheduleSpec, TimerEvent, Timers}, series::{register, SeriesSet}};
 
 // holds details about an available strategy plugin
+// - the directory of the plugin holds the modules its script can require
 #[derive(Clone)]
 pub struct StrategyPlugin {
     pub name: String,
     pub lua_script: String,
     pub settings: HashMap<String, String>,
+    pub directory: String,
 }
 
 impl StrategyPlugin {
-    pub fn new(name: String, lua_script: String, settings: HashMap<String, String>) -> StrategyPlugin {
+    pub fn new(name: String, lua_script: String, settings: HashMap<String, String>, directory: String) -> StrategyPlugin {
         StrategyPlugin {
-            name: name,
-            lua_script: lua_script,
-            settings: settings,
+            name,
+            lua_script,
+            settings,
+            directory,
         }
     }
 }
 
+// number of bars of every symbol and interval kept in the history passed to on_bar (unless env sets a history limit)
+pub const HISTORY_LIMIT: usize = 300;
+
+// cash a backtest starts with
+pub const INITIAL_CASH: f64 = 100000.0;
+
+// the name of the table of the callbacks of the timers of a strategy in its registry
+const TIMERS_KEY: &str = "schedule_callbacks";
+
+// a backtest fails when its query is still running without returning a bar for this long
+const QUERY_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// settings of a strategy that are used by the runtime instead of the script
+// - datasets is a list of exchange_symbol names, start and end are unix seconds or utc dates
+pub const RESERVED_SETTINGS: [&str; 3] = ["datasets", "start", "end"];
+
+// the histories of a strategy keyed by exchange, symbol and interval
+type Histories = Arc<Mutex<HashMap<(String, String, String), History>>>;
+
+// the state of a backtest of a strategy
+// - bars are read from the query in chunks and passed to the strategy one at a time
+struct StrategyRun {
+    query: Query,
+
+    // the base interval of every exchange and symbol (the resolution of its dataset or of the rollup the query reads)
+    // and the seconds between two bars of the query (the smallest base interval)
+    bases: HashMap<(String, String), Interval>,
+    base_seconds: i64,
+    bars: u64,
+    last_timestamps: HashMap<(String, String), i64>,
+    start_timestamp: Option<i64>,
+    end_timestamp: i64,
+    started: i64,
+
+    // the trading calendar and the open session of every exchange
+    calendars: HashMap<String, TradingCalendar>,
+    sessions: HashMap<String, Session>,
+    warmed_up: bool,
+
+    // when the query last returned bars (a query running without bars for too long is stalled)
+    last_progress: Instant,
+}
+
 // an instance of a strategy that has been started
+// - the lua script is loaded into its own sandboxed state with the runtime hooks (input, indicator, orders, positions)
+// - the inputs and the env settings declared by the script are validated before any data is read
+// - orders of the script are collected while a bar is processed and submitted to the order simulator after it
+// - the lifecycle callbacks of the script are optional and called in simulated time with the bars:
+//   on_start(context), on_warmup_complete(), on_session_open(session), on_data_gap(info), on_order(order) for every status,
+//   on_fill(fill), on_position_change(position), on_corporate_action(event) at the ex-date, on_bar(bar, history), on_session_close(session)
+//   and on_end(summary)
+// - timers of schedule(spec, callback) fire once the clock of the bars reaches their time, after the sessions opening or closing
+//   at the same time: the timers due at the open of a bar fire before its candlesticks (their orders are filled by the bar)
 pub struct Strategy {
     pub name: String,
+    #[allow(dead_code)]
     pub lua_script_hash: String,
     pub lua_state: Lua,
-    pub indicators: HashMap<String, Indicator>,
+    pub sandbox: Sandbox,
+    pub indicators: Arc<Mutex<Indicators>>,
+
+    // the history of every symbol and interval is a ring buffer shared with the script and the indicators
+    pub histories: Histories,
+
+    // the series derived by the script from indicators and histories, updated once the indicators are
+    pub series: Arc<Mutex<SeriesSet>>,
+    pub timers: Arc<Mutex<Timers>>,
     pub datasets: HashMap<String, Dataset>,
     pub settings: HashMap<String, String>,
-    pub orders: Vec<Order>,
-    pub pending_orders: Vec<Order>,
-    pub metrics: Option<Vec<Metric<()>>>,
+    pub inputs: Inputs,
+    pub env: StrategyEnv,
+    pub log: Arc<Mutex<StrategyLog>>,
+    pub pending_orders: Arc<Mutex<Vec<Order>>>,
+    pub simulator: Arc<Mutex<OrderSimulator>>,
+    pub sessions_path: Option<String>,
+    pub summary: Option<BacktestSummary>,
+    run: Option<StrategyRun>,
 }
 
 impl Strategy {
     pub fn new(
         name: String,
         lua_script: String,
+        directory: String,
         settings: HashMap<String, String>,
         datasets: HashMap<String, Dataset>,
-        // metrics: Option<Vec<Metric<()>>>,
-    ) -> Strategy {
-
-        let name = name.clone();
-        let settings_clone = settings.clone();
+        indicator_plugins: HashMap<String, IndicatorPlugin>,
+    ) -> Result<Strategy, Error> {
+        let pending_orders = Arc::new(Mutex::new(Vec::new()));
+        let simulator = Arc::new(Mutex::new(OrderSimulator::new(INITIAL_CASH)));
+        let inputs = Arc::new(Mutex::new(Inputs::default()));
+        let env = Arc::new(Mutex::new(StrategyEnv::default()));
+        let log = Arc::new(Mutex::new(StrategyLog::default()));
+        let indicators = Arc::new(Mutex::new(Indicators::new(indicator_plugins, HISTORY_LIMIT)));
+        let histories: Histories = Arc::new(Mutex::new(HashMap::new()));
+        let series = Arc::new(Mutex::new(SeriesSet::new(HISTORY_LIMIT)));
+        let timers = Arc::new(Mutex::new(Timers::default()));
 
         // create new Lua state
-        let lua = Lua::new();
+        let sandbox = Sandbox::new(directory);
+        let lua = sandbox.create_lua()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("strategy {}: error creating the Lua state: {}", name, e)))?;
 
         // setup wrapper to control method calls from Rust into LUA or vice versa
-        let lua_context = lua.context(|lua_ctx| {
-            /*let get_setting_handle = lua_ctx.create_function(move |_, (name, data_type, default_value): (String, String, String)| {
-                let value = match settings_clone.get(&name) {
-                    Some(setting_value) => {
-                        let value = None;
-
-                        // TODO: parse the setting value to the correct type
-                        if data_type == "integer" {
-                            value = setting_value.parse::<i32>().unwrap();
-                        }
-                        else if data_type == "float" {
-                            value = setting_value.parse::<i32>().unwrap();
-                        }
-                        else if data_type == "string" {
-                            value = setting_value.parse::<String>().unwrap();
-                        }
-
-                        value.unwrap()
-                    },
-                    None => {
-                        ""
-                    }
-                };
-                let setting_value = settings_clone.get(&name).unwrap();
-
-                match (data_type.as_str(), setting_value) {
-                    ("integer", value) if value.parse::<i64>().is_ok() => {
-                        let parsed_value = value.parse::<i64>().unwrap();
-                        // Some(Box::new(parsed_value))
-
-                        Ok(parsed_value)
-                    },
-                    ("float", value) if value.parse::<f64>().is_ok() => {
-                        let parsed_value = value.parse::<f64>().unwrap();
-                        // Some(Box::new(parsed_value))
-
-                        Ok(parsed_value)
-                    },
-                    ("string", value) => {
-                        let string_value = value.clone();
-                        // Some(Box::new(string_value))
-
-                        Ok(string_value)
-                    },
-                    _ => Err(format!("Invalid data type {} for setting {}", data_type, name)),
-                }
+        let result = lua.context(|lua_ctx| {
+            register(lua_ctx, series.clone())?;
 
+            // create a rust hook to be called from with lua script
+            // a typed input of the script resolved from the settings (get_setting is kept as an alias)
+            let inputs_hook = inputs.clone();
+            let settings_hook = settings.clone();
+            LuaHook::new_external(&lua_ctx, "input", move |_, (name, data_type, default_value, options): (String, String, Value, Option<Table>)| {
+                let definition = InputDefinition::from_lua(name, data_type, default_value, options);
+                Ok(inputs_hook.lock().unwrap().declare(definition, &settings_hook))
+            });
+            lua_ctx.globals().set("get_setting", lua_ctx.globals().get::<_, Function>("input")?)?;
+
+            // a runtime setting of the strategy (logging, data checks, history limit, intervals)
+            let env_hook = env.clone();
+            LuaHook::new_external(&lua_ctx, "env", move |_, (name, value): (String, Value)| {
+                env_hook.lock().unwrap().set(&name, value);
+                Ok(())
+            });
+
+            // print calls of the script are also written to the log
+            let log_hook = log.clone();
+            LuaHook::new_external(&lua_ctx, "print", move |lua_ctx, values: Variadic<Value>| {
+                let tostring: Function = lua_ctx.globals().get("tostring")?;
+                let values = values.into_iter().map(|value| tostring.call::<_, String>(value)).collect::<rlua::Result<Vec<String>>>()?;
+                println!("{}", values.join("\t"));
+                log_hook.lock().unwrap().write(format!("print {}", values.join("\t")));
                 Ok(())
-            }).unwrap();*/
-   
+            });
+            // an indicator of a symbol and interval: indicator(name, inputs..., interval, symbol, exchange)
+            // - the first argument that is an interval ends the inputs, the symbol and exchange default to the datasets
+            let indicators_hook = indicators.clone();
+            let histories_hook = histories.clone();
+            let env_hook = env.clone();
+            let datasets_hook = datasets.values().map(|dataset| (dataset.exchange.clone(), dataset.symbol.clone())).collect::<Vec<(String, String)>>();
+            LuaHook::new_external(&lua_ctx, "indicator", move |_, (name, arguments): (String, Variadic<Value>)| {
+                let position = arguments.iter()
+                    .position(|argument| matches!(argument, Value::String(value) if value.to_str().is_ok_and(|value| Interval::from_string(value).is_ok())))
+                    .ok_or_else(|| rlua::Error::RuntimeError(format!("indicator {}: missing interval", name)))?;
+                let mut arguments = arguments.into_iter().map(value_to_text);
+                let inputs = arguments.by_ref().take(position).collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| rlua::Error::RuntimeError(format!("indicator {}: inputs must be numbers, strings or bools", name)))?;
+                let interval = arguments.next().flatten().unwrap_or_default();
+                let symbol = arguments.next().flatten();
+                let exchange = arguments.next().flatten();
 
-            // create a rust hook to be called from with lua script
-            LuaHook::new_external(&lua_ctx, "get_setting", |_, (name, data_type, default_value): (String, String, String)| {Ok(1)});
-            LuaHook::new_external(&lua_ctx, "indicator", |_, (name, periods, interval, symbol): (String, i32, String, String)| {
-                // check if indicator is already created
-                // create new indicator and add to indicator list
-                // - backpropagate up to 300 of the latest candles (so our values are up-to-date)
-                // get latest indicator values
-
-                Ok(1)
+                let matching = datasets_hook.iter()
+                    .filter(|(ex, sym)| symbol.as_ref().is_none_or(|symbol| symbol == sym) && exchange.as_ref().is_none_or(|exchange| exchange == ex))
+                    .collect::<Vec<&(String, String)>>();
+                let (exchange, symbol) = match matching.as_slice() {
+                    [dataset] => (*dataset).clone(),
+                    [] => return Err(rlua::Error::RuntimeError(format!("indicator {}: the strategy has no dataset for {}", name, symbol.unwrap_or_default()))),
+                    _ => return Err(rlua::Error::RuntimeError(format!("indicator {}: a symbol (and exchange) is needed with more than one dataset", name))),
+                };
+
+                let histories = histories_hook.lock().unwrap();
+                let history = histories.get(&(exchange.clone(), symbol.clone(), interval.clone())).map(|history| history.0.clone());
+                let history = history.as_ref().map(|history| history.lock().unwrap());
+                let mut indicators = indicators_hook.lock().unwrap();
+                indicators.history_limit = env_hook.lock().unwrap().history_limit;
+                indicators.get_or_create(&name, inputs, &interval, &exchange, &symbol, history.as_deref())
+                    .map_err(|e| rlua::Error::RuntimeError(e.to_string()))
+            });
+
+            // the net quantity of a position (nil when there is no position)
+            let simulator_hook = simulator.clone();
+            LuaHook::new_external(&lua_ctx, "get_position", move |_, (exchange, symbol): (String, String)| {
+                let quantity = simulator_hook.lock().unwrap().position(&exchange, &symbol);
+                Ok(if quantity == 0.0 { None } else { Some(quantity) })
             });
-            LuaHook::new_external(&lua_ctx, "get_position", |_, (exchange, symbol): (String, String)| {Ok(1)});
-            LuaHook::new_external(&lua_ctx, "execute_market_order", |_, (exchange, symbol, quantity, side): (String, String, f64, String)| {Ok(1)});
-            LuaHook::new_external(&lua_ctx, "execute_limit_order", |_, (exchange, symbol, price, quantity, side): (String, String, f64, f64, String)| {Ok(1)});
-
-            let result = lua_ctx.load(&lua_script.clone()).exec();
-            if let Err(e) = result {
-                println!("Error loading Lua script: {}", e);
-                return;
-            }
-
-            // status, exchange, symbol, timestamp, open, high, low, close, volume
-            let test_value: (String, String, String, i32, f64, f64, f64, f64);
-            
-            // called on new bar
-            LuaHook::call(&lua_ctx, "on_bar", MultiValue::from(test_value));
-            // called on order update
-            LuaHook::call(&lua_ctx, "on_order", MultiValue::new());
+
+            // orders are submitted once the bar has been processed
+            let pending_hook = pending_orders.clone();
+            LuaHook::new_external(&lua_ctx, "execute_market_order", move |_, (exchange, symbol, quantity, side): (String, String, f64, String)| {
+                let side = OrderSide::from_string(&side).ok_or_else(|| rlua::Error::RuntimeError(format!("invalid order side {}", side)))?;
+                pending_hook.lock().unwrap().push(Order::new(exchange, symbol, side, OrderType::Market, quantity, None));
+                Ok(true)
+            });
+            let pending_hook = pending_orders.clone();
+            LuaHook::new_external(&lua_ctx, "execute_limit_order", move |_, (exchange, symbol, price, quantity, side): (String, String, f64, f64, String)| {
+                let side = OrderSide::from_string(&side).ok_or_else(|| rlua::Error::RuntimeError(format!("invalid order side {}", side)))?;
+                pending_hook.lock().unwrap().push(Order::new(exchange, symbol, side, OrderType::Limit, quantity, Some(price)));
+                Ok(true)
+            });
+
+            // a timer calling a function of the script: schedule(spec, callback) returns the id of the timer
+            // - the callback gets an event with the id, spec, time (the scheduled time), missed (times passed without bars) and exchange
+            lua_ctx.set_named_registry_value(TIMERS_KEY, lua_ctx.create_table()?)?;
+            let timers_hook = timers.clone();
+            let mut exchanges_hook = datasets.values().map(|dataset| dataset.exchange.clone()).collect::<Vec<String>>();
+            exchanges_hook.sort();
+            exchanges_hook.dedup();
+            LuaHook::new_external(&lua_ctx, "schedule", move |lua_ctx, (spec, callback): (String, Function)| {
+                let schedule = ScheduleSpec::from_string(&spec, &exchanges_hook)
+                    .map_err(|e| rlua::Error::RuntimeError(format!("schedule {}: {}", spec, e)))?;
+                let id = timers_hook.lock().unwrap().add(spec, schedule);
+                lua_ctx.named_registry_value::<_, Table>(TIMERS_KEY)?.set(id, callback)?;
+                Ok(id)
+            });
+            let timers_hook = timers.clone();
+            LuaHook::new_external(&lua_ctx, "unschedule", move |lua_ctx, id: u64| {
+                lua_ctx.named_registry_value::<_, Table>(TIMERS_KEY)?.set(id, Value::Nil)?;
+                Ok(timers_hook.lock().unwrap().remove(id))
+            });
+
+            // a command returned by on_bar (the runtime routes it once on_bar returns)
+            LuaHook::new_external(&lua_ctx, "command", |_, (command, params): (String, Table)| {
+                params.set("command", command)?;
+                Ok(params)
+            });
+
+            sandbox.reset();
+            lua_ctx.load(&lua_script).set_name(&name)?.exec()?;
+            if !LuaHook::exists(&lua_ctx, "on_bar") {
+                return Err(rlua::Error::RuntimeError("missing on_bar function".to_string()));
+            }
+
+            Ok(())
         });
+        if let Err(e) = result {
+            return Err(Error::new(ErrorKind::InvalidData, format!("strategy {}: error loading Lua script: {}", name, sandbox.describe(&e))));
+        }
 
-        // verify that the lua script has the required functions
-        // start up lua context
-        // extract the settings required from the LUA script
-        // verify the required settings match up with the supplied settings from settings.txt
-        // perform initialization tests (run through some dummy data to see how the script responds)
-        // prepare synchronized datasets (by timestamp)
+        // every problem of the settings and the env is reported at once
+        let mut inputs = inputs.lock().unwrap().clone();
+        inputs.validate(&settings, &RESERVED_SETTINGS);
+        let env = env.lock().unwrap().clone();
+        series.lock().unwrap().capacity = env.history_limit;
+        let errors = inputs.errors.iter().chain(env.errors.iter()).cloned().collect::<Vec<String>>();
+        if !errors.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("strategy {}: invalid settings:\n  {}", name, errors.join("\n  "))));
+        }
 
-        Strategy {
-            name: name,
-            lua_script_hash: "test".to_string(),
+        Ok(Strategy {
+            name,
+            lua_script_hash: format!("{:08x}", crc32fast::hash(lua_script.as_bytes())),
             lua_state: lua,
-            indicators: HashMap::new(),
-            settings: settings,
+            sandbox,
+            indicators,
+            histories,
+            series,
+            timers,
+            settings,
+            inputs,
+            env,
+            log,
             datasets,
-            orders: Vec::new(),
-            pending_orders: Vec::new(),
-            metrics: None
+            pending_orders,
+            simulator,
+            sessions_path: None,
+            summary: None,
+            run: None,
+        })
+    }
+
+    // write the orders and the summary of every backtest into a session folder of a path
+    pub fn with_sessions_path(mut self, sessions_path: String) -> Self {
+        self.sessions_path = Some(sessions_path);
+        self
+    }
+
+    // open the query of the datasets of the strategy
+    pub fn start(&mut self, database: Database, client_id: u64) -> Result<(), Error> {
+        let mut datasets = self.datasets.values().collect::<Vec<&Dataset>>();
+        datasets.sort_by(|a, b| a.name.cmp(&b.name));
+        if datasets.is_empty() || datasets.len() > 5 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("strategy {}: a backtest needs 1 to 5 datasets ({} given)", self.name, datasets.len())));
+        }
+        let symbols = datasets.iter()
+            .map(|dataset| (Exchange::new_with(dataset.exchange.clone()), Symbol { name: dataset.symbol.clone(), ..Symbol::new() }))
+            .collect();
+        self.simulator.lock().unwrap().set_commission(self.env.commission);
+
+        let mut calendars = HashMap::new();
+        let mut bases = HashMap::new();
+        for dataset in datasets.iter() {
+            if !calendars.contains_key(&dataset.exchange) {
+                calendars.insert(dataset.exchange.clone(), database.get_calendar(Exchange::new_with(dataset.exchange.clone()))?);
+            }
+            let resolution = database.get_resolution(Exchange::new_with(dataset.exchange.clone()), Symbol { name: dataset.symbol.clone(), ..Symbol::new() })?;
+            bases.insert((dataset.exchange.clone(), dataset.symbol.clone()), resolution);
+
+            // the orders of the strategy are rounded and validated with the contract of their symbol
+            let symbol = database.get_symbol(Exchange::new_with(dataset.exchange.clone()), Symbol { name: dataset.symbol.clone(), ..Symbol::new() })?;
+            if let Some(spec) = symbol.spec {
+                self.simulator.lock().unwrap().set_spec(&dataset.exchange, spec);
+            }
+        }
+
+        // the intervals of the indicators created by the script are consolidated with the intervals of the env
+        // - the base intervals are not consolidated unless rollups are read (a rollup is only read when it can build every interval)
+        let base_names = bases.values().map(|base| base.name.clone()).collect::<Vec<String>>();
+        let mut intervals = self.env.intervals.clone();
+        for interval in self.indicators.lock().unwrap().intervals() {
+            if (self.env.rollups || !base_names.contains(&interval)) && !intervals.contains(&interval) {
+                intervals.push(interval);
+            }
+        }
+        if intervals.len() > MAX_INTERVALS {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("strategy {}: a strategy can consolidate up to {} intervals ({} given)", self.name, MAX_INTERVALS, intervals.len())));
+        }
+
+        let mut query = Query::new_with(client_id, database).with_symbols(symbols).with_verify(self.env.verify).with_rollups(self.env.rollups)
+            .with_adjustment(self.env.price_adjustment).with_extended_hours(self.env.extended_hours).with_alignment(self.env.alignment);
+        if !intervals.is_empty() {
+            query = query.with_intervals(intervals.clone());
+        }
+        if let Some(levels) = self.env.order_book {
+            query = query.with_order_book(levels);
+        }
+        if !self.env.fields.is_empty() {
+            query = query.with_fields(self.env.fields.clone());
+        }
+        if let Some(snapshot_id) = self.env.snapshot {
+            query = query.with_snapshot(snapshot_id);
+        }
+        let setting_time = |key: &str| match self.settings.get(key) {
+            Some(time) => parse_time(time).map(Some).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("strategy {}: invalid {} time {}", self.name, key, time))),
+            None => Ok(None),
+        };
+        if let Some(start_timestamp) = setting_time("start")? {
+            query = query.with_start_time(start_timestamp);
+        }
+        if let Some(end_timestamp) = setting_time("end")? {
+            query = query.with_end_time(end_timestamp);
+        }
+
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs() as i64);
+
+        // the log of the backtest is written into its session folder
+        let mut log = self.log.lock().unwrap();
+        match (self.env.logging, self.sessions_path.as_ref()) {
+            (true, Some(sessions_path)) => {
+                let session_path = format!("{}/{}", sessions_path, started);
+                fs::create_dir_all(&session_path)?;
+                log.open(&format!("{}/log.txt", session_path))?;
+            },
+            _ => log.close(),
         }
+        drop(log);
+
+        // the query id is kept so the next chunks can be read
+        let result = query.database.query(client_id, query.clone())?;
+        query.id = Some(result.id);
+        query.coverage = result.coverage;
+        query.resolutions = result.resolutions;
+
+        // the base bars are named after the resolution the query reads
+        for (id, seconds) in query.resolutions.iter() {
+            if let Some((exchange, symbol)) = result.symbols.name(*id) {
+                bases.insert((exchange.to_string(), symbol.to_string()), Interval::from_seconds(*seconds));
+            }
+        }
+        let base_seconds = bases.values().map(|base| base.length()).min().unwrap_or(60);
+        let mut available = bases.values().map(|base| base.name.clone()).collect::<Vec<String>>();
+        available.sort_by_key(|interval| Interval::from_string(interval).map_or(0, |interval| interval.length()));
+        available.dedup();
+        let intervals = intervals.into_iter().filter(|interval| !available.contains(interval)).collect::<Vec<String>>();
+        available.extend(intervals.iter().cloned());
+        self.indicators.lock().unwrap().intervals = Some(available.clone());
+
+        self.log.lock().unwrap().write(format!("backtest started (intervals {})", intervals.join(",")));
+
+        self.histories.lock().unwrap().clear();
+        println!("strategy {}: backtest started", self.name);
+        self.run = Some(StrategyRun {
+            query,
+            bases,
+            base_seconds,
+            bars: 0,
+            last_timestamps: HashMap::new(),
+            start_timestamp: None,
+            end_timestamp: 0,
+            started,
+            calendars,
+            sessions: HashMap::new(),
+            warmed_up: false,
+            last_progress: Instant::now(),
+        });
+
+        // the context of the backtest passed to on_start once the histories are seeded
+        let result = self.seed_histories().and_then(|_| self.callback("on_start", |lua_ctx| {
+            let context_t = lua_ctx.create_table()?;
+            context_t.set("name", self.name.as_str())?;
+            let mut datasets = self.datasets.values().collect::<Vec<&Dataset>>();
+            datasets.sort_by(|a, b| a.name.cmp(&b.name));
+            let datasets_t = lua_ctx.create_table()?;
+            for (i, dataset) in datasets.iter().enumerate() {
+                let dataset_t = lua_ctx.create_table()?;
+                dataset_t.set("exchange", dataset.exchange.as_str())?;
+                dataset_t.set("symbol", dataset.symbol.as_str())?;
+                datasets_t.set(i + 1, dataset_t)?;
+            }
+            context_t.set("datasets", datasets_t)?;
+            context_t.set("intervals", available.clone())?;
+            if let Some(run) = self.run.as_ref() {
+                context_t.set("start_timestamp", run.query.start_timestamp)?;
+                context_t.set("end_timestamp", run.query.end_timestamp)?;
+                context_t.set("coverage", create_coverage_table(lua_ctx, &run.query.coverage)?)?;
+            }
+            context_t.set("alignment", self.env.alignment.as_str())?;
+            context_t.set("initial_cash", self.simulator.lock().unwrap().initial_cash)?;
+            context_t.set("history_limit", self.env.history_limit)?;
+            context_t.set("logging", self.env.logging)?;
+            context_t.set("price_adjustment", self.env.price_adjustment.as_str())?;
+            context_t.set("extended_hours", self.env.extended_hours)?;
+            context_t.set("snapshot", self.env.snapshot)?;
+            context_t.set("commission", self.env.commission)?;
+            let inputs_t = lua_ctx.create_table()?;
+            for (name, value) in self.inputs.values.iter() {
+                inputs_t.set(name.as_str(), value.clone())?;
+            }
+            context_t.set("inputs", inputs_t)?;
+            context_t.to_lua_multi(lua_ctx)
+        }));
+
+        // the query is stopped when the strategy fails to start
+        if result.is_err() {
+            if let Some(mut run) = self.run.take() {
+                run.query.stop()?;
+            }
+        }
+        result
+    }
+
+    // call a callback of the script (callbacks the script does not define are skipped)
+    // - a command returned by the callback is routed to the order simulator
+    fn callback<A>(&self, name: &str, arguments: A) -> Result<(), Error>
+    where
+        A: for<'lua> FnOnce(Context<'lua>) -> rlua::Result<MultiValue<'lua>>,
+    {
+        self.call(name, |lua_ctx| match LuaHook::exists(&lua_ctx, name) {
+            true => lua_ctx.globals().get(name).map(Some),
+            false => Ok(None),
+        }, arguments)
     }
 
-    // 
-    pub fn run(&self,) {
-        // println!("running strategy: {}", self.name);
+    // call a function of the script found by a lookup (the call is skipped when there is no function)
+    fn call<F, A>(&self, name: &str, function: F, arguments: A) -> Result<(), Error>
+    where
+        F: for<'lua> FnOnce(Context<'lua>) -> rlua::Result<Option<Function<'lua>>>,
+        A: for<'lua> FnOnce(Context<'lua>) -> rlua::Result<MultiValue<'lua>>,
+    {
+        let pending_orders = self.pending_orders.clone();
+        self.sandbox.reset();
+        let result = self.lua_state.context(|lua_ctx| {
+            let function = match function(lua_ctx)? {
+                Some(function) => function,
+                None => return Ok(()),
+            };
 
+            if let Value::Table(command) = function.call::<_, Value>(arguments(lua_ctx)?)? {
+                match command.get::<_, Option<String>>("command")?.as_deref() {
+                    Some("order") => pending_orders.lock().unwrap().push(create_order(&command)?),
+                    Some(command) => println!("strategy {}: unsupported command {}", self.name, command),
+                    None => {},
+                }
+            }
+
+            Ok(())
+        });
+
+        result.map_err(|e: rlua::Error| Error::new(ErrorKind::InvalidData, format!("strategy {}: error in {}: {}", self.name, name, self.sandbox.describe(&e))))
+    }
+
+    // process the next chunk of bars of the backtest (returns false once every bar has been processed)
+    pub fn step(&mut self) -> Result<bool, Error> {
+        let result = match self.run.as_mut() {
+            Some(run) => {
+                let result = run.query.next()?;
+                if !result.bars.is_empty() {
+                    run.last_progress = Instant::now();
+                } else if result.status == "running" {
+                    if run.last_progress.elapsed() >= QUERY_STALL_TIMEOUT {
+                        let e = Error::new(ErrorKind::TimedOut, format!("strategy {}: the query returned no bars for {}s", self.name, QUERY_STALL_TIMEOUT.as_secs()));
+                        self.log.lock().unwrap().write(format!("error {}", e));
+                        return Err(e);
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                result
+            },
+            None => return Ok(false),
+        };
+
+        for bar in result.bars.iter() {
+            if let Err(e) = self.process_bar(&result.symbols, bar) {
+                self.log.lock().unwrap().write(format!("error {}", e));
+                return Err(e);
+            }
+        }
+
+        if result.status == "complete" {
+            self.finish(BacktestStatus::Complete)?;
+            return Ok(false);
+        }
+
+        Ok(true)
     }
+
+    // fill the open orders with a bar and pass its candlesticks to the strategy
+    // - the base candlesticks are passed first, then the candlesticks of the intervals that completed at the bar
+    // - bars missing before a base candlestick are handled by the missing data mode of the strategy
+    fn process_bar(&mut self, symbols: &SymbolTable, bar: &Bar) -> Result<(), Error> {
+        let base_seconds = match self.run.as_mut() {
+            Some(run) => {
+                run.bars += 1;
+                run.start_timestamp.get_or_insert(bar.timestamp);
+                run.end_timestamp = bar.timestamp;
+                run.base_seconds
+            },
+            None => return Ok(()),
+        };
+        self.log.lock().unwrap().timestamp = Some(bar.timestamp);
+
+        let mut exchanges = bar.candlesticks.iter().filter_map(|(id, _)| symbols.name(id)).map(|(exchange, _)| exchange.to_string()).collect::<Vec<String>>();
+        exchanges.sort();
+        exchanges.dedup();
+        for exchange in exchanges.iter() {
+            self.open_session(exchange, bar.timestamp)?;
+        }
+
+        // the orders of the timers due at the open of the bar are filled by the bar
+        self.fire_timers(bar.timestamp)?;
+        self.submit_orders(bar.timestamp)?;
+
+        // the corporate actions with an ex-date since the last bar of their symbol are passed before its candlesticks
+        for (id, corporate_action) in bar.corporate_actions.iter() {
+            if let Some((exchange, symbol)) = symbols.name(*id) {
+                self.callback("on_corporate_action", |lua_ctx| create_corporate_action_table(lua_ctx, exchange, symbol, corporate_action)?.to_lua_multi(lua_ctx))?;
+            }
+        }
+
+        let mut candlesticks = Vec::new();
+        for (id, candlestick) in bar.candlesticks.iter() {
+            if let Some((exchange, symbol)) = symbols.name(id) {
+                let base = match self.run.as_ref().and_then(|run| run.bases.get(&(exchange.to_string(), symbol.to_string()))) {
+                    Some(base) => base.clone(),
+                    None => Interval::from_seconds(base_seconds),
+                };
+                for missing in self.missing_bars(exchange, symbol, candlestick, &base)? {
+                    candlesticks.push((exchange.to_string(), symbol.to_string(), base.name.clone(), missing, None));
+                }
+
+                self.fill_orders(exchange, symbol, candlestick)?;
+                candlesticks.push((exchange.to_string(), symbol.to_string(), base.name.clone(), candlestick.clone(), bar.get_order_book(id).cloned()));
+            }
+        }
+
+        // the consolidated candlesticks are indexed by the intervals of the query (shortest interval first)
+        if let Some(run) = self.run.as_ref() {
+            let mut intervals = bar.consolidated.iter().enumerate()
+                .filter_map(|(interval_index, consolidated)| run.query.intervals.get(interval_index).map(|interval| (interval, consolidated)))
+                .collect::<Vec<(&String, &BarMap)>>();
+            intervals.sort_by_key(|(interval, _)| Interval::from_string(interval).map_or(0, |interval| interval.length()));
+            for (interval, consolidated) in intervals {
+                for (id, candlestick) in consolidated.iter() {
+                    if let Some((exchange, symbol)) = symbols.name(id) {
+                        candlesticks.push((exchange.to_string(), symbol.to_string(), interval.clone(), candlestick.clone(), None));
+                    }
+                }
+            }
+        }
+
+        for (exchange, symbol, interval, candlestick, order_book) in candlesticks {
+            self.on_bar(exchange, symbol, interval, candlestick, order_book)?;
+        }
+
+        self.submit_orders(bar.timestamp)?;
+        self.check_warmup()?;
+
+        // a session closes with its last bar
+        for exchange in exchanges.iter() {
+            let closed = self.run.as_ref().and_then(|run| run.sessions.get(exchange)).is_some_and(|session| bar.timestamp + base_seconds >= session.close);
+            if closed {
+                self.close_session(exchange)?;
+            }
+        }
+
+        // the timers due at the close of the bar fire once its sessions are closed
+        self.fire_timers(bar.timestamp + base_seconds)?;
+        self.submit_orders(bar.timestamp)
+    }
+
+    // submit the orders of the strategy, they are filled by the next bar of their symbol
+    fn submit_orders(&mut self, timestamp: i64) -> Result<(), Error> {
+        let orders = self.pending_orders.lock().unwrap().drain(..).collect::<Vec<Order>>();
+        let mut submitted = Vec::new();
+        let mut simulator = self.simulator.lock().unwrap();
+        let mut log = self.log.lock().unwrap();
+        for order in orders {
+            let description = format!("{} {} {}:{} {}", order.side.as_str(), order.quantity, order.exchange, order.symbol, order.fill_type.as_str());
+            let id = simulator.submit(order, timestamp);
+            log.write(format!("order {} {}", id, description));
+            submitted.extend(simulator.orders.last().cloned());
+        }
+        drop(log);
+        drop(simulator);
+        for order in submitted {
+            self.callback("on_order", |lua_ctx| create_order_table(lua_ctx, &order)?.to_lua_multi(lua_ctx))?;
+        }
+
+        Ok(())
+    }
+
+    // move the clock of the timers to a timestamp and call the callbacks of the timers that are due
+    // - a timer removed by the callback of an earlier timer does not fire
+    fn fire_timers(&mut self, clock: i64) -> Result<(), Error> {
+        let events = match self.run.as_ref() {
+            Some(run) => self.timers.lock().unwrap().advance(clock, &run.calendars),
+            None => return Ok(()),
+        };
+
+        for event in events {
+            if !self.timers.lock().unwrap().contains(event.id) {
+                continue;
+            }
+
+            self.log.lock().unwrap().write(format!("timer {} {} at {}", event.id, event.spec, event.time));
+            let name = format!("timer {} ({})", event.id, event.spec);
+            self.call(&name, |lua_ctx| lua_ctx.named_registry_value::<_, Table>(TIMERS_KEY)?.get(event.id), |lua_ctx| {
+                create_timer_table(lua_ctx, &event, clock)?.to_lua_multi(lua_ctx)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // fill the open orders of a symbol with a candlestick
+    // - every filled order is passed to on_order and on_fill, then the changed position to on_position_change
+    fn fill_orders(&mut self, exchange: &str, symbol: &str, candlestick: &Candlestick) -> Result<(), Error> {
+        let mut simulator = self.simulator.lock().unwrap();
+        let previous_quantity = simulator.position(exchange, symbol);
+        let fills = simulator.update(exchange, symbol, candlestick);
+        let filled = fills.into_iter()
+            .filter_map(|fill| simulator.orders.iter().find(|order| order.id == fill.order_id).cloned().map(|order| (order, fill)))
+            .collect::<Vec<(Order, Fill)>>();
+        let position = simulator.positions.get(&(exchange.to_string(), symbol.to_string())).cloned();
+        drop(simulator);
+
+        let mut log = self.log.lock().unwrap();
+        for (_, fill) in filled.iter() {
+            log.write(format!("fill {} {} {} {}:{} at {} (commission {})", fill.order_id, fill.side.as_str(), fill.quantity, fill.exchange, fill.symbol, fill.price, fill.commission));
+        }
+        drop(log);
+
+        for (order, fill) in filled.iter() {
+            self.callback("on_order", |lua_ctx| create_order_table(lua_ctx, order)?.to_lua_multi(lua_ctx))?;
+            self.callback("on_fill", |lua_ctx| create_fill_table(lua_ctx, fill)?.to_lua_multi(lua_ctx))?;
+        }
+        if let Some(position) = position.filter(|position| position.quantity != previous_quantity) {
+            self.callback("on_position_change", |lua_ctx| create_position_table(lua_ctx, &position, previous_quantity)?.to_lua_multi(lua_ctx))?;
+        }
+
+        Ok(())
+    }
+
+    // open the session of an exchange a bar belongs to
+    // - sessions follow the trading calendar of the exchange (utc days when it has no calendar), bars outside of a session open none
+    // - a session that ended without a bar at its close is closed first
+    fn open_session(&mut self, exchange: &str, timestamp: i64) -> Result<(), Error> {
+        let (current, session) = match self.run.as_ref() {
+            Some(run) => (
+                run.sessions.get(exchange).copied(),
+                run.calendars.get(exchange).and_then(|calendar| calendar.session_at(timestamp)).filter(|session| session.contains(timestamp, false)),
+            ),
+            None => return Ok(()),
+        };
+        if current.is_some_and(|current| timestamp >= current.close) {
+            self.close_session(exchange)?;
+        }
+
+        let session = match session {
+            Some(session) if current.is_none_or(|current| current.date != session.date) => session,
+            _ => return Ok(()),
+        };
+        if let Some(run) = self.run.as_mut() {
+            run.sessions.insert(exchange.to_string(), session);
+        }
+        self.log.lock().unwrap().write(format!("session {} {} open", exchange, session.date));
+        self.callback("on_session_open", |lua_ctx| create_session_table(lua_ctx, exchange, &session)?.to_lua_multi(lua_ctx))
+    }
+
+    // close the open session of an exchange
+    fn close_session(&mut self, exchange: &str) -> Result<(), Error> {
+        let session = match self.run.as_mut().and_then(|run| run.sessions.remove(exchange)) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        self.log.lock().unwrap().write(format!("session {} {} close", exchange, session.date));
+        self.callback("on_session_close", |lua_ctx| create_session_table(lua_ctx, exchange, &session)?.to_lua_multi(lua_ctx))
+    }
+
+    // call on_warmup_complete once every dataset has a bar and every indicator created by the strategy has a value
+    fn check_warmup(&mut self) -> Result<(), Error> {
+        let run = match self.run.as_mut() {
+            Some(run) if !run.warmed_up => run,
+            _ => return Ok(()),
+        };
+        if run.last_timestamps.len() < self.datasets.len() || !self.indicators.lock().unwrap().is_ready() {
+            return Ok(());
+        }
+        run.warmed_up = true;
+
+        self.log.lock().unwrap().write(format!("warmup complete after {} bars", run.bars));
+        self.callback("on_warmup_complete", |lua_ctx| ().to_lua_multi(lua_ctx))
+    }
+
+    // find the base bars missing from a symbol before a candlestick
+    // - gaps longer than MAX_GAP_BARS are market closures and are not missing data
+    // - smoothing returns flat bars at the last close, stop returns an error
+    fn missing_bars(&mut self, exchange: &str, symbol: &str, candlestick: &Candlestick, base: &Interval) -> Result<Vec<Candlestick>, Error> {
+        let run = match self.run.as_mut() {
+            Some(run) => run,
+            None => return Ok(Vec::new()),
+        };
+        let key = (exchange.to_string(), symbol.to_string());
+        let last_timestamp = run.last_timestamps.insert(key.clone(), candlestick.timestamp);
+        let last_close = self.histories.lock().unwrap().get(&(key.0, key.1, base.name.clone())).and_then(|history| history.0.lock().unwrap().get(HistoryField::Close, 1));
+
+        let base_seconds = base.length();
+        let missing = match last_timestamp {
+            Some(last_timestamp) if base_seconds > 0 => (candlestick.timestamp - last_timestamp) / base_seconds - 1,
+            _ => 0,
+        };
+        if missing <= 0 || missing > MAX_GAP_BARS {
+            return Ok(Vec::new());
+        }
+
+        let last_timestamp = last_timestamp.unwrap_or_default();
+        self.log.lock().unwrap().write(format!("gap of {} bars in {}:{} after {}", missing, exchange, symbol, last_timestamp));
+        self.callback("on_data_gap", |lua_ctx| {
+            let info_t = lua_ctx.create_table()?;
+            info_t.set("exchange", exchange)?;
+            info_t.set("symbol", symbol)?;
+            info_t.set("interval", base.name.as_str())?;
+            info_t.set("from", last_timestamp)?;
+            info_t.set("to", candlestick.timestamp)?;
+            info_t.set("bars", missing)?;
+            info_t.set("mode", self.env.data_missing_mode.as_str())?;
+            info_t.to_lua_multi(lua_ctx)
+        })?;
+        match (self.env.data_missing_mode, last_close) {
+            (DataMissingMode::Stop, _) => Err(Error::new(ErrorKind::InvalidData, format!("strategy {}: {} bars of {}:{} are missing after {}", self.name, missing, exchange, symbol, last_timestamp))),
+            (DataMissingMode::Smoothing, Some(close)) => Ok((1..=missing)
+                .map(|i| Candlestick::new_with(last_timestamp + i * base_seconds, close, close, close, close, 0.0))
+                .collect()),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // get the history of a symbol and interval (created the first time)
+    fn history(&self, exchange: &str, symbol: &str, interval: &str) -> History {
+        self.histories.lock().unwrap().entry((exchange.to_string(), symbol.to_string(), interval.to_string()))
+            .or_insert_with(|| History(Arc::new(Mutex::new(HistoryBuffer::new(exchange.to_string(), symbol.to_string(), interval.to_string(), self.env.history_limit)))))
+            .clone()
+    }
+
+    // add the bars that end before the start of the backtest to the histories and the indicators (oldest first)
+    // - the strategy and its indicators can use a full history from the first bar (a backtest from the start of the data has no history)
+    fn seed_histories(&mut self) -> Result<(), Error> {
+        let (query, start_timestamp) = match self.run.as_ref() {
+            Some(run) => match run.query.start_timestamp {
+                Some(start_timestamp) => (run.query.clone(), start_timestamp),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+
+        let mut count = 0;
+        for seed in query.history(self.env.history_limit, start_timestamp)? {
+            let history = self.history(&seed.exchange, &seed.symbol, &seed.interval);
+            for candlestick in seed.candlesticks.iter() {
+                history.0.lock().unwrap().push(candlestick);
+                self.indicators.lock().unwrap().update(&seed.exchange, &seed.symbol, &seed.interval, candlestick)?;
+                self.series.lock().unwrap().update();
+            }
+            count += seed.candlesticks.len();
+        }
+        self.log.lock().unwrap().write(format!("history seeded with {} bars", count));
+
+        Ok(())
+    }
+
+    // add a candlestick to the history of its symbol and interval and call the on_bar hook of the script
+    // - a command returned by on_bar is routed to the order simulator
+    // - base bars read with the order book have the book as of the bar in bar.order_book
+    fn on_bar(&mut self, exchange: String, symbol: String, interval: String, candlestick: Candlestick, order_book: Option<OrderBook>) -> Result<(), Error> {
+        if self.run.is_none() {
+            return Ok(());
+        }
+        let history = self.history(&exchange, &symbol, &interval);
+        history.0.lock().unwrap().push(&candlestick);
+
+        // the indicators are updated before the strategy so they include the candlestick
+        self.indicators.lock().unwrap().update(&exchange, &symbol, &interval, &candlestick)?;
+        self.series.lock().unwrap().update();
+
+        self.callback("on_bar", |lua_ctx| {
+            let bar_t = create_bar_table(lua_ctx, &exchange, &symbol, &interval, &candlestick)?;
+            if let Some(order_book) = order_book.as_ref() {
+                bar_t.set("order_book", create_order_book_table(lua_ctx, order_book)?)?;
+            }
+            let history_u = lua_ctx.create_userdata(history.clone())?;
+            (bar_t, history_u).to_lua_multi(lua_ctx)
+        })
+    }
+
+    // end a backtest before every bar has been processed (when the strategy is stopped or fails)
+    // - the bars processed so far are summarized and written like a complete backtest
+    pub fn stop(&mut self, status: BacktestStatus) -> Result<(), Error> {
+        self.finish(status)
+    }
+
+    // summarize the backtest, stop its query and write its session files
+    // - the sessions still open when the data ends are closed, then the summary is passed to on_end
+    fn finish(&mut self, status: BacktestStatus) -> Result<(), Error> {
+        if let Some(run) = self.run.as_mut() {
+            run.query.stop()?;
+        }
+
+        let mut exchanges = self.run.as_ref().map_or(Vec::new(), |run| run.sessions.keys().cloned().collect::<Vec<String>>());
+        exchanges.sort();
+        for exchange in exchanges.iter() {
+            self.close_session(exchange)?;
+        }
+
+        let run = match self.run.take() {
+            Some(run) => run,
+            None => return Ok(()),
+        };
+
+        let mut summary = self.simulator.lock().unwrap().summary(run.bars, run.start_timestamp.unwrap_or(0), run.end_timestamp);
+        summary.status = status;
+        summary.metrics = self.end(&summary)?;
+        println!("strategy {}: backtest {}", self.name, status.as_str());
+        print!("{}", summary.to_text());
+
+        if let Some(sessions_path) = self.sessions_path.clone() {
+            let session_path = format!("{}/{}", sessions_path, run.started);
+            self.write_session(&session_path, &summary)?;
+            println!("strategy {}: session written to {}", self.name, session_path);
+        }
+
+        self.summary = Some(summary);
+        Ok(())
+    }
+
+    // call on_end with the summary of the backtest
+    // - a table returned by on_end holds custom metrics (names are letters, digits and underscores, values are numbers, strings or bools)
+    fn end(&self, summary: &BacktestSummary) -> Result<Vec<(String, String)>, Error> {
+        self.sandbox.reset();
+        let result = self.lua_state.context(|lua_ctx| {
+            if !LuaHook::exists(&lua_ctx, "on_end") {
+                return Ok(Vec::new());
+            }
+
+            let mut metrics = Vec::new();
+            if let Value::Table(metrics_t) = LuaHook::call(&lua_ctx, "on_end", create_summary_table(lua_ctx, summary)?.to_lua_multi(lua_ctx)?)? {
+                for pair in metrics_t.pairs::<Value, Value>() {
+                    let (name, value) = pair?;
+                    match (value_to_text(name), value_to_text(value)) {
+                        (Some(name), Some(value)) if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => metrics.push((name, value.replace('\n', " "))),
+                        (name, _) => println!("strategy {}: unsupported metric {}", self.name, name.unwrap_or_default()),
+                    }
+                }
+            }
+            metrics.sort();
+
+            Ok(metrics)
+        });
+
+        result.map_err(|e: rlua::Error| Error::new(ErrorKind::InvalidData, format!("strategy {}: error in on_end: {}", self.name, self.sandbox.describe(&e))))
+    }
+
+    // write the settings, orders and metrics of a backtest into a session folder
+    // - the settings include the values of every input (defaults too) so the backtest can be run again with them
+    fn write_session(&self, session_path: &str, summary: &BacktestSummary) -> Result<(), Error> {
+        fs::create_dir_all(session_path)?;
+
+        let mut settings = self.settings.clone();
+        for (name, value) in self.inputs.values.iter() {
+            settings.insert(name.clone(), value.to_text());
+        }
+        let mut settings = settings.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<String>>();
+        settings.sort();
+        fs::write(format!("{}/settings.txt", session_path), settings.join("\n"))?;
+
+        let mut writer = csv::Writer::from_path(format!("{}/orders.csv", session_path)).map_err(Error::from)?;
+        writer.write_record(["id", "timestamp", "exchange", "symbol", "side", "type", "quantity", "limit", "price", "status"]).map_err(Error::from)?;
+        for order in self.simulator.lock().unwrap().orders.iter() {
+            writer.write_record([
+                order.id.to_string(),
+                order.timestamp.to_string(),
+                order.exchange.clone(),
+                order.symbol.clone(),
+                order.side.as_str().to_string(),
+                order.fill_type.as_str().to_string(),
+                order.quantity.to_string(),
+                order.limit.map_or(String::new(), |limit| limit.to_string()),
+                order.price.to_string(),
+                order.status.as_str().to_string(),
+            ]).map_err(Error::from)?;
+        }
+        writer.flush()?;
+
+        let mut writer = csv::Writer::from_path(format!("{}/metrics.csv", session_path)).map_err(Error::from)?;
+        writer.write_record(["name", "value"]).map_err(Error::from)?;
+        for line in summary.to_text().lines() {
+            let (name, value) = line.split_once('=').unwrap_or((line, ""));
+            writer.write_record([name, value]).map_err(Error::from)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// create an order from the parameters of an order command
+// - exchange, symbol, side and quantity are required, type defaults to market and limit orders need a price
+pub fn create_order(params: &Table) -> rlua::Result<Order> {
+    let side: String = params.get("side")?;
+    let side = OrderSide::from_string(&side).ok_or_else(|| rlua::Error::RuntimeError(format!("invalid order side {}", side)))?;
+    let fill_type = params.get::<_, Option<String>>("type")?.unwrap_or("market".to_string());
+    let fill_type = OrderType::from_string(&fill_type).ok_or_else(|| rlua::Error::RuntimeError(format!("invalid order type {}", fill_type)))?;
+
+    Ok(Order::new(params.get("exchange")?, params.get("symbol")?, side, fill_type, params.get("quantity")?, params.get("price")?))
+}
+
+// convert a candlestick into a bar table passed to the on_bar hook
+// - extra fields declared by the dataset (open interest, funding rate, etc.) are set next to the default fields
+pub fn create_bar_table<'lua>(lua_ctx: Context<'lua>, exchange: &str, symbol: &str, interval: &str, candlestick: &Candlestick) -> rlua::Result<Table<'lua>> {
+    let bar_t = lua_ctx.create_table()?;
+    bar_t.set("exchange", exchange)?;
+    bar_t.set("symbol", symbol)?;
+    bar_t.set("interval", interval)?;
+    bar_t.set("timestamp", candlestick.timestamp)?;
+    bar_t.set("open", candlestick.open)?;
+    bar_t.set("high", candlestick.high)?;
+    bar_t.set("low", candlestick.low)?;
+    bar_t.set("close", candlestick.close)?;
+    bar_t.set("volume", candlestick.volume)?;
+
+    for (name, value) in candlestick.extensions.iter() {
+        match value {
+            FieldValue::Number(value) => bar_t.set(name.as_str(), *value)?,
+            FieldValue::Text(value) => bar_t.set(name.as_str(), value.as_str())?,
+        }
+    }
+
+    Ok(bar_t)
+}
+
+// convert an order into an order table passed to on_order
+pub fn create_order_table<'lua>(lua_ctx: Context<'lua>, order: &Order) -> rlua::Result<Table<'lua>> {
+    let order_t = lua_ctx.create_table()?;
+    order_t.set("id", order.id)?;
+    order_t.set("timestamp", order.timestamp)?;
+    order_t.set("exchange", order.exchange.as_str())?;
+    order_t.set("symbol", order.symbol.as_str())?;
+    order_t.set("status", order.status.as_str())?;
+    order_t.set("side", order.side.as_str())?;
+    order_t.set("type", order.fill_type.as_str())?;
+    order_t.set("quantity", order.quantity)?;
+    order_t.set("limit", order.limit)?;
+    order_t.set("price", order.price)?;
+    order_t.set("label", order.label.as_deref())?;
+
+    Ok(order_t)
+}
+
+// convert a fill into a fill table passed to on_fill
+pub fn create_fill_table<'lua>(lua_ctx: Context<'lua>, fill: &Fill) -> rlua::Result<Table<'lua>> {
+    let fill_t = lua_ctx.create_table()?;
+    fill_t.set("order_id", fill.order_id)?;
+    fill_t.set("timestamp", fill.timestamp)?;
+    fill_t.set("exchange", fill.exchange.as_str())?;
+    fill_t.set("symbol", fill.symbol.as_str())?;
+    fill_t.set("side", fill.side.as_str())?;
+    fill_t.set("quantity", fill.quantity)?;
+    fill_t.set("price", fill.price)?;
+    fill_t.set("commission", fill.commission)?;
+
+    Ok(fill_t)
+}
+
+// convert a position into a position table passed to on_position_change (with the quantity before the change)
+pub fn create_position_table<'lua>(lua_ctx: Context<'lua>, position: &Position, previous_quantity: f64) -> rlua::Result<Table<'lua>> {
+    let position_t = lua_ctx.create_table()?;
+    position_t.set("exchange", position.exchange.as_str())?;
+    position_t.set("symbol", position.symbol.as_str())?;
+    position_t.set("quantity", position.quantity)?;
+    position_t.set("previous_quantity", previous_quantity)?;
+    position_t.set("average_price", position.average_price)?;
+    position_t.set("realized_pnl", position.realized_pnl)?;
+
+    Ok(position_t)
+}
+
+// convert a trading session into a session table passed to on_session_open and on_session_close
+// - the date is the local date of the exchange, open and close are timestamps
+pub fn create_session_table<'lua>(lua_ctx: Context<'lua>, exchange: &str, session: &Session) -> rlua::Result<Table<'lua>> {
+    let session_t = lua_ctx.create_table()?;
+    session_t.set("exchange", exchange)?;
+    session_t.set("date", session.date.to_string())?;
+    session_t.set("open", session.open)?;
+    session_t.set("close", session.close)?;
+
+    Ok(session_t)
+}
+
+// convert a timer event into an event table passed to the callback of the timer
+// - time is the scheduled time and timestamp is the clock of the bars when the timer fired
+pub fn create_timer_table<'lua>(lua_ctx: Context<'lua>, event: &TimerEvent, clock: i64) -> rlua::Result<Table<'lua>> {
+    let event_t = lua_ctx.create_table()?;
+    event_t.set("id", event.id)?;
+    event_t.set("spec", event.spec.as_str())?;
+    event_t.set("time", event.time)?;
+    event_t.set("timestamp", clock)?;
+    event_t.set("missed", event.missed)?;
+    event_t.set("exchange", event.exchange.as_deref())?;
+
+    Ok(event_t)
+}
+
+// convert the summary of a backtest into a summary table passed to on_end
+pub fn create_summary_table<'lua>(lua_ctx: Context<'lua>, summary: &BacktestSummary) -> rlua::Result<Table<'lua>> {
+    let summary_t = lua_ctx.create_table()?;
+    summary_t.set("status", summary.status.as_str())?;
+    summary_t.set("bars", summary.bars)?;
+    summary_t.set("orders", summary.orders)?;
+    summary_t.set("fills", summary.fills)?;
+    summary_t.set("invalid_orders", summary.invalid_orders)?;
+    summary_t.set("start_timestamp", summary.start_timestamp)?;
+    summary_t.set("end_timestamp", summary.end_timestamp)?;
+    summary_t.set("initial_equity", summary.initial_equity)?;
+    summary_t.set("final_equity", summary.final_equity)?;
+    summary_t.set("total_return", summary.total_return())?;
+    summary_t.set("realized_pnl", summary.realized_pnl)?;
+    summary_t.set("commission", summary.commission)?;
+    summary_t.set("max_drawdown", summary.max_drawdown)?;
+
+    Ok(summary_t)
+}
+
+// convert the coverage of the datasets into a list of tables
+// - the effective range is the range of bars the backtest reads for the dataset (nil when none are read)
+// - status is the listing status of the dataset at the first bar of the backtest (not_listed when its bars start later)
+fn create_coverage_table<'lua>(lua_ctx: Context<'lua>, coverage: &[Coverage]) -> rlua::Result<Table<'lua>> {
+    let first = coverage.iter().filter_map(|coverage| coverage.effective_range()).map(|(start, _)| start).min();
+    let coverage_t = lua_ctx.create_table()?;
+    for (i, coverage) in coverage.iter().enumerate() {
+        let effective = coverage.effective_range();
+        let dataset_t = lua_ctx.create_table()?;
+        dataset_t.set("exchange", coverage.exchange.as_str())?;
+        dataset_t.set("symbol", coverage.symbol.as_str())?;
+        dataset_t.set("start_timestamp", coverage.start_timestamp)?;
+        dataset_t.set("end_timestamp", coverage.end_timestamp)?;
+        dataset_t.set("effective_start", effective.map(|(start, _)| start))?;
+        dataset_t.set("effective_end", effective.map(|(_, end)| end))?;
+        if let Some(first) = first {
+            dataset_t.set("status", coverage.status(first).as_str())?;
+        }
+        coverage_t.set(i + 1, dataset_t)?;
+    }
+
+    Ok(coverage_t)
+}
+
+// convert an order book snapshot into a table of bid and ask levels
+// - book.bids[1] is the best bid and book.asks[1] is the best ask, each level has a price and size
+// - best_bid, best_ask and mid_price are nil when a side of the book is empty
+pub fn create_order_book_table<'lua>(lua_ctx: Context<'lua>, order_book: &OrderBook) -> rlua::Result<Table<'lua>> {
+    let book_t = lua_ctx.create_table()?;
+    book_t.set("timestamp", order_book.timestamp)?;
+
+    let bids_t = lua_ctx.create_table()?;
+    for (i, level) in order_book.bids.iter().enumerate() {
+        let level_t = lua_ctx.create_table()?;
+        level_t.set("price", level.price)?;
+        level_t.set("size", level.size)?;
+        bids_t.set(i + 1, level_t)?;
+    }
+    book_t.set("bids", bids_t)?;
+
+    let asks_t = lua_ctx.create_table()?;
+    for (i, level) in order_book.asks.iter().enumerate() {
+        let level_t = lua_ctx.create_table()?;
+        level_t.set("price", level.price)?;
+        level_t.set("size", level.size)?;
+        asks_t.set(i + 1, level_t)?;
+    }
+    book_t.set("asks", asks_t)?;
+    book_t.set("best_bid", order_book.best_bid())?;
+    book_t.set("best_ask", order_book.best_ask())?;
+    book_t.set("mid_price", order_book.mid_price())?;
+
+    Ok(book_t)
+}
+
+// convert a corporate action into an event table (type is "split" or "dividend")
+// - value is the split ratio or the cash dividend per share
+pub fn create_corporate_action_table<'lua>(lua_ctx: Context<'lua>, exchange: &str, symbol: &str, corporate_action: &CorporateAction) -> rlua::Result<Table<'lua>> {
+    let event_t = lua_ctx.create_table()?;
+    event_t.set("exchange", exchange)?;
+    event_t.set("symbol", symbol)?;
+    event_t.set("timestamp", corporate_action.timestamp)?;
+    event_t.set("type", match corporate_action.kind {
+        CorporateActionKind::Split => "split",
+        CorporateActionKind::Dividend => "dividend",
+    })?;
+    event_t.set("value", corporate_action.value)?;
+
+    Ok(event_t)
 }
 
 // loads all strategy plugins from the filesystem using the given path
@@ -185,16 +1173,73 @@ pub fn load_strategy_plugins(strategies_path: String) -> Result<HashMap<String,
 
             let mut settings = HashMap::new();
             for line in settings_contents.lines() {
+                // blank lines and comments are skipped, a key without a value is checked by the inputs of the strategy
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
                 let mut parts = line.splitn(2, '=');
-                let key = parts.next().unwrap();
-                let value = parts.next().unwrap();
+                let key = parts.next().unwrap_or_default().trim();
+                let value = parts.next().unwrap_or_default().trim();
                 settings.insert(key.to_string(), value.to_string());
             }
 
-            let strategy = StrategyPlugin::new(strategy_name.clone(), lua_contents, settings);
+            let directory = format!("{}/{}", &strategies_path, strategy_name);
+            let strategy = StrategyPlugin::new(strategy_name.clone(), lua_contents, settings, directory);
 
             strategies.insert(strategy_name, strategy);
         }
     }
     Ok(strategies)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{storage::Reader, tasks::merge::ConflictPolicy};
+
+    #[test]
+    fn backtests_of_a_failed_query_end_as_failed() {
+        let path = std::env::temp_dir().join(format!("strategy_failed_query_{}", std::process::id())).to_string_lossy().to_string();
+        fs::create_dir_all(&path).unwrap();
+        let source = format!("{}/source.csv", path);
+        let mut contents = "timestamp,open,high,low,close,volume\n".to_string();
+        for i in 0..100 {
+            contents.push_str(&format!("{},1,2,0.5,1.5,10\n", 1704067200 + i * 60));
+        }
+        fs::write(&source, contents).unwrap();
+
+        let database = Database::new().with_path(path.clone());
+        let summary = database.update_dataset(Exchange::new_with("TEST".to_string()), Symbol { name: "FAILED".to_string(), ..Symbol::new() }, source, ConflictPolicy::PreferNewer).unwrap();
+
+        // damage the close of the first record so the query fails under the fail policy
+        let filename = summary.files[0].filename.clone();
+        let header = Reader::new(filename.clone()).unwrap().header().unwrap().clone();
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes[header.size() + 40] ^= 0xff;
+        fs::write(&filename, bytes).unwrap();
+
+        let mut datasets = HashMap::new();
+        datasets.insert("TEST_FAILED".to_string(), Dataset::new("TEST_FAILED".to_string()));
+        let mut strategy = Strategy::new("failed".to_string(), "function on_bar(bar)\nend".to_string(), path.clone(), HashMap::new(), datasets, HashMap::new()).unwrap();
+        strategy.start(database, 1).unwrap();
+
+        // the steps end with the error of the query instead of running without bars
+        let start = Instant::now();
+        let error = loop {
+            match strategy.step() {
+                Ok(running) => {
+                    assert!(running, "the backtest completed");
+                    assert!(start.elapsed() < Duration::from_secs(10), "the query did not fail");
+                },
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        strategy.stop(BacktestStatus::Failed).unwrap();
+        assert_eq!(strategy.summary.as_ref().unwrap().status, BacktestStatus::Failed);
+        assert_eq!(strategy.summary.as_ref().unwrap().bars, 0);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use crate::{models::{Order, OrderSide, OrderStatus, OrderType}, database::models::{candlestick::Candlestick, contract::ContractSpec}};
+
+// represents a fill of an order
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub order_id: i32,
+    pub exchange: String,
+    pub symbol: String,
+    pub timestamp: i64,
+    pub side: OrderSide,
+    pub quantity: f64,
+    pub price: f64,
+    pub commission: f64,
+}
+
+// represents the net position of a strategy in a symbol
+// - the quantity is negative for a short position
+// - the multiplier is the value of a price point per unit of quantity (1 unless the contract sets it)
+#[derive(Debug, Clone, Default)]
+pub struct Position {
+    pub exchange: String,
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_price: f64,
+    pub realized_pnl: f64,
+    pub multiplier: f64,
+}
+
+impl Position {
+    pub fn new(exchange: String, symbol: String) -> Self {
+        Self {
+            exchange,
+            symbol,
+            multiplier: 1.0,
+            ..Default::default()
+        }
+    }
+
+    // set the value of a price point per unit of quantity
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    // add a fill to the position and return the profit or loss it realized
+    fn apply(&mut self, side: OrderSide, quantity: f64, price: f64) -> f64 {
+        let signed = match side {
+            OrderSide::Buy => quantity,
+            OrderSide::Sell => -quantity,
+        };
+
+        // the part of the fill that reduces the position realizes a profit or loss
+        let mut realized = 0.0;
+        if self.quantity != 0.0 && self.quantity.signum() != signed.signum() {
+            let closed = signed.abs().min(self.quantity.abs());
+            realized = closed * (price - self.average_price) * self.quantity.signum() * self.multiplier;
+        }
+
+        let quantity = self.quantity + signed;
+        if quantity == 0.0 {
+            self.average_price = 0.0;
+        }
+        else if self.quantity == 0.0 || quantity.signum() != self.quantity.signum() {
+            // a new position or a position that was reversed starts at the fill price
+            self.average_price = price;
+        }
+        else if quantity.abs() > self.quantity.abs() {
+            self.average_price = (self.average_price * self.quantity + price * signed) / quantity;
+        }
+
+        self.quantity = quantity;
+        self.realized_pnl += realized;
+        realized
+    }
+}
+
+// represents how a backtest ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BacktestStatus {
+    // every bar of the datasets was processed
+    #[default]
+    Complete,
+    // the strategy was stopped before the end of the datasets
+    Stopped,
+    // the strategy or its query failed before the end of the datasets
+    Failed,
+}
+
+impl BacktestStatus {
+    // get the name of the value used in results files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BacktestStatus::Complete => "complete",
+            BacktestStatus::Stopped => "stopped",
+            BacktestStatus::Failed => "failed",
+        }
+    }
+}
+
+// represents the results of a backtest
+#[derive(Debug, Clone, Default)]
+pub struct BacktestSummary {
+    pub status: BacktestStatus,
+    pub bars: u64,
+    pub orders: u64,
+    pub fills: u64,
+    pub invalid_orders: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub initial_equity: f64,
+    pub final_equity: f64,
+    pub realized_pnl: f64,
+    pub commission: f64,
+    pub max_drawdown: f64,
+
+    // the metrics the strategy returned from on_end (name and value)
+    pub metrics: Vec<(String, String)>,
+}
+
+impl BacktestSummary {
+    // get the return of the backtest in percent
+    pub fn total_return(&self) -> f64 {
+        match self.initial_equity {
+            equity if equity > 0.0 => (self.final_equity / equity - 1.0) * 100.0,
+            _ => 0.0,
+        }
+    }
+
+    // write the summary as key=value lines
+    pub fn to_text(&self) -> String {
+        let lines = vec![
+            format!("status={}", self.status.as_str()),
+            format!("bars={}", self.bars),
+            format!("orders={}", self.orders),
+            format!("fills={}", self.fills),
+            format!("invalid_orders={}", self.invalid_orders),
+            format!("start_timestamp={}", self.start_timestamp),
+            format!("end_timestamp={}", self.end_timestamp),
+            format!("initial_equity={:.2}", self.initial_equity),
+            format!("final_equity={:.2}", self.final_equity),
+            format!("total_return={:.2}", self.total_return()),
+            format!("realized_pnl={:.2}", self.realized_pnl),
+            format!("commission={:.2}", self.commission),
+            format!("max_drawdown={:.2}", self.max_drawdown),
+        ];
+        let lines = lines.into_iter().chain(self.metrics.iter().map(|(name, value)| format!("{}={}", name, value))).collect::<Vec<String>>();
+
+        lines.join("\n") + "\n"
+    }
+}
+
+// simulates the execution of the orders of a strategy against the bars of a backtest
+// - orders are filled on the next bar of their symbol after they were submitted
+// - market orders fill at the open, limit orders fill when the bar trades through the limit
+//   (at the open when it gaps through the limit)
+// - positions are netted by exchange and symbol and valued at the last close of the symbol
+pub struct OrderSimulator {
+    pub orders: Vec<Order>,
+    pub fills: Vec<Fill>,
+    pub positions: HashMap<(String, String), Position>,
+    pub cash: f64,
+    pub initial_cash: f64,
+
+    // commission of a fill as a fraction of its value
+    pub commission_rate: f64,
+
+    // contract specifications of the symbols (orders of other symbols are not rounded or validated)
+    pub specs: HashMap<(String, String), ContractSpec>,
+
+    last_prices: HashMap<(String, String), f64>,
+    peak_equity: f64,
+    max_drawdown: f64,
+    next_id: i32,
+}
+
+impl OrderSimulator {
+    // create a new simulator with a starting cash balance
+    pub fn new(initial_cash: f64) -> Self {
+        Self {
+            orders: Vec::new(),
+            fills: Vec::new(),
+            positions: HashMap::new(),
+            cash: initial_cash,
+            initial_cash,
+            commission_rate: 0.0,
+            specs: HashMap::new(),
+            last_prices: HashMap::new(),
+            peak_equity: initial_cash,
+            max_drawdown: 0.0,
+            next_id: 1,
+        }
+    }
+
+    // set the commission of a fill as a fraction of its value
+    pub fn set_commission(&mut self, commission_rate: f64) {
+        self.commission_rate = commission_rate;
+    }
+
+    // set the contract specification of a symbol
+    pub fn set_spec(&mut self, exchange: &str, spec: ContractSpec) {
+        self.specs.insert((exchange.to_string(), spec.symbol.clone()), spec);
+    }
+
+    // submit an order at a timestamp and return its id (invalid orders are kept but never filled)
+    // - the order is rounded to the contract of its symbol and checked against its lot size and min notional
+    // - orders of a symbol that is not listed at the timestamp are invalid
+    // - a market order is valued at the last close of its symbol
+    pub fn submit(&mut self, mut order: Order, timestamp: i64) -> i32 {
+        order.id = self.next_id;
+        order.timestamp = timestamp;
+        if order.quantity <= 0.0 || (order.fill_type == OrderType::Limit && order.limit.is_none()) {
+            println!("simulator: invalid order {} for {}:{}", order.id, order.exchange, order.symbol);
+            order.status = OrderStatus::Invalid;
+        }
+        else if let Some(spec) = self.specs.get(&(order.exchange.clone(), order.symbol.clone())) {
+            if !spec.is_listed(timestamp) {
+                println!("simulator: invalid order {} for {}:{} (not listed at {})", order.id, order.exchange, order.symbol, timestamp);
+                order.status = OrderStatus::Invalid;
+            }
+            order.price = self.last_prices.get(&(order.exchange.clone(), order.symbol.clone())).copied().unwrap_or(0.0);
+            order.apply_spec(spec);
+            order.price = 0.0;
+        }
+
+        self.next_id += 1;
+        self.orders.push(order);
+        self.next_id - 1
+    }
+
+    // fill the open orders of a symbol with the next bar and return the fills
+    pub fn update(&mut self, exchange: &str, symbol: &str, candlestick: &Candlestick) -> Vec<Fill> {
+        let spec = self.specs.get(&(exchange.to_string(), symbol.to_string()));
+        let multiplier = spec.map_or(1.0, |spec| spec.multiplier);
+
+        let mut fills = Vec::new();
+        for order in self.orders.iter_mut() {
+            if order.status != OrderStatus::Submitted || order.exchange != exchange || order.symbol != symbol || order.timestamp >= candlestick.timestamp {
+                continue;
+            }
+
+            let price = match (order.fill_type, order.side, order.limit) {
+                (OrderType::Market, _, _) => candlestick.open,
+                (OrderType::Limit, OrderSide::Buy, Some(limit)) if candlestick.low <= limit => candlestick.open.min(limit),
+                (OrderType::Limit, OrderSide::Sell, Some(limit)) if candlestick.high >= limit => candlestick.open.max(limit),
+                _ => continue,
+            };
+            let price = spec.map_or(price, |spec| spec.round_price(price));
+
+            order.status = OrderStatus::Filled;
+            order.price = price;
+            fills.push(Fill {
+                order_id: order.id,
+                exchange: exchange.to_string(),
+                symbol: symbol.to_string(),
+                timestamp: candlestick.timestamp,
+                side: order.side,
+                quantity: order.quantity,
+                price,
+                commission: order.quantity * price * multiplier * self.commission_rate,
+            });
+        }
+
+        for fill in fills.iter() {
+            let key = (fill.exchange.clone(), fill.symbol.clone());
+            let position = self.positions.entry(key).or_insert_with(|| Position::new(fill.exchange.clone(), fill.symbol.clone()).with_multiplier(multiplier));
+            position.apply(fill.side, fill.quantity, fill.price);
+
+            let value = fill.quantity * fill.price * multiplier;
+            match fill.side {
+                OrderSide::Buy => self.cash -= value + fill.commission,
+                OrderSide::Sell => self.cash += value - fill.commission,
+            }
+        }
+        self.fills.extend(fills.iter().cloned());
+
+        self.last_prices.insert((exchange.to_string(), symbol.to_string()), candlestick.close);
+        self.update_drawdown();
+
+        fills
+    }
+
+    // get the net quantity of a position
+    pub fn position(&self, exchange: &str, symbol: &str) -> f64 {
+        self.positions.get(&(exchange.to_string(), symbol.to_string())).map_or(0.0, |position| position.quantity)
+    }
+
+    // get the value of the cash and the positions at the last close of their symbols
+    pub fn equity(&self) -> f64 {
+        self.cash + self.positions.values()
+            .map(|position| position.quantity * position.multiplier * self.last_prices.get(&(position.exchange.clone(), position.symbol.clone())).copied().unwrap_or(position.average_price))
+            .sum::<f64>()
+    }
+
+    // track the largest drop of the equity from its peak (in percent)
+    fn update_drawdown(&mut self) {
+        let equity = self.equity();
+        self.peak_equity = self.peak_equity.max(equity);
+        if self.peak_equity > 0.0 {
+            self.max_drawdown = self.max_drawdown.max((1.0 - equity / self.peak_equity) * 100.0);
+        }
+    }
+
+    // summarize the orders, fills and equity of the backtest
+    pub fn summary(&self, bars: u64, start_timestamp: i64, end_timestamp: i64) -> BacktestSummary {
+        BacktestSummary {
+            status: BacktestStatus::Complete,
+            bars,
+            orders: self.orders.len() as u64,
+            fills: self.fills.len() as u64,
+            invalid_orders: self.orders.iter().filter(|order| order.status == OrderStatus::Invalid).count() as u64,
+            start_timestamp,
+            end_timestamp,
+            initial_equity: self.initial_cash,
+            final_equity: self.equity(),
+            realized_pnl: self.positions.values().map(|position| position.realized_pnl).sum(),
+            commission: self.fills.iter().map(|fill| fill.commission).sum(),
+            max_drawdown: self.max_drawdown,
+            metrics: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::contract::AssetClass;
+
+    fn simulator() -> OrderSimulator {
+        let mut spec = ContractSpec::new("ES".to_string(), "USD".to_string(), AssetClass::Future);
+        spec.tick_size = 0.25;
+        spec.lot_size = 1.0;
+        spec.min_notional = 1000.0;
+        spec.multiplier = 50.0;
+
+        let mut simulator = OrderSimulator::new(100000.0);
+        simulator.set_spec("CME", spec);
+        simulator.update("CME", "ES", &Candlestick::new_with(0, 100.0, 100.0, 100.0, 100.0, 1.0));
+        simulator
+    }
+
+    fn order(side: OrderSide, quantity: f64, limit: Option<f64>) -> Order {
+        let fill_type = if limit.is_some() { OrderType::Limit } else { OrderType::Market };
+        Order::new("CME".to_string(), "ES".to_string(), side, fill_type, quantity, limit)
+    }
+
+    #[test]
+    fn orders_are_rounded_to_the_contract() {
+        let mut simulator = simulator();
+        simulator.submit(order(OrderSide::Buy, 2.7, Some(100.1)), 0);
+
+        let order = simulator.orders.last().unwrap();
+        assert_eq!(order.status, OrderStatus::Submitted);
+        assert_eq!(order.quantity, 2.0);
+        assert_eq!(order.limit, Some(100.0));
+    }
+
+    #[test]
+    fn orders_below_the_lot_size_or_min_notional_are_invalid() {
+        let mut simulator = simulator();
+        simulator.submit(order(OrderSide::Buy, 0.5, None), 0);
+        simulator.submit(order(OrderSide::Buy, 1.0, Some(10.0)), 0);
+        simulator.submit(order(OrderSide::Buy, 1.0, None), 0);
+
+        let statuses = simulator.orders.iter().map(|order| order.status).collect::<Vec<OrderStatus>>();
+        assert_eq!(statuses, vec![OrderStatus::Invalid, OrderStatus::Invalid, OrderStatus::Submitted]);
+    }
+
+    #[test]
+    fn orders_outside_of_the_listing_are_invalid() {
+        let mut simulator = simulator();
+        let mut spec = simulator.specs[&("CME".to_string(), "ES".to_string())].clone();
+        spec.listing_timestamp = Some(60);
+        spec.delisting_timestamp = Some(180);
+        simulator.set_spec("CME", spec);
+        for timestamp in [0, 60, 120, 180] {
+            simulator.submit(order(OrderSide::Buy, 1.0, None), timestamp);
+        }
+
+        let statuses = simulator.orders.iter().map(|order| order.status).collect::<Vec<OrderStatus>>();
+        assert_eq!(statuses, vec![OrderStatus::Invalid, OrderStatus::Submitted, OrderStatus::Submitted, OrderStatus::Invalid]);
+    }
+
+    #[test]
+    fn fills_are_valued_with_the_multiplier() {
+        let mut simulator = simulator();
+        simulator.submit(order(OrderSide::Buy, 2.0, None), 0);
+        simulator.update("CME", "ES", &Candlestick::new_with(60, 101.0, 102.0, 100.0, 102.0, 1.0));
+        assert_eq!(simulator.cash, 100000.0 - 2.0 * 101.0 * 50.0);
+        assert_eq!(simulator.equity(), 100000.0 + 2.0 * 1.0 * 50.0);
+
+        simulator.submit(order(OrderSide::Sell, 2.0, None), 60);
+        simulator.update("CME", "ES", &Candlestick::new_with(120, 103.0, 103.0, 103.0, 103.0, 1.0));
+        assert_eq!(simulator.positions[&("CME".to_string(), "ES".to_string())].realized_pnl, 2.0 * 2.0 * 50.0);
+        assert_eq!(simulator.cash, 100000.0 + 2.0 * 2.0 * 50.0);
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, thread::JoinHandle};
+use std::{collections::HashMap, thread::JoinHandle, sync::{Arc, Mutex}};
 
-use crate::{utils::Config, datasets::{Dataset, load_datasets}, plugins::{strategies::{Strategy, load_strategy_plugins, StrategyPlugin}, indicators::{load_indicators, IndicatorPlugin}}, threads::{ThreadManager}};
+use crate::{database::{database::Database, models::{exchange::Exchange, symbol::Symbol, interval::Interval}, tasks::merge::ConflictPolicy}, utils::{Config, parse_time}, datasets::{Dataset, load_datasets}, plugins::{strategies::{Strategy, load_strategy_plugins, StrategyPlugin}, indicators::{load_indicators, IndicatorPlugin}}, threads::{ThreadManager, ThreadState}, simulator::BacktestStatus};
 
 pub struct Core {
     pub config: Config,
@@ -8,12 +8,24 @@ pub struct Core {
     pub datasets: HashMap<String, Dataset>,
     pub indicators: HashMap<String, IndicatorPlugin>,
     pub strategies: HashMap<String, StrategyPlugin>,
+    #[allow(dead_code)]
     pub running_strategies: HashMap<String, JoinHandle<()>>,
+    pub database: Database,
+    pub next_client_id: u64,
 }
 
 impl Core {
     // create a new system core with a given config
     pub fn new(config: &Config) -> Core {
+        // rollup intervals of the config (invalid intervals are skipped)
+        let rollups = config.rollups.iter().filter_map(|interval| match Interval::from_string(interval) {
+            Ok(interval) => Some(interval),
+            Err(e) => {
+                println!("Skipping rollup interval: {}", e);
+                None
+            }
+        }).collect();
+
         Core {
             config: config.clone(),
             thread_manager: ThreadManager::new(),
@@ -21,6 +33,8 @@ impl Core {
             indicators: HashMap::new(),
             strategies: HashMap::new(),
             running_strategies: HashMap::new(),
+            database: Database::new().with_rollups(rollups),
+            next_client_id: 0,
         }
     }
 
@@ -63,38 +77,74 @@ impl Core {
         let strategy_settings = strategy_plugin.settings.clone();
         let strategy_script = strategy_plugin.lua_script.clone();
 
+        // a strategy runs once at a time
+        let state = self.thread_manager.get_state(strategy_name.clone());
+        if state == "running" || state == "paused" {
+            println!("Strategy is already {}", state);
+            return false;
+        }
+
         // use strategy plugin's settings to put together a list of datasets
         let mut datasets = HashMap::new();
-        let datasets_string = strategy_settings.get("datasets").unwrap();
-        let datasets_parts: Vec<&str> = datasets_string.split(",").collect();
-        for dataset_name in datasets_parts {
+        let datasets_string = match strategy_settings.get("datasets") {
+            Some(datasets_string) => datasets_string,
+            None => {
+                println!("Strategy has no datasets setting");
+                return false;
+            }
+        };
+        for dataset_name in datasets_string.split(",").map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            if !dataset_name.contains('_') {
+                println!("Unsupported dataset name {} (expected exchange_symbol)", dataset_name);
+                return false;
+            }
             let name = dataset_name.to_string();
             let dataset = Dataset::new(name.clone());
             datasets.insert(name, dataset);
         }
 
         // create and initialize strategy
-        let strategy = Strategy::new(strategy_name.clone(), strategy_script, strategy_settings, datasets);
+        let strategy = match Strategy::new(strategy_plugin.name.clone(), strategy_script, strategy_plugin.directory.clone(), strategy_settings, datasets, self.indicators.clone()) {
+            Ok(strategy) => strategy,
+            Err(e) => {
+                println!("Error loading strategy: {}", e);
+                return false;
+            }
+        };
+        let mut strategy = strategy.with_sessions_path(format!("{}/{}/sessions", self.config.strategies_path, strategy_name));
 
-        /*let state = self.thread_manager.get_state(strategy_name.clone());
-        if state == "stopped" {
-            let is_started = self.thread_manager.start(strategy_name.clone(), move || {
-                // println!("Inside strategy thread, implement LUA and loop through datasets");
-
-                // load up LUA script
-                // setup synchronized datasets stream
-                // start loop through each iteration
-                // track orders
-                // output orders
-                // output performance metrics
-                // strategy.run();
-
-            });
-
-            return is_started;
-        }*/
+        // open the query of the backtest before the thread is started so errors are reported here
+        self.next_client_id += 1;
+        self.database.connect_client(self.next_client_id);
+        if let Err(e) = strategy.start(self.database.clone(), self.next_client_id) {
+            println!("Error starting strategy: {}", e);
+            self.database.disconnect_client(self.next_client_id);
+            return false;
+        }
 
-        false
+        // the strategy thread reads a chunk of bars every time it is run until the backtest is complete
+        // - a strategy that fails or is stopped ends its backtest with the bars it processed (its query is stopped and its client disconnected)
+        let strategy = Arc::new(Mutex::new(strategy));
+        let strategy_exit = strategy.clone();
+        self.thread_manager.start_with(strategy_name.clone(), move || {
+            let mut strategy = strategy.lock().unwrap();
+            match strategy.step() {
+                Ok(running) => running,
+                Err(e) => {
+                    println!("Error running strategy: {}", e);
+                    if let Err(e) = strategy.stop(BacktestStatus::Failed) {
+                        println!("Error stopping strategy: {}", e);
+                    }
+                    false
+                }
+            }
+        }, move |state| {
+            if let ThreadState::Stopped = state {
+                if let Err(e) = strategy_exit.lock().unwrap().stop(BacktestStatus::Stopped) {
+                    println!("Error stopping strategy: {}", e);
+                }
+            }
+        })
     }
 
     // stop a strategy instance (thread)
@@ -106,7 +156,7 @@ impl Core {
         }
 
         let state = self.thread_manager.get_state(strategy_name.clone());
-        if state == "running" {
+        if state == "running" || state == "paused" {
             return self.thread_manager.stop(strategy_name.clone());
         }
 
@@ -138,7 +188,7 @@ impl Core {
         }
 
         let state = self.thread_manager.get_state(strategy_name.clone());
-        if state == "stopped" {
+        if state == "paused" {
             return self.thread_manager.resume(strategy_name.clone());
         }
 
@@ -153,8 +203,141 @@ impl Core {
             return "not found".to_string();
         }
 
-        let state = self.thread_manager.get_state(strategy_name.clone());
+        self.thread_manager.get_state(strategy_name.clone())
+    }
+
+    // append the newer records of a source file to a dataset
+    pub fn update_dataset(&mut self, exchange: String, symbol: String, source: String, policy: ConflictPolicy) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.update_dataset(Exchange::new_with(exchange), symbol, source, policy) {
+            Ok(summary) => {
+                println!("{} records added, {} skipped ({} records from {} to {}, snapshot {})", summary.added, summary.skipped, summary.record_count, summary.start_timestamp, summary.end_timestamp, summary.snapshot_id);
+                true
+            },
+            Err(e) => {
+                println!("Error updating dataset: {}", e);
+                false
+            }
+        }
+    }
+
+    // merge overlapping source files into a dataset
+    pub fn merge_datasets(&mut self, exchange: String, symbol: String, sources: Vec<String>, policy: ConflictPolicy) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.merge_datasets(Exchange::new_with(exchange), symbol, sources, policy) {
+            Ok(summary) => {
+                println!("{} records added, {} replaced, {} duplicates ({} records from {} to {}, snapshot {})", summary.added, summary.replaced, summary.duplicates, summary.record_count, summary.start_timestamp, summary.end_timestamp, summary.snapshot_id);
+                true
+            },
+            Err(e) => {
+                println!("Error merging datasets: {}", e);
+                false
+            }
+        }
+    }
+
+    // split a dataset that is stored in a single file into time partitions
+    pub fn partition_dataset(&mut self, exchange: String, symbol: String) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.partition_dataset(Exchange::new_with(exchange), symbol) {
+            Ok(summary) => {
+                println!("{} records written into {} partitions (snapshot {})", summary.record_count, summary.files.len(), summary.snapshot_id);
+                true
+            },
+            Err(e) => {
+                println!("Error partitioning dataset: {}", e);
+                false
+            }
+        }
+    }
+
+    // build the rollups of a dataset that was written before rollups were enabled
+    pub fn build_rollups(&mut self, exchange: String, symbol: String) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.build_rollups(Exchange::new_with(exchange), symbol) {
+            Ok(rollups) => {
+                for rollup in rollups.iter() {
+                    println!("{} rollup: {} bars from {} to {} (snapshot {})", rollup.interval, rollup.record_count, rollup.start_timestamp, rollup.end_timestamp, rollup.snapshot_id);
+                }
+                true
+            },
+            Err(e) => {
+                println!("Error building rollups: {}", e);
+                false
+            }
+        }
+    }
 
-        return state;
+    // list the snapshots of a dataset
+    pub fn list_snapshots(&mut self, exchange: String, symbol: String) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.snapshots(Exchange::new_with(exchange), symbol) {
+            Ok(snapshots) => {
+                for snapshot in snapshots.iter() {
+                    println!("snapshot {}: created {}, content {}, source {}, {} files", snapshot.snapshot_id, snapshot.created, snapshot.content_hash, snapshot.source_hash, snapshot.files.len());
+                }
+                true
+            },
+            Err(e) => {
+                println!("Error listing snapshots: {}", e);
+                false
+            }
+        }
     }
-}
\ No newline at end of file
+
+    // show the latest completed bar of an interval at or before a time (unix seconds or a utc date and time)
+    pub fn as_of(&mut self, exchange: String, symbol: String, interval: String, time: String) -> bool {
+        let timestamp = match parse_time(&time) {
+            Some(timestamp) => timestamp,
+            None => {
+                println!("Unsupported time {}", time);
+                return false;
+            }
+        };
+
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.as_of(Exchange::new_with(exchange), symbol, interval.clone(), timestamp) {
+            Ok(Some(candlestick)) => {
+                println!("{} bar at {}: open {}, high {}, low {}, close {}, volume {}", interval, candlestick.timestamp, candlestick.open, candlestick.high, candlestick.low, candlestick.close, candlestick.volume);
+                true
+            },
+            Ok(None) => {
+                println!("No {} bar at or before {}", interval, timestamp);
+                false
+            },
+            Err(e) => {
+                println!("Error looking up bar: {}", e);
+                false
+            }
+        }
+    }
+
+    // import the order book snapshots of a csv file for a symbol
+    pub fn import_order_books(&mut self, exchange: String, symbol: String, source: String) -> bool {
+        let symbol = Symbol { name: symbol, ..Symbol::new() };
+        match self.database.import_order_books(Exchange::new_with(exchange.clone()), symbol.clone(), source) {
+            Ok((snapshots, levels)) => {
+                println!("imported {} order book snapshots ({} levels) of {}:{}", snapshots, levels, exchange, symbol.name);
+                true
+            },
+            Err(e) => {
+                println!("Error importing order book snapshots: {}", e);
+                false
+            }
+        }
+    }
+
+    // scan a damaged stmdb file and write the records that can still be read into a repaired copy
+    pub fn repair_file(&mut self, filename: String) -> bool {
+        match self.database.repair_file(filename) {
+            Ok(report) => {
+                print!("{}", report.to_text());
+                true
+            },
+            Err(e) => {
+                println!("Error repairing file: {}", e);
+                false
+            }
+        }
+    }
+}
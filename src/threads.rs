@@ -11,7 +11,8 @@ use std::{thread, sync::{Mutex, Arc}, time::Duration, collections::HashMap};
 pub enum ThreadState {
     Running,
     Paused,
-    Stopped
+    Stopped,
+    Finished
 }
 
 #[derive(Clone)]
@@ -27,21 +28,46 @@ impl ThreadManager {
     }
 
     // state a named thread using a closure
+    // - the closure is called in a loop while the thread is running, the thread finishes when it returns false
+    #[allow(dead_code)]
     pub fn start<F>(&mut self, name: String, f: F) -> bool
     where
-        F: Fn() + Send + 'static,
+        F: Fn() -> bool + Send + 'static,
+    {
+        self.start_with(name, f, |_| {})
+    }
+
+    // start a named thread using a closure and a closure that is called once when the thread exits
+    // - the exit closure gets the state the thread exited in (finished or stopped) so it can clean up after the thread
+    pub fn start_with<F, E>(&mut self, name: String, f: F, on_exit: E) -> bool
+    where
+        F: Fn() -> bool + Send + 'static,
+        E: FnOnce(ThreadState) + Send + 'static,
     {
         let state = Arc::new(Mutex::new(ThreadState::Running));
         let state_clone = state.clone();
 
         thread::spawn(move || {
             loop {
-                match *state_clone.lock().unwrap() {
-                    ThreadState::Running => f(),
+                let current_state = *state_clone.lock().unwrap();
+                match current_state {
+                    ThreadState::Running => {
+                        if !f() {
+                            // a thread stopped while the closure ran keeps its stopped state
+                            let mut state = state_clone.lock().unwrap();
+                            if !matches!(*state, ThreadState::Stopped) {
+                                *state = ThreadState::Finished;
+                            }
+                            break;
+                        }
+                    },
                     ThreadState::Paused => thread::sleep(Duration::from_millis(10)),
-                    ThreadState::Stopped => break,
+                    ThreadState::Stopped | ThreadState::Finished => break,
                 }
             }
+
+            let exit_state = *state_clone.lock().unwrap();
+            on_exit(exit_state);
         });
 
         self.threads.insert(name.clone(), state);
@@ -90,10 +116,52 @@ impl ThreadManager {
             match current_state {
                 ThreadState::Running => return "running".to_string(),
                 ThreadState::Paused => return "paused".to_string(),
-                ThreadState::Stopped => return "stopped".to_string()
+                ThreadState::Stopped => return "stopped".to_string(),
+                ThreadState::Finished => return "finished".to_string()
             }
         }
 
         "stopped".to_string()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn finished_threads_report_their_state() {
+        let mut manager = ThreadManager::new();
+        let (sender, receiver) = mpsc::channel();
+        manager.start_with("test".to_string(), || false, move |state| sender.send(state).unwrap());
+
+        let state = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(state, ThreadState::Finished));
+        assert_eq!(manager.get_state("test".to_string()), "finished");
+    }
+
+    #[test]
+    fn threads_stopped_during_their_last_step_stay_stopped() {
+        let mut manager = ThreadManager::new();
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (stopped_sender, stopped_receiver) = mpsc::channel::<()>();
+        let (exit_sender, exit_receiver) = mpsc::channel();
+        let started_sender = Mutex::new(started_sender);
+        let stopped_receiver = Mutex::new(stopped_receiver);
+        manager.start_with("test".to_string(), move || {
+            // the thread is stopped while this step runs, then the step ends the thread
+            started_sender.lock().unwrap().send(()).unwrap();
+            stopped_receiver.lock().unwrap().recv().unwrap();
+            false
+        }, move |state| exit_sender.send(state).unwrap());
+
+        started_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(manager.stop("test".to_string()));
+        stopped_sender.send(()).unwrap();
+
+        let state = exit_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(state, ThreadState::Stopped));
+        assert_eq!(manager.get_state("test".to_string()), "stopped");
+    }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use std::{fs, io::Read};
+use chrono::NaiveDateTime;
 
 // Config is a struct that holds the configuration for the program
 #[derive(Clone)]
@@ -11,6 +12,9 @@ pub struct Config {
 
     // The name of the path to the strategy lua files and session settings to read
     pub strategies_path: String,
+
+    // The intervals of the rollups the database keeps in sync with the datasets (ex: 1h,1d)
+    pub rollups: Vec<String>,
 }
 
 impl Config {
@@ -19,7 +23,8 @@ impl Config {
         Config {
             datasets_path,
             indicators_path,
-            strategies_path
+            strategies_path,
+            rollups: Vec::new(),
         }
     }
 
@@ -29,6 +34,7 @@ impl Config {
             "datasets_path" => self.datasets_path = value.to_string(),
             "indicators_path" => self.indicators_path = value.to_string(),
             "strategies_path" => self.strategies_path = value.to_string(),
+            "rollups" => self.rollups = value.split(',').map(|interval| interval.trim().to_string()).filter(|interval| !interval.is_empty()).collect(),
             _ => println!("Unknown key: {}", key),
         }
     }
@@ -41,15 +47,26 @@ pub fn read_config(path: String) -> Config {
     let mut contents = String::new();
     file.read_to_string(&mut contents).unwrap();
     for line in contents.lines() {
-        let mut parts = line.splitn(2, '=');
-        let key = parts.next().unwrap();
-        let value = parts.next().unwrap();
+        let (key, value) = line.split_once('=').unwrap();
         config.set(key, value);
     }
     config
 }
 
+// parse a time as unix seconds or a utc date and time (2020-06-01T13:37 or 2020-06-01T13:37:00)
+pub fn parse_time(value: &str) -> Option<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp);
+    }
+
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+        .map(|datetime| datetime.and_utc().timestamp())
+}
+
 // read a csv file and return a Vec of Vec of f64
+#[allow(dead_code)]
 pub fn read_data(path: String) -> Vec<Vec<f64>> {
     let mut data = Vec::new();
     let mut file = fs::File::open(path).unwrap();
@@ -1,16 +1,30 @@
 
 -- Define inputs, get inputs (from settings file) and define default values for inputs
-local rsi_periods        = input("rsi_periods", "integer", 14)
-local rsi_buy_threshold  = input("rsi_buy_threshold", "integer", 30)
-local rsi_sell_threshold = input("rsi_sell_threshold", "integer", 70)
-local quantity           = input("order_quantity", "float", 100)
+local rsi_periods        = input("rsi_periods", "integer", 14, {min = 2})
+local rsi_buy_threshold  = input("rsi_buy_threshold", "integer", 30, {min = 0, max = 100})
+local rsi_sell_threshold = input("rsi_sell_threshold", "integer", 70, {min = 0, max = 100})
+local quantity           = input("order_quantity", "float", 100, {min = 0})
 
 -- change environment settings
 env("logging", true)                         -- will log events (orders, errors, any print calls) to file
-env("data_integrity_checks", false)          -- will stop if any data integrity issues are detected
+env("data_integrity_checks", false)          -- will only report data integrity issues (true stops on them)
 env("data_missing_mode", "smoothing")        -- will smooth out any gaps in the data as best as it can
 env("history_limit", 300)                    -- set the max number of historic data to track
-env("intervals", ["3m", "5m", "10m", "30m"]) -- can optionally consolidate candles to multiple timeframes (default 1m)
+env("intervals", {"3m", "5m", "10m", "30m"}) -- can optionally consolidate candles to multiple timeframes (default 1m)
+-- env("rollups", true)                      -- can read the rollups of the datasets when they can build every interval
+-- env("order_book", 5)                      -- can read the top 5 levels of the order book with every base bar (bar.order_book)
+-- env("fields", {"funding_rate"})           -- can only pass some of the extra fields of the datasets with the bars (default every field)
+-- env("price_adjustment", "total_return")   -- can adjust the prices for splits (split_adjusted) or splits and dividends (default raw)
+-- env("extended_hours", true)               -- can pass the pre/post-market bars of exchanges with a calendar (default regular sessions)
+-- env("snapshot", 3)                        -- can read the datasets as they were at a snapshot of the catalog (default the latest data)
+-- env("alignment", "intersection")          -- can only read the range every dataset has bars in, or every dataset over its own range (independent, default union)
+-- env("commission", 0.001)                  -- can charge a commission of 0.1% of the value of every fill (default none)
+
+-- timers fire in the time of the bars, with cron specs in utc or relative to the sessions of the exchange
+-- schedule("59 23 * * *", function(event) print("daily at 23:59 utc") end)
+-- schedule("market_open mon", function(event) print("every monday at the open") end)
+-- local timer = schedule("market_close -5m", function(event) print("5 minutes before every close") end)
+-- unschedule(timer)
 
 -- when a new bar of data is received
 -- passes through the current bar and a list of up to 300 of the latest bars
@@ -25,38 +39,62 @@ function on_bar(bar, history)
     local low       = bar.low       -- the low price of the bar
     local close     = bar.close     -- the close price of the bar
     local volume    = bar.volume    -- the volume price of the bar
+    -- with env("order_book", levels) base bars have the latest book: bar.order_book.bids[1].price is the best bid
 
 
     -- get the RSI indicator of the last x bars of the given timeframe of the given symbol
-    -- errors if indicator is not found
-    indicator("rsi", rsi_periods, interval, symbol)
+    -- errors if indicator is not found, the value is nil until the indicator has enough bars
+    local rsi = indicator("rsi", rsi_periods, interval, symbol, exchange):val()
     -- instead of just getting the current indicator value, we can get x number of previous values
-    -- local rsi = indicator("rsi", rsi_periods, interval, symbol):history(10)
+    -- local rsi = indicator("rsi", rsi_periods, interval, symbol, exchange):history(10)
+    -- indicators and history columns are series: they support + - * / and helpers that cost the same on every bar
+    -- local trend = history.close:crossover(indicator("ema", 20, interval, symbol, exchange)) and history.close:rising(3)
+    if rsi == nil or interval ~= "1m" then
+        return true
+    end
 
     -- if RSI is below a given threshold and we don't have a position, buy
     if rsi < rsi_buy_threshold and not get_position(exchange, symbol) then
-        return command("order", (exchange, symbol, "market", quantity, "buy"))
+        return command("order", {exchange = exchange, symbol = symbol, type = "market", quantity = quantity, side = "buy"})
     end
 
     -- if RSI is above a given threshold and we have a position, sell
     if rsi > rsi_sell_threshold and get_position(exchange, symbol) then
-        return command("order", (exchange, symbol, "market", quantity, "sell"))
+        return command("order", {exchange = exchange, symbol = symbol, type = "market", quantity = quantity, side = "sell"})
     end
 
 
     -- other commands we can send
     -- "insight" - log some market insight
 
-    print("on_bar executed")
-    print("bar: " .. bar)
-
 
     -- we let the system know our signal is good
     return true
 end
 
--- when a new bar of data is received
--- passes through the order event
+-- every callback below is optional, the runtime skips the ones a strategy does not define
+
+-- when the backtest starts (context has name, datasets, intervals, initial_cash, history_limit and inputs)
+function on_start(context)
+    print("starting " .. context.name .. " with " .. context.initial_cash)
+end
+
+-- when the status of an order changes (submitted, filled or invalid)
 function on_order(order)
 
+end
+
+-- when an order is filled (fill has order_id, side, quantity, price and commission)
+function on_fill(fill)
+
+end
+
+-- when a split or a dividend of a symbol goes ex (event has exchange, symbol, timestamp, type and value)
+function on_corporate_action(event)
+
+end
+
+-- when the backtest is complete, the returned table is added to the metrics of the session
+function on_end(summary)
+    return {return_per_fill = summary.fills > 0 and summary.total_return / summary.fills or 0}
 end
\ No newline at end of file
@@ -10,17 +10,20 @@ local order_quantity     = get_setting("order_quantity", "float", 0.01)
 
 -- when a new bar of data is received
 function on_bar(bar)
-    -- Get the RSI indicator of the last x bars of the given timeframe of the given symbol
-    local rsi = indicator("rsi", rsi_periods, interval, symbol)
+    -- Get the RSI indicator of the last x bars of the given timeframe of the given symbol (nil until it has enough bars)
+    local rsi = indicator("rsi", rsi_periods, interval, symbol, exchange):val()
+    if rsi == nil or bar.interval ~= interval or bar.symbol ~= symbol then
+        return true
+    end
 
     -- If RSI is below a given threshold and we don't have a position, buy
-    if rsi < rsi_buy_threshold and not get_position(interval) then
-        execute_market_order(interval, order_quantity, "buy")
+    if rsi < rsi_buy_threshold and not get_position(exchange, symbol) then
+        execute_market_order(exchange, symbol, order_quantity, "buy")
     end
 
     -- If RSI is above a given threshold and we have a position, sell
-    if rsi > rsi_sell_threshold and get_position(interval) then
-        execute_market_order(interval, order_quantity, "sell")
+    if rsi > rsi_sell_threshold and get_position(exchange, symbol) then
+        execute_market_order(exchange, symbol, order_quantity, "sell")
     end
 
     print("on_bar executed")
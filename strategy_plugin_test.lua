@@ -16,7 +16,7 @@ local iRSI = indicator("rsi", 14, "5m", "SPY", "NYSE")
 local iEMA = indicator("ema", 14, "5m", "SPY", "NYSE")
 print(iRSI:val())
 print(dump(iRSI:history(100)))
--- print((iRSI + iEMA):crossover("up")) :FUTURE
+print((iRSI - iEMA):crossover())
 
 -- when a new bar of data is received
 -- passes through the current bar and a list of up to 300 of the latest bars